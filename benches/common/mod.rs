@@ -0,0 +1,40 @@
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use rust_bril::representation::{RichAbstractProgram, RichProgram};
+
+// Programs shared by the benches: the largest ones in `benchmarks/`, pinned so that results stay
+// comparable from one commit to the next
+
+pub const PROGRAMS: &[&str] = &[
+    "benchmarks/mem/connected-components.bril",
+    "benchmarks/mixed/cholesky.bril",
+    "benchmarks/mem/systolic.bril",
+    "benchmarks/float/conjugate-gradient.bril",
+];
+
+pub fn path(program: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(program)
+}
+
+/// short name for benchmark ids, e.g. `cholesky`
+pub fn name(program: &str) -> String {
+    Path::new(program)
+        .file_stem()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn source(program: &str) -> String {
+    std::fs::read_to_string(path(program)).unwrap()
+}
+
+pub fn load(program: &str) -> RichProgram {
+    RichProgram::from_file(&path(program)).unwrap()
+}
+
+pub fn ssa(program: &str) -> RichAbstractProgram {
+    RichAbstractProgram::try_from_program(load(program)).unwrap()
+}
@@ -0,0 +1,210 @@
+mod common;
+
+use std::{collections::BTreeMap, path::PathBuf, process::ExitCode};
+
+use criterion::{BatchSize, BenchmarkId, Criterion};
+use rust_bril::{
+    optimizations::builtin_passes,
+    representation::{parse_program, DominanceInfo, RichAbstractProgram},
+};
+
+// Every stage of the pipeline over the pinned programs: parsing, SSA construction, dominance, each
+// built-in pass and leaving SSA form. `cargo bench --bench suite`
+//
+// After criterion is done, the latest estimate of each benchmark is written to the JSON file
+// named by BENCH_RECORD. When BENCH_BASELINE names an earlier record, benchmarks that got more
+// than BENCH_TOLERANCE percent (default 10) slower are listed and the run fails.
+
+const GROUPS: &[&str] = &["parse", "ssa", "dominance", "passes", "from_ssa"];
+const RECORD_VERSION: u64 = 1;
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for &program in common::PROGRAMS {
+        let source = common::source(program);
+        group.bench_function(common::name(program), |b| {
+            b.iter(|| parse_program(&source).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn ssa(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ssa");
+    for &program in common::PROGRAMS {
+        let loaded = common::load(program);
+        group.bench_function(common::name(program), |b| {
+            b.iter_batched(
+                || loaded.clone(),
+                |program| RichAbstractProgram::try_from_program(program).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn dominance(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dominance");
+    for &program in common::PROGRAMS {
+        let ssa = common::ssa(program);
+        group.bench_function(common::name(program), |b| {
+            b.iter(|| {
+                ssa.program
+                    .functions
+                    .values()
+                    .map(|af| DominanceInfo::from(&af.cfg))
+                    .collect::<Vec<_>>()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn passes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("passes");
+    for &program in common::PROGRAMS {
+        let functions: Vec<_> = common::ssa(program)
+            .program
+            .functions
+            .into_values()
+            .collect();
+        for pass in builtin_passes() {
+            let id = BenchmarkId::new(pass.name(), common::name(program));
+            group.bench_function(id, |b| {
+                b.iter_batched(
+                    || functions.clone(),
+                    |mut functions| {
+                        for af in functions.iter_mut() {
+                            pass.run(af).unwrap();
+                        }
+                        functions
+                    },
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn from_ssa(c: &mut Criterion) {
+    let mut group = c.benchmark_group("from_ssa");
+    for &program in common::PROGRAMS {
+        let ssa = common::ssa(program);
+        group.bench_function(common::name(program), |b| {
+            b.iter_batched(
+                || ssa.clone(),
+                |ssa| ssa.into_program(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// where criterion keeps its results, following its own lookup
+fn criterion_home() -> PathBuf {
+    if let Some(home) = std::env::var_os("CRITERION_HOME") {
+        return home.into();
+    }
+    let target = std::env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| common::path("target"));
+    target.join("criterion")
+}
+
+/// mean time in nanoseconds of every benchmark of the suite, keyed by `group/name`
+fn collect_results() -> BTreeMap<String, f64> {
+    let home = criterion_home();
+    let mut results = BTreeMap::new();
+    for group in GROUPS {
+        let pattern = home.join(group).join("**/new/estimates.json");
+        for path in glob::glob(&pattern.to_string_lossy()).unwrap().flatten() {
+            let estimates: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            let id = path
+                .parent()
+                .and_then(|new| new.parent())
+                .and_then(|dir| dir.strip_prefix(&home).ok())
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/");
+            if let Some(mean) = estimates["mean"]["point_estimate"].as_f64() {
+                results.insert(id, mean);
+            }
+        }
+    }
+    results
+}
+
+/// benchmarks slower than `baseline` by more than `tolerance` percent, with both times
+fn regressions(
+    results: &BTreeMap<String, f64>,
+    baseline: &BTreeMap<String, f64>,
+    tolerance: f64,
+) -> Vec<(String, f64, f64)> {
+    results
+        .iter()
+        .filter_map(|(id, &now)| {
+            let &before = baseline.get(id)?;
+            (now > before * (1.0 + tolerance / 100.0)).then(|| (id.clone(), before, now))
+        })
+        .collect()
+}
+
+fn main() -> ExitCode {
+    let mut c = Criterion::default().configure_from_args();
+    parse(&mut c);
+    ssa(&mut c);
+    dominance(&mut c);
+    passes(&mut c);
+    from_ssa(&mut c);
+    c.final_summary();
+
+    let results = collect_results();
+    if let Some(path) = std::env::var_os("BENCH_RECORD") {
+        let record = serde_json::json!({ "version": RECORD_VERSION, "results": results });
+        std::fs::write(&path, serde_json::to_string_pretty(&record).unwrap()).unwrap();
+        println!(
+            "recorded {} results in {}",
+            results.len(),
+            path.to_string_lossy()
+        );
+    }
+
+    let Some(path) = std::env::var_os("BENCH_BASELINE") else {
+        return ExitCode::SUCCESS;
+    };
+    let record: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(
+        record["version"].as_u64(),
+        Some(RECORD_VERSION),
+        "unsupported record version in {}",
+        path.to_string_lossy()
+    );
+    let baseline: BTreeMap<String, f64> =
+        serde_json::from_value(record["results"].clone()).unwrap();
+    let tolerance = std::env::var("BENCH_TOLERANCE")
+        .ok()
+        .and_then(|t| t.parse().ok())
+        .unwrap_or(10.0);
+
+    let slower = regressions(&results, &baseline, tolerance);
+    for (id, before, now) in &slower {
+        println!(
+            "regression: {} {:.1} us -> {:.1} us ({:+.1}%)",
+            id,
+            before / 1000.0,
+            now / 1000.0,
+            (now / before - 1.0) * 100.0
+        );
+    }
+    if slower.is_empty() {
+        println!("no regressions over {}%", tolerance);
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use rust_bril::optimizations::{dce, lvn, FunctionPass};
+
+// LVN and DCE over the largest benchmarks, where the transfer functions dominate the run time.
+// `cargo bench --bench transfer`
+
+fn transfers(c: &mut Criterion) {
+    for &program in common::PROGRAMS {
+        let functions: Vec<_> = common::ssa(program)
+            .program
+            .functions
+            .into_values()
+            .collect();
+        let name = common::name(program);
+        for (pass, run) in [("lvn", lvn as FunctionPass), ("dce", dce as FunctionPass)] {
+            c.bench_function(&format!("{}/{}", pass, name), |b| {
+                b.iter_batched(
+                    || functions.clone(),
+                    |functions| {
+                        for af in functions {
+                            run(af).unwrap();
+                        }
+                    },
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+}
+
+criterion_group!(benches, transfers);
+criterion_main!(benches);
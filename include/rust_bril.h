@@ -0,0 +1,42 @@
+/* C interface to rust_bril, build with `cargo build --release --features ffi` and link against
+ * target/release/librust_bril.so (or .dylib / .dll). */
+#ifndef RUST_BRIL_H
+#define RUST_BRIL_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum BrilStatus {
+    BRIL_OK = 0,
+    BRIL_NULL_ARGUMENT = 1,
+    BRIL_INVALID_UTF8 = 2,
+    BRIL_INVALID_PROGRAM = 3,
+    BRIL_UNKNOWN_PASS = 4,
+    BRIL_PASS_FAILED = 5,
+    BRIL_PANIC = 6,
+} BrilStatus;
+
+typedef struct BrilProgram BrilProgram;
+
+/* load a NUL terminated Bril JSON program; free the result with bril_program_free */
+BrilStatus bril_program_from_json(const char *json, BrilProgram **out);
+
+/* run a comma separated list of passes: "lvn", "dvnt", "dce", "licm", "constprop", "copyprop",
+   "loadcse", "unroll", "rotate", "reassoc", "branchfold", "simplifycfg" */
+BrilStatus bril_run_passes(BrilProgram *program, const char *passes);
+
+/* serialize as Bril JSON, in SSA form if keep_ssa is non-zero; free the result with bril_string_free */
+BrilStatus bril_program_to_json(const BrilProgram *program, int keep_ssa, char **out);
+
+/* message of the last failed call on this thread, or NULL */
+const char *bril_last_error(void);
+
+void bril_program_free(BrilProgram *program);
+void bril_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
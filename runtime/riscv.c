@@ -0,0 +1,118 @@
+// Runtime for the assembly `rust_bril optimize --output-format riscv` writes, e.g.
+//
+//     rust_bril optimize prog.bril -O2 --output-format riscv -o prog.s
+//     riscv64-linux-gnu-gcc -static prog.s runtime/riscv.c -o prog
+//     qemu-riscv64 ./prog 5
+//
+// Values print the way `rust_bril interpret` prints them.
+
+#include <inttypes.h>
+#include <math.h>
+#include <stdbool.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static void fail(const char *message, const char *detail) {
+    fprintf(stderr, "error: %s%s\n", message, detail);
+    exit(2);
+}
+
+void bril_check_args(int64_t argc, int64_t expected) {
+    if (argc - 1 != expected) {
+        fprintf(stderr, "error: expected %" PRId64 " arguments, got %" PRId64 "\n", expected,
+                argc - 1);
+        exit(2);
+    }
+}
+
+int64_t bril_parse_int(const char *text) {
+    char *end;
+    int64_t x = strtoll(text, &end, 10);
+    if (*text == '\0' || *end != '\0') fail("not an int: ", text);
+    return x;
+}
+
+bool bril_parse_bool(const char *text) {
+    if (strcmp(text, "true") == 0) return true;
+    if (strcmp(text, "false") == 0) return false;
+    fail("not a bool: ", text);
+    return false;
+}
+
+double bril_parse_float(const char *text) {
+    char *end;
+    double x = strtod(text, &end);
+    if (*text == '\0' || *end != '\0') fail("not a float: ", text);
+    return x;
+}
+
+// the code point of a single UTF-8 encoded character
+uint32_t bril_parse_char(const char *text) {
+    const unsigned char *s = (const unsigned char *)text;
+    int length = s[0] < 0x80 ? 1 : s[0] < 0xe0 ? 2 : s[0] < 0xf0 ? 3 : 4;
+    uint32_t c = length == 1 ? s[0] : s[0] & (0x3f >> (length - 1));
+    for (int i = 1; i < length; i++) {
+        if ((s[i] & 0xc0) != 0x80) fail("not a char: ", text);
+        c = c << 6 | (s[i] & 0x3f);
+    }
+    if (s[0] == 0 || s[length] != 0) fail("not a char: ", text);
+    return c;
+}
+
+void bril_print_int(int64_t x) { printf("%" PRId64, x); }
+
+void bril_print_bool(bool b) { printf(b ? "true" : "false"); }
+
+void bril_print_float(double x) {
+    if (isnan(x)) {
+        printf("NaN");
+    } else if (isinf(x)) {
+        printf(x > 0 ? "Infinity" : "-Infinity");
+    } else if (x != 0.0 && (fabs(x) >= 1e10 || fabs(x) <= 1e-10)) {
+        // 1.5e+10 and 1.5e-7 rather than C's 1.5e+10 and 1.5e-07
+        char text[64];
+        snprintf(text, sizeof text, "%.17e", x);
+        char *exponent = strchr(text, 'e') + 2;
+        while (exponent[0] == '0' && exponent[1] != '\0') {
+            memmove(exponent, exponent + 1, strlen(exponent));
+        }
+        printf("%s", text);
+    } else {
+        printf("%.17f", x);
+    }
+}
+
+void bril_print_char(uint32_t c) {
+    if (c < 0x80) {
+        putchar(c);
+    } else if (c < 0x800) {
+        putchar(0xc0 | c >> 6);
+        putchar(0x80 | (c & 0x3f));
+    } else if (c < 0x10000) {
+        putchar(0xe0 | c >> 12);
+        putchar(0x80 | (c >> 6 & 0x3f));
+        putchar(0x80 | (c & 0x3f));
+    } else {
+        putchar(0xf0 | c >> 18);
+        putchar(0x80 | (c >> 12 & 0x3f));
+        putchar(0x80 | (c >> 6 & 0x3f));
+        putchar(0x80 | (c & 0x3f));
+    }
+}
+
+void bril_print_ptr(void *p) { printf("<ptr %p>", p); }
+
+void bril_print_space(void) { putchar(' '); }
+
+void bril_print_newline(void) { putchar('\n'); }
+
+// every value takes 8 bytes
+void *bril_alloc(int64_t count) {
+    if (count <= 0) fail("cannot allocate a non-positive number of values", "");
+    void *p = calloc(count, 8);
+    if (p == NULL) fail("out of memory", "");
+    return p;
+}
+
+void bril_free(void *p) { free(p); }
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use crate::representation::{AbstractFunction, Code, Literal, MemoryOp, Type, ValueOp};
+
+// Flow-insensitive points-to facts for the pointers of a function in SSA form. Each pointer is
+// traced back to the `alloc` that produced it, with its offset into the allocation while every
+// `ptradd` on the way adds a constant; pointers from arguments, loads and calls may point
+// anywhere. An allocation site runs again on every loop iteration, so equal facts do not make two
+// pointers equal. Only different sites, or different offsets from one site, prove that two
+// pointers never alias.
+
+/// Where a pointer may point
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PointsTo {
+    /// into an allocation made by the `alloc` that defines `site`, at `offset` if it is constant
+    Allocation {
+        site: String,
+        offset: Option<i64>,
+    },
+    Anywhere,
+}
+
+impl PointsTo {
+    fn join(self, other: PointsTo) -> PointsTo {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (
+                PointsTo::Allocation { site, .. },
+                PointsTo::Allocation {
+                    site: other_site, ..
+                },
+            ) if site == other_site => PointsTo::Allocation { site, offset: None },
+            _ => PointsTo::Anywhere,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AliasInfo {
+    points_to: HashMap<String, PointsTo>,
+    /// pointer -> the pointer it is a copy of, through any number of `id`s
+    copies: HashMap<String, String>,
+}
+
+impl From<&AbstractFunction> for AliasInfo {
+    fn from(af: &AbstractFunction) -> Self {
+        let blocks = &af.cfg.basic_blocks;
+        let codes: Vec<&Code> = (blocks.iter())
+            .flat_map(|b| b.preheader.iter().chain(&b.instructions))
+            .collect();
+
+        let mut definitions: HashMap<&str, usize> = HashMap::new();
+        let args = af.args.iter().flatten().map(|arg| arg.name.as_str());
+        let phis = blocks
+            .iter()
+            .flat_map(|b| &b.phi_nodes)
+            .map(|p| p.dest.as_str());
+        for dest in args
+            .chain(phis)
+            .chain(codes.iter().filter_map(|c| c.get_destination()))
+        {
+            *definitions.entry(dest).or_default() += 1;
+        }
+        let single = |var: &str| definitions.get(var) == Some(&1);
+
+        let constants: HashMap<&str, i64> = (codes.iter())
+            .filter_map(|code| match code {
+                Code::Constant {
+                    dest,
+                    value: Literal::Int(x),
+                    ..
+                } if single(dest) => Some((dest.as_str(), *x)),
+                _ => None,
+            })
+            .collect();
+        let copies: HashMap<String, String> = (codes.iter())
+            .filter_map(|code| match code {
+                Code::Value {
+                    op: ValueOp::Id,
+                    dest,
+                    value_type: Type::Ptr(_),
+                    args: Some(args),
+                    ..
+                } if single(dest) && single(&args[0]) => Some((dest.clone(), args[0].clone())),
+                _ => None,
+            })
+            .collect();
+
+        // facts only grow towards `Anywhere`, so iterating until nothing changes terminates
+        let mut points_to: HashMap<String, PointsTo> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for code in &codes {
+                let fact = match code {
+                    Code::Memory {
+                        op: MemoryOp::Alloc,
+                        dest: Some(dest),
+                        ..
+                    } => Some(PointsTo::Allocation {
+                        site: dest.clone(),
+                        offset: Some(0),
+                    }),
+                    Code::Memory {
+                        op: MemoryOp::PtrAdd,
+                        args: Some(args),
+                        ..
+                    } => match points_to.get(&args[0]) {
+                        Some(PointsTo::Allocation { site, offset }) => {
+                            let added = constants.get(args[1].as_str());
+                            Some(PointsTo::Allocation {
+                                site: site.clone(),
+                                offset: offset.zip(added.copied()).map(|(a, b)| a.wrapping_add(b)),
+                            })
+                        }
+                        other => other.cloned(),
+                    },
+                    Code::Value {
+                        op: ValueOp::Id,
+                        value_type: Type::Ptr(_),
+                        args: Some(args),
+                        ..
+                    } => points_to.get(&args[0]).cloned(),
+                    Code::Value {
+                        value_type: Type::Ptr(_),
+                        ..
+                    }
+                    | Code::Memory {
+                        op: MemoryOp::Load,
+                        ptr_type: Some(Type::Ptr(_)),
+                        ..
+                    } => Some(PointsTo::Anywhere),
+                    _ => None,
+                };
+                if let (Some(dest), Some(fact)) = (code.get_destination(), fact) {
+                    changed |= update(&mut points_to, dest, fact, single(dest));
+                }
+            }
+            for phi in blocks.iter().flat_map(|b| &b.phi_nodes) {
+                if matches!(phi.phi_type, Type::Ptr(_)) {
+                    let fact = (phi.phi_args.iter())
+                        .filter_map(|(var, _)| points_to.get(var).cloned())
+                        .reduce(PointsTo::join);
+                    if let Some(fact) = fact {
+                        changed |= update(&mut points_to, &phi.dest, fact, single(&phi.dest));
+                    }
+                }
+            }
+            for arg in af.args.iter().flatten() {
+                if matches!(arg.arg_type, Type::Ptr(_)) {
+                    changed |= update(&mut points_to, &arg.name, PointsTo::Anywhere, true);
+                }
+            }
+        }
+
+        Self { points_to, copies }
+    }
+}
+
+impl AliasInfo {
+    pub fn points_to(&self, pointer: &str) -> PointsTo {
+        self.points_to
+            .get(pointer)
+            .cloned()
+            .unwrap_or(PointsTo::Anywhere)
+    }
+
+    /// whether the two pointers can point to the same cell
+    pub fn may_alias(&self, a: &str, b: &str) -> bool {
+        match (self.points_to(a), self.points_to(b)) {
+            (
+                PointsTo::Allocation { site, offset },
+                PointsTo::Allocation {
+                    site: other_site,
+                    offset: other_offset,
+                },
+            ) => {
+                site == other_site
+                    && (offset.is_none() || other_offset.is_none() || offset == other_offset)
+            }
+            _ => true,
+        }
+    }
+
+    /// the pointer that `pointer` is a copy of, or `pointer` itself. Copies always hold the same
+    /// address, unlike pointers with the same facts
+    pub fn representative<'a>(&'a self, pointer: &'a str) -> &'a str {
+        let mut current = pointer;
+        while let Some(source) = self.copies.get(current) {
+            current = source;
+        }
+        current
+    }
+}
+
+/// join `fact` into what is known about `dest`, returning whether that changed
+fn update(
+    points_to: &mut HashMap<String, PointsTo>,
+    dest: &str,
+    fact: PointsTo,
+    single_definition: bool,
+) -> bool {
+    let fact = match points_to.get(dest) {
+        _ if !single_definition => PointsTo::Anywhere,
+        Some(old) => old.clone().join(fact),
+        None => fact,
+    };
+    if points_to.get(dest) == Some(&fact) {
+        return false;
+    }
+    points_to.insert(dest.to_string(), fact);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::function_from_text;
+
+    #[test]
+    fn separates_sites_and_constant_offsets() {
+        let text = r#"
+@main(p: ptr<int>, n: int) {
+  one: int = const 1;
+  two: int = const 2;
+  a: ptr<int> = alloc two;
+  b: ptr<int> = alloc two;
+  a1: ptr<int> = ptradd a one;
+  an: ptr<int> = ptradd a n;
+  c: ptr<int> = id a1;
+  print n;
+}
+"#;
+        let af = function_from_text(text);
+        // SSA form numbers every definition
+        let var = |name: &str| format!("{}_0", name);
+        let alias = AliasInfo::from(&af);
+
+        assert!(!alias.may_alias(&var("a"), &var("b")));
+        assert!(!alias.may_alias(&var("a"), &var("a1")));
+        assert!(alias.may_alias(&var("a"), &var("an")));
+        assert!(!alias.may_alias(&var("b"), &var("an")));
+        assert!(alias.may_alias(&var("p"), &var("b")));
+        assert_eq!(alias.representative(&var("c")), var("a1"));
+        assert_eq!(
+            alias.points_to(&var("c")),
+            PointsTo::Allocation {
+                site: var("a"),
+                offset: Some(1)
+            }
+        );
+    }
+}
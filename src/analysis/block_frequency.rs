@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+
+use crate::{
+    analysis::LoopInfo,
+    representation::{
+        AbstractFunction, BlockId, ControlFlowGraph, DominanceInfo, FunctionAttribute, Terminator,
+    },
+};
+
+// How often the blocks of a function run, for passes that weigh code by it. With a profile
+// attached (`--profile-in`) the counts are used, otherwise they are estimated. Branch
+// probabilities come from heuristics in the style of Ball and Larus, tried in order until one
+// applies: a branch that stays in its loop is likely, one into a block that calls a `cold`
+// function or returns is unlikely, and anything else is even. Frequencies follow as in Wu and
+// Larus: each loop, innermost first, is walked from its header to find how often control comes
+// back, which scales the header by the expected number of iterations, and then the function is
+// walked from the entry. Backedges are left out of both walks, so they visit blocks in a
+// topological order.
+
+/// chance that a branch stays in the innermost loop it is in
+const LOOP_TAKEN: f64 = 0.88;
+/// chance of a branch into a block that calls a `cold` function
+const COLD_TAKEN: f64 = 0.05;
+/// chance of a branch into a block that returns
+const RETURN_TAKEN: f64 = 0.28;
+/// trips assumed of a loop that the probabilities say never ends
+const MAX_LOOP_TRIPS: f64 = 1000.0;
+
+/// How often each block and edge of a function runs, measured or estimated
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlockFrequencies {
+    /// runs of each block per run of the entry block, by block id
+    pub blocks: Vec<f64>,
+    /// chance that control leaves `from` for `to`, keyed by `(from, to)`
+    pub probabilities: HashMap<(BlockId, BlockId), f64>,
+    /// whether the frequencies come from a profile
+    pub measured: bool,
+}
+
+impl BlockFrequencies {
+    pub fn block(&self, block: BlockId) -> f64 {
+        self.blocks.get(block).copied().unwrap_or_default()
+    }
+
+    pub fn probability(&self, from: BlockId, to: BlockId) -> f64 {
+        self.probabilities
+            .get(&(from, to))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// runs of the edge `from` -> `to` per run of the entry block
+    pub fn edge(&self, from: BlockId, to: BlockId) -> f64 {
+        self.block(from) * self.probability(from, to)
+    }
+
+    /// The static estimate, ignoring any profile attached to the blocks
+    pub fn estimate(af: &AbstractFunction) -> Self {
+        let loop_info = LoopInfo::from(af);
+        let probabilities = estimate_probabilities(af, &loop_info);
+        let blocks = propagate(&af.cfg, af.dominance_info(), &loop_info, &probabilities);
+        Self {
+            blocks,
+            probabilities,
+            measured: false,
+        }
+    }
+
+    /// The counts of the profile attached to the blocks of `af`, `None` without one. Branches
+    /// out of blocks that never ran keep their estimated probabilities
+    pub fn measure(af: &AbstractFunction) -> Option<Self> {
+        let blocks = &af.cfg.basic_blocks;
+        let counts: Vec<u64> = (blocks.iter())
+            .map(|block| block.frequency)
+            .collect::<Option<_>>()?;
+        let mut frequencies = Self::estimate(af);
+        let calls = counts.first().copied().unwrap_or_default();
+        frequencies.blocks = (counts.iter())
+            .map(|&n| match calls {
+                0 => 0.0,
+                calls => n as f64 / calls as f64,
+            })
+            .collect();
+        for (from, successors) in af.cfg.successors.iter().enumerate() {
+            if counts[from] == 0 {
+                continue;
+            }
+            for to in successors {
+                let taken = blocks[from].edge_frequencies.get(&blocks[to].label);
+                let taken = taken.copied().unwrap_or_default();
+                let probability = taken as f64 / counts[from] as f64;
+                frequencies.probabilities.insert((from, to), probability);
+            }
+        }
+        frequencies.measured = true;
+        Some(frequencies)
+    }
+}
+
+impl From<&AbstractFunction> for BlockFrequencies {
+    /// The profile attached to the blocks, or else the estimate
+    fn from(af: &AbstractFunction) -> Self {
+        Self::measure(af).unwrap_or_else(|| Self::estimate(af))
+    }
+}
+
+/// the chance of every edge, see the module comment
+fn estimate_probabilities(
+    af: &AbstractFunction,
+    loop_info: &LoopInfo,
+) -> HashMap<(BlockId, BlockId), f64> {
+    let blocks = &af.cfg.basic_blocks;
+    let calls_cold = |b: BlockId| {
+        (blocks[b].instructions.iter())
+            .any(|code| af.calls_function_with(code, FunctionAttribute::Cold))
+    };
+    let returns = |b: BlockId| matches!(blocks[b].terminator, Terminator::Ret(_));
+
+    let mut probabilities = HashMap::new();
+    for (from, successors) in af.cfg.successors.iter().enumerate() {
+        let targets: Vec<BlockId> = successors.iter().collect();
+        let [first, second] = targets[..] else {
+            for &to in &targets {
+                probabilities.insert((from, to), 1.0 / targets.len() as f64);
+            }
+            continue;
+        };
+        let stays = |to: BlockId| {
+            let innermost = loop_info.innermost_loop(from);
+            innermost.is_some_and(|l| loop_info.loops[l].body.contains(&to))
+        };
+        // the chance of `first`, from the first heuristic that tells the two apart
+        let heuristics: [(&dyn Fn(BlockId) -> bool, f64); 3] = [
+            (&stays, LOOP_TAKEN),
+            (&calls_cold, COLD_TAKEN),
+            (&returns, RETURN_TAKEN),
+        ];
+        let chance = (heuristics.iter())
+            .find(|(applies, _)| applies(first) != applies(second))
+            .map_or(0.5, |(applies, taken)| match applies(first) {
+                true => *taken,
+                false => 1.0 - taken,
+            });
+        probabilities.insert((from, first), chance);
+        probabilities.insert((from, second), 1.0 - chance);
+    }
+    probabilities
+}
+
+/// runs of every block per run of the entry, see the module comment
+fn propagate(
+    cfg: &ControlFlowGraph,
+    dominance: &DominanceInfo,
+    loop_info: &LoopInfo,
+    probabilities: &HashMap<(BlockId, BlockId), f64>,
+) -> Vec<f64> {
+    let backedge = |from: BlockId, to: BlockId| dominance.dominated_by(from, to);
+    let order = topological_order(cfg, dominance);
+    let probability =
+        |from: BlockId, to: BlockId| probabilities.get(&(from, to)).copied().unwrap_or_default();
+
+    // iterations of each loop per entry into it, by header
+    let mut scale = vec![1.0; cfg.basic_blocks.len()];
+    let mut local = vec![0.0; cfg.basic_blocks.len()];
+    for l in loop_info.loops.iter().rev() {
+        for &b in order.iter().filter(|b| l.body.contains(b)) {
+            local[b] = match b == l.header {
+                true => 1.0,
+                false => {
+                    let into = (cfg.predecessors[b].iter())
+                        .filter(|&p| l.body.contains(&p) && !backedge(p, b))
+                        .map(|p| local[p] * probability(p, b));
+                    into.sum::<f64>() * scale[b]
+                }
+            };
+        }
+        let back: f64 = (l.latches.iter())
+            .map(|&latch| local[latch] * probability(latch, l.header))
+            .sum();
+        scale[l.header] = 1.0 / (1.0 - back).max(1.0 / MAX_LOOP_TRIPS);
+    }
+
+    let mut frequencies = vec![0.0; cfg.basic_blocks.len()];
+    for &b in &order {
+        frequencies[b] = match b {
+            0 => 1.0,
+            _ => {
+                let into = (cfg.predecessors[b].iter())
+                    .filter(|&p| !backedge(p, b))
+                    .map(|p| frequencies[p] * probability(p, b));
+                into.sum::<f64>() * scale[b]
+            }
+        };
+    }
+    frequencies
+}
+
+/// blocks in an order where each comes after its predecessors over edges other than backedges.
+/// Blocks of an irreducible cycle, which has no such order, come last by id
+fn topological_order(cfg: &ControlFlowGraph, dominance: &DominanceInfo) -> Vec<BlockId> {
+    let mut waiting: Vec<usize> = (0..cfg.basic_blocks.len())
+        .map(|b| {
+            (cfg.predecessors[b].iter())
+                .filter(|&p| !dominance.dominated_by(p, b))
+                .count()
+        })
+        .collect();
+    let mut ready: Vec<BlockId> = (0..waiting.len()).filter(|&b| waiting[b] == 0).collect();
+    ready.reverse();
+    let mut order = Vec::with_capacity(waiting.len());
+    let mut placed = vec![false; waiting.len()];
+    while let Some(b) = ready.pop() {
+        order.push(b);
+        placed[b] = true;
+        for s in cfg.successors[b].iter() {
+            if dominance.dominated_by(b, s) {
+                continue;
+            }
+            waiting[s] -= 1;
+            if waiting[s] == 0 {
+                ready.push(s);
+            }
+        }
+    }
+    order.extend((0..placed.len()).filter(|&b| !placed[b]));
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::function_from_text;
+
+    #[test]
+    fn loops_run_often_and_returns_rarely() {
+        let text = r#"
+@main(n: int) {
+  i: int = const 0;
+  one: int = const 1;
+  negative: bool = lt n i;
+  br negative .bad .loop;
+.bad:
+  ret;
+.loop:
+  more: bool = lt i n;
+  br more .body .end;
+.body:
+  i: int = add i one;
+  jmp .loop;
+.end:
+  print i;
+}
+"#;
+        let af = function_from_text(text);
+        let id = |label: &str| af.cfg.label_map[label];
+        let start = af.cfg.predecessors[id("bad")].iter().next().unwrap();
+        let frequencies = af.block_frequencies();
+        assert!(!frequencies.measured);
+        assert_eq!(frequencies.block(0), 1.0);
+        assert_eq!(frequencies.probability(start, id("bad")), RETURN_TAKEN);
+        assert_eq!(frequencies.probability(id("loop"), id("body")), LOOP_TAKEN);
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        // the loop is entered 0.72 times and left with a chance of 0.12 per iteration
+        assert!(
+            close(frequencies.block(id("loop")), 6.0),
+            "{:?}",
+            frequencies
+        );
+        assert!(close(frequencies.block(id("end")), 1.0 - RETURN_TAKEN));
+        assert!(close(
+            frequencies.edge(id("body"), id("loop")),
+            6.0 * LOOP_TAKEN
+        ));
+    }
+}
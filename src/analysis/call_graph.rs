@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::representation::AbstractProgram;
+
+/// Static call graph of a program: which function calls which, and how many call sites each edge has
+#[derive(Debug, Clone, Default)]
+pub struct CallGraph {
+    /// every function in the program, sorted by name
+    pub functions: BTreeSet<String>,
+    /// (caller, callee) -> number of call sites
+    pub calls: BTreeMap<(String, String), usize>,
+}
+
+impl From<&AbstractProgram> for CallGraph {
+    fn from(program: &AbstractProgram) -> Self {
+        let mut graph = CallGraph {
+            functions: program.functions.keys().cloned().collect(),
+            calls: BTreeMap::new(),
+        };
+
+        for (caller, af) in program.functions.iter() {
+            for block in af.cfg.basic_blocks.iter() {
+                for instruction in block.instructions.iter().chain(block.preheader.iter()) {
+                    if !instruction.is_call() {
+                        continue;
+                    }
+                    for callee in instruction.get_funcs().into_iter().flatten() {
+                        *graph
+                            .calls
+                            .entry((caller.clone(), callee.clone()))
+                            .or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        graph
+    }
+}
+
+impl CallGraph {
+    /// functions called directly by `caller`
+    pub fn callees<'a>(&'a self, caller: &'a str) -> impl Iterator<Item = &'a String> + 'a {
+        self.calls
+            .keys()
+            .filter(move |(from, _)| from == caller)
+            .map(|(_, to)| to)
+    }
+
+    /// Strongly connected components (mutually recursive functions), callees before callers
+    pub fn sccs(&self) -> Vec<Vec<String>> {
+        struct Tarjan<'a> {
+            graph: &'a CallGraph,
+            index: HashMap<&'a str, usize>,
+            low: HashMap<&'a str, usize>,
+            stack: Vec<&'a str>,
+            on_stack: BTreeSet<&'a str>,
+            sccs: Vec<Vec<String>>,
+        }
+
+        impl<'a> Tarjan<'a> {
+            fn visit(&mut self, v: &'a str) {
+                let idx = self.index.len();
+                self.index.insert(v, idx);
+                self.low.insert(v, idx);
+                self.stack.push(v);
+                self.on_stack.insert(v);
+
+                for w in self.graph.callees(v) {
+                    let w = w.as_str();
+                    if !self.graph.functions.contains(w) {
+                        continue; // call to a function outside the program
+                    }
+                    if !self.index.contains_key(w) {
+                        self.visit(w);
+                        self.low.insert(v, self.low[v].min(self.low[w]));
+                    } else if self.on_stack.contains(w) {
+                        self.low.insert(v, self.low[v].min(self.index[w]));
+                    }
+                }
+
+                if self.low[v] == self.index[v] {
+                    let mut scc = vec![];
+                    while let Some(w) = self.stack.pop() {
+                        self.on_stack.remove(w);
+                        scc.push(w.to_string());
+                        if w == v {
+                            break;
+                        }
+                    }
+                    scc.sort();
+                    self.sccs.push(scc);
+                }
+            }
+        }
+
+        let mut tarjan = Tarjan {
+            graph: self,
+            index: HashMap::new(),
+            low: HashMap::new(),
+            stack: vec![],
+            on_stack: BTreeSet::new(),
+            sccs: vec![],
+        };
+        for f in self.functions.iter() {
+            if !tarjan.index.contains_key(f.as_str()) {
+                tarjan.visit(f);
+            }
+        }
+        tarjan.sccs
+    }
+
+    /// whether `function` belongs to a cycle in the call graph
+    pub fn is_recursive(&self, function: &str) -> bool {
+        self.calls
+            .contains_key(&(function.to_string(), function.to_string()))
+            || self
+                .sccs()
+                .iter()
+                .any(|scc| scc.len() > 1 && scc.iter().any(|f| f == function))
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::{
+    analysis::{signatures, typecheck, verify_cfg, verify_ssa},
+    representation::{
+        Code, EffectOp, Function, MemoryOp, Position, Program, RichAbstractProgram, RichProgram,
+        ValueOp,
+    },
+};
+
+// Diagnostics for `rust_bril verify`: errors for programs the compiler cannot accept (uses of
+// uninitialized variables, type errors, malformed control flow) and warnings for code that is
+// legal but suspicious (unused variables, unreachable code, allocations that are never freed)
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub function: Option<String>,
+    pub message: String,
+    pub position: Option<Position>,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(pos) = self.position {
+            write!(f, "{}:{}: ", pos.row, pos.col)?;
+        }
+        write!(f, "{}: {}", self.severity, self.message)?;
+        match &self.function {
+            Some(function) => write!(f, " (in @{})", function),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Diagnostic {
+    fn warning(function: &Function, message: String, position: Option<Position>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            function: Some(function.name.clone()),
+            message,
+            position,
+        }
+    }
+}
+
+/// Check a program without transforming it: the warnings of [`lint_function`] for every function,
+/// then SSA construction (which rejects uses of uninitialized variables) and the IR verifiers
+pub fn check_program(program: &Program) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> =
+        program.functions.iter().flat_map(lint_function).collect();
+
+    let rich = RichProgram {
+        original_text: Vec::new(),
+        program: program.clone(),
+    };
+    let abstract_program = match RichAbstractProgram::try_from_program(rich) {
+        Ok(abstract_program) => abstract_program.program,
+        Err(e) => {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                function: None,
+                message: e.to_string(),
+                position: e.position().copied(),
+            });
+            return diagnostics;
+        }
+    };
+
+    let signatures = signatures(&abstract_program);
+    let mut functions: Vec<_> = abstract_program.functions.values().collect();
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+    for af in functions {
+        let error = |message, position| Diagnostic {
+            severity: Severity::Error,
+            function: Some(af.name.clone()),
+            message,
+            position,
+        };
+        // the later checks index blocks through the CFG, so they only run on a sound one
+        let cfg = verify_cfg(af);
+        if !cfg.is_empty() {
+            diagnostics.extend(cfg.into_iter().map(|message| error(message, None)));
+            continue;
+        }
+        diagnostics.extend(
+            verify_ssa(af)
+                .into_iter()
+                .map(|message| error(message, None)),
+        );
+        let types = typecheck(af, Some(&signatures));
+        diagnostics.extend(types.iter().map(|e| error(e.to_string(), e.position)));
+    }
+    diagnostics
+}
+
+/// Warnings about one function: variables that are never read, instructions no path reaches and
+/// allocations that are never freed, returned, stored or passed to a call
+pub fn lint_function(function: &Function) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let used: HashSet<&str> = function
+        .instrs
+        .iter()
+        .flat_map(|code| code.get_arguments().into_iter().flatten())
+        .map(String::as_str)
+        .collect();
+
+    // variables can be redefined, so only warn once per name
+    let mut reported = HashSet::new();
+    for code in &function.instrs {
+        if let Some(dest) = code.get_destination() {
+            if !used.contains(dest) && !code.is_call() && reported.insert(dest) {
+                let message = format!("{} is never used", dest);
+                diagnostics.push(Diagnostic::warning(function, message, code.get_position()));
+            }
+        }
+    }
+
+    // code after a jump, branch or return is unreachable until the next label that is jumped to
+    let targets: HashSet<&str> = function
+        .instrs
+        .iter()
+        .flat_map(|code| code.get_labels().into_iter().flatten())
+        .map(String::as_str)
+        .collect();
+    let mut reachable = true;
+    for code in &function.instrs {
+        match code {
+            Code::Label { label, .. } => reachable = reachable || targets.contains(label.as_str()),
+            _ if !reachable => {
+                let message = "unreachable code".to_string();
+                diagnostics.push(Diagnostic::warning(function, message, code.get_position()));
+                // one warning per unreachable stretch
+                reachable = true;
+                continue;
+            }
+            _ => {}
+        }
+        if let Code::Effect {
+            op: EffectOp::Jmp | EffectOp::Br | EffectOp::Ret,
+            ..
+        } = code
+        {
+            reachable = false;
+        }
+    }
+
+    diagnostics.extend(leaks(function));
+    diagnostics
+}
+
+/// allocations whose pointer, or a copy or offset of it, never reaches `free`, `ret`, a call or
+/// memory
+fn leaks(function: &Function) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for alloc in &function.instrs {
+        let Code::Memory {
+            op: MemoryOp::Alloc,
+            dest: Some(dest),
+            ..
+        } = alloc
+        else {
+            continue;
+        };
+
+        // names the pointer flows into through `id` and `ptradd`
+        let mut aliases: HashSet<&str> = HashSet::from([dest.as_str()]);
+        loop {
+            let before = aliases.len();
+            for code in &function.instrs {
+                let copies = matches!(
+                    code,
+                    Code::Value {
+                        op: ValueOp::Id,
+                        ..
+                    } | Code::Memory {
+                        op: MemoryOp::PtrAdd,
+                        ..
+                    }
+                );
+                let reads_alias = code
+                    .get_arguments()
+                    .is_some_and(|args| args.first().is_some_and(|a| aliases.contains(a.as_str())));
+                if let (true, true, Some(dest)) = (copies, reads_alias, code.get_destination()) {
+                    aliases.insert(dest);
+                }
+            }
+            if aliases.len() == before {
+                break;
+            }
+        }
+
+        let escapes = function.instrs.iter().any(|code| {
+            let args = code.get_arguments().into_iter().flatten();
+            let reads_alias = |mut args: std::iter::Flatten<_>| -> bool {
+                args.any(|a: &String| aliases.contains(a.as_str()))
+            };
+            match code {
+                Code::Memory {
+                    op: MemoryOp::Free, ..
+                }
+                | Code::Effect {
+                    op: EffectOp::Ret | EffectOp::Call,
+                    ..
+                }
+                | Code::Value {
+                    op: ValueOp::Call, ..
+                } => reads_alias(args),
+                // storing the pointer itself, not storing through it
+                Code::Memory {
+                    op: MemoryOp::Store,
+                    args: Some(args),
+                    ..
+                } => args.get(1).is_some_and(|a| aliases.contains(a.as_str())),
+                _ => false,
+            }
+        });
+        if !escapes {
+            let message = format!("memory allocated for {} is never freed", dest);
+            diagnostics.push(Diagnostic::warning(function, message, alloc.get_position()));
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::representation::parse_program;
+
+    fn messages(text: &str) -> Vec<String> {
+        let program = parse_program(text).unwrap();
+        check_program(&program)
+            .into_iter()
+            .map(|d| format!("{}: {}", d.severity, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn reports_warnings_and_errors() {
+        let text = "@main {\n  one: int = const 1;\n  p: ptr<int> = alloc one;\n  q: ptr<int> = id p;\n  unused: int = const 2;\n  print one;\n  ret;\n  print one;\n}\n";
+        assert_eq!(
+            messages(text),
+            [
+                "warning: q is never used",
+                "warning: unused is never used",
+                "warning: unreachable code",
+                "warning: memory allocated for p is never freed",
+            ]
+        );
+
+        let text = "@main {\n  b: bool = const true;\n  print b;\n  free b;\n}\n";
+        let errors = messages(text);
+        assert!(errors[0].starts_with("error: type: 'free b_0;'"));
+        assert!(messages("@main {\n  print x;\n}\n")[0].contains("uninitialized variable: x"));
+        // a branch on an undefined condition is an error too, not a panic in SSA construction
+        let text = "@main {\n  br c .a .b;\n.a:\n  ret;\n.b:\n  ret;\n}\n";
+        assert!(messages(text)[0].contains("branching on uninitialized variable: c"));
+    }
+}
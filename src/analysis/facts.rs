@@ -0,0 +1,127 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::Serialize;
+
+use crate::{
+    analysis::LoopInfo,
+    dataflow::{
+        run_parallel_dataflow_analysis, ConstantPropagation, ConstantValue, LiveVariables,
+        ReachingDefinitions, WorklistResult,
+    },
+    representation::{AbstractFunction, AbstractProgram, BlockId},
+};
+
+/// Analysis results of one basic block, with blocks referred to by label
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockFacts {
+    pub live_in: BTreeSet<String>,
+    pub live_out: BTreeSet<String>,
+    /// variable -> labels of the blocks whose definitions reach the block entry
+    pub reaching_in: BTreeMap<String, BTreeSet<String>>,
+    pub reaching_out: BTreeMap<String, BTreeSet<String>>,
+    /// variable -> value, for the variables with a constant value at the block entry
+    pub constants_in: BTreeMap<String, String>,
+    pub constants_out: BTreeMap<String, String>,
+    pub immediate_dominator: Option<String>,
+    pub dominance_frontier: BTreeSet<String>,
+    pub loop_depth: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoopFacts {
+    pub header: String,
+    pub latches: BTreeSet<String>,
+    pub exits: BTreeSet<String>,
+    pub body: BTreeSet<String>,
+    pub depth: usize,
+    /// header of the enclosing loop
+    pub parent: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionFacts {
+    pub blocks: BTreeMap<String, BlockFacts>,
+    pub loops: Vec<LoopFacts>,
+}
+
+/// Collect liveness, reaching definitions, dominance and loop facts for every function
+pub fn program_facts(program: &AbstractProgram) -> WorklistResult<BTreeMap<String, FunctionFacts>> {
+    program
+        .functions
+        .iter()
+        .map(|(name, af)| Ok((name.clone(), function_facts(af)?)))
+        .collect()
+}
+
+pub fn function_facts(af: &AbstractFunction) -> WorklistResult<FunctionFacts> {
+    let mut af = af.clone();
+    let live = run_parallel_dataflow_analysis::<LiveVariables>(&mut af)?;
+    let reaching = run_parallel_dataflow_analysis::<ReachingDefinitions>(&mut af)?;
+    let constants = run_parallel_dataflow_analysis::<ConstantPropagation>(&mut af)?;
+    let loop_info = LoopInfo::from(&af);
+
+    let label = |id: &BlockId| af.cfg.basic_blocks[*id].label.clone();
+
+    let blocks = af
+        .cfg
+        .basic_blocks
+        .iter()
+        .map(|block| {
+            let (live_in, live_out) = &live[&block.id];
+            let (reaching_in, reaching_out) = &reaching[&block.id];
+            let (constants_in, constants_out) = &constants[&block.id];
+            let facts = BlockFacts {
+                live_in: live_in.iter().cloned().collect(),
+                live_out: live_out.iter().cloned().collect(),
+                reaching_in: reaching_in
+                    .iter()
+                    .map(|(var, defs)| (var.clone(), labels(&af, defs.iter().copied())))
+                    .collect(),
+                reaching_out: reaching_out
+                    .iter()
+                    .map(|(var, defs)| (var.clone(), labels(&af, defs.iter().copied())))
+                    .collect(),
+                constants_in: known_constants(constants_in),
+                constants_out: known_constants(constants_out),
+                immediate_dominator: af
+                    .dominance_info()
+                    .get_immediate_dominator(block.id)
+                    .map(|id| label(&id)),
+                dominance_frontier: labels(
+                    &af,
+                    af.dominance_info().get_dominance_frontier(block.id),
+                ),
+                loop_depth: loop_info.loop_depth(block.id),
+            };
+            (block.label.clone(), facts)
+        })
+        .collect();
+
+    let loops = loop_info
+        .loops
+        .iter()
+        .map(|l| LoopFacts {
+            header: label(&l.header),
+            latches: labels(&af, l.latches.iter().copied()),
+            exits: labels(&af, l.exits.iter().copied()),
+            body: labels(&af, l.body.iter().copied()),
+            depth: l.depth,
+            parent: l.parent.map(|p| label(&loop_info.loops[p].header)),
+        })
+        .collect();
+
+    Ok(FunctionFacts { blocks, loops })
+}
+
+fn known_constants(values: &HashMap<String, ConstantValue>) -> BTreeMap<String, String> {
+    (values.iter())
+        .filter(|(_, value)| matches!(value, ConstantValue::Constant(_)))
+        .map(|(var, value)| (var.clone(), value.to_string()))
+        .collect()
+}
+
+fn labels(af: &AbstractFunction, ids: impl IntoIterator<Item = BlockId>) -> BTreeSet<String> {
+    ids.into_iter()
+        .map(|id| af.cfg.basic_blocks[id].label.clone())
+        .collect()
+}
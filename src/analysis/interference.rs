@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    dataflow::{run_dataflow_analysis, LiveVariables, WorklistResult},
+    representation::{AbstractFunction, Code, Terminator},
+};
+
+// Two variables interfere when one is assigned while the other is live, so they cannot share a
+// register. Each block is walked backwards from what the live variables analysis says is live out
+// of it; phi nodes assign their variables at the top of the block and the arguments of a function
+// are all assigned on entry. Coloring is Chaitin's: variables with fewer neighbours than colors
+// are set aside until only hard ones are left, then colored in reverse, most preferred color first
+
+/// Variables of a function that are live at the same time
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterferenceGraph {
+    pub function: String,
+    /// every variable of the function, with the variables it interferes with
+    pub edges: BTreeMap<String, BTreeSet<String>>,
+    /// variables live across a call
+    pub live_across_call: BTreeSet<String>,
+}
+
+impl InterferenceGraph {
+    pub fn build(af: &mut AbstractFunction) -> WorklistResult<Self> {
+        Self::build_with(af, Code::is_call)
+    }
+
+    /// build the graph, counting the instructions `is_call` accepts as calls
+    pub fn build_with(
+        af: &mut AbstractFunction,
+        is_call: impl Fn(&Code) -> bool,
+    ) -> WorklistResult<Self> {
+        let liveness = run_dataflow_analysis::<LiveVariables>(af)?;
+        let mut graph = InterferenceGraph {
+            function: af.name.clone(),
+            ..Default::default()
+        };
+
+        for block in &af.cfg.basic_blocks {
+            // a backward analysis keeps what flows in from the successors first
+            let mut live: BTreeSet<String> = liveness[&block.id].0.iter().cloned().collect();
+            if let Terminator::Ret(code) | Terminator::Br(_, _, code) = &block.terminator {
+                for arg in code.get_arguments().into_iter().flatten() {
+                    graph.add_node(arg);
+                    live.insert(arg.clone());
+                }
+            }
+            for code in block.preheader.iter().chain(&block.instructions).rev() {
+                if let Some(dest) = code.get_destination() {
+                    graph.assign(dest, &live);
+                    live.remove(dest);
+                }
+                if is_call(code) {
+                    graph.live_across_call.extend(live.iter().cloned());
+                }
+                for arg in code.get_arguments().into_iter().flatten() {
+                    graph.add_node(arg);
+                    live.insert(arg.clone());
+                }
+            }
+            let phi_dests: BTreeSet<String> =
+                block.phi_nodes.iter().map(|phi| phi.dest.clone()).collect();
+            let at_top: BTreeSet<String> = live.union(&phi_dests).cloned().collect();
+            for phi in &block.phi_nodes {
+                graph.assign(&phi.dest, &at_top);
+                for (arg, _) in &phi.phi_args {
+                    graph.add_node(arg);
+                }
+            }
+        }
+
+        let params: BTreeSet<String> = (af.args.iter().flatten())
+            .map(|arg| arg.name.clone())
+            .collect();
+        let entry: BTreeSet<String> = (liveness.get(&0).into_iter())
+            .flat_map(|(_, live_in)| live_in.iter().cloned())
+            .chain(params.iter().cloned())
+            .collect();
+        for param in &params {
+            graph.assign(param, &entry);
+        }
+        Ok(graph)
+    }
+
+    fn add_node(&mut self, variable: &str) {
+        self.edges.entry(variable.to_string()).or_default();
+    }
+
+    /// `variable` is assigned while everything in `live` is live
+    fn assign(&mut self, variable: &str, live: &BTreeSet<String>) {
+        self.add_node(variable);
+        for other in live.iter().filter(|other| *other != variable) {
+            self.edges.get_mut(variable).unwrap().insert(other.clone());
+            self.edges
+                .entry(other.clone())
+                .or_default()
+                .insert(variable.to_string());
+        }
+    }
+
+    pub fn interferes(&self, a: &str, b: &str) -> bool {
+        self.edges
+            .get(a)
+            .is_some_and(|neighbours| neighbours.contains(b))
+    }
+
+    /// the graph of only the variables `keep` accepts
+    pub fn subgraph(&self, keep: impl Fn(&str) -> bool) -> Self {
+        InterferenceGraph {
+            function: self.function.clone(),
+            edges: (self.edges.iter())
+                .filter(|(v, _)| keep(v))
+                .map(|(v, neighbours)| {
+                    let kept = neighbours.iter().filter(|n| keep(n)).cloned().collect();
+                    (v.clone(), kept)
+                })
+                .collect(),
+            live_across_call: (self.live_across_call.iter())
+                .filter(|v| keep(v))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// A color for every variable from `colors(variable)`, which lists the colors it may take
+    /// from most to least preferred, such that no neighbours share one. Variables left without
+    /// a color have to live somewhere else, e.g. on the stack
+    pub fn color<C: Copy + Eq>(
+        &self,
+        colors: impl Fn(&str) -> Vec<C>,
+    ) -> BTreeMap<String, Option<C>> {
+        let palettes: BTreeMap<&str, Vec<C>> = (self.edges.keys())
+            .map(|v| (v.as_str(), colors(v)))
+            .collect();
+
+        // simplify: set aside a variable with fewer neighbours than colors, which can always be
+        // colored, or else the most constrained one, which is the likeliest to go without
+        let mut degree: BTreeMap<&str, usize> = (self.edges.iter())
+            .map(|(v, neighbours)| (v.as_str(), neighbours.len()))
+            .collect();
+        let mut order = Vec::with_capacity(degree.len());
+        while !degree.is_empty() {
+            let next = (degree.iter())
+                .find(|(v, d)| **d < palettes[**v].len())
+                .or_else(|| degree.iter().max_by_key(|(_, d)| **d))
+                .map(|(v, _)| *v)
+                .expect("some variable is left");
+            degree.remove(next);
+            for neighbour in &self.edges[next] {
+                if let Some(d) = degree.get_mut(neighbour.as_str()) {
+                    *d -= 1;
+                }
+            }
+            order.push(next);
+        }
+
+        // select: in reverse, every variable sees at most the neighbours set aside after it
+        let mut coloring: BTreeMap<String, Option<C>> = BTreeMap::new();
+        for variable in order.into_iter().rev() {
+            let taken: Vec<C> = (self.edges[variable].iter())
+                .filter_map(|n| coloring.get(n).copied().flatten())
+                .collect();
+            let color = (palettes[variable].iter())
+                .find(|c| !taken.contains(c))
+                .copied();
+            coloring.insert(variable.to_string(), color);
+        }
+        coloring
+    }
+
+    /// Render as an undirected Graphviz graph, variables live across a call drawn as boxes
+    pub fn to_dot(&self) -> String {
+        let quote = |v: &str| format!("\"{}\"", v.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = format!(
+            "graph {} {{\n",
+            quote(&format!("interference_{}", self.function))
+        );
+        for variable in self.edges.keys() {
+            match self.live_across_call.contains(variable) {
+                true => dot.push_str(&format!("  {} [shape=box];\n", quote(variable))),
+                false => dot.push_str(&format!("  {};\n", quote(variable))),
+            }
+        }
+        for (variable, neighbours) in &self.edges {
+            for neighbour in neighbours.iter().filter(|n| *n > variable) {
+                dot.push_str(&format!("  {} -- {};\n", quote(variable), quote(neighbour)));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::representation::parse_program;
+
+    #[test]
+    fn variables_live_at_once_interfere() {
+        let program = parse_program(
+            r#"
+            @main(n: int) {
+              a: int = const 1;
+              b: int = add a n;
+              c: int = call @f b;
+              d: int = add c a;
+              print d;
+            }
+            @f(x: int): int { ret x; }
+            "#,
+        )
+        .unwrap();
+        let main = program.functions.into_iter().next().unwrap();
+        let mut af = AbstractFunction::from(main);
+        let graph = InterferenceGraph::build(&mut af).unwrap();
+
+        assert!(graph.interferes("a", "n") && graph.interferes("a", "b"));
+        assert!(graph.interferes("a", "c") && graph.interferes("c", "a"));
+        assert!(!graph.interferes("b", "c") && !graph.interferes("d", "a"));
+        assert_eq!(graph.live_across_call, BTreeSet::from(["a".to_string()]));
+        assert!(graph.to_dot().contains("  \"a\" [shape=box];\n  \"b\";\n"));
+        assert!(graph.to_dot().contains("  \"a\" -- \"b\";\n"));
+
+        // with one color, `a` is the one to go without, having a neighbour in every other
+        // variable but `d`
+        let coloring = graph.color(|_| vec![0]);
+        assert_eq!(coloring["a"], None);
+        assert_eq!(coloring.values().filter(|c| c.is_none()).count(), 1);
+        for (v, c) in coloring.iter().filter(|(_, c)| c.is_some()) {
+            let neighbours = &graph.edges[v];
+            assert!(neighbours.iter().all(|n| coloring[n] != *c));
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use crate::representation::{AbstractFunction, BlockId, ControlFlowGraph, DominanceInfo};
+
+/// A natural loop. Loops sharing a header are merged, so every header owns exactly one loop
+#[derive(Debug, Clone)]
+pub struct NaturalLoop {
+    pub header: BlockId,
+    /// sources of the backedges into the header
+    pub latches: BTreeSet<BlockId>,
+    /// every block of the loop, including the header and the latches
+    pub body: BTreeSet<BlockId>,
+    /// blocks outside the loop that are jumped to from inside it
+    pub exits: BTreeSet<BlockId>,
+    /// index of the innermost enclosing loop
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// 1 for outermost loops
+    pub depth: usize,
+}
+
+/// The loop nest forest of a function
+#[derive(Debug, Clone, Default)]
+pub struct LoopInfo {
+    /// loops ordered from outermost to innermost, so a parent always precedes its children
+    pub loops: Vec<NaturalLoop>,
+}
+
+impl From<&AbstractFunction> for LoopInfo {
+    fn from(af: &AbstractFunction) -> Self {
+        LoopInfo::new(&af.cfg, af.dominance_info())
+    }
+}
+
+impl LoopInfo {
+    pub fn new(cfg: &ControlFlowGraph, dominance_info: &DominanceInfo) -> Self {
+        // a backedge is an edge source -> header where header dominates source
+        let mut loops: Vec<NaturalLoop> = vec![];
+        for source in 0..cfg.basic_blocks.len() {
+            for header in &cfg.successors[source] {
+                if !dominance_info.dominated_by(source, header) {
+                    continue;
+                }
+                let body = find_loop_body(cfg, header, source);
+                match loops.iter_mut().find(|l| l.header == header) {
+                    Some(l) => {
+                        l.latches.insert(source);
+                        l.body.extend(body);
+                    }
+                    None => loops.push(NaturalLoop {
+                        header,
+                        latches: BTreeSet::from([source]),
+                        body,
+                        exits: BTreeSet::new(),
+                        parent: None,
+                        children: vec![],
+                        depth: 0,
+                    }),
+                }
+            }
+        }
+
+        for l in loops.iter_mut() {
+            l.exits = l
+                .body
+                .iter()
+                .flat_map(|&b| cfg.successors[b].iter())
+                .filter(|s| !l.body.contains(s))
+                .collect();
+        }
+
+        // outer loops strictly contain inner ones, so sorting by size puts parents first
+        loops.sort_by(|a, b| {
+            b.body
+                .len()
+                .cmp(&a.body.len())
+                .then(a.header.cmp(&b.header))
+        });
+        for i in 0..loops.len() {
+            let parent = (0..i)
+                .rev()
+                .find(|&j| loops[j].body.contains(&loops[i].header));
+            loops[i].parent = parent;
+            loops[i].depth = parent.map_or(1, |p| loops[p].depth + 1);
+            if let Some(p) = parent {
+                loops[p].children.push(i);
+            }
+        }
+
+        LoopInfo { loops }
+    }
+
+    /// index of the innermost loop containing `block_id`
+    pub fn innermost_loop(&self, block_id: BlockId) -> Option<usize> {
+        // inner loops come last
+        (0..self.loops.len())
+            .rev()
+            .find(|&i| self.loops[i].body.contains(&block_id))
+    }
+
+    /// number of loops containing `block_id`
+    pub fn loop_depth(&self, block_id: BlockId) -> usize {
+        self.innermost_loop(block_id)
+            .map_or(0, |i| self.loops[i].depth)
+    }
+}
+
+/// blocks that reach `latch` without passing through `header`, plus the header itself
+fn find_loop_body(cfg: &ControlFlowGraph, header: BlockId, latch: BlockId) -> BTreeSet<BlockId> {
+    let mut body = BTreeSet::from([header, latch]);
+    let mut worklist = VecDeque::new();
+    if header != latch {
+        worklist.push_back(latch);
+    }
+
+    while let Some(node) = worklist.pop_front() {
+        for pred in &cfg.predecessors[node] {
+            if body.insert(pred) {
+                worklist.push_back(pred);
+            }
+        }
+    }
+    body
+}
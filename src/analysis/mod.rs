@@ -0,0 +1,25 @@
+mod alias;
+mod block_frequency;
+mod call_graph;
+mod check;
+mod facts;
+mod interference;
+mod loops;
+mod profile_data;
+mod purity;
+mod ranges;
+mod typecheck;
+mod verify;
+
+pub use alias::*;
+pub use block_frequency::*;
+pub use call_graph::*;
+pub use check::*;
+pub use facts::*;
+pub use interference::*;
+pub use loops::*;
+pub use profile_data::*;
+pub use purity::*;
+pub use ranges::*;
+pub use typecheck::*;
+pub use verify::*;
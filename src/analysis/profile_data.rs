@@ -0,0 +1,171 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    interpreter::{BlockCounts, BlockProfile, ProfileError, ENTRY_BLOCK},
+    representation::{AbstractFunction, BlockId, Label},
+};
+
+// Profiles for profile-guided passes. A profile names blocks by label, so it fits the CFG of the
+// program it was recorded on. The conversion to SSA form invents a preamble block and labels for
+// blocks without one; both belong to the entry block of the source, so a profile recorded on a
+// program that went through SSA form still fits a fresh CFG of it
+
+/// Execution counts of the blocks and edges of one function's CFG
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockWeights {
+    /// indexed by block id
+    pub blocks: Vec<u64>,
+    pub edges: HashMap<(BlockId, BlockId), u64>,
+}
+
+impl BlockWeights {
+    pub fn block(&self, block: BlockId) -> u64 {
+        self.blocks.get(block).copied().unwrap_or_default()
+    }
+
+    pub fn edge(&self, from: BlockId, to: BlockId) -> u64 {
+        self.edges.get(&(from, to)).copied().unwrap_or_default()
+    }
+}
+
+/// A block profile, ready to attach to CFGs
+#[derive(Debug, Clone, Default)]
+pub struct ProfileData {
+    functions: HashMap<String, BlockCounts>,
+}
+
+fn invented(label: &str) -> bool {
+    label.starts_with("function_preamble_") || label.starts_with("no_label_")
+}
+
+impl From<BlockProfile> for ProfileData {
+    fn from(profile: BlockProfile) -> Self {
+        let functions = profile
+            .functions
+            .into_iter()
+            .map(|(name, mut counts)| {
+                counts.blocks.retain(|block, _| !invented(block));
+                let mut edges = std::mem::take(&mut counts.edges);
+                edges.retain(|(_, to), _| !invented(to));
+                for ((from, to), n) in edges {
+                    let from = if invented(&from) {
+                        ENTRY_BLOCK.to_string()
+                    } else {
+                        from
+                    };
+                    *counts.edges.entry((from, to)).or_default() += n;
+                }
+                (name, counts)
+            })
+            .collect();
+        Self { functions }
+    }
+}
+
+impl ProfileData {
+    /// Read a profile written by `interpret --write-profile`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ProfileError> {
+        BlockProfile::from_file(path).map(Self::from)
+    }
+
+    /// how often `function` was called, 0 if it never ran
+    pub fn calls(&self, function: &str) -> u64 {
+        self.functions
+            .get(function)
+            .and_then(|counts| counts.blocks.get(ENTRY_BLOCK))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The counts of the blocks and edges of `af`, `None` if the function never ran. Blocks and
+    /// edges the profile does not know, such as those of code that never ran, weigh 0
+    pub fn weights(&self, af: &AbstractFunction) -> Option<BlockWeights> {
+        let counts = self.functions.get(&af.name)?;
+        let name = |block: BlockId| {
+            let label = af.cfg.basic_blocks[block].label.as_str();
+            match invented(label) {
+                true => ENTRY_BLOCK,
+                false => label,
+            }
+        };
+        let count = |block: BlockId| counts.blocks.get(name(block)).copied().unwrap_or_default();
+
+        let blocks = (0..af.cfg.basic_blocks.len()).map(count).collect();
+        let mut edges = HashMap::new();
+        for (from, successors) in af.cfg.successors.iter().enumerate() {
+            for to in successors {
+                // only the entry block reaches an invented block
+                let n = match name(to) {
+                    ENTRY_BLOCK => count(to),
+                    label => {
+                        let edge = (name(from).to_string(), label.to_string());
+                        counts.edges.get(&edge).copied().unwrap_or_default()
+                    }
+                };
+                edges.insert((from, to), n);
+            }
+        }
+        Some(BlockWeights { blocks, edges })
+    }
+
+    /// Store the counts of `af` in the `frequency` and `edge_frequencies` of its blocks. Every
+    /// block of a function that never ran gets 0
+    pub fn attach(&self, af: &mut AbstractFunction) {
+        let weights = self.weights(af).unwrap_or_default();
+        let cfg = &mut af.cfg;
+        let labels: Vec<Label> = (cfg.basic_blocks.iter())
+            .map(|block| block.label.clone())
+            .collect();
+        for (id, block) in cfg.basic_blocks.iter_mut().enumerate() {
+            block.frequency = Some(weights.block(id));
+            block.edge_frequencies = (cfg.successors[id].iter())
+                .map(|to| (labels[to].clone(), weights.edge(id, to)))
+                .collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        interpreter::Interpreter,
+        testing::{function_from_text, program_from_text},
+    };
+
+    #[test]
+    fn weights_follow_the_profile_through_ssa_form() {
+        let text = "@main {\n  i: int = const 0;\n  two: int = const 2;\n  one: int = const 1;\n.loop:\n  more: bool = lt i two;\n  br more .body .end;\n.body:\n  i: int = add i one;\n  jmp .loop;\n.end:\n  print i;\n}\n";
+        // record the profile on the program after a round trip through SSA form
+        let mut ssa = program_from_text(text).program;
+        ssa.functions = vec![function_from_text(text).to_function()];
+        let execution = Interpreter::new(&ssa)
+            .block_counts(true)
+            .run(&[], &mut std::io::sink())
+            .unwrap();
+        let profile = BlockProfile::from_json(&execution.blocks.unwrap().to_json()).unwrap();
+        let data = ProfileData::from(profile);
+        assert_eq!(data.calls("main"), 1);
+
+        let af = function_from_text(text);
+        let weights = data.weights(&af).unwrap();
+        let id = |label: &str| af.cfg.label_map[label];
+        assert_eq!(weights.block(0), 1);
+        assert_eq!(weights.block(id("loop")), 3);
+        assert_eq!(weights.block(id("body")), 2);
+        assert_eq!(weights.edge(id("body"), id("loop")), 2);
+        assert_eq!(weights.edge(id("loop"), id("end")), 1);
+        let into_loop: u64 = af.cfg.predecessors[id("loop")]
+            .iter()
+            .map(|p| weights.edge(p, id("loop")))
+            .sum();
+        assert_eq!(into_loop, 3);
+
+        let body = id("body");
+        let mut af = af;
+        data.attach(&mut af);
+        let body = &af.cfg.basic_blocks[body];
+        assert_eq!(body.frequency, Some(2));
+        assert_eq!(body.edge_frequencies["loop"], 2);
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    analysis::CallGraph,
+    representation::{opcode, AbstractFunction, AbstractProgram, Code, FunctionAttribute},
+};
+
+// Which functions are free of side effects, so that calls to them can be numbered, hoisted and
+// removed like any other value. A function is pure when its own code neither prints, speculates
+// nor touches memory, cannot fail (opcodes the registry marks as failing, like `div`), and only
+// calls pure functions. Starting from every function whose own code qualifies, callers of impure
+// or unknown functions are dropped until nothing changes, so mutually recursive functions stay
+// pure unless something in their cycle is not. Like the `pure` attribute, this says nothing
+// about termination. Functions annotated `pure` are trusted as they are.
+
+/// The functions of a program that have no side effects
+#[derive(Debug, Clone, Default)]
+pub struct Purity {
+    pure: BTreeSet<String>,
+}
+
+impl From<&AbstractProgram> for Purity {
+    fn from(program: &AbstractProgram) -> Self {
+        let graph = CallGraph::from(program);
+        let annotated = |af: &AbstractFunction| {
+            (af.attrs.iter().flatten()).any(|attr| attr == &FunctionAttribute::Pure)
+        };
+        let annotated: BTreeSet<String> = (program.functions.values())
+            .filter(|af| annotated(af))
+            .map(|af| af.name.clone())
+            .collect();
+        let mut pure: BTreeSet<String> = (program.functions.values())
+            .filter(|af| annotated.contains(&af.name) || !has_local_effects(af))
+            .map(|af| af.name.clone())
+            .collect();
+
+        loop {
+            let impure: Vec<String> = (pure.iter())
+                .filter(|f| !annotated.contains(*f))
+                .filter(|f| graph.callees(f).any(|callee| !pure.contains(callee)))
+                .cloned()
+                .collect();
+            if impure.is_empty() {
+                break;
+            }
+            for f in impure {
+                log::debug!("@{} calls an impure function", f);
+                pure.remove(&f);
+            }
+        }
+
+        Self { pure }
+    }
+}
+
+impl Purity {
+    pub fn is_pure(&self, function: &str) -> bool {
+        self.pure.contains(function)
+    }
+
+    /// the pure functions, sorted by name
+    pub fn functions(&self) -> impl Iterator<Item = &String> {
+        self.pure.iter()
+    }
+}
+
+/// whether the code of `af` itself, calls aside, has side effects or can fail
+fn has_local_effects(af: &AbstractFunction) -> bool {
+    (af.cfg.basic_blocks.iter())
+        .flat_map(|b| b.preheader.iter().chain(&b.instructions))
+        .any(|code| match code {
+            Code::Value { op, .. } if opcode(*op).is_some_and(|info| !info.pure) => true,
+            code if code.is_call() => false,
+            code => code.has_side_effects(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::abstract_program_from_text;
+
+    #[test]
+    fn effects_spread_to_callers() {
+        let text = r#"
+@square(x: int): int {
+  y: int = mul x x;
+  ret y;
+}
+@even(n: int): bool {
+  zero: int = const 0;
+  one: int = const 1;
+  base: bool = eq n zero;
+  br base .yes .no;
+.yes:
+  t: bool = const true;
+  ret t;
+.no:
+  m: int = sub n one;
+  r: bool = call @odd m;
+  ret r;
+}
+@odd(n: int): bool {
+  zero: int = const 0;
+  one: int = const 1;
+  base: bool = eq n zero;
+  br base .yes .no;
+.yes:
+  f: bool = const false;
+  ret f;
+.no:
+  m: int = sub n one;
+  r: bool = call @even m;
+  ret r;
+}
+@half(x: int): int {
+  two: int = const 2;
+  y: int = div x two;
+  ret y;
+}
+@show(x: int): int {
+  y: int = call @square x;
+  print y;
+  ret y;
+}
+@main(n: int) {
+  y: int = call @show n;
+  b: bool = call @even y;
+  print b;
+}
+"#;
+        let program = abstract_program_from_text(text);
+        let purity = Purity::from(&program);
+
+        let pure: Vec<&str> = purity.functions().map(String::as_str).collect();
+        assert_eq!(pure, ["even", "odd", "square"]);
+        assert!(!purity.is_pure("half") && !purity.is_pure("main"));
+    }
+}
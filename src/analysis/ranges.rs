@@ -0,0 +1,509 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{
+    analysis::{Diagnostic, Severity},
+    representation::{
+        AbstractFunction, BlockId, Code, Function, Literal, Program, RichAbstractProgram,
+        RichProgram, Terminator, Type, ValueOp,
+    },
+};
+
+// Interval analysis on SSA form. Every integer variable has one definition, so one range per
+// variable covers all paths; on top of that, each block narrows the ranges with the outcomes of
+// the branches that must have been taken to reach it (`i < n` on the way into a loop body bounds
+// `i` there). A variable whose range keeps growing around a loop is widened to the end of i64
+// after a few rounds, so the analysis terminates
+
+/// An inclusive range of i64 values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub lo: i64,
+    pub hi: i64,
+}
+
+impl Interval {
+    pub const FULL: Interval = Interval {
+        lo: i64::MIN,
+        hi: i64::MAX,
+    };
+
+    pub fn constant(value: i64) -> Self {
+        Self {
+            lo: value,
+            hi: value,
+        }
+    }
+
+    pub fn contains(self, value: i64) -> bool {
+        self.lo <= value && value <= self.hi
+    }
+
+    pub fn join(self, other: Self) -> Self {
+        Self {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    /// the values in both, `None` if there are none
+    pub fn meet(self, other: Self) -> Option<Self> {
+        let lo = self.lo.max(other.lo);
+        let hi = self.hi.min(other.hi);
+        (lo <= hi).then_some(Self { lo, hi })
+    }
+
+    /// the range of the exact result of `op`, which may not fit in i64, and `None` for a division
+    /// that always divides by zero
+    fn exact(op: ValueOp, a: Self, b: Self) -> Option<(i128, i128)> {
+        let corners = |divisors: &[(i64, i64)]| {
+            let mut values = Vec::new();
+            for &(b_lo, b_hi) in divisors {
+                for x in [a.lo, a.hi] {
+                    for y in [b_lo, b_hi] {
+                        let (x, y) = (x as i128, y as i128);
+                        values.push(match op {
+                            ValueOp::Add => x + y,
+                            ValueOp::Sub => x - y,
+                            ValueOp::Mul => x * y,
+                            _ => x / y,
+                        });
+                    }
+                }
+            }
+            Some((*values.iter().min()?, *values.iter().max()?))
+        };
+        match op {
+            // the quotient is extreme at the divisors closest to zero on either side
+            ValueOp::Div => {
+                let mut divisors = Vec::new();
+                if b.lo < 0 {
+                    divisors.push((b.lo, b.hi.min(-1)));
+                }
+                if b.hi > 0 {
+                    divisors.push((b.lo.max(1), b.hi));
+                }
+                corners(&divisors)
+            }
+            _ => corners(&[(b.lo, b.hi)]),
+        }
+    }
+
+    /// the result of `op` as bril computes it, wrapping around on overflow
+    fn apply(op: ValueOp, a: Self, b: Self) -> Option<Self> {
+        let (lo, hi) = Self::exact(op, a, b)?;
+        match (i64::try_from(lo), i64::try_from(hi)) {
+            (Ok(lo), Ok(hi)) => Some(Self { lo, hi }),
+            _ => Some(Self::FULL),
+        }
+    }
+
+    /// `self` widened to the end of i64 in every direction it grew beyond `old`
+    fn widen(self, old: Self) -> Self {
+        Self {
+            lo: if self.lo < old.lo { i64::MIN } else { self.lo },
+            hi: if self.hi > old.hi { i64::MAX } else { self.hi },
+        }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+
+/// a boolean that records the outcome of a comparison
+#[derive(Debug, Clone)]
+enum Condition {
+    Compare(ValueOp, String, String),
+    Not(String),
+}
+
+/// updates of a variable's range before it is widened
+const WIDEN_AFTER: u32 = 3;
+
+/// Ranges of the integer variables of a function in SSA form
+pub struct Ranges {
+    values: HashMap<String, Interval>,
+    /// per block, the ranges that the branches taken to reach it narrow
+    narrowed: Vec<HashMap<String, Interval>>,
+    conditions: HashMap<String, Condition>,
+}
+
+impl Ranges {
+    pub fn of(af: &AbstractFunction) -> Self {
+        let blocks = &af.cfg.basic_blocks;
+        let mut ranges = Self {
+            values: HashMap::new(),
+            narrowed: vec![HashMap::new(); blocks.len()],
+            conditions: HashMap::new(),
+        };
+        for arg in af.args.iter().flatten() {
+            if arg.arg_type == Type::Int {
+                ranges.values.insert(arg.name.clone(), Interval::FULL);
+            }
+        }
+        for code in blocks.iter().flat_map(|b| &b.instructions) {
+            if let Code::Value {
+                op,
+                dest,
+                args: Some(args),
+                ..
+            } = code
+            {
+                let condition = match (op, args.as_slice()) {
+                    (
+                        ValueOp::Lt | ValueOp::Le | ValueOp::Gt | ValueOp::Ge | ValueOp::Eq,
+                        [a, b],
+                    ) => Condition::Compare(*op, a.clone(), b.clone()),
+                    (ValueOp::Not, [a]) => Condition::Not(a.clone()),
+                    _ => continue,
+                };
+                ranges.conditions.insert(dest.clone(), condition);
+            }
+        }
+
+        // dominators first, so each block starts from what its immediate dominator narrowed
+        let mut order = Vec::new();
+        let mut stack = if blocks.is_empty() { vec![] } else { vec![0] };
+        while let Some(block) = stack.pop() {
+            order.push(block);
+            let children: Vec<_> = af
+                .dominance_info()
+                .get_immediate_dominated(block)
+                .iter()
+                .collect();
+            stack.extend(children.into_iter().rev());
+        }
+
+        let mut updates: HashMap<String, u32> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &order {
+                let mut narrowed = match af.dominance_info().get_immediate_dominator(block) {
+                    Some(idom) => ranges.narrowed[idom].clone(),
+                    None => HashMap::new(),
+                };
+                if let [pred] = af.cfg.predecessors[block].iter().collect::<Vec<_>>()[..] {
+                    narrowed.extend(ranges.edge(af, pred, block));
+                }
+                ranges.narrowed[block] = narrowed;
+
+                let mut defined = Vec::new();
+                for phi in &blocks[block].phi_nodes {
+                    if phi.phi_type != Type::Int {
+                        continue;
+                    }
+                    let incoming = phi.phi_args.iter().filter_map(|(var, label)| {
+                        let pred = *af.cfg.label_map.get(label)?;
+                        let mut edge = ranges.narrowed[pred].clone();
+                        edge.extend(ranges.edge(af, pred, block));
+                        edge.get(var).or(ranges.values.get(var)).copied()
+                    });
+                    if let Some(range) = incoming.reduce(Interval::join) {
+                        defined.push((phi.dest.clone(), range));
+                    }
+                }
+                for code in &blocks[block].instructions {
+                    let Some(dest) = code.get_destination() else {
+                        continue;
+                    };
+                    if let Some(range) = ranges.evaluate(code, block) {
+                        defined.push((dest.to_string(), range));
+                    }
+                }
+
+                for (dest, range) in defined {
+                    let old = ranges.values.get(&dest).copied();
+                    let mut new = old.map_or(range, |old| old.join(range));
+                    if old == Some(new) {
+                        continue;
+                    }
+                    let count = updates.entry(dest.clone()).or_default();
+                    *count += 1;
+                    if let (Some(old), true) = (old, *count > WIDEN_AFTER) {
+                        new = new.widen(old);
+                    }
+                    ranges.values.insert(dest, new);
+                    changed = true;
+                }
+            }
+        }
+        ranges
+    }
+
+    /// the range of `var` in `block`, `None` if it has no value there yet
+    pub fn get(&self, var: &str, block: BlockId) -> Option<Interval> {
+        self.narrowed[block]
+            .get(var)
+            .or(self.values.get(var))
+            .copied()
+    }
+
+    fn evaluate(&self, code: &Code, block: BlockId) -> Option<Interval> {
+        match code {
+            Code::Constant {
+                value: Literal::Int(value),
+                ..
+            } => Some(Interval::constant(*value)),
+            Code::Value {
+                op: op @ (ValueOp::Add | ValueOp::Sub | ValueOp::Mul | ValueOp::Div),
+                args: Some(args),
+                ..
+            } => match args.as_slice() {
+                [a, b] => Interval::apply(*op, self.get(a, block)?, self.get(b, block)?),
+                _ => None,
+            },
+            Code::Value {
+                op: ValueOp::Id,
+                args: Some(args),
+                value_type: Type::Int,
+                ..
+            } => self.get(args.first()?, block),
+            Code::Value {
+                op: ValueOp::Char2int,
+                ..
+            } => Some(Interval {
+                lo: 0,
+                hi: char::MAX as i64,
+            }),
+            // calls, loads and bit casts can produce any integer
+            _ => (code.get_type() == Some(Type::Int)).then_some(Interval::FULL),
+        }
+    }
+
+    /// what taking the edge from `pred` to `block` says about the operands of the branch condition
+    fn edge(
+        &self,
+        af: &AbstractFunction,
+        pred: BlockId,
+        block: BlockId,
+    ) -> Vec<(String, Interval)> {
+        let Terminator::Br(then, otherwise, code) = &af.cfg.basic_blocks[pred].terminator else {
+            return Vec::new();
+        };
+        let Some(condition) = code.get_arguments().and_then(|args| args.first()) else {
+            return Vec::new();
+        };
+        let label = &af.cfg.basic_blocks[block].label;
+        if then == otherwise {
+            return Vec::new();
+        }
+        let mut taken = then == label;
+        let mut condition = condition;
+        let (op, a, b) = loop {
+            match self.conditions.get(condition) {
+                Some(Condition::Compare(op, a, b)) => break (*op, a, b),
+                Some(Condition::Not(inner)) => {
+                    condition = inner;
+                    taken = !taken;
+                }
+                None => return Vec::new(),
+            }
+        };
+        let (Some(ra), Some(rb)) = (self.get(a, pred), self.get(b, pred)) else {
+            return Vec::new();
+        };
+
+        // a < b when taken, a >= b otherwise, and so on
+        let op = match (op, taken) {
+            (op, true) => op,
+            (ValueOp::Lt, false) => ValueOp::Ge,
+            (ValueOp::Le, false) => ValueOp::Gt,
+            (ValueOp::Gt, false) => ValueOp::Le,
+            (ValueOp::Ge, false) => ValueOp::Lt,
+            // a != b only narrows a range that ends at the other value
+            _ => {
+                let mut narrowed = Vec::new();
+                if rb.lo == rb.hi {
+                    narrowed.extend(exclude(ra, rb.lo).map(|r| (a.clone(), r)));
+                }
+                if ra.lo == ra.hi {
+                    narrowed.extend(exclude(rb, ra.lo).map(|r| (b.clone(), r)));
+                }
+                return narrowed;
+            }
+        };
+        let (a_bound, b_bound) = match op {
+            ValueOp::Lt => (
+                Interval {
+                    lo: i64::MIN,
+                    hi: rb.hi.saturating_sub(1),
+                },
+                Interval {
+                    lo: ra.lo.saturating_add(1),
+                    hi: i64::MAX,
+                },
+            ),
+            ValueOp::Le => (
+                Interval {
+                    lo: i64::MIN,
+                    hi: rb.hi,
+                },
+                Interval {
+                    lo: ra.lo,
+                    hi: i64::MAX,
+                },
+            ),
+            ValueOp::Gt => (
+                Interval {
+                    lo: rb.lo.saturating_add(1),
+                    hi: i64::MAX,
+                },
+                Interval {
+                    lo: i64::MIN,
+                    hi: ra.hi.saturating_sub(1),
+                },
+            ),
+            ValueOp::Ge => (
+                Interval {
+                    lo: rb.lo,
+                    hi: i64::MAX,
+                },
+                Interval {
+                    lo: i64::MIN,
+                    hi: ra.hi,
+                },
+            ),
+            _ => (rb, ra),
+        };
+        // an empty range means the edge is never taken, which is not this analysis' business
+        [(a, ra.meet(a_bound)), (b, rb.meet(b_bound))]
+            .into_iter()
+            .filter_map(|(var, range)| Some((var.clone(), range?)))
+            .collect()
+    }
+}
+
+/// `range` without `value`, if that leaves a range
+fn exclude(range: Interval, value: i64) -> Option<Interval> {
+    if range.lo == value && range.hi > value {
+        Some(Interval {
+            lo: value + 1,
+            ..range
+        })
+    } else if range.hi == value && range.lo < value {
+        Some(Interval {
+            hi: value - 1,
+            ..range
+        })
+    } else {
+        None
+    }
+}
+
+/// Warnings for every `div` whose divisor may be zero and every integer `add`, `sub`, `mul` and
+/// `div` that may overflow, with the ranges that make it possible. Programs that do not convert to
+/// SSA form have no warnings here; [`check_program`](crate::analysis::check_program) reports why
+pub fn check_arithmetic(program: &Program) -> Vec<Diagnostic> {
+    let rich = RichProgram {
+        original_text: Vec::new(),
+        program: program.clone(),
+    };
+    let Ok(abstract_program) = RichAbstractProgram::try_from_program(rich) else {
+        return Vec::new();
+    };
+    let mut diagnostics = Vec::new();
+    for function in &program.functions {
+        if let Some(af) = abstract_program.program.functions.get(&function.name) {
+            diagnostics.extend(arithmetic_hazards(function, af));
+        }
+    }
+    diagnostics
+}
+
+fn arithmetic_hazards(function: &Function, af: &AbstractFunction) -> Vec<Diagnostic> {
+    let ranges = Ranges::of(af);
+    // SSA form renames `x` to `x_0`, `x_1`, ..., but messages should use the names in the source
+    let names: HashSet<&str> = function
+        .instrs
+        .iter()
+        .filter_map(Code::get_destination)
+        .chain(function.args.iter().flatten().map(|a| a.name.as_str()))
+        .collect();
+    let source_name = |var: &str| -> String {
+        match var.rsplit_once('_') {
+            Some((name, _)) if !names.contains(var) && names.contains(name) => name.to_string(),
+            _ => var.to_string(),
+        }
+    };
+
+    let mut diagnostics = Vec::new();
+    for block in &af.cfg.basic_blocks {
+        for code in &block.instructions {
+            let Code::Value {
+                op: op @ (ValueOp::Add | ValueOp::Sub | ValueOp::Mul | ValueOp::Div),
+                args: Some(args),
+                value_type: Type::Int,
+                ..
+            } = code
+            else {
+                continue;
+            };
+            let [a, b] = args.as_slice() else {
+                continue;
+            };
+            let (Some(ra), Some(rb)) = (ranges.get(a, block.id), ranges.get(b, block.id)) else {
+                continue;
+            };
+            let opcode = code.get_opcode_string();
+            let (a, b) = (source_name(a), source_name(b));
+            let mut warn = |message: String| {
+                diagnostics.push(Diagnostic {
+                    severity: Severity::Warning,
+                    function: Some(function.name.clone()),
+                    message,
+                    position: code.get_position(),
+                });
+            };
+
+            if matches!(op, ValueOp::Div) && rb.contains(0) {
+                warn(match rb == Interval::constant(0) {
+                    true => format!("{} {} {} always divides by zero", opcode, a, b),
+                    false => format!(
+                        "{} {} {} may divide by zero: {} is in {}",
+                        opcode, a, b, b, rb
+                    ),
+                });
+            }
+            if let Some((lo, hi)) = Interval::exact(*op, ra, rb) {
+                if i64::try_from(lo).is_err() || i64::try_from(hi).is_err() {
+                    warn(format!(
+                        "{} {} {} may overflow: {} is in {} and {} is in {}, so the result is in [{}, {}]",
+                        opcode, a, b, a, ra, b, rb, lo, hi
+                    ));
+                }
+            }
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::representation::parse_program;
+
+    #[test]
+    fn bounds_loop_counters_and_reports_hazards() {
+        let program = parse_program(
+            "@main(n: int) {\n  i: int = const 0;\n  ten: int = const 10;\n  one: int = const 1;\n.loop:\n  small: bool = lt i ten;\n  br small .body .end;\n.body:\n  q: int = div ten i;\n  r: int = div ten one;\n  i: int = add i one;\n  jmp .loop;\n.end:\n  big: int = mul n ten;\n  print big;\n}\n",
+        )
+        .unwrap();
+        let messages: Vec<String> = check_arithmetic(&program)
+            .into_iter()
+            .map(|d| d.message)
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "div ten i may divide by zero: i is in [0, 9]",
+                "mul n ten may overflow: n is in [-9223372036854775808, 9223372036854775807] and ten is in [10, 10], so the result is in [-92233720368547758080, 92233720368547758070]",
+            ]
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::{
+    analysis::{block_code, Signatures},
+    representation::{
+        format_instruction, format_type, opcode, AbstractFunction, Code, EffectOp, Literal,
+        MemoryOp, Position, Type, ValueOp,
+    },
+};
+
+// Type checking of a function in SSA form: the operands and result of every instruction have the
+// types its opcode expects, calls match the declaration of the callee and `ret` matches the
+// function's return type. Each error points at the instruction it is about, so `rust_bril verify`
+// can report where it was written
+
+/// An instruction whose operands or result do not have the types its opcode expects
+#[derive(Debug, Clone, Error)]
+#[error("type: '{instruction}' in .{block_label}: {reason}")]
+pub struct TypeError {
+    pub block_label: String,
+    pub instruction: String,
+    pub reason: String,
+    pub position: Option<Position>,
+    /// source file of the instruction, when it is not the program's main file
+    pub src: Option<Box<str>>,
+}
+
+/// Every instruction whose types do not match its opcode. Calls are only checked against
+/// `signatures` when given
+pub fn typecheck(af: &AbstractFunction, signatures: Option<&Signatures>) -> Vec<TypeError> {
+    let mut types: HashMap<&str, &Type> = HashMap::new();
+    for arg in af.args.iter().flatten() {
+        types.insert(&arg.name, &arg.arg_type);
+    }
+    for block in &af.cfg.basic_blocks {
+        for phi in &block.phi_nodes {
+            if phi.phi_type != Type::None {
+                types.insert(&phi.dest, &phi.phi_type);
+            }
+        }
+        for code in block_code(af, block.id) {
+            if let (Some(dest), Some(t)) = (code.get_destination(), type_of(code)) {
+                types.insert(dest, t);
+            }
+        }
+    }
+
+    let mut errors = Vec::new();
+    for block in &af.cfg.basic_blocks {
+        let terminator = block.terminator.code();
+        for code in block_code(af, block.id).chain(terminator) {
+            if let Err(reason) = check_types(code, &types, af.return_type.as_ref(), signatures) {
+                errors.push(TypeError {
+                    block_label: block.label.clone(),
+                    instruction: format_instruction(code),
+                    reason,
+                    position: code.get_position(),
+                    src: code.get_source().map(Box::from),
+                });
+            }
+        }
+    }
+    errors
+}
+
+/// [`typecheck`] as one line per error, for [`verify_function`](crate::analysis::verify_function)
+pub fn verify_types(af: &AbstractFunction, signatures: Option<&Signatures>) -> Vec<String> {
+    (typecheck(af, signatures).iter())
+        .map(TypeError::to_string)
+        .collect()
+}
+
+fn type_of(code: &Code) -> Option<&Type> {
+    match code {
+        Code::Constant { constant_type, .. } => Some(constant_type),
+        Code::Value { value_type, .. } => Some(value_type),
+        Code::Memory { ptr_type, .. } => ptr_type.as_ref(),
+        _ => None,
+    }
+}
+
+/// operand types of a value operation and the type it produces; `None` for `id`, `call` and `phi`
+fn value_signature(op: ValueOp) -> Option<(&'static [Type], Type)> {
+    opcode(op).map(|info| (info.operands, info.result.clone()))
+}
+
+fn check_types(
+    code: &Code,
+    types: &HashMap<&str, &Type>,
+    return_type: Option<&Type>,
+    signatures: Option<&Signatures>,
+) -> Result<(), String> {
+    let args: Vec<Option<&Type>> = code
+        .get_arguments()
+        .into_iter()
+        .flatten()
+        .map(|arg| types.get(arg.as_str()).copied())
+        .collect();
+    // operands without a known type are undefined, which verify_ssa reports
+    let expect_args = |expected: &[&Type]| {
+        if args.len() != expected.len() {
+            return Err(format!(
+                "expects {} arguments, found {}",
+                expected.len(),
+                args.len()
+            ));
+        }
+        for (i, (found, expected)) in args.iter().zip(expected).enumerate() {
+            if let Some(found) = found.filter(|found| found != expected) {
+                return Err(format!(
+                    "argument {} is {}, expected {}",
+                    i + 1,
+                    format_type(found),
+                    format_type(expected)
+                ));
+            }
+        }
+        Ok(())
+    };
+    let expect_result = |found: &Type, expected: &Type| match found == expected {
+        true => Ok(()),
+        false => Err(format!(
+            "produces {}, expected {}",
+            format_type(found),
+            format_type(expected)
+        )),
+    };
+
+    match code {
+        Code::Constant {
+            constant_type,
+            value,
+            ..
+        } => {
+            let fits = matches!(
+                (constant_type, value),
+                (Type::Int | Type::Float, Literal::Int(_))
+                    | (Type::Float, Literal::Float(_))
+                    | (Type::Bool, Literal::Bool(_))
+                    | (Type::Char, Literal::Char(_))
+            );
+            match fits {
+                true => Ok(()),
+                false => Err(format!(
+                    "literal does not fit {}",
+                    format_type(constant_type)
+                )),
+            }
+        }
+        Code::Value {
+            op: ValueOp::Id,
+            value_type,
+            ..
+        } => expect_args(&[value_type]),
+        Code::Value {
+            op: ValueOp::Call,
+            value_type,
+            funcs,
+            ..
+        } => check_call(funcs, Some(value_type), &args, signatures),
+        Code::Effect {
+            op: EffectOp::Call,
+            funcs,
+            ..
+        } => check_call(funcs, None, &args, signatures),
+        Code::Value { op, value_type, .. } => match value_signature(*op) {
+            Some((operands, result)) => {
+                expect_args(&operands.iter().collect::<Vec<_>>())?;
+                expect_result(value_type, &result)
+            }
+            None => Ok(()),
+        },
+        Code::Effect {
+            op: EffectOp::Br | EffectOp::Guard,
+            ..
+        } => expect_args(&[&Type::Bool]),
+        Code::Effect {
+            op: EffectOp::Ret, ..
+        } => match return_type {
+            Some(t) => expect_args(&[t]),
+            None => expect_args(&[]),
+        },
+        Code::Memory {
+            op,
+            ptr_type,
+            args: names,
+            ..
+        } => {
+            let pointee = |i: usize| match names.iter().flatten().nth(i) {
+                Some(arg) => match types.get(arg.as_str()) {
+                    Some(Type::Ptr(inner)) => Ok(Some(inner.as_ref())),
+                    Some(t) => Err(format!("{} is {}, expected a pointer", arg, format_type(t))),
+                    None => Ok(None),
+                },
+                None => Ok(None),
+            };
+            match (op, ptr_type) {
+                (MemoryOp::Alloc, Some(t)) if t.is_ptr() => expect_args(&[&Type::Int]),
+                (MemoryOp::Alloc, _) => Err("alloc must produce a pointer".to_string()),
+                (MemoryOp::Free, _) => pointee(0).map(|_| ()),
+                (MemoryOp::Load, Some(t)) => match pointee(0)? {
+                    Some(inner) => expect_result(t, inner),
+                    None => Ok(()),
+                },
+                (MemoryOp::Store, _) => match (pointee(0)?, args.get(1).copied().flatten()) {
+                    (Some(inner), Some(value)) if inner != value => Err(format!(
+                        "stores {} through a pointer to {}",
+                        format_type(value),
+                        format_type(inner)
+                    )),
+                    _ => Ok(()),
+                },
+                (MemoryOp::PtrAdd, Some(t)) => {
+                    pointee(0)?;
+                    if args
+                        .get(1)
+                        .copied()
+                        .flatten()
+                        .is_some_and(|t| *t != Type::Int)
+                    {
+                        return Err("ptradd offset must be an int".to_string());
+                    }
+                    match names
+                        .iter()
+                        .flatten()
+                        .next()
+                        .and_then(|base| types.get(base.as_str()))
+                    {
+                        Some(base) => expect_result(t, base),
+                        None => Ok(()),
+                    }
+                }
+                (MemoryOp::Load | MemoryOp::PtrAdd, None) => Err("missing result type".to_string()),
+            }
+        }
+        Code::Effect { .. } | Code::Label { .. } | Code::Noop { .. } => Ok(()),
+    }
+}
+
+fn check_call(
+    funcs: &Option<Box<[String]>>,
+    result: Option<&Type>,
+    args: &[Option<&Type>],
+    signatures: Option<&Signatures>,
+) -> Result<(), String> {
+    let (Some(signatures), Some(callee)) = (signatures, funcs.iter().flatten().next()) else {
+        return Ok(());
+    };
+    let Some((parameters, return_type)) = signatures.get(callee) else {
+        return Err(format!("calls unknown function @{}", callee));
+    };
+    if parameters.len() != args.len() {
+        return Err(format!(
+            "@{} takes {} arguments, found {}",
+            callee,
+            parameters.len(),
+            args.len()
+        ));
+    }
+    for (i, (found, expected)) in args.iter().zip(parameters).enumerate() {
+        if let Some(found) = found.filter(|found| *found != expected) {
+            return Err(format!(
+                "argument {} of @{} is {}, expected {}",
+                i + 1,
+                callee,
+                format_type(found),
+                format_type(expected)
+            ));
+        }
+    }
+    match (result, return_type) {
+        (Some(found), Some(expected)) if found != expected => Err(format!(
+            "@{} returns {}, not {}",
+            callee,
+            format_type(expected),
+            format_type(found)
+        )),
+        (Some(_), None) => Err(format!("@{} does not return a value", callee)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analysis::signatures, testing::abstract_program_from_text};
+
+    #[test]
+    fn reports_mistyped_instructions_where_they_are_written() {
+        let text = "@main {\n  x: float = const 1.5;\n  b: int = fle x x;\n  p: ptr<int> = alloc b;\n  q: ptr<int> = ptradd p x;\n  y: int = call @f x;\n  free p;\n}\n@f(a: int): int {\n  ret;\n}\n";
+        let program = abstract_program_from_text(text);
+        let signatures = signatures(&program);
+        let errors = |name: &str| typecheck(&program.functions[name], Some(&signatures));
+
+        let rows: Vec<(u32, String)> = (errors("main").into_iter())
+            .map(|e| (e.position.unwrap().row, e.reason))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (3, "produces int, expected bool".to_string()),
+                (5, "ptradd offset must be an int".to_string()),
+                (6, "argument 1 of @f is float, expected int".to_string()),
+            ]
+        );
+        assert_eq!(errors("f")[0].reason, "expects 1 arguments, found 0");
+    }
+}
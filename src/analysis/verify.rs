@@ -0,0 +1,327 @@
+use std::collections::{HashMap, HashSet};
+
+use thiserror::Error;
+
+use crate::{
+    analysis::verify_types,
+    representation::{
+        format_instruction, AbstractFunction, AbstractProgram, BlockId, BlockSet, Code,
+        DominanceInfo, EffectOp, Terminator, Type,
+    },
+};
+
+// Consistency checks on a function in SSA form, run between passes by `--verify`: the CFG agrees
+// with the block terminators, every variable is defined once and before it is used, phi nodes
+// have one value per predecessor, and operands have the types their opcodes expect
+
+/// Everything the verifiers found wrong with one function
+#[derive(Debug, Clone, Error)]
+#[error("@{function} is malformed:\n  {}", problems.join("\n  "))]
+pub struct VerifyError {
+    pub function: String,
+    /// one line per problem, prefixed with the verifier that found it (`cfg`, `ssa` or `type`)
+    pub problems: Vec<String>,
+}
+
+/// Argument and return types of every function, to check calls
+pub type Signatures = HashMap<String, (Vec<Type>, Option<Type>)>;
+
+pub fn signatures(program: &AbstractProgram) -> Signatures {
+    program
+        .functions
+        .values()
+        .map(|af| {
+            let args = af.args.iter().flatten().map(|a| a.arg_type.clone());
+            (af.name.clone(), (args.collect(), af.return_type.clone()))
+        })
+        .collect()
+}
+
+/// Run every verifier. Calls are only checked against `signatures` when given
+pub fn verify_function(
+    af: &AbstractFunction,
+    signatures: Option<&Signatures>,
+) -> Result<(), VerifyError> {
+    // the later checks index blocks through the CFG, so they only run on a sound one
+    let mut problems = verify_cfg(af);
+    if problems.is_empty() {
+        problems.extend(verify_ssa(af));
+        problems.extend(verify_types(af, signatures));
+    }
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(VerifyError {
+            function: af.name.clone(),
+            problems,
+        }),
+    }
+}
+
+/// block contents in execution order: preheader, then the block's own instructions
+pub(super) fn block_code(af: &AbstractFunction, block: BlockId) -> impl Iterator<Item = &Code> {
+    let block = &af.cfg.basic_blocks[block];
+    block.preheader.iter().chain(block.instructions.iter())
+}
+
+/// Block ids, the label map and the successor and predecessor sets agree with the terminators,
+/// and control flow only leaves a block through its terminator
+pub fn verify_cfg(af: &AbstractFunction) -> Vec<String> {
+    let cfg = &af.cfg;
+    let blocks = cfg.basic_blocks.len();
+    let mut problems = Vec::new();
+    if cfg.successors.len() != blocks || cfg.predecessors.len() != blocks {
+        return vec![format!(
+            "cfg: {} blocks but {} successor and {} predecessor sets",
+            blocks,
+            cfg.successors.len(),
+            cfg.predecessors.len()
+        )];
+    }
+
+    for (index, block) in cfg.basic_blocks.iter().enumerate() {
+        if block.id != index {
+            problems.push(format!(
+                "cfg: .{} has id {} at index {}",
+                block.label, block.id, index
+            ));
+        }
+        if cfg.label_map.get(&block.label) != Some(&index) {
+            problems.push(format!(
+                "cfg: label map does not point .{} at its block",
+                block.label
+            ));
+        }
+
+        let mut target = |label: &String| match cfg.label_map.get(label) {
+            Some(&id) => Some(id),
+            None => {
+                problems.push(format!("cfg: .{} jumps to unknown .{}", block.label, label));
+                None
+            }
+        };
+        let expected: BlockSet = match &block.terminator {
+            Terminator::Passthrough => {
+                BlockSet::from_ids(blocks, (index + 1 < blocks).then_some(index + 1))
+            }
+            Terminator::Ret(_) => BlockSet::new(blocks),
+            Terminator::Jmp(label, _) => BlockSet::from_ids(blocks, target(label)),
+            Terminator::Br(then, otherwise, _) => {
+                let then = target(then);
+                let otherwise = target(otherwise);
+                BlockSet::from_ids(blocks, then.into_iter().chain(otherwise))
+            }
+            Terminator::Speculate(rollbacks, _) => {
+                let next = (index + 1 < blocks).then_some(index + 1);
+                let rollbacks: Vec<BlockId> = rollbacks.iter().filter_map(&mut target).collect();
+                BlockSet::from_ids(blocks, next.into_iter().chain(rollbacks))
+            }
+        };
+        if expected != cfg.successors[index] {
+            problems.push(format!(
+                "cfg: successors of .{} are {:?}, its terminator goes to {:?}",
+                block.label, cfg.successors[index], expected
+            ));
+        }
+        for successor in &cfg.successors[index] {
+            if !cfg
+                .predecessors
+                .get(successor)
+                .is_some_and(|p| p.contains(index))
+            {
+                problems.push(format!(
+                    "cfg: .{} is a successor of .{} but not the other way around",
+                    index, block.label
+                ));
+            }
+        }
+        for predecessor in &cfg.predecessors[index] {
+            if !cfg
+                .successors
+                .get(predecessor)
+                .is_some_and(|s| s.contains(index))
+            {
+                problems.push(format!(
+                    "cfg: block {} is listed as a predecessor of .{} but does not jump there",
+                    predecessor, block.label
+                ));
+            }
+        }
+
+        for code in block_code(af, index) {
+            let leaves_block = matches!(
+                code,
+                Code::Label { .. }
+                    | Code::Effect {
+                        op: EffectOp::Jmp | EffectOp::Br | EffectOp::Ret,
+                        ..
+                    }
+            );
+            if leaves_block {
+                problems.push(format!(
+                    "cfg: .{} contains '{}' in its body instead of its terminator",
+                    block.label,
+                    format_instruction(code)
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// Every variable has a single definition, every use is dominated by it and phi nodes only name
+/// predecessor blocks
+pub fn verify_ssa(af: &AbstractFunction) -> Vec<String> {
+    let dominance = DominanceInfo::from(&af.cfg);
+    let mut problems = Vec::new();
+
+    // variable -> (block, index in `block_code`), phi nodes and arguments at index 0
+    let mut defined: Vec<(&str, BlockId, usize)> = Vec::new();
+    defined.extend(
+        af.args
+            .iter()
+            .flatten()
+            .map(|arg| (arg.name.as_str(), 0, 0)),
+    );
+    for block in &af.cfg.basic_blocks {
+        defined.extend(
+            block
+                .phi_nodes
+                .iter()
+                .map(|phi| (phi.dest.as_str(), block.id, 0)),
+        );
+        for (index, code) in block_code(af, block.id).enumerate() {
+            if let Some(dest) = code.get_destination() {
+                defined.push((dest, block.id, index + 1));
+            }
+        }
+    }
+    let mut definitions: HashMap<&str, (BlockId, usize)> = HashMap::new();
+    for (var, block, index) in defined {
+        if definitions.insert(var, (block, index)).is_some() {
+            problems.push(format!("ssa: {} is defined more than once", var));
+        }
+    }
+
+    for block in &af.cfg.basic_blocks {
+        let mut check_use = |var: &str, index: usize, code: &Code| {
+            let available = match definitions.get(var) {
+                None => false,
+                Some(&(def_block, def_index)) if def_block == block.id => def_index <= index,
+                Some(&(def_block, _)) => dominance.dominated_by(block.id, def_block),
+            };
+            if !available {
+                problems.push(format!(
+                    "ssa: '{}' in .{} uses {} before it is defined",
+                    format_instruction(code),
+                    block.label,
+                    var
+                ));
+            }
+        };
+        let body: Vec<&Code> = block_code(af, block.id).collect();
+        for (index, code) in body.iter().enumerate() {
+            for arg in code.get_arguments().into_iter().flatten() {
+                check_use(arg, index, code);
+            }
+        }
+        if let Some(code) = block.terminator.code() {
+            for arg in code.get_arguments().into_iter().flatten() {
+                check_use(arg, body.len(), code);
+            }
+        }
+
+        let predecessors: HashSet<&str> = af.cfg.predecessors[block.id]
+            .iter()
+            .map(|p| af.cfg.basic_blocks[p].label.as_str())
+            .collect();
+        for phi in &block.phi_nodes {
+            let mut seen = HashSet::new();
+            for (var, label) in &phi.phi_args {
+                if !predecessors.contains(label.as_str()) {
+                    problems.push(format!(
+                        "ssa: phi node {} in .{} names .{}, which is not a predecessor",
+                        phi.dest, block.label, label
+                    ));
+                }
+                if !seen.insert(label.as_str()) {
+                    problems.push(format!(
+                        "ssa: phi node {} in .{} has two values for .{}",
+                        phi.dest, block.label, label
+                    ));
+                }
+                if !definitions.contains_key(var.as_str()) {
+                    problems.push(format!(
+                        "ssa: phi node {} in .{} reads undefined {}",
+                        phi.dest, block.label, var
+                    ));
+                }
+            }
+            for label in predecessors.difference(&seen) {
+                problems.push(format!(
+                    "ssa: phi node {} in .{} has no value for .{}",
+                    phi.dest, block.label, label
+                ));
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::abstract_program_from_text;
+
+    #[test]
+    fn accepts_ssa_construction_output() {
+        let program = abstract_program_from_text(
+            "@main(n: int) {\n  i: int = const 0;\n.loop:\n  c: bool = lt i n;\n  br c .body .done;\n.body:\n  one: int = const 1;\n  i: int = add i one;\n  jmp .loop;\n.done:\n  print i;\n}\n",
+        );
+        let signatures = signatures(&program);
+        verify_function(&program.functions["main"], Some(&signatures)).unwrap();
+    }
+
+    #[test]
+    fn reports_phi_nodes_missing_a_predecessor() {
+        let mut program = abstract_program_from_text(
+            "@main(n: int) {\n  i: int = const 0;\n.loop:\n  c: bool = lt i n;\n  br c .body .done;\n.body:\n  one: int = const 1;\n  i: int = add i one;\n  jmp .loop;\n.done:\n  print i;\n}\n",
+        );
+        let af = program.functions.get_mut("main").unwrap();
+        let phi = (af.cfg.basic_blocks.iter_mut())
+            .find_map(|b| b.phi_nodes.first_mut())
+            .unwrap();
+        let (_, dropped) = phi.phi_args.pop().unwrap();
+        let problems = verify_ssa(af);
+        assert!(
+            problems
+                .iter()
+                .any(|p| p.ends_with(&format!("has no value for .{}", dropped))),
+            "{:?}",
+            problems
+        );
+    }
+
+    #[test]
+    fn reports_broken_functions() {
+        let mut program = abstract_program_from_text(
+            "@main {\n  a: int = const 1;\n  b: bool = not a;\n  print b;\n}\n",
+        );
+        let af = program.functions.get_mut("main").unwrap();
+        let error = verify_function(af, None).unwrap_err();
+        assert!(error.problems[0].starts_with("type: 'b_0: bool = not a_0;'"));
+
+        // a second definition of `a_0`
+        let block = af
+            .cfg
+            .basic_blocks
+            .iter_mut()
+            .find(|b| !b.instructions.is_empty());
+        let block = block.unwrap();
+        let copy = block.instructions[0].clone();
+        block.instructions.push(copy);
+        let error = verify_function(af, None).unwrap_err();
+        assert!(error
+            .problems
+            .contains(&"ssa: a_0 is defined more than once".to_string()));
+    }
+}
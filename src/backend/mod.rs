@@ -0,0 +1,5 @@
+mod register_allocation;
+mod riscv;
+
+pub use register_allocation::*;
+pub use riscv::*;
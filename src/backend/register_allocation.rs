@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    analysis::InterferenceGraph,
+    dataflow::WorklistResult,
+    representation::{AbstractFunction, Code, EffectOp, MemoryOp, Type, ValueOp},
+};
+
+// Colors the interference graph of a function with the allocatable registers of each class,
+// cheapest first. A variable that finds every register taken by its neighbours is spilled to its
+// own stack slot. Variables live across a call only get callee-saved registers, so calls need not
+// save anything. The argument registers and t0-t2 / ft0-ft2 are never allocated: they stay free
+// for passing arguments and for loading spilled operands
+
+const INT_TEMPORARIES: &[&str] = &["t3", "t4", "t5", "t6"];
+const INT_SAVED: &[&str] = &[
+    "s1", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11",
+];
+const FLOAT_TEMPORARIES: &[&str] = &[
+    "ft3", "ft4", "ft5", "ft6", "ft7", "ft8", "ft9", "ft10", "ft11",
+];
+const FLOAT_SAVED: &[&str] = &[
+    "fs0", "fs1", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9", "fs10", "fs11",
+];
+
+/// Where a variable lives for the whole function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Register(&'static str),
+    /// index of an 8 byte slot in the stack frame
+    Stack(usize),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Allocation {
+    pub locations: BTreeMap<String, Location>,
+    /// callee-saved registers the function assigns, which it has to save and restore
+    pub callee_saved: Vec<&'static str>,
+    pub stack_slots: usize,
+}
+
+impl Allocation {
+    pub fn location(&self, variable: &str) -> Location {
+        self.locations[variable]
+    }
+}
+
+/// Whether a variable of type `t` lives in a floating point register
+pub fn is_float(t: Option<&Type>) -> bool {
+    matches!(t, Some(Type::Float))
+}
+
+/// Whether the lowering of `code` calls a function, clobbering the caller-saved registers
+pub fn lowers_to_call(code: &Code) -> bool {
+    matches!(
+        code,
+        Code::Value {
+            op: ValueOp::Call,
+            ..
+        } | Code::Effect {
+            op: EffectOp::Call | EffectOp::Print,
+            ..
+        } | Code::Memory {
+            op: MemoryOp::Alloc | MemoryOp::Free,
+            ..
+        }
+    )
+}
+
+/// the registers a variable of type `t` may be given, cheapest first
+fn registers(t: Option<&Type>, live_across_call: bool) -> Vec<&'static str> {
+    let (temporaries, saved) = match is_float(t) {
+        true => (FLOAT_TEMPORARIES, FLOAT_SAVED),
+        false => (INT_TEMPORARIES, INT_SAVED),
+    };
+    let temporaries = if live_across_call { &[] } else { temporaries };
+    temporaries.iter().chain(saved).copied().collect()
+}
+
+/// Assign every variable of `af` a register or a stack slot. `types` holds the type of every
+/// variable; variables without one are treated as integers
+pub fn allocate_registers(
+    af: &mut AbstractFunction,
+    types: &HashMap<String, Type>,
+) -> WorklistResult<Allocation> {
+    let mut graph = InterferenceGraph::build_with(af, lowers_to_call)?;
+    // print calls the runtime once per argument, so the later ones outlive a call
+    for code in af
+        .cfg
+        .basic_blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+    {
+        if let Code::Effect {
+            op: EffectOp::Print,
+            args: Some(args),
+            ..
+        } = code
+        {
+            graph.live_across_call.extend(args.iter().cloned());
+        }
+    }
+
+    // integer and float registers are colored separately
+    let mut allocation = Allocation::default();
+    for float in [false, true] {
+        let class = graph.subgraph(|v| is_float(types.get(v)) == float);
+        let coloring = class.color(|v| registers(types.get(v), class.live_across_call.contains(v)));
+        for (variable, register) in coloring {
+            let location = match register {
+                Some(register) => {
+                    let saved = INT_SAVED.iter().chain(FLOAT_SAVED).any(|r| *r == register);
+                    if saved && !allocation.callee_saved.contains(&register) {
+                        allocation.callee_saved.push(register);
+                    }
+                    Location::Register(register)
+                }
+                None => {
+                    allocation.stack_slots += 1;
+                    Location::Stack(allocation.stack_slots - 1)
+                }
+            };
+            log::trace!("@{}: {} -> {:?}", af.name, variable, location);
+            allocation.locations.insert(variable, location);
+        }
+    }
+    allocation.callee_saved.sort();
+    Ok(allocation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::representation::parse_program;
+
+    #[test]
+    fn interfering_variables_get_different_registers() {
+        // 22 ints live across calls do not fit the 11 callee-saved registers, while `y` is only
+        // live between them
+        let mut text = String::from("@id(v: int): int { ret v; }\n@main {\n");
+        for i in 0..20 {
+            text += &format!("  v{}: int = const {};\n", i, i);
+        }
+        text += "  x: int = call @id v0;\n  y: int = add x v0;\n  z: int = add y y;\n  print x z";
+        for i in 0..20 {
+            text += &format!(" v{}", i);
+        }
+        text += ";\n}\n";
+        let program = parse_program(&text).unwrap();
+        let main = program
+            .functions
+            .into_iter()
+            .find(|f| f.name == "main")
+            .unwrap();
+        let types = (main.instrs.iter())
+            .filter_map(|code| Some((code.get_destination()?.to_string(), code.get_type()?)))
+            .collect();
+        let mut af = AbstractFunction::from(main);
+        let allocation = allocate_registers(&mut af, &types).unwrap();
+
+        let mut registers: Vec<_> = (allocation.locations.iter())
+            .filter(|(v, _)| *v != "y")
+            .filter_map(|(_, l)| match l {
+                Location::Register(r) => Some(*r),
+                Location::Stack(_) => None,
+            })
+            .collect();
+        registers.sort();
+        registers.dedup();
+        assert!(registers.iter().all(|r| r.starts_with('s')));
+        assert_eq!(registers.len(), INT_SAVED.len());
+        assert_eq!(allocation.stack_slots, 22 - INT_SAVED.len());
+        assert_eq!(allocation.location("y"), Location::Register("t3"));
+        assert_eq!(allocation.callee_saved.len(), INT_SAVED.len());
+    }
+}
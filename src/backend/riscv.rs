@@ -0,0 +1,668 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::{
+    backend::{allocate_registers, is_float, Allocation, Location},
+    dataflow::WorklistError,
+    representation::{
+        AbstractFunction, Argument, Code, EffectOp, Function, Literal, MemoryOp, Program,
+        Terminator, Type, ValueOp,
+    },
+};
+
+// Lowers a program out of SSA form to RV64IMFD assembly for the GNU assembler, one instruction
+// selection rule per opcode. Functions keep every variable where the register allocator put it
+// and call each other with the standard convention: arguments in a0-a7 and fa0-fa7, the rest on
+// the stack, results in a0 or fa0. Printing, allocation and parsing the arguments of `main` are
+// calls into the runtime in runtime/riscv.c, and a C `main` parses the command line and calls
+// `@main`. Bril functions become `bril.<name>` so they cannot clash with the runtime or libc.
+// Like a C compiler this does not check for division by zero or invalid characters
+
+/// Why a program cannot be lowered
+#[derive(Debug, Error)]
+pub enum LoweringError {
+    #[error("@{function}: the RISC-V backend does not support `{op}`")]
+    Unsupported { function: String, op: String },
+    #[error(transparent)]
+    Dataflow(#[from] WorklistError),
+}
+
+/// Arguments of a call passed in registers, per register class
+const ARGUMENT_REGISTERS: usize = 8;
+
+/// Where a function finds its callee-saved registers and spill slots, relative to s0, which
+/// holds the stack pointer on entry
+struct Frame {
+    size: i64,
+    saved: Vec<(&'static str, i64)>,
+    /// offset of slot 0, slot `n` is 8 bytes below slot `n - 1`
+    slots: i64,
+}
+
+impl Frame {
+    /// below the return address and the caller's s0 come the callee-saved registers, the spill
+    /// slots and, at the bottom, the arguments of calls that do not fit in registers
+    fn new(allocation: &Allocation, outgoing: usize) -> Self {
+        let saved: Vec<_> = (allocation.callee_saved.iter().enumerate())
+            .map(|(i, register)| (*register, -24 - 8 * i as i64))
+            .collect();
+        let slots = -24 - 8 * saved.len() as i64;
+        let used = 16 + 8 * (saved.len() + allocation.stack_slots + outgoing) as i64;
+        Frame {
+            size: (used + 15) / 16 * 16,
+            saved,
+            slots,
+        }
+    }
+}
+
+/// assembly of one function
+struct Emitter<'a> {
+    out: String,
+    symbol: String,
+    allocation: Allocation,
+    types: &'a HashMap<String, Type>,
+    frame: Frame,
+}
+
+impl<'a> Emitter<'a> {
+    fn new(
+        symbol: String,
+        allocation: Allocation,
+        types: &'a HashMap<String, Type>,
+        outgoing: usize,
+    ) -> Self {
+        Emitter {
+            out: String::new(),
+            symbol,
+            frame: Frame::new(&allocation, outgoing),
+            allocation,
+            types,
+        }
+    }
+
+    fn line(&mut self, instruction: &str) {
+        self.out.push_str(&format!("    {}\n", instruction));
+    }
+
+    fn is_float(&self, variable: &str) -> bool {
+        is_float(self.types.get(variable))
+    }
+
+    fn block_label(&self, block: usize) -> String {
+        format!(".L{}.{}", self.symbol, block)
+    }
+
+    /// `offset(s0)`, through t2 when the offset does not fit in an immediate
+    fn address(&mut self, offset: i64) -> String {
+        if (-2048..2048).contains(&offset) {
+            return format!("{}(s0)", offset);
+        }
+        self.line(&format!("li t2, {}", offset));
+        self.line("add t2, s0, t2");
+        "0(t2)".to_string()
+    }
+
+    fn slot_address(&mut self, slot: usize) -> String {
+        self.address(self.frame.slots - 8 * slot as i64)
+    }
+
+    fn adjust_sp(&mut self, amount: i64) {
+        match (-2048..2048).contains(&amount) {
+            _ if amount == 0 => {}
+            true => self.line(&format!("addi sp, sp, {}", amount)),
+            false => {
+                self.line(&format!("li t0, {}", amount));
+                self.line("add sp, sp, t0");
+            }
+        }
+    }
+
+    /// the register holding `variable`, loading it into scratch register `scratch` (0 or 1)
+    /// if it was spilled
+    fn read(&mut self, variable: &str, scratch: usize) -> String {
+        match self.allocation.location(variable) {
+            Location::Register(register) => register.to_string(),
+            Location::Stack(slot) => {
+                let (load, register) = match self.is_float(variable) {
+                    true => ("fld", format!("ft{}", scratch)),
+                    false => ("ld", format!("t{}", scratch)),
+                };
+                let address = self.slot_address(slot);
+                self.line(&format!("{} {}, {}", load, register, address));
+                register
+            }
+        }
+    }
+
+    /// the register to compute `variable` into; `write` stores it if it was spilled
+    fn dest(&self, variable: &str) -> &'static str {
+        match (self.allocation.location(variable), self.is_float(variable)) {
+            (Location::Register(register), _) => register,
+            (Location::Stack(_), true) => "ft0",
+            (Location::Stack(_), false) => "t0",
+        }
+    }
+
+    fn write(&mut self, variable: &str) {
+        let source = self.dest(variable);
+        self.store_from(variable, source);
+    }
+
+    /// copy `register` into `variable`
+    fn store_from(&mut self, variable: &str, register: &str) {
+        let float = self.is_float(variable);
+        match self.allocation.location(variable) {
+            Location::Register(r) if r == register => {}
+            Location::Register(r) => match float {
+                true => self.line(&format!("fmv.d {}, {}", r, register)),
+                false => self.line(&format!("mv {}, {}", r, register)),
+            },
+            Location::Stack(slot) => {
+                let address = self.slot_address(slot);
+                let store = if float { "fsd" } else { "sd" };
+                self.line(&format!("{} {}, {}", store, register, address));
+            }
+        }
+    }
+
+    /// copy `variable` into `register`
+    fn load_into(&mut self, variable: &str, register: &str) {
+        let float = self.is_float(variable);
+        match self.allocation.location(variable) {
+            Location::Register(r) if r == register => {}
+            Location::Register(r) => match float {
+                true => self.line(&format!("fmv.d {}, {}", register, r)),
+                false => self.line(&format!("mv {}, {}", register, r)),
+            },
+            Location::Stack(slot) => {
+                let address = self.slot_address(slot);
+                let load = if float { "fld" } else { "ld" };
+                self.line(&format!("{} {}, {}", load, register, address));
+            }
+        }
+    }
+
+    fn prologue(&mut self) {
+        self.out.push_str(&format!("{}:\n", self.symbol));
+        self.line("addi sp, sp, -16");
+        self.line("sd ra, 8(sp)");
+        self.line("sd s0, 0(sp)");
+        self.line("addi s0, sp, 16");
+        self.adjust_sp(16 - self.frame.size);
+        for (register, offset) in self.frame.saved.clone() {
+            let address = self.address(offset);
+            let store = if register.starts_with('f') {
+                "fsd"
+            } else {
+                "sd"
+            };
+            self.line(&format!("{} {}, {}", store, register, address));
+        }
+    }
+
+    fn epilogue(&mut self) {
+        self.out.push_str(&format!(".L{}.ret:\n", self.symbol));
+        for (register, offset) in self.frame.saved.clone() {
+            let address = self.address(offset);
+            let load = if register.starts_with('f') {
+                "fld"
+            } else {
+                "ld"
+            };
+            self.line(&format!("{} {}, {}", load, register, address));
+        }
+        self.line("addi sp, s0, -16");
+        self.line("ld ra, 8(sp)");
+        self.line("ld s0, 0(sp)");
+        self.line("addi sp, sp, 16");
+        self.line("ret");
+    }
+
+    /// move the arguments into place, call `callee` and copy the result into `dest`
+    fn call(&mut self, callee: &str, args: &[String], dest: Option<&str>) {
+        let (mut ints, mut floats, mut stack) = (0, 0, 0);
+        for arg in args {
+            match self.is_float(arg) {
+                true if floats < ARGUMENT_REGISTERS => {
+                    self.load_into(arg, &format!("fa{}", floats));
+                    floats += 1;
+                }
+                false if ints < ARGUMENT_REGISTERS => {
+                    self.load_into(arg, &format!("a{}", ints));
+                    ints += 1;
+                }
+                float => {
+                    let register = self.read(arg, 0);
+                    let store = if float { "fsd" } else { "sd" };
+                    self.line(&format!("{} {}, {}(sp)", store, register, 8 * stack));
+                    stack += 1;
+                }
+            }
+        }
+        self.line(&format!("call {}", callee));
+        if let Some(dest) = dest {
+            let result = if self.is_float(dest) { "fa0" } else { "a0" };
+            self.store_from(dest, result);
+        }
+    }
+
+    /// copy the arguments from where the caller put them into their variables
+    fn receive(&mut self, params: &[Argument]) {
+        let (mut ints, mut floats, mut stack) = (0, 0, 0);
+        for param in params {
+            match self.is_float(&param.name) {
+                true if floats < ARGUMENT_REGISTERS => {
+                    self.store_from(&param.name, &format!("fa{}", floats));
+                    floats += 1;
+                }
+                false if ints < ARGUMENT_REGISTERS => {
+                    self.store_from(&param.name, &format!("a{}", ints));
+                    ints += 1;
+                }
+                float => {
+                    let (load, scratch) = if float { ("fld", "ft0") } else { ("ld", "t0") };
+                    self.line(&format!("{} {}, {}(s0)", load, scratch, 8 * stack));
+                    self.store_from(&param.name, scratch);
+                    stack += 1;
+                }
+            }
+        }
+    }
+
+    fn print(&mut self, args: &[String]) {
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                self.line("call bril_print_space");
+            }
+            let kind = match self.types.get(arg) {
+                Some(Type::Bool) => "bool",
+                Some(Type::Float) => "float",
+                Some(Type::Char) => "char",
+                Some(Type::Ptr(_)) => "ptr",
+                _ => "int",
+            };
+            let register = if kind == "float" { "fa0" } else { "a0" };
+            self.load_into(arg, register);
+            self.line(&format!("call bril_print_{}", kind));
+        }
+        self.line("call bril_print_newline");
+    }
+
+    fn constant(&mut self, dest: &str, value: &Literal) {
+        let rd = self.dest(dest);
+        match (self.is_float(dest), value) {
+            (true, value) => {
+                let bits = value.cast_to(&Type::Float);
+                let Literal::Float(x) = bits else {
+                    unreachable!("cast to a float")
+                };
+                self.line(&format!("li t1, {}", x.to_bits() as i64));
+                self.line(&format!("fmv.d.x {}, t1", rd));
+            }
+            (false, Literal::Int(x)) => self.line(&format!("li {}, {}", rd, x)),
+            (false, Literal::Bool(b)) => self.line(&format!("li {}, {}", rd, *b as i64)),
+            (false, Literal::Char(c)) => self.line(&format!("li {}, {}", rd, *c as u32)),
+            (false, Literal::Float(x)) => self.line(&format!("li {}, {}", rd, *x as i64)),
+        }
+        self.write(dest);
+    }
+
+    fn value(&mut self, op: ValueOp, dest: &str, args: &[String]) -> Result<(), String> {
+        let a = args
+            .first()
+            .map(|arg| self.read(arg, 0))
+            .unwrap_or_default();
+        let b = args.get(1).map(|arg| self.read(arg, 1)).unwrap_or_default();
+        let rd = self.dest(dest);
+        let instructions = match op {
+            ValueOp::Add => vec![format!("add {}, {}, {}", rd, a, b)],
+            ValueOp::Sub => vec![format!("sub {}, {}, {}", rd, a, b)],
+            ValueOp::Mul => vec![format!("mul {}, {}, {}", rd, a, b)],
+            ValueOp::Div => vec![format!("div {}, {}, {}", rd, a, b)],
+            ValueOp::Eq | ValueOp::Ceq => vec![
+                format!("sub {}, {}, {}", rd, a, b),
+                format!("seqz {}, {}", rd, rd),
+            ],
+            ValueOp::Lt | ValueOp::Clt => vec![format!("slt {}, {}, {}", rd, a, b)],
+            ValueOp::Gt | ValueOp::Cgt => vec![format!("slt {}, {}, {}", rd, b, a)],
+            ValueOp::Le | ValueOp::Cle => vec![
+                format!("slt {}, {}, {}", rd, b, a),
+                format!("xori {}, {}, 1", rd, rd),
+            ],
+            ValueOp::Ge | ValueOp::Cge => vec![
+                format!("slt {}, {}, {}", rd, a, b),
+                format!("xori {}, {}, 1", rd, rd),
+            ],
+            ValueOp::Not => vec![format!("xori {}, {}, 1", rd, a)],
+            ValueOp::And => vec![format!("and {}, {}, {}", rd, a, b)],
+            ValueOp::Or => vec![format!("or {}, {}, {}", rd, a, b)],
+            ValueOp::Id if rd == a => vec![],
+            ValueOp::Id if self.is_float(dest) => vec![format!("fmv.d {}, {}", rd, a)],
+            ValueOp::Id | ValueOp::Char2int | ValueOp::Int2char => {
+                vec![format!("mv {}, {}", rd, a)]
+            }
+            ValueOp::Fadd => vec![format!("fadd.d {}, {}, {}", rd, a, b)],
+            ValueOp::Fsub => vec![format!("fsub.d {}, {}, {}", rd, a, b)],
+            ValueOp::Fmul => vec![format!("fmul.d {}, {}, {}", rd, a, b)],
+            ValueOp::Fdiv => vec![format!("fdiv.d {}, {}, {}", rd, a, b)],
+            ValueOp::Feq => vec![format!("feq.d {}, {}, {}", rd, a, b)],
+            ValueOp::Flt => vec![format!("flt.d {}, {}, {}", rd, a, b)],
+            ValueOp::Fle => vec![format!("fle.d {}, {}, {}", rd, a, b)],
+            ValueOp::Fgt => vec![format!("flt.d {}, {}, {}", rd, b, a)],
+            ValueOp::Fge => vec![format!("fle.d {}, {}, {}", rd, b, a)],
+            ValueOp::Float2bits => vec![format!("fmv.x.d {}, {}", rd, a)],
+            ValueOp::Bits2float => vec![format!("fmv.d.x {}, {}", rd, a)],
+            #[cfg(feature = "bitops")]
+            ValueOp::Shl => vec![format!("sll {}, {}, {}", rd, a, b)],
+            #[cfg(feature = "bitops")]
+            ValueOp::Shr => vec![format!("sra {}, {}, {}", rd, a, b)],
+            #[cfg(feature = "bitops")]
+            ValueOp::Bitand => vec![format!("and {}, {}, {}", rd, a, b)],
+            #[cfg(feature = "bitops")]
+            ValueOp::Bitor => vec![format!("or {}, {}, {}", rd, a, b)],
+            #[cfg(feature = "bitops")]
+            ValueOp::Bitxor => vec![format!("xor {}, {}, {}", rd, a, b)],
+            ValueOp::Call | ValueOp::Phi => return Err(format!("{:?}", op).to_lowercase()),
+        };
+        for instruction in instructions {
+            self.line(&instruction);
+        }
+        self.write(dest);
+        Ok(())
+    }
+
+    fn memory(&mut self, op: MemoryOp, dest: Option<&str>, args: &[String]) {
+        match (op, dest) {
+            (MemoryOp::Alloc, Some(dest)) => self.call("bril_alloc", args, Some(dest)),
+            (MemoryOp::Free, _) => self.call("bril_free", args, None),
+            (MemoryOp::Load, Some(dest)) => {
+                let pointer = self.read(&args[0], 0);
+                let rd = self.dest(dest);
+                let load = if self.is_float(dest) { "fld" } else { "ld" };
+                self.line(&format!("{} {}, 0({})", load, rd, pointer));
+                self.write(dest);
+            }
+            (MemoryOp::Store, _) => {
+                let pointer = self.read(&args[0], 0);
+                let value = self.read(&args[1], 1);
+                let store = if self.is_float(&args[1]) { "fsd" } else { "sd" };
+                self.line(&format!("{} {}, 0({})", store, value, pointer));
+            }
+            (MemoryOp::PtrAdd, Some(dest)) => {
+                let pointer = self.read(&args[0], 0);
+                let offset = self.read(&args[1], 1);
+                let rd = self.dest(dest);
+                self.line(&format!("slli t1, {}, 3", offset));
+                self.line(&format!("add {}, {}, t1", rd, pointer));
+                self.write(dest);
+            }
+            (MemoryOp::Alloc | MemoryOp::Load | MemoryOp::PtrAdd, None) => {}
+        }
+    }
+
+    fn instruction(&mut self, code: &Code) -> Result<(), String> {
+        let args = code.get_arguments().map(Vec::as_slice).unwrap_or_default();
+        match code {
+            Code::Constant { dest, value, .. } => self.constant(dest, value),
+            Code::Value {
+                op: ValueOp::Call,
+                dest,
+                funcs,
+                ..
+            } => self.call(&symbol(&funcs.as_deref().unwrap()[0]), args, Some(dest)),
+            Code::Value { op, dest, .. } => self.value(*op, dest, args)?,
+            Code::Effect {
+                op: EffectOp::Call,
+                funcs,
+                ..
+            } => self.call(&symbol(&funcs.as_deref().unwrap()[0]), args, None),
+            Code::Effect {
+                op: EffectOp::Print,
+                ..
+            } => self.print(args),
+            Code::Effect { op, .. } => return Err(format!("{:?}", op).to_lowercase()),
+            Code::Memory { op, dest, .. } => self.memory(*op, dest.as_deref(), args),
+            Code::Noop { .. } | Code::Label { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// jump to the end of `block` unless that is where the next block starts anyway
+    fn terminator(&mut self, af: &AbstractFunction, block: usize) -> Result<(), String> {
+        let target = |label: &str| af.cfg.label_map[label];
+        let next = block + 1;
+        match &af.cfg.basic_blocks[block].terminator {
+            Terminator::Passthrough => {}
+            Terminator::Jmp(label, _) if target(label) == next => {}
+            Terminator::Jmp(label, _) => {
+                let label = self.block_label(target(label));
+                self.line(&format!("j {}", label));
+            }
+            Terminator::Br(then, otherwise, code) => {
+                let condition = self.read(&code.get_arguments().unwrap()[0], 0);
+                let (then, otherwise) = (target(then), target(otherwise));
+                match (then == next, otherwise == next) {
+                    (_, true) => {
+                        let label = self.block_label(then);
+                        self.line(&format!("bnez {}, {}", condition, label));
+                    }
+                    (true, false) => {
+                        let label = self.block_label(otherwise);
+                        self.line(&format!("beqz {}, {}", condition, label));
+                    }
+                    (false, false) => {
+                        let (then, otherwise) =
+                            (self.block_label(then), self.block_label(otherwise));
+                        self.line(&format!("bnez {}, {}", condition, then));
+                        self.line(&format!("j {}", otherwise));
+                    }
+                }
+            }
+            Terminator::Ret(code) => {
+                if let Some(value) = code.get_arguments().and_then(|args| args.first()) {
+                    let register = if self.is_float(value) { "fa0" } else { "a0" };
+                    self.load_into(value, register);
+                }
+                if next < af.cfg.basic_blocks.len() {
+                    self.line(&format!("j .L{}.ret", self.symbol));
+                }
+            }
+            Terminator::Speculate(..) => return Err("speculate".to_string()),
+        }
+        Ok(())
+    }
+}
+
+/// the assembler symbol of Bril function `name`
+fn symbol(name: &str) -> String {
+    let name: String = (name.chars())
+        .map(|c| match c.is_ascii_alphanumeric() || "_.$".contains(c) {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    format!("bril.{}", name)
+}
+
+/// arguments of a call with `args` that go on the stack
+fn stack_arguments<'a>(
+    args: impl IntoIterator<Item = &'a String>,
+    types: &HashMap<String, Type>,
+) -> usize {
+    let floats = args.into_iter().map(|arg| is_float(types.get(arg)));
+    let (floats, ints): (Vec<bool>, Vec<bool>) = floats.partition(|float| *float);
+    floats.len().saturating_sub(ARGUMENT_REGISTERS) + ints.len().saturating_sub(ARGUMENT_REGISTERS)
+}
+
+/// the type of every argument and destination of `function`
+fn variable_types(function: &Function) -> HashMap<String, Type> {
+    let params =
+        (function.args.iter().flatten()).map(|arg| (arg.name.clone(), arg.arg_type.clone()));
+    let dests = (function.instrs.iter())
+        .filter_map(|code| Some((code.get_destination()?.to_string(), code.get_type()?)));
+    params.chain(dests).collect()
+}
+
+fn lower_function(function: &Function) -> Result<String, LoweringError> {
+    let types = variable_types(function);
+    let mut af = AbstractFunction::from(function.clone());
+    let unsupported = |op: String| LoweringError::Unsupported {
+        function: function.name.clone(),
+        op,
+    };
+    if af.speculates() {
+        return Err(unsupported("speculate".to_string()));
+    }
+    let allocation = allocate_registers(&mut af, &types)?;
+
+    let outgoing = (af.cfg.basic_blocks.iter())
+        .flat_map(|block| &block.instructions)
+        .filter(|code| code.is_call())
+        .map(|code| stack_arguments(code.get_arguments().into_iter().flatten(), &types))
+        .max()
+        .unwrap_or(0);
+    let mut emitter = Emitter::new(symbol(&function.name), allocation, &types, outgoing);
+    emitter.prologue();
+    emitter.receive(function.args.as_deref().unwrap_or_default());
+
+    for block in &af.cfg.basic_blocks {
+        let label = emitter.block_label(block.id);
+        match block.label.starts_with("no_label_") || block.label.starts_with("function_preamble_")
+        {
+            true => emitter.out.push_str(&format!("{}:\n", label)),
+            false => emitter
+                .out
+                .push_str(&format!("{}:  # .{}\n", label, block.label)),
+        }
+        for code in &block.instructions {
+            emitter.instruction(code).map_err(unsupported)?;
+        }
+        emitter.terminator(&af, block.id).map_err(unsupported)?;
+    }
+    emitter.epilogue();
+    Ok(emitter.out)
+}
+
+/// the C entry point: check and parse the command line, then call `@main` with it
+fn lower_entry(main: &Function) -> String {
+    let params = main.args.as_deref().unwrap_or_default();
+    let types: HashMap<String, Type> = (params.iter())
+        .map(|arg| (arg.name.clone(), arg.arg_type.clone()))
+        .collect();
+    // every argument gets a stack slot, and argv the slot after them
+    let allocation = Allocation {
+        locations: (params.iter().enumerate())
+            .map(|(i, arg)| (arg.name.clone(), Location::Stack(i)))
+            .collect(),
+        callee_saved: Vec::new(),
+        stack_slots: params.len() + 1,
+    };
+    let outgoing = stack_arguments(params.iter().map(|arg| &arg.name), &types);
+    let mut emitter = Emitter::new("main".to_string(), allocation, &types, outgoing);
+    emitter.prologue();
+    let argv = emitter.slot_address(params.len());
+    emitter.line(&format!("sd a1, {}", argv));
+    emitter.line(&format!("li a1, {}", params.len()));
+    emitter.line("call bril_check_args");
+    for (i, param) in params.iter().enumerate() {
+        let argv = emitter.slot_address(params.len());
+        emitter.line(&format!("ld t0, {}", argv));
+        emitter.line(&format!("ld a0, {}(t0)", 8 * (i + 1)));
+        let kind = match param.arg_type {
+            Type::Bool => "bool",
+            Type::Float => "float",
+            Type::Char => "char",
+            _ => "int",
+        };
+        emitter.line(&format!("call bril_parse_{}", kind));
+        let result = if kind == "float" { "fa0" } else { "a0" };
+        emitter.store_from(&param.name, result);
+    }
+    let args: Vec<String> = params.iter().map(|arg| arg.name.clone()).collect();
+    emitter.call(&symbol(&main.name), &args, None);
+    emitter.line("li a0, 0");
+    emitter.epilogue();
+    emitter.out
+}
+
+/// RISC-V assembly for `program`, which must be out of SSA form. Link it with runtime/riscv.c
+pub fn lower_to_riscv(program: &Program) -> Result<String, LoweringError> {
+    let mut out = String::from("    # generated by rust_bril, link with runtime/riscv.c\n");
+    out.push_str("    .text\n");
+    for function in &program.functions {
+        out.push_str("\n    .p2align 2\n");
+        out.push_str(&lower_function(function)?);
+    }
+    if let Some(main) = program.functions.iter().find(|f| f.name == "main") {
+        out.push_str("\n    .globl main\n    .p2align 2\n");
+        out.push_str(&lower_entry(main));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::representation::parse_program;
+
+    #[test]
+    fn lowers_calls_and_arithmetic() {
+        let program = parse_program(
+            r#"
+            @main(n: int) {
+              one: int = const 1;
+              m: int = add n one;
+              r: float = call @half m;
+              print m r;
+            }
+            @half(x: int): float {
+              two: float = const 2;
+              f: float = call @float x;
+              h: float = fdiv f two;
+              ret h;
+            }
+            @float(x: int): float {
+              zero: float = const 0;
+              one: float = const 1;
+              none: int = const 0;
+              step: int = const 1;
+              .loop:
+                done: bool = le x none;
+                br done .end .body;
+              .body:
+                zero: float = fadd zero one;
+                x: int = sub x step;
+                jmp .loop;
+              .end:
+                ret zero;
+            }
+            "#,
+        )
+        .unwrap();
+        let assembly = lower_to_riscv(&program).unwrap();
+        for expected in [
+            "bril.main:",
+            "call bril.half",
+            "fdiv.d",
+            "call bril_print_float",
+            "call bril_parse_int",
+            "# .loop",
+            "main:",
+        ] {
+            assert!(
+                assembly.contains(expected),
+                "no {} in\n{}",
+                expected,
+                assembly
+            );
+        }
+
+        let speculating = parse_program("@main { speculate; commit; }").unwrap();
+        assert!(matches!(
+            lower_to_riscv(&speculating),
+            Err(LoweringError::Unsupported { .. })
+        ));
+    }
+}
@@ -1,10 +1,116 @@
-use log::LevelFilter;
+use log::{LevelFilter, Record};
 use log4rs::{
-    append::console::{ConsoleAppender, Target},
+    append::{
+        console::{ConsoleAppender, Target},
+        rolling_file::{
+            policy::compound::{
+                roll::fixed_window::FixedWindowRoller, trigger::size::SizeTrigger, CompoundPolicy,
+            },
+            RollingFileAppender,
+        },
+    },
     config::{Appender, Config, Root},
     encode::pattern::PatternEncoder,
+    filter::{Filter, Response},
 };
-use std::error::Error;
+use std::{error::Error, path::PathBuf, str::FromStr};
+
+const PATTERN: &str = "{d(%Y-%m-%d %H:%M:%S)} [{l:<5}] {t} - {m}{n}";
+
+/// Log files roll over at this size, keeping `LOG_FILE_BACKUPS` older files next to them
+const LOG_FILE_LIMIT: u64 = 10 * 1024 * 1024;
+const LOG_FILE_BACKUPS: u32 = 3;
+
+/// A log level for one module, written `module=level`, e.g. `dataflow=trace`.
+///
+/// The module matches any run of whole path segments of the log target, so `dataflow`,
+/// `phi_nodes` and `rust_bril::representation` all work.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    pub module: String,
+    pub level: LevelFilter,
+}
+
+impl LogFilter {
+    fn matches(&self, target: &str) -> bool {
+        let target = format!("::{}::", target);
+        target.contains(&format!("::{}::", self.module))
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (module, level) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected module=level, found '{}'", s))?;
+        let level = level
+            .trim()
+            .parse()
+            .map_err(|_| format!("unknown log level '{}'", level))?;
+        Ok(LogFilter {
+            module: module.trim().to_string(),
+            level,
+        })
+    }
+}
+
+/// Applies the level of the most specific matching `LogFilter`, or `default`
+#[derive(Debug)]
+struct ModuleFilter {
+    default: LevelFilter,
+    filters: Vec<LogFilter>,
+}
+
+impl ModuleFilter {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.filters
+            .iter()
+            .filter(|f| f.matches(target))
+            .max_by_key(|f| f.module.len())
+            .map_or(self.default, |f| f.level)
+    }
+
+    /// the most verbose level any module may log at
+    fn max_level(&self) -> LevelFilter {
+        self.filters
+            .iter()
+            .map(|f| f.level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+impl Filter for ModuleFilter {
+    fn filter(&self, record: &Record) -> Response {
+        if record.level() <= self.level(record.target()) {
+            Response::Neutral
+        } else {
+            Response::Reject
+        }
+    }
+}
+
+/// Where log records go and which ones are kept
+#[derive(Debug, Clone)]
+pub struct LoggerOptions {
+    /// level of every module without a filter
+    pub level: LevelFilter,
+    /// also write records to this file, rolling it over at 10 MiB
+    pub file: Option<PathBuf>,
+    /// per-module levels. With a `file` they only apply to the file, so stderr keeps `level`
+    pub filters: Vec<LogFilter>,
+}
+
+impl Default for LoggerOptions {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Info,
+            file: None,
+            filters: Vec::new(),
+        }
+    }
+}
 
 /// Initialize the logging system with a console-only handler.
 ///
@@ -21,6 +127,7 @@ use std::error::Error;
 /// # Examples
 /// ```rust
 /// use log::LevelFilter;
+/// use rust_bril::bril_logger::init_logger;
 ///
 /// // Initialize with Info level logging
 /// init_logger(LevelFilter::Info).expect("Failed to initialize logger");
@@ -30,18 +137,80 @@ use std::error::Error;
 /// log::debug!("This won't be shown with Info level");
 /// ```
 pub fn init_logger(level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    init_logger_with(&LoggerOptions {
+        level,
+        ..Default::default()
+    })
+}
+
+/// Initialize logging to stderr and optionally a rolling log file, with per-module levels.
+///
+/// # Arguments
+/// * `options` - The default level, log file and module filters
+///
+/// # Returns
+/// * `Ok(())` - If logging was successfully initialized
+/// * `Err(Box<dyn Error>)` - If the log file cannot be opened or a logger is already set
+///
+/// # Examples
+/// ```rust,no_run
+/// use log::LevelFilter;
+/// use rust_bril::bril_logger::{init_logger_with, LoggerOptions};
+///
+/// // dataflow traces go to bril.log, stderr only shows info and above
+/// init_logger_with(&LoggerOptions {
+///     level: LevelFilter::Info,
+///     file: Some("bril.log".into()),
+///     filters: vec!["dataflow=trace".parse().unwrap()],
+/// })
+/// .expect("Failed to initialize logger");
+/// ```
+pub fn init_logger_with(options: &LoggerOptions) -> Result<(), Box<dyn Error>> {
+    let filtered = ModuleFilter {
+        default: options.level,
+        filters: options.filters.clone(),
+    };
+    let console_filter = match options.file {
+        Some(_) => ModuleFilter {
+            default: options.level,
+            filters: Vec::new(),
+        },
+        None => ModuleFilter {
+            default: options.level,
+            filters: options.filters.clone(),
+        },
+    };
+    let max_level = filtered.max_level();
+
     let console_appender = ConsoleAppender::builder()
         .target(Target::Stderr)
-        .encoder(Box::new(PatternEncoder::new(
-            "{d(%Y-%m-%d %H:%M:%S)} [{l:<5}] {t} - {m}{n}",
-        )))
+        .encoder(Box::new(PatternEncoder::new(PATTERN)))
         .build();
+    let mut config = Config::builder().appender(
+        Appender::builder()
+            .filter(Box::new(console_filter))
+            .build("console", Box::new(console_appender)),
+    );
+    let mut root = Root::builder().appender("console");
 
-    let config = Config::builder()
-        .appender(Appender::builder().build("console", Box::new(console_appender)))
-        .build(Root::builder().appender("console").build(level))?;
+    if let Some(file) = &options.file {
+        let backups = format!("{}.{{}}", file.display());
+        let policy = CompoundPolicy::new(
+            Box::new(SizeTrigger::new(LOG_FILE_LIMIT)),
+            Box::new(FixedWindowRoller::builder().build(&backups, LOG_FILE_BACKUPS)?),
+        );
+        let file_appender = RollingFileAppender::builder()
+            .encoder(Box::new(PatternEncoder::new(PATTERN)))
+            .build(file, Box::new(policy))?;
+        config = config.appender(
+            Appender::builder()
+                .filter(Box::new(filtered))
+                .build("file", Box::new(file_appender)),
+        );
+        root = root.appender("file");
+    }
 
-    log4rs::init_config(config)?;
+    log4rs::init_config(config.build(root.build(max_level))?)?;
     Ok(())
 }
 
@@ -137,6 +306,28 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_module_filters() {
+        let filter = ModuleFilter {
+            default: LevelFilter::Info,
+            filters: vec![
+                "dataflow=trace".parse().unwrap(),
+                "rust_bril::dataflow::worklist=warn".parse().unwrap(),
+            ],
+        };
+        assert_eq!(
+            filter.level("rust_bril::dataflow::reaching"),
+            LevelFilter::Trace
+        );
+        assert_eq!(
+            filter.level("rust_bril::dataflow::worklist"),
+            LevelFilter::Warn
+        );
+        assert_eq!(filter.level("rust_bril::dataflowx"), LevelFilter::Info);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+        assert!("dataflow".parse::<LogFilter>().is_err());
+    }
+
     #[test]
     fn test_convenience_functions() {
         init_test_logger();
@@ -0,0 +1,809 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use log::LevelFilter;
+use serde::Deserialize;
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    analysis::ProfileData,
+    bril_logger::LogFilter,
+    optimizations::{builtin_pass_names, pass_by_name, preset_passes, PassManager, PrintAfter},
+    representation::{ProgramFormat, SsaForm},
+    visualization::Artifact,
+};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatsFormat {
+    Table,
+    Json,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogLevel {
+    /// Trace level logging (most verbose)
+    Trace,
+    /// Debug level logging
+    Debug,
+    /// Info level logging (default)
+    Info,
+    /// Warning level logging
+    Warn,
+    /// Error level logging
+    Error,
+    /// No logging
+    Off,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Bril JSON
+    Json,
+    /// Bril text format
+    Bril,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Bril JSON
+    Json,
+    /// Bril text format
+    #[value(alias = "text")]
+    #[serde(alias = "text")]
+    Bril,
+    /// Bril JSON, still in SSA form (same as -S)
+    SsaJson,
+    /// RV64 assembly to link with runtime/riscv.c
+    Riscv,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PhiPlacement {
+    /// a phi node at every join a definition reaches
+    Minimal,
+    /// phi nodes only for variables read in a block before it assigns them
+    SemiPruned,
+    /// phi nodes only where the variable is live, found with a liveness analysis
+    Pruned,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Emit {
+    /// control flow graph of every function, as Graphviz DOT
+    CfgDot,
+    /// control flow graph of every function, as Mermaid flowcharts
+    CfgMermaid,
+    /// dominator tree of every function, as Graphviz DOT
+    DomDot,
+    /// dominator tree of every function, as Mermaid flowcharts
+    DomMermaid,
+    /// call graph with static call counts and recursive functions clustered, as Graphviz DOT
+    CallgraphDot,
+    /// loop nest tree of every function (headers, latches, exits, depths), as Graphviz DOT
+    LoopsDot,
+    /// which variables of every function are live at the same time, as Graphviz DOT
+    InterferenceDot,
+    /// self-contained HTML report with the CFG, dataflow facts, SSA form and optimization diff
+    Html,
+}
+
+// #[derive(ValueEnum, Clone, Debug, PartialEq, Eq)]
+// enum DataflowAnalysis {
+//     /// set of variables that are initialized by the end of each basic block
+//     InitializedVariables,
+
+//     /// set of variables that are referenced at some point in the future
+//     LiveVariables,
+// }
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    after_help = "Without a subcommand, rust_bril optimizes, e.g. `rust_bril prog.bril --dce`"
+)]
+pub struct Cli {
+    /// Set the log level (trace, debug, info, warn, error, off) [default: info]
+    #[arg(long, value_enum, global = true)]
+    pub log_level: Option<LogLevel>,
+
+    /// Read defaults from this file instead of .rust_bril.toml in the working or home directory
+    #[arg(long, value_name = "FILE", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Also write logs to this file, rolled over at 10 MiB with 3 backups (FILE.1 to FILE.3)
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+
+    /// Per-module log level, e.g. dataflow=trace or phi_nodes=debug. Repeatable or comma
+    /// separated. With --log-file it only applies to the file
+    #[arg(long, value_delimiter = ',', global = true)]
+    pub log_filter: Vec<LogFilter>,
+
+    /// Print how long parsing, SSA construction, each pass and emission took, per function, to
+    /// stderr, with the peak instruction count of each function during each pass
+    #[arg(long, action, global = true)]
+    pub time_passes: bool,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run passes over a program and print the result (the default)
+    Optimize(OptimizeArgs),
+    /// Print liveness, reaching definitions, dominance and loop facts as JSON, keyed by function
+    /// and block label
+    Analyze(AnalyzeArgs),
+    /// Run a program with a Bril interpreter, after the selected passes
+    Interpret(InterpretArgs),
+    /// Draw control flow graphs, dominator trees, the call graph or loop nests, or write an HTML
+    /// report
+    Viz(VizArgs),
+    /// Check that a program is well formed, converts to SSA form and back, and survives the
+    /// selected passes. Exits with 0 if it is clean, 1 if there are warnings and 2 if there are
+    /// errors
+    Verify(VerifyArgs),
+    /// Print a program as canonically formatted Bril text, or check or rewrite .bril files in place
+    Fmt(FmtArgs),
+    /// Generate random well-typed programs and check that the selected passes keep their output
+    Fuzz(FuzzArgs),
+    /// Shrink a program the selected passes miscompile to a small program they still miscompile
+    #[command(mut_arg("files", |arg| arg.help(
+        "Program to shrink (if omitted or '-', read from stdin). Files ending in .bril are \
+         parsed from the Bril text format"
+    )))]
+    Reduce(ReduceArgs),
+    /// Run benchmarks as written and after several pipelines, check that every pipeline keeps
+    /// their output and compare their dynamic instruction counts, like brench. Without files it
+    /// runs benchmarks/**/*.bril
+    Bench(BenchArgs),
+    /// Run a program as written with several argument lists and list the instructions and blocks
+    /// no run reached, as an annotated listing and a JSON summary
+    Coverage(CoverageArgs),
+    /// Add counters to a program that it prints as its last line of output, to profile it with
+    /// any Bril interpreter
+    Instrument(InstrumentArgs),
+    /// Turn the counts an instrumented program printed into a profile like `interpret
+    /// --write-profile` writes
+    ReadCounters(ReadCountersArgs),
+    /// Add up profiles of several runs, optionally weighted, into one profile
+    MergeProfiles(MergeProfilesArgs),
+}
+
+impl Command {
+    pub fn pipeline_mut(&mut self) -> Option<&mut PipelineArgs> {
+        match self {
+            Command::Optimize(args) => Some(&mut args.pipeline),
+            Command::Analyze(args) => Some(&mut args.pipeline),
+            Command::Interpret(args) => Some(&mut args.pipeline),
+            Command::Viz(args) => Some(&mut args.pipeline),
+            Command::Verify(args) => Some(&mut args.pipeline),
+            Command::Fuzz(args) => Some(&mut args.pipeline),
+            Command::Reduce(args) => Some(&mut args.pipeline),
+            Command::Fmt(_)
+            | Command::Bench(_)
+            | Command::Coverage(_)
+            | Command::Instrument(_)
+            | Command::ReadCounters(_)
+            | Command::MergeProfiles(_) => None,
+        }
+    }
+}
+
+/// Where the program comes from and how to read it
+#[derive(Args, Debug)]
+pub struct InputArgs {
+    /// Input file (if omitted or '-', read from stdin). Files ending in .bril are parsed from the Bril text format.
+    /// `optimize` takes several files or glob patterns such as 'benchmarks/**/*.bril'
+    #[arg(value_name = "FILE")]
+    pub files: Vec<String>,
+
+    /// Format of the input program (defaults to the file extension, or json for stdin)
+    #[arg(long, value_enum)]
+    pub input_format: Option<InputFormat>,
+
+    /// Parse .bril files with the external bril2json tool instead of the built-in parser
+    #[arg(long, action)]
+    pub bril2json: bool,
+
+    /// JSON file mapping function names to attributes (pure, cold, inline(always), inline(never))
+    #[arg(long)]
+    pub attributes: Option<String>,
+}
+
+/// Which passes to run and what to report about them
+#[derive(Args, Debug)]
+pub struct PipelineArgs {
+    /// Optimization preset: 0 runs nothing, 1 constant folding and dead code elimination, 2
+    /// dominator-based value numbering and loop invariant code motion, 3 adds inlining and loop
+    /// unrolling; each ends with CFG simplification
+    #[arg(short = 'O', value_parser = clap::value_parser!(u8).range(0..=3))]
+    pub opt_level: Option<u8>,
+
+    /// Comma separated passes to run, in order (e.g. licm,lvn,dce,dce). Passes may repeat
+    #[arg(long, value_delimiter = ',', value_parser = parse_pass_name)]
+    pub passes: Vec<String>,
+
+    /// Run dead code elimination, same as --passes dce
+    #[arg(long, action)]
+    pub dce: bool,
+
+    /// Run local value numbering, same as --passes lvn
+    #[arg(long, action)]
+    pub lvn: bool,
+
+    /// Run loop invariant code motion, same as --passes licm
+    #[arg(long, action)]
+    pub loops: bool,
+
+    /// Copies of the body per iteration for the loops `unroll` does not unroll completely
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub unroll_factor: Option<u64>,
+
+    /// Block and edge counts from `interpret --write-profile` or `read-counters`, attached to the
+    /// blocks of every function for the passes that weigh code by how often it runs (`inline`)
+    #[arg(long, value_name = "FILE")]
+    pub profile_in: Option<String>,
+
+    /// Where SSA construction places phi nodes. Minimal and semi-pruned skip the liveness analysis
+    /// of pruned SSA, which can be slow on huge functions, and leave more phi nodes
+    #[arg(long, value_enum, default_value = "pruned")]
+    pub ssa: PhiPlacement,
+
+    /// Rerun the pipeline over each function until a round removes no instruction, at most
+    /// MAX_ROUNDS times (10 by default), and log how many rounds it took
+    #[arg(long, value_name = "MAX_ROUNDS", num_args = 0..=1, require_equals = true)]
+    #[arg(default_missing_value = "10")]
+    #[arg(value_parser = clap::value_parser!(u64).range(1..))]
+    pub fixpoint: Option<u64>,
+
+    /// Dump functions as Bril text (in SSA form) after every pass
+    #[arg(long, action)]
+    pub print_after_all: bool,
+
+    /// Dump functions as Bril text (in SSA form) after the given passes, comma separated
+    #[arg(long, value_delimiter = ',', value_parser = parse_pass_name)]
+    pub print_after: Vec<String>,
+
+    /// Only dump these functions with --print-after, comma separated names without the @
+    #[arg(long, value_delimiter = ',')]
+    pub print_function: Vec<String>,
+
+    /// Write --print-after dumps to one file per pass and function in this directory, instead
+    /// of stderr
+    #[arg(long)]
+    pub print_dir: Option<String>,
+
+    /// Only optimize, analyze or draw these functions, comma separated names without the @. The
+    /// other functions are written out as they were read
+    #[arg(long = "function", value_name = "NAME", value_delimiter = ',')]
+    pub functions: Vec<String>,
+
+    /// Only apply the first N passes of the pipeline to each function and list every pass
+    /// application on stderr. Bisect N to find the pass that breaks a program
+    #[arg(long, value_name = "N")]
+    pub opt_bisect_limit: Option<usize>,
+
+    /// Check the CFG, SSA form and types of every function after each pass and stop at the
+    /// first pass that breaks them (on by default in debug builds)
+    #[arg(long, action, overrides_with = "no_verify")]
+    pub verify: bool,
+
+    /// Don't run the IR verifiers between passes, even in a debug build
+    #[arg(long, action, overrides_with = "verify")]
+    pub no_verify: bool,
+
+    /// When a pass panics or fails --verify, write the program as it was before that pass and a
+    /// crash.json report into a new directory under DIR
+    #[arg(long, value_name = "DIR")]
+    pub crash_dir: Option<PathBuf>,
+
+    /// Print what each pass changed (instructions removed, expressions folded, code hoisted, ...)
+    /// to stderr once the pipeline finishes
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "table")]
+    pub stats: Option<StatsFormat>,
+
+    /// Write instruction, block and phi counts before and after the pipeline, and the --stats
+    /// counters, per function to FILE: CSV if it ends in .csv, JSON otherwise
+    #[arg(long, value_name = "FILE")]
+    pub stats_out: Option<PathBuf>,
+
+    /// The pipeline built from --passes and the pass flags
+    #[arg(skip)]
+    pub pipeline: Vec<String>,
+}
+
+/// Where and how to write the program
+#[derive(Args, Debug)]
+pub struct OutputArgs {
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Format of the output program, for both --output and stdout (defaults to the --output
+    /// extension, or json)
+    #[arg(long, value_enum, alias = "format")]
+    pub output_format: Option<OutputFormat>,
+
+    /// Pretty-print JSON output (the default for --output files)
+    #[arg(long, action, conflicts_with = "compact")]
+    pub pretty: bool,
+
+    /// Print JSON output on a single line (the default for stdout)
+    #[arg(long, action)]
+    pub compact: bool,
+
+    /// Don't push out of SSA form
+    #[arg(short = 'S', action)]
+    pub show_ssa: bool,
+
+    /// Print Bril text with the external bril2txt tool instead of the built-in printer
+    #[arg(long, action)]
+    pub bril2txt: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct OptimizeArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Read newline-delimited JSON programs and write one optimized program (or
+    /// {"error": ...}) per line, until the input ends
+    #[arg(long, action, conflicts_with = "input_format")]
+    pub ndjson: bool,
+
+    /// Optimize every input into this directory, mirroring the input directory layout, and
+    /// print a summary of successes and failures. Required for more than one input
+    #[arg(long, conflicts_with_all = ["output", "ndjson"])]
+    pub out_dir: Option<String>,
+
+    /// Number of files of a batch to optimize in parallel (defaults to the number of CPUs)
+    #[arg(short, long, requires = "out_dir")]
+    pub jobs: Option<usize>,
+
+    /// Skip SSA
+    #[arg(short = 's', action)]
+    pub skip_pass: bool,
+
+    /// Print a per-function diff of the Bril text before and after the pipeline instead of the
+    /// program (which still goes to --output if given). Colored on a terminal
+    #[arg(long, action, conflicts_with_all = ["ndjson", "out_dir"])]
+    pub diff: bool,
+
+    /// Run the pipeline over every input and print the --stats table and how many lines of each
+    /// function would change, without writing any program
+    #[arg(long, action, conflicts_with_all = ["ndjson", "diff", "skip_pass"])]
+    pub dry_run: bool,
+
+    /// Run the program before and after the pipeline with the built-in interpreter and fail if
+    /// their output or success differs
+    #[arg(long, action, conflicts_with_all = ["ndjson", "out_dir", "dry_run", "skip_pass"])]
+    pub verify_exec: bool,
+
+    /// Arguments of @main for --verify-exec, as one space-separated list per use. Defaults to the
+    /// `# ARGS:` comment of the input
+    #[arg(long, value_name = "ARGS", requires = "verify_exec")]
+    pub exec_args: Vec<String>,
+
+    /// Only check the program, like `rust_bril verify`: exit with 0 if it is clean, 1 if there
+    /// are warnings and 2 if there are errors
+    #[arg(long, action, conflicts_with_all = ["ndjson", "out_dir", "diff", "dry_run", "skip_pass"])]
+    pub check: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct AnalyzeArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct InterpretArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+
+    /// External interpreter to run instead of the built-in one, it reads Bril JSON from stdin
+    #[arg(long)]
+    pub interpreter: Option<String>,
+
+    /// Run the program as it was read, without the passes and the round trip through SSA form
+    #[arg(short = 's', action)]
+    pub skip_pass: bool,
+
+    /// Report the number of executed instructions on stderr (brili -p)
+    #[arg(short, long, action)]
+    pub profile: bool,
+
+    /// Also report how often each opcode ran, most frequent first
+    #[arg(long, action, conflicts_with = "interpreter")]
+    pub profile_ops: bool,
+
+    /// Also report how often each block was entered and each edge between blocks taken
+    #[arg(long, action, conflicts_with = "interpreter")]
+    pub profile_blocks: bool,
+
+    /// Save the block and edge counts to this file, for profile-guided passes
+    #[arg(long, conflicts_with = "interpreter")]
+    pub write_profile: Option<String>,
+
+    /// Write every executed instruction to this file, indented by call depth
+    #[arg(long, conflicts_with = "interpreter")]
+    pub trace: Option<String>,
+
+    /// Report memory errors with the instructions that allocated and freed the memory, and list
+    /// every allocation that is not freed at exit
+    #[arg(long, action, conflicts_with = "interpreter")]
+    pub memcheck: bool,
+
+    /// Free memory once nothing points to it and allow allocations that are never freed, for
+    /// benchmarks that do not free what they allocate
+    #[arg(long, action, conflicts_with_all = ["interpreter", "memcheck"])]
+    pub gc: bool,
+
+    /// Stop with an error after this many instructions
+    #[arg(long, conflicts_with = "interpreter")]
+    pub fuel: Option<u64>,
+
+    /// Stop with an error after this many seconds
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, conflicts_with = "interpreter")]
+    pub timeout: Option<Duration>,
+
+    /// Arguments of @main, after a `--`
+    #[arg(last = true)]
+    pub args: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct VizArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+
+    /// What to draw
+    #[arg(long, value_enum, default_value = "cfg-dot")]
+    pub emit: Emit,
+
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+
+    /// Also warn about divisions by values that may be zero and integer arithmetic that may
+    /// overflow, with the ranges the interval analysis derives
+    #[arg(long, action)]
+    pub arithmetic: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct FmtArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[arg(short, long, conflicts_with_all = ["check", "write"])]
+    pub output: Option<String>,
+
+    /// Don't print anything, show a diff of every .bril file that is not formatted and exit with
+    /// 1 if there is one
+    #[arg(long, action)]
+    pub check: bool,
+
+    /// Rewrite .bril files in place. Needed to format more than one file
+    #[arg(short, long, action, conflicts_with = "check")]
+    pub write: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct FuzzArgs {
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+
+    /// Seed of the first program, the others use the following seeds
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Number of programs
+    #[arg(long, default_value_t = 100)]
+    pub count: u64,
+
+    /// Functions besides @main
+    #[arg(long, default_value_t = 2)]
+    pub helpers: usize,
+
+    /// Print the generated programs instead of testing them
+    #[arg(long, action)]
+    pub print: bool,
+
+    /// Write every program the pipeline breaks to DIR/fuzz-<seed>.bril
+    #[arg(long, value_name = "DIR")]
+    pub out_dir: Option<PathBuf>,
+
+    /// Shrink the programs the pipeline breaks before writing them, like `rust_bril reduce`
+    #[arg(long, action, requires = "out_dir")]
+    pub reduce: bool,
+
+    /// Generate no loops, only straight-line code and branches
+    #[arg(long, action)]
+    pub no_loops: bool,
+
+    /// Generate no alloc, load, store or free
+    #[arg(long, action)]
+    pub no_memory: bool,
+
+    /// Generate no calls between functions
+    #[arg(long, action)]
+    pub no_calls: bool,
+
+    /// Generate no float variables or float arithmetic
+    #[arg(long, action)]
+    pub no_floats: bool,
+
+    /// Instructions a run may execute before it counts as a timeout
+    #[arg(long, default_value_t = 10_000_000)]
+    pub fuel: u64,
+
+    /// Seconds a run may take before it counts as a timeout
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub timeout: Option<Duration>,
+}
+
+fn parse_seconds(text: &str) -> Result<Duration, String> {
+    let seconds: f64 = text.parse().map_err(|e| format!("{}", e))?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("{}", e))
+}
+
+#[derive(Args, Debug)]
+pub struct ReduceArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub pipeline: PipelineArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Arguments of @main to run with, as one string per run. Defaults to the `# ARGS:` comment
+    /// of the input
+    #[arg(long, value_name = "ARGS")]
+    pub exec_args: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct CoverageArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Arguments of @main to run with, as one string per run. Defaults to the `# ARGS:` comment
+    /// of the input
+    #[arg(long, value_name = "ARGS")]
+    pub exec_args: Vec<String>,
+
+    /// Write the annotated listing here instead of to stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Write a JSON summary of what was covered to this file
+    #[arg(long, value_name = "FILE")]
+    pub json: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct InstrumentArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Write what each printed count means to this file, for `read-counters`
+    #[arg(long, value_name = "FILE")]
+    pub map: String,
+
+    /// Count calls of every function instead of executions of every block
+    #[arg(long, action)]
+    pub functions: bool,
+
+    /// Count every edge between blocks too, splitting critical edges
+    #[arg(long, action, conflicts_with = "functions")]
+    pub edges: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ReadCountersArgs {
+    /// The map `instrument --map` wrote
+    #[arg(long, value_name = "FILE")]
+    pub map: String,
+
+    /// Output of the instrumented program (defaults to stdin)
+    pub file: Option<String>,
+
+    /// Write the profile here instead of to stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct MergeProfilesArgs {
+    /// Profiles written by `interpret --write-profile` or `read-counters`
+    #[arg(required = true)]
+    pub files: Vec<String>,
+
+    /// How much each profile counts, in the order of the files (defaults to 1)
+    #[arg(long, value_name = "N")]
+    pub weight: Vec<u64>,
+
+    /// Write the merged profile here instead of to stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// A pipeline to compare with the baseline, as NAME=PASSES where PASSES are comma separated
+    /// pass names or presets O0 to O3 (e.g. `--run o2=O2 --run lvn=lvn,dce`). Repeat it for more
+    /// pipelines; without it the presets O1 and O2 run
+    #[arg(long, value_name = "NAME=PASSES", value_parser = parse_bench_run)]
+    pub run: Vec<(String, Vec<String>)>,
+
+    /// Write a CSV file in brench's format (benchmark,run,result) instead of a table
+    #[arg(long, action)]
+    pub csv: bool,
+
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Instructions a run may execute before it counts as a timeout
+    #[arg(long, default_value_t = 1_000_000_000)]
+    pub fuel: u64,
+
+    /// Number of benchmarks to run in parallel (defaults to the number of CPUs)
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+}
+
+impl From<InputFormat> for ProgramFormat {
+    fn from(input_format: InputFormat) -> Self {
+        match input_format {
+            InputFormat::Json => ProgramFormat::Json,
+            InputFormat::Bril => ProgramFormat::Bril,
+        }
+    }
+}
+
+impl From<Emit> for Artifact {
+    fn from(emit: Emit) -> Self {
+        match emit {
+            Emit::CfgDot => Artifact::CfgDot,
+            Emit::CfgMermaid => Artifact::CfgMermaid,
+            Emit::DomDot => Artifact::DomDot,
+            Emit::DomMermaid => Artifact::DomMermaid,
+            Emit::CallgraphDot => Artifact::CallgraphDot,
+            Emit::LoopsDot => Artifact::LoopsDot,
+            Emit::InterferenceDot => Artifact::InterferenceDot,
+            Emit::Html => Artifact::Html,
+        }
+    }
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(log_level: LogLevel) -> Self {
+        match log_level {
+            LogLevel::Trace => LevelFilter::Trace,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Off => LevelFilter::Off,
+        }
+    }
+}
+
+/// `NAME=PASSES` of `bench --run`, with the presets in PASSES expanded
+pub(super) fn parse_bench_run(run: &str) -> Result<(String, Vec<String>), String> {
+    let (name, passes) = run.split_once('=').ok_or("expected NAME=PASSES")?;
+    let mut names = Vec::new();
+    for pass in passes.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match pass
+            .strip_prefix('O')
+            .and_then(|level| level.parse::<u8>().ok())
+        {
+            Some(level @ 0..=3) => names.extend(preset_passes(level).iter().map(|p| p.to_string())),
+            _ => names.push(parse_pass_name(pass)?),
+        }
+    }
+    Ok((name.to_string(), names))
+}
+
+fn parse_pass_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    match pass_by_name(name) {
+        Some(pass) => Ok(pass.name().to_string()),
+        None => Err(format!("expected one of {}", builtin_pass_names())),
+    }
+}
+
+impl PipelineArgs {
+    /// the passes selected on the command line, in pipeline order
+    pub fn manager(&self) -> PassManager {
+        let mut manager = PassManager::from_names(self.pipeline.iter().map(String::as_str))
+            .expect("pass names are checked while parsing arguments");
+        if self.print_after_all || !self.print_after.is_empty() {
+            manager.print_after(PrintAfter {
+                all: self.print_after_all,
+                passes: self.print_after.clone(),
+                functions: self.print_function.clone(),
+                directory: self.print_dir.as_ref().map(PathBuf::from),
+            });
+        }
+        if let Some(rounds) = self.fixpoint {
+            manager.fixpoint(rounds as usize);
+        }
+        if let Some(limit) = self.opt_bisect_limit {
+            manager.bisect_limit(limit);
+        }
+        if let Some(factor) = self.unroll_factor {
+            manager.unroll_factor(factor as usize);
+        }
+        if let Some(path) = &self.profile_in {
+            match ProfileData::load(path) {
+                Ok(profile) => {
+                    manager.profile(profile);
+                }
+                Err(e) => {
+                    log::error!("Failed to load the profile from '{}': {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        manager.ssa_form(match self.ssa {
+            PhiPlacement::Minimal => SsaForm::Minimal,
+            PhiPlacement::SemiPruned => SsaForm::SemiPruned,
+            PhiPlacement::Pruned => SsaForm::Pruned,
+        });
+        if self.verify || self.no_verify {
+            manager.verify(self.verify);
+        }
+        if let Some(directory) = &self.crash_dir {
+            manager.crash_dir(directory);
+        }
+        if !self.functions.is_empty() {
+            manager.only_functions(self.functions.clone());
+        }
+        manager
+    }
+}
+
+impl OutputArgs {
+    pub fn keeps_ssa(&self) -> bool {
+        self.show_ssa || self.output_format == Some(OutputFormat::SsaJson)
+    }
+}
@@ -0,0 +1,273 @@
+use rayon::prelude::*;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use super::{optimize_in_ssa, print_stats, restore_unselected, OptimizeArgs, OutputFormat};
+use crate::{
+    backend::lower_to_riscv,
+    optimizations::PassManager,
+    representation::{BrilFrontend, ProgramError, ProgramFormat, RichProgram},
+    timing::Timer,
+};
+
+/// one line on stderr, rewritten as the files of a batch finish. Only shown on a terminal
+pub(super) struct Progress {
+    total: usize,
+    done: AtomicUsize,
+    visible: bool,
+}
+
+impl Progress {
+    pub(super) fn new(total: usize) -> Self {
+        Self {
+            total,
+            done: AtomicUsize::new(0),
+            visible: std::io::stderr().is_terminal(),
+        }
+    }
+
+    pub(super) fn finished(&self, input: &Path, elapsed: Duration) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if !self.visible {
+            return;
+        }
+        let width = 30;
+        let filled = width * done / self.total;
+        eprint!(
+            "\r\x1b[2K[{}{}] {}/{} {} ({:.1?})",
+            "#".repeat(filled),
+            " ".repeat(width - filled),
+            done,
+            self.total,
+            input.display(),
+            elapsed
+        );
+        if done == self.total {
+            eprintln!();
+        }
+    }
+}
+
+/// optimize every input into `out_dir` in parallel, reporting failures at the end instead of
+/// stopping
+pub(super) fn run_batch(inputs: &[PathBuf], out_dir: &Path, args: &OptimizeArgs) {
+    let root = common_ancestor(inputs);
+    let pipeline = args.pipeline.manager();
+    let progress = Progress::new(inputs.len());
+    let threads = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.unwrap_or(0))
+        .build();
+    let pool = match threads {
+        Ok(pool) => pool,
+        Err(e) => {
+            log::error!("Failed to start worker threads: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let results: Vec<Result<(), String>> = pool.install(|| {
+        inputs
+            .par_iter()
+            .map(|input| {
+                let relative = input.strip_prefix(&root).unwrap_or(input);
+                let output = out_dir.join(relative);
+                let start = Instant::now();
+                let result = optimize_file(input, &output, &pipeline, args);
+                let elapsed = start.elapsed();
+                match &result {
+                    Ok(()) => log::info!(
+                        "{} -> {} ({:.1?})",
+                        input.display(),
+                        output.display(),
+                        elapsed
+                    ),
+                    Err(_) => log::error!("{} failed ({:.1?})", input.display(), elapsed),
+                }
+                progress.finished(input, elapsed);
+                result
+            })
+            .collect()
+    });
+    let failures: Vec<(&PathBuf, String)> = inputs
+        .iter()
+        .zip(results)
+        .filter_map(|(input, result)| result.err().map(|e| (input, e)))
+        .collect();
+
+    print_stats(&pipeline, &args.pipeline);
+    for (input, e) in &failures {
+        eprintln!("error: {}\n{}", input.display(), e.trim_end());
+    }
+    eprintln!(
+        "{} succeeded, {} failed, outputs in {}",
+        inputs.len() - failures.len(),
+        failures.len(),
+        out_dir.display()
+    );
+    if !failures.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// optimize one file of a batch and write it to `output`, in the input's format unless
+/// --output-format says otherwise
+fn optimize_file(
+    input: &Path,
+    output: &Path,
+    pipeline: &PassManager,
+    args: &OptimizeArgs,
+) -> Result<(), String> {
+    let mut program = load_file(input, args)?;
+    if !args.skip_pass {
+        let selected = &args.pipeline.functions;
+        let original = (!selected.is_empty()).then(|| program.program.clone());
+        let abstract_program = optimize_in_ssa(program, pipeline)?;
+        let _timer = Timer::new("out of ssa");
+        program = if args.output.keeps_ssa() {
+            abstract_program.into_ssa_program()
+        } else {
+            abstract_program.into_program()
+        };
+        if let Some(original) = original {
+            restore_unselected(&mut program.program, &original, selected);
+        }
+    }
+
+    let _timer = Timer::new("emission");
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let format = match args.output.output_format {
+        Some(OutputFormat::Bril) => ProgramFormat::Bril,
+        Some(OutputFormat::Json | OutputFormat::SsaJson) => ProgramFormat::Json,
+        Some(OutputFormat::Riscv) => {
+            let assembly = lower_to_riscv(&program.program).map_err(|e| e.to_string())?;
+            return std::fs::write(output, assembly)
+                .map_err(|e| format!("cannot write {}: {}", output.display(), e));
+        }
+        None if output.extension().and_then(|e| e.to_str()) == Some("bril") => ProgramFormat::Bril,
+        None => ProgramFormat::Json,
+    };
+    program
+        .to_file_as(output, format, !args.output.compact)
+        .map_err(|e| format!("cannot write {}: {}", output.display(), e))
+}
+
+/// read one input of a batch with the --attributes file merged in
+pub(super) fn load_file(input: &Path, args: &OptimizeArgs) -> Result<RichProgram, String> {
+    let frontend = args.input.frontend();
+    let mut program = {
+        let _timer = Timer::new("parse");
+        match args.input.input_format {
+            None => RichProgram::from_file_with(input, frontend.clone()),
+            Some(format) => std::fs::read_to_string(input)
+                .map_err(ProgramError::from)
+                .and_then(|content| RichProgram::from_text(&content, format.into(), frontend)),
+        }
+        .map_err(|e| e.to_string())?
+    };
+    if let Some(attributes) = &args.input.attributes {
+        program
+            .apply_attributes_file(Path::new(attributes))
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(program)
+}
+
+/// deepest directory containing every path
+fn common_ancestor(paths: &[PathBuf]) -> PathBuf {
+    let mut ancestor = match paths.first().and_then(|p| p.parent()) {
+        Some(parent) => parent.to_path_buf(),
+        None => return PathBuf::new(),
+    };
+    for path in paths {
+        while !path.starts_with(&ancestor) {
+            if !ancestor.pop() {
+                return PathBuf::new();
+            }
+        }
+    }
+    ancestor
+}
+
+/// optimize newline-delimited JSON programs one at a time, so a single process can serve a whole
+/// batch. A program that fails produces an error line instead of stopping the stream
+pub(super) fn run_ndjson(args: &OptimizeArgs) {
+    let input: Box<dyn BufRead> = match args.input.source() {
+        Some(file) => match File::open(file) {
+            Ok(f) => Box::new(BufReader::new(f)),
+            Err(e) => {
+                log::error!("Failed to open '{}': {}", file, e);
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(std::io::stdin().lock()),
+    };
+    let mut output: Box<dyn Write> = match &args.output.output {
+        Some(file) => match File::create(file) {
+            Ok(f) => Box::new(BufWriter::new(f)),
+            Err(e) => {
+                log::error!("Failed to create '{}': {}", file, e);
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let pipeline = args.pipeline.manager();
+
+    for (i, line) in input.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("Failed to read line {}: {}", i + 1, e);
+                std::process::exit(1);
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match optimize_program(&line, args, &pipeline) {
+            Ok(program) => program.to_string(),
+            Err(e) => {
+                log::warn!("program on line {} failed: {}", i + 1, e);
+                serde_json::json!({ "error": e }).to_string()
+            }
+        };
+        if let Err(e) = writeln!(output, "{}", response).and_then(|_| output.flush()) {
+            log::error!("Failed to write output: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// run the selected passes on one JSON program, reporting errors instead of exiting
+fn optimize_program(
+    json: &str,
+    args: &OptimizeArgs,
+    pipeline: &PassManager,
+) -> Result<RichProgram, String> {
+    let mut program = RichProgram::from_text(json, ProgramFormat::Json, BrilFrontend::Native)
+        .map_err(|e| e.to_string())?;
+    if let Some(attributes) = &args.input.attributes {
+        program
+            .apply_attributes_file(Path::new(attributes))
+            .map_err(|e| e.to_string())?;
+    }
+    if args.skip_pass {
+        return Ok(program);
+    }
+
+    let abstract_program = optimize_in_ssa(program, pipeline)?;
+
+    Ok(if args.output.keeps_ssa() {
+        abstract_program.into_ssa_program()
+    } else {
+        abstract_program.into_program()
+    })
+}
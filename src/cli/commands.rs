@@ -0,0 +1,513 @@
+use clap::{error::ErrorKind, CommandFactory};
+use rayon::prelude::*;
+use similar::TextDiff;
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+    process::{Command as Process, Stdio},
+    time::Instant,
+};
+
+use super::{
+    batch::Progress,
+    emit::{emit, write_artifact},
+    exec_inputs,
+    input::expand_paths,
+    optimize_in_ssa, parse_bench_run, print_stats, retain_selected, run_passes, AnalyzeArgs,
+    BenchArgs, Cli, CoverageArgs, Emit, FmtArgs, FuzzArgs, InputFormat, InstrumentArgs,
+    InterpretArgs, MergeProfilesArgs, OutputArgs, OutputFormat, ReadCountersArgs, ReduceArgs,
+    VizArgs,
+};
+use crate::{
+    interpreter::{BlockProfile, Coverage, Interpreter},
+    optimizations::{self, CounterError, CounterMap, Granularity, PassManager},
+    representation::{
+        format_program, format_source, BrilFrontend, Program, ProgramFormat, RichAbstractProgram,
+        RichProgram,
+    },
+    testing::{self, differential_test_with, Bench, FuzzConfig, Fuzzer, Limits, Miscompilation},
+    timing::Timer,
+    visualization::Artifact,
+};
+
+pub fn analyze(args: &AnalyzeArgs) {
+    let mut abstract_program = run_passes(&args.pipeline, args.input.load());
+    retain_selected(&mut abstract_program, &args.pipeline.functions);
+    write_artifact(
+        Artifact::AnalysisJson,
+        None,
+        &abstract_program,
+        args.output.as_deref(),
+    );
+}
+
+pub fn viz(args: &VizArgs) {
+    let rich_program = args.input.load();
+    // the HTML report diffs against the program as it was loaded
+    let mut original_program = (args.emit == Emit::Html).then(|| rich_program.clone());
+    let mut abstract_program = run_passes(&args.pipeline, rich_program);
+    retain_selected(&mut abstract_program, &args.pipeline.functions);
+    if let (Some(original), false) = (&mut original_program, args.pipeline.functions.is_empty()) {
+        let selected = &args.pipeline.functions;
+        original
+            .program
+            .functions
+            .retain(|f| selected.contains(&f.name));
+    }
+    write_artifact(
+        args.emit.into(),
+        original_program.as_ref(),
+        &abstract_program,
+        args.output.as_deref(),
+    );
+}
+
+/// optimize the program and pipe it, as JSON, into the interpreter
+pub fn interpret(args: &InterpretArgs) {
+    let program = match args.skip_pass {
+        true => args.input.load(),
+        false => {
+            let abstract_program = run_passes(&args.pipeline, args.input.load());
+            let _timer = Timer::new("out of ssa");
+            abstract_program.into_program()
+        }
+    };
+    let Some(interpreter) = &args.interpreter else {
+        run_builtin(&program.program, args);
+        return;
+    };
+    let json = match program.render(ProgramFormat::Json, false) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Failed to serialize program: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut command = Process::new(interpreter);
+    if args.profile {
+        command.arg("-p");
+    }
+    command.args(&args.args).stdin(Stdio::piped());
+    let status = command.spawn().and_then(|mut child| {
+        // dropping stdin closes it, so the interpreter sees the end of the program
+        child.stdin.take().unwrap().write_all(json.as_bytes())?;
+        child.wait()
+    });
+    match status {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(e) => {
+            log::error!("Failed to run '{}': {}", interpreter, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// run the program with the built-in interpreter, exiting with 2 if it fails like brili does
+fn run_builtin(program: &Program, args: &InterpretArgs) {
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut trace = match args.trace.as_deref().map(File::create).transpose() {
+        Ok(file) => file.map(BufWriter::new),
+        Err(e) => {
+            log::error!("Failed to create the trace file: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let execution = {
+        let _timer = Timer::new("interpret");
+        Interpreter::new(program)
+            .memcheck(args.memcheck)
+            .gc(args.gc)
+            .fuel(args.fuel)
+            .timeout(args.timeout)
+            .block_counts(args.profile_blocks || args.write_profile.is_some())
+            .trace(trace.as_mut().map(|t| t as &mut dyn Write))
+            .run(&args.args, &mut out)
+    };
+    drop(out);
+    match execution {
+        Ok(execution) => {
+            if args.profile || args.profile_ops {
+                eprintln!("{}", execution.profile.summary());
+            }
+            if args.profile_ops {
+                eprint!("{}", execution.profile.to_table());
+            }
+            if let (Some(blocks), true) = (&execution.blocks, args.profile_blocks) {
+                eprint!("{}", blocks.to_table());
+            }
+            if let (Some(blocks), Some(path)) = (&execution.blocks, &args.write_profile) {
+                if let Err(e) = std::fs::write(path, blocks.to_json()) {
+                    log::error!("Failed to write the profile to '{}': {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
+pub fn fuzz(args: &FuzzArgs) {
+    let config = FuzzConfig {
+        functions: args.helpers,
+        loops: !args.no_loops,
+        memory: !args.no_memory,
+        calls: !args.no_calls,
+        floats: !args.no_floats,
+        ..FuzzConfig::default()
+    };
+    let mut pipeline = args.pipeline.manager();
+    let limits = Limits {
+        fuel: Some(args.fuel),
+        timeout: args.timeout,
+    };
+    let mut failed = 0;
+    let mut timeouts = 0;
+    for seed in args.seed..args.seed.saturating_add(args.count) {
+        let text = Fuzzer::new(seed, config.clone()).text();
+        if args.print {
+            println!("# seed {}\n{}", seed, text);
+            continue;
+        }
+        let program = RichProgram::from_text(&text, ProgramFormat::Bril, BrilFrontend::Native)
+            .expect("generated programs parse");
+        pipeline.fuzz_seed(seed);
+        let original = program.program.clone();
+        let result = optimize_in_ssa(program, &pipeline)
+            .map(RichAbstractProgram::into_program)
+            .and_then(|optimized| {
+                differential_test_with(&original, &optimized.program, &[Vec::new()], limits)
+                    .map_err(|divergence| divergence.to_string())
+            });
+        let e = match result {
+            Ok(runs) => {
+                if runs.iter().any(|run| run.timed_out) {
+                    log::warn!("seed {} times out", seed);
+                    timeouts += 1;
+                }
+                continue;
+            }
+            Err(e) => e,
+        };
+        failed += 1;
+        eprintln!("seed {}: {}", seed, e.trim_end());
+        if let Some(dir) = &args.out_dir {
+            let path = dir.join(format!("fuzz-{}.bril", seed));
+            let text = match args.reduce {
+                true => {
+                    let failure = Miscompilation::find(&original, &pipeline, &[Vec::new()])
+                        .expect("the failure reproduces");
+                    format_program(&testing::reduce(&original, |p| failure.reproduces(p)))
+                }
+                false => text,
+            };
+            if let Err(e) = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&path, &text))
+            {
+                log::error!("cannot write {}: {}", path.display(), e);
+            }
+        }
+    }
+    if args.print {
+        return;
+    }
+    print_stats(&pipeline, &args.pipeline);
+    println!(
+        "{} programs, {} failed, {} timed out",
+        args.count, failed, timeouts
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+pub fn reduce(args: &ReduceArgs) {
+    let program = args.input.load();
+    let inputs = exec_inputs(&args.exec_args, &program);
+    let manager = args.pipeline.manager();
+    let Some(failure) = Miscompilation::find(&program.program, &manager, &inputs) else {
+        log::error!("the selected passes do not break this program");
+        std::process::exit(1);
+    };
+    log::info!("reducing a {:?} failure", failure.failure);
+    let reduced = testing::reduce(&program.program, |p| failure.reproduces(p));
+    let reduced = RichProgram {
+        original_text: Vec::new(),
+        program: reduced,
+    };
+    emit(reduced, &args.output);
+}
+
+pub fn coverage(args: &CoverageArgs) {
+    let program = args.input.load();
+    let mut coverage = Coverage::new(&program.program);
+    for inputs in exec_inputs(&args.exec_args, &program) {
+        let execution = Interpreter::new(&program.program).run(&inputs, &mut std::io::sink());
+        match execution {
+            Ok(execution) => coverage.add(&execution),
+            Err(e) => log::warn!("skipping the run with [{}]: {}", inputs.join(" "), e),
+        }
+    }
+    let summary = coverage.summary();
+    eprintln!("{}", summary);
+
+    let listing = coverage.annotate();
+    let written = match &args.output {
+        Some(path) => std::fs::write(path, listing).map_err(|e| (path.as_str(), e)),
+        None => {
+            print!("{}", listing);
+            Ok(())
+        }
+    };
+    let written = written.and_then(|_| match &args.json {
+        Some(path) => std::fs::write(path, summary.to_json()).map_err(|e| (path.as_str(), e)),
+        None => Ok(()),
+    });
+    if let Err((path, e)) = written {
+        log::error!("Failed to write '{}': {}", path, e);
+        std::process::exit(1);
+    }
+}
+
+pub fn instrument(args: &InstrumentArgs) {
+    let program = args.input.load();
+    let granularity = match (args.functions, args.edges) {
+        (true, _) => Granularity::Functions,
+        (_, true) => Granularity::Edges,
+        _ => Granularity::Blocks,
+    };
+    let instrumented = match optimizations::instrument(&program.program, granularity) {
+        Ok(instrumented) => instrumented,
+        Err(e) => {
+            log::error!("Failed to instrument the program: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = std::fs::write(&args.map, instrumented.map.to_json()) {
+        log::error!("Failed to write the counter map to '{}': {}", args.map, e);
+        std::process::exit(1);
+    }
+    let program = RichProgram {
+        original_text: Vec::new(),
+        program: instrumented.program,
+    };
+    emit(program, &args.output);
+}
+
+pub fn read_counters(args: &ReadCountersArgs) {
+    let mut output = String::new();
+    let read = match &args.file {
+        Some(path) => std::fs::read_to_string(path).map(|text| output = text),
+        None => std::io::stdin().read_to_string(&mut output).map(|_| ()),
+    };
+    let profile = read
+        .map_err(CounterError::from)
+        .and_then(|_| CounterMap::from_file(&args.map))
+        .and_then(|map| map.read_output(&output));
+    let profile = match profile {
+        Ok(profile) => profile.to_json(),
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    match &args.output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, profile) {
+                log::error!("Failed to write the profile to '{}': {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        None => println!("{}", profile),
+    }
+}
+
+pub fn merge_profiles(args: &MergeProfilesArgs) {
+    if args.weight.len() > args.files.len() {
+        log::error!(
+            "{} weights for {} profiles",
+            args.weight.len(),
+            args.files.len()
+        );
+        std::process::exit(2);
+    }
+    let mut merged = BlockProfile::default();
+    for (i, path) in args.files.iter().enumerate() {
+        match BlockProfile::from_file(path) {
+            Ok(profile) => merged.merge(&profile, args.weight.get(i).copied().unwrap_or(1)),
+            Err(e) => {
+                log::error!("Failed to read '{}': {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let profile = merged.to_json();
+    match &args.output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, profile) {
+                log::error!("Failed to write the profile to '{}': {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        None => println!("{}", profile),
+    }
+}
+
+/// what `bench` runs when it is given no files
+const DEFAULT_BENCHMARKS: &str = "benchmarks/**/*.bril";
+
+pub fn bench(args: &BenchArgs) {
+    let runs = match args.run.is_empty() {
+        true => ["O1=O1", "O2=O2"]
+            .map(|run| parse_bench_run(run).expect("presets are valid runs"))
+            .to_vec(),
+        false => args.run.clone(),
+    };
+    let mut bench = Bench::new().fuel(args.fuel).frontend(args.input.frontend());
+    for (name, passes) in runs {
+        let passes = PassManager::from_names(passes.iter().map(String::as_str))
+            .expect("pass names were checked when parsing --run");
+        bench = bench.run(name, passes);
+    }
+
+    let benchmarks = match args.input.files.is_empty() {
+        true => expand_paths(&[DEFAULT_BENCHMARKS.to_string()]),
+        false => args.input.paths(),
+    };
+    let progress = Progress::new(benchmarks.len());
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.unwrap_or(0))
+        .build()
+        .unwrap_or_else(|e| {
+            log::error!("Failed to start worker threads: {}", e);
+            std::process::exit(1);
+        });
+    let rows = pool.install(|| {
+        benchmarks
+            .par_iter()
+            .map(|path| {
+                let start = Instant::now();
+                let row = bench.measure_one(path);
+                progress.finished(path, start.elapsed());
+                row
+            })
+            .collect()
+    });
+    let report = bench.report(rows);
+
+    let text = match args.csv {
+        true => report.to_csv(),
+        false => report.to_table(),
+    };
+    match &args.output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, text) {
+                log::error!("Failed to write '{}': {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", text),
+    }
+    let incorrect = report.incorrect();
+    if incorrect > 0 {
+        eprintln!(
+            "{} runs printed something else than the baseline",
+            incorrect
+        );
+        std::process::exit(1);
+    }
+}
+
+pub fn fmt(args: &FmtArgs) {
+    if !args.check && !args.write {
+        fmt_to_output(args);
+        return;
+    }
+
+    let inputs = args.input.paths();
+    if inputs.is_empty() {
+        Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--check and --write need files",
+            )
+            .exit();
+    }
+    let mut failed = false;
+    for input in &inputs {
+        let formatted = std::fs::read_to_string(input)
+            .map_err(|e| e.to_string())
+            .and_then(|text| Ok((format_source(&text).map_err(|e| e.to_string())?, text)));
+        let (formatted, text) = match formatted {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("error: {}: {}", input.display(), e);
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == text {
+            continue;
+        }
+        if args.check {
+            let name = input.display().to_string();
+            let diff = TextDiff::from_lines(&text, &formatted);
+            print!("{}", diff.unified_diff().header(&name, &name));
+            failed = true;
+        } else if let Err(e) = std::fs::write(input, formatted) {
+            eprintln!("error: cannot write {}: {}", input.display(), e);
+            failed = true;
+        } else {
+            log::info!("formatted {}", input.display());
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// format one program to --output or stdout. Bril text keeps its comments, JSON is printed
+fn fmt_to_output(args: &FmtArgs) {
+    let is_bril = match (args.input.input_format, args.input.source()) {
+        (Some(format), _) => format == InputFormat::Bril,
+        (None, Some(file)) => Path::new(file).extension().is_some_and(|ext| ext == "bril"),
+        (None, None) => false,
+    };
+    let output = OutputArgs {
+        output: args.output.clone(),
+        output_format: Some(OutputFormat::Bril),
+        pretty: false,
+        compact: false,
+        show_ssa: false,
+        bril2txt: false,
+    };
+    if !is_bril || args.input.attributes.is_some() {
+        emit(args.input.load(), &output);
+        return;
+    }
+
+    let source_name = args.input.source().unwrap_or("<stdin>");
+    let text = match args.input.source() {
+        Some(file) => std::fs::read_to_string(file),
+        None => std::io::read_to_string(std::io::stdin()),
+    };
+    let formatted = text
+        .map_err(|e| e.to_string())
+        .and_then(|text| format_source(&text).map_err(|e| e.to_string()));
+    let written = match (formatted, &args.output) {
+        (Ok(formatted), Some(file)) => std::fs::write(file, formatted).map_err(|e| e.to_string()),
+        (Ok(formatted), None) => {
+            print!("{}", formatted);
+            Ok(())
+        }
+        (Err(e), _) => Err(e),
+    };
+    if let Err(e) = written {
+        log::error!("Failed to format '{}': {}", source_name, e);
+        std::process::exit(1);
+    }
+}
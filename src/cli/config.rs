@@ -0,0 +1,92 @@
+use serde::Deserialize;
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use super::{LogLevel, OutputFormat};
+use crate::optimizations::{builtin_pass_names, pass_by_name, preset_passes};
+
+/// Defaults for flags that were not given on the command line, from `.rust_bril.toml` in the
+/// working directory or else the home directory, or from --config
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub log_level: Option<LogLevel>,
+    /// preset pipeline, followed by `passes`, when no pipeline flag is given
+    pub opt_level: Option<u8>,
+    #[serde(default)]
+    pub passes: Vec<String>,
+    pub output_format: Option<OutputFormat>,
+    /// bril2json used by --bril2json
+    pub bril2json: Option<PathBuf>,
+    /// bril2txt used by --bril2txt
+    pub bril2txt: Option<PathBuf>,
+}
+
+const CONFIG_FILE: &str = ".rust_bril.toml";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// the loaded configuration, empty before `main` loads it
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+impl Config {
+    /// --config if given, otherwise the first config file found. No file means no defaults
+    pub fn load(explicit: Option<&Path>) -> Result<(Self, Option<PathBuf>), String> {
+        let home = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(CONFIG_FILE));
+        let path = match explicit {
+            Some(path) => path.to_path_buf(),
+            None => match [Some(PathBuf::from(CONFIG_FILE)), home]
+                .into_iter()
+                .flatten()
+                .find(|path| path.is_file())
+            {
+                Some(path) => path,
+                None => return Ok((Self::default(), None)),
+            },
+        };
+
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let config: Config = toml::from_str(&text)
+            .map_err(|e| format!("invalid config {}: {}", path.display(), e))?;
+        if config.opt_level.is_some_and(|level| level > 3) {
+            return Err(format!(
+                "invalid config {}: opt-level must be 0 to 3",
+                path.display()
+            ));
+        }
+        if let Some(pass) = config
+            .passes
+            .iter()
+            .find(|pass| pass_by_name(pass).is_none())
+        {
+            return Err(format!(
+                "invalid config {}: unknown pass '{}', expected one of {}",
+                path.display(),
+                pass,
+                builtin_pass_names()
+            ));
+        }
+        Ok((config, Some(path)))
+    }
+
+    /// make this the configuration `config` returns. The first call wins
+    pub fn install(self) -> &'static Config {
+        CONFIG.get_or_init(|| self)
+    }
+
+    /// the configured pipeline, a preset followed by the listed passes
+    pub fn pipeline(&self) -> Vec<String> {
+        let preset = self.opt_level.map(preset_passes).unwrap_or_default();
+        let passes = self.passes.iter().filter_map(|pass| pass_by_name(pass));
+        preset
+            .iter()
+            .map(|pass| pass.to_string())
+            .chain(passes.map(|pass| pass.name().to_string()))
+            .collect()
+    }
+}
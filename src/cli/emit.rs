@@ -0,0 +1,113 @@
+use std::path::Path;
+
+use super::{config, OutputArgs, OutputFormat};
+use crate::{
+    backend::lower_to_riscv,
+    representation::{ProgramError, ProgramFormat, RichAbstractProgram, RichProgram},
+    timing::Timer,
+    visualization::{render_artifact, Artifact},
+};
+
+/// render an artifact and write it to `output` if given, otherwise to stdout
+pub(super) fn write_artifact(
+    kind: Artifact,
+    original_program: Option<&RichProgram>,
+    abstract_program: &RichAbstractProgram,
+    output: Option<&str>,
+) {
+    let _timer = Timer::new("emission");
+    let artifact = match render_artifact(kind, original_program, abstract_program) {
+        Ok(artifact) => artifact,
+        Err(e) => {
+            eprint!(
+                "{}",
+                e.to_string_with_context(&abstract_program.original_text)
+            );
+            std::process::exit(1);
+        }
+    };
+    match output {
+        Some(filepath) => {
+            log::info!("writing artifact to file '{}'", filepath);
+            if let Err(e) = std::fs::write(filepath, artifact) {
+                log::error!("Failed to write artifact to file '{}': {}", filepath, e);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", artifact),
+    }
+}
+
+/// write the program to `--output` if given, otherwise to stdout
+pub(super) fn emit(program: RichProgram, args: &OutputArgs) {
+    let _timer = Timer::new("emission");
+    let output = args.output.as_deref().map(Path::new);
+    let format = match (args.output_format, output) {
+        (Some(OutputFormat::Riscv), _) => return emit_assembly(&program, output),
+        (Some(OutputFormat::Bril), _) => ProgramFormat::Bril,
+        (Some(OutputFormat::Json | OutputFormat::SsaJson), _) => ProgramFormat::Json,
+        (None, Some(path)) if path.extension().and_then(|e| e.to_str()) == Some("s") => {
+            return emit_assembly(&program, output)
+        }
+        (None, Some(path)) if path.extension().and_then(|e| e.to_str()) == Some("bril") => {
+            ProgramFormat::Bril
+        }
+        (None, _) => ProgramFormat::Json,
+    };
+    // files default to pretty JSON, stdout to compact JSON
+    let pretty = args.pretty || (output.is_some() && !args.compact);
+    let rendered = match format {
+        ProgramFormat::Bril if args.bril2txt => {
+            let tool = config().bril2txt.clone().unwrap_or("bril2txt".into());
+            program.render_with_bril2txt(&tool)
+        }
+        _ => program.render(format, pretty),
+    };
+
+    let Some(path) = output else {
+        match rendered {
+            Ok(text) if format == ProgramFormat::Bril => print!("{}", text),
+            Ok(text) => println!("{}", text),
+            Err(e) => {
+                log::error!("Failed to serialize program: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    };
+
+    log::info!("writing program to file '{}'", path.display());
+    let written = rendered.and_then(|text| std::fs::write(path, text).map_err(ProgramError::from));
+    if let Err(e) = written {
+        log::error!(
+            "Failed to write program to file '{}': {}",
+            path.display(),
+            e
+        );
+        std::process::exit(1);
+    }
+}
+
+/// lower the program to RISC-V assembly and write it to `output` if given, otherwise to stdout
+fn emit_assembly(program: &RichProgram, output: Option<&Path>) {
+    let assembly = match lower_to_riscv(&program.program) {
+        Ok(assembly) => assembly,
+        Err(e) => {
+            log::error!("Failed to lower program to RISC-V: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let Some(path) = output else {
+        print!("{}", assembly);
+        return;
+    };
+    log::info!("writing assembly to file '{}'", path.display());
+    if let Err(e) = std::fs::write(path, assembly) {
+        log::error!(
+            "Failed to write assembly to file '{}': {}",
+            path.display(),
+            e
+        );
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,111 @@
+use clap::{error::ErrorKind, CommandFactory};
+use std::path::{Path, PathBuf};
+
+use super::{config, Cli, InputArgs, InputFormat};
+use crate::{
+    representation::{BrilFrontend, ProgramError, RichProgram},
+    timing::Timer,
+};
+
+impl InputArgs {
+    /// the input file, or `None` for stdin. Only `optimize` takes several inputs
+    pub fn source(&self) -> Option<&str> {
+        if self.files.len() > 1 {
+            Cli::command()
+                .error(
+                    ErrorKind::TooManyValues,
+                    "only `optimize` and `fmt --check/--write` take more than one input",
+                )
+                .exit();
+        }
+        self.files.first().map(String::as_str).filter(|f| *f != "-")
+    }
+
+    /// the input files, with glob patterns expanded in sorted order
+    pub fn paths(&self) -> Vec<PathBuf> {
+        expand_paths(&self.files)
+    }
+
+    pub fn frontend(&self) -> BrilFrontend {
+        if self.bril2json {
+            BrilFrontend::Bril2json(config().bril2json.clone().unwrap_or("bril2json".into()))
+        } else {
+            BrilFrontend::Native
+        }
+    }
+
+    /// read the program and merge the --attributes file, exiting on failure
+    pub fn load(&self) -> RichProgram {
+        self.try_load().unwrap_or_else(|e| {
+            log::error!("{}", e);
+            std::process::exit(1);
+        })
+    }
+
+    /// read the program and merge the --attributes file
+    pub fn try_load(&self) -> Result<RichProgram, String> {
+        let frontend = self.frontend();
+        let source_name = self.source().unwrap_or("<stdin>");
+        let loaded = {
+            let _timer = Timer::new("parse");
+            load_program(self.source(), self.input_format, frontend)
+        };
+        let mut rich_program =
+            loaded.map_err(|e| format!("Failed to load program from '{}': {}", source_name, e))?;
+        log::info!("loaded program from '{}'", source_name);
+
+        if let Some(attributes) = &self.attributes {
+            rich_program
+                .apply_attributes_file(Path::new(attributes))
+                .map_err(|e| format!("Failed to load attributes from '{}': {}", attributes, e))?;
+        }
+        Ok(rich_program)
+    }
+}
+
+/// The files, with glob patterns replaced by the files they match
+pub(super) fn expand_paths(files: &[String]) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for file in files {
+        if !is_glob(file) {
+            paths.push(PathBuf::from(file));
+            continue;
+        }
+        let matches: Vec<PathBuf> = match glob::glob(file) {
+            Ok(matches) => matches.filter_map(Result::ok).collect(),
+            Err(e) => {
+                log::error!("Invalid glob pattern '{}': {}", file, e);
+                std::process::exit(2);
+            }
+        };
+        if matches.is_empty() {
+            log::error!("No files match '{}'", file);
+            std::process::exit(2);
+        }
+        paths.extend(matches);
+    }
+    paths
+}
+
+pub(super) fn is_glob(file: &str) -> bool {
+    file.contains(['*', '?', '['])
+}
+
+/// read the program from `source`, or from stdin if no file is given
+fn load_program(
+    source: Option<&str>,
+    input_format: Option<InputFormat>,
+    frontend: BrilFrontend,
+) -> Result<RichProgram, ProgramError> {
+    match (source, input_format) {
+        (Some(file), None) => RichProgram::from_file_with(Path::new(file), frontend),
+        (Some(file), Some(format)) => {
+            let content = std::fs::read_to_string(file)?;
+            RichProgram::from_text(&content, format.into(), frontend)
+        }
+        (None, format) => {
+            let format = format.unwrap_or(InputFormat::Json);
+            RichProgram::from_reader(std::io::stdin().lock(), format.into(), frontend)
+        }
+    }
+}
@@ -0,0 +1,15 @@
+// The command line front end of the `rust_bril` binary: the arguments, the configuration file
+// and the body of every subcommand. Unlike the rest of the library, this module reports errors
+// on stderr and exits the process
+mod args;
+mod batch;
+mod commands;
+mod config;
+mod emit;
+mod input;
+mod optimize;
+
+pub use args::*;
+pub use commands::*;
+pub use config::*;
+pub use optimize::*;
@@ -0,0 +1,315 @@
+use clap::{error::ErrorKind, CommandFactory};
+use std::{io::IsTerminal, path::Path};
+
+use super::{
+    batch::{load_file, run_batch, run_ndjson},
+    emit::emit,
+    input::is_glob,
+    Cli, InputArgs, OptimizeArgs, PipelineArgs, StatsFormat, VerifyArgs,
+};
+use crate::{
+    analysis::{check_arithmetic, check_program, Severity},
+    optimizations::{run_pipeline_in_ssa, PassManager},
+    representation::{validate_program_json, Program, RichAbstractProgram, RichProgram},
+    testing::{args_comment, differential_test},
+    timing::Timer,
+    visualization::{diff_programs, FunctionChange},
+};
+
+pub fn optimize(args: &OptimizeArgs) {
+    if args.ndjson {
+        run_ndjson(args);
+        return;
+    }
+    if args.dry_run {
+        dry_run(args);
+        return;
+    }
+    if args.check {
+        std::process::exit(check(&args.input, &args.pipeline, false));
+    }
+
+    // a glob may match a single file, but it still asks for a batch
+    let inputs = args.input.paths();
+    if inputs.len() > 1 || args.out_dir.is_some() || args.input.files.iter().any(|f| is_glob(f)) {
+        let Some(out_dir) = &args.out_dir else {
+            Cli::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "--out-dir is required to optimize more than one input",
+                )
+                .exit();
+        };
+        run_batch(&inputs, Path::new(out_dir), args);
+        return;
+    }
+
+    let rich_program = args.input.load();
+    if args.skip_pass {
+        emit(rich_program, &args.output);
+        return;
+    }
+    let selected = &args.pipeline.functions;
+    let original_program = (args.diff || args.verify_exec || !selected.is_empty())
+        .then(|| rich_program.program.clone());
+    let exec_inputs = args
+        .verify_exec
+        .then(|| exec_inputs(&args.exec_args, &rich_program));
+
+    let abstract_program = run_passes(&args.pipeline, rich_program);
+
+    // convert out of SSA form
+    let mut final_program = {
+        let _timer = Timer::new("out of ssa");
+        if args.output.keeps_ssa() {
+            abstract_program.into_ssa_program()
+        } else {
+            abstract_program.into_program()
+        }
+    };
+
+    if let Some(original_program) = original_program {
+        restore_unselected(&mut final_program.program, &original_program, selected);
+        if let Some(inputs) = exec_inputs {
+            let _timer = Timer::new("verify exec");
+            if let Err(divergence) =
+                differential_test(&original_program, &final_program.program, &inputs)
+            {
+                eprintln!("error: {}", divergence);
+                std::process::exit(1);
+            }
+            log::info!("the optimized program agrees on {} inputs", inputs.len());
+        }
+        if args.diff {
+            print_diff(&original_program, &final_program.program);
+            if args.output.output.is_none() {
+                return;
+            }
+        }
+    }
+    emit(final_program, &args.output);
+}
+
+/// the argument lists of --exec-args, or else of the `# ARGS:` comment of the input, or else
+/// none
+pub(super) fn exec_inputs(exec_args: &[String], program: &RichProgram) -> Vec<Vec<String>> {
+    if !exec_args.is_empty() {
+        return exec_args
+            .iter()
+            .map(|list| list.split_whitespace().map(str::to_string).collect())
+            .collect();
+    }
+    vec![args_comment(&program.original_text.join("\n")).unwrap_or_default()]
+}
+
+/// put back the functions --function did not select, as they were loaded
+pub(super) fn restore_unselected(program: &mut Program, original: &Program, selected: &[String]) {
+    if selected.is_empty() {
+        return;
+    }
+    for function in &mut program.functions {
+        if selected.contains(&function.name) {
+            continue;
+        }
+        if let Some(loaded) = original.functions.iter().find(|f| f.name == function.name) {
+            *function = loaded.clone();
+        }
+    }
+}
+
+/// drop the functions --function did not select, so analyses and drawings only show the others
+pub(super) fn retain_selected(program: &mut RichAbstractProgram, selected: &[String]) {
+    if !selected.is_empty() {
+        program
+            .program
+            .functions
+            .retain(|name, _| selected.contains(name));
+    }
+}
+
+/// print the changed functions as unified diffs, then how many functions changed
+fn print_diff(before: &Program, after: &Program) {
+    let color = std::io::stdout().is_terminal();
+    let diffs = diff_programs(before, after);
+    let mut changed = 0;
+    for diff in &diffs {
+        if diff.change != FunctionChange::Unchanged {
+            changed += 1;
+            print!("{}", diff.to_unified(color));
+        }
+    }
+    println!("{} of {} functions changed", changed, diffs.len());
+}
+
+/// optimize every input, or stdin, and summarize the per-function changes instead of writing
+/// the results
+fn dry_run(args: &OptimizeArgs) {
+    let pipeline = args.pipeline.manager();
+    let inputs = args.input.paths();
+    let programs: Vec<(String, Result<RichProgram, String>)> = match inputs.is_empty() {
+        true => vec![("<stdin>".to_string(), Ok(args.input.load()))],
+        false => inputs
+            .iter()
+            .map(|input| (input.display().to_string(), load_file(input, args)))
+            .collect(),
+    };
+
+    let mut failed = 0;
+    for (name, program) in programs {
+        let result = program.and_then(|program| {
+            let before = program.program.clone();
+            let after = optimize_in_ssa(program, &pipeline)?;
+            let mut after = after.into_program().program;
+            restore_unselected(&mut after, &before, &args.pipeline.functions);
+            Ok(diff_programs(&before, &after))
+        });
+        let diffs = match result {
+            Ok(diffs) => diffs,
+            Err(e) => {
+                failed += 1;
+                eprintln!("error: {}\n{}", name, e.trim_end());
+                continue;
+            }
+        };
+        let changed: Vec<_> = diffs
+            .iter()
+            .filter(|diff| diff.change != FunctionChange::Unchanged)
+            .collect();
+        println!(
+            "{}: {} of {} functions would change",
+            name,
+            changed.len(),
+            diffs.len()
+        );
+        for diff in changed {
+            let (removed, added) = diff.line_counts();
+            println!("  @{}: -{} +{} lines", diff.name, removed, added);
+        }
+    }
+
+    if args.pipeline.stats.is_none() {
+        eprint!("{}", pipeline.statistics().to_table());
+    }
+    print_stats(&pipeline, &args.pipeline);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// exit status of `verify` and `optimize --check` for a program without problems
+const CHECK_CLEAN: i32 = 0;
+/// exit status when the only problems are warnings
+const CHECK_WARNINGS: i32 = 1;
+/// exit status for errors, the same status clap uses for a bad command line
+const CHECK_ERRORS: i32 = 2;
+
+pub fn verify(args: &VerifyArgs) {
+    std::process::exit(check(&args.input, &args.pipeline, args.arithmetic));
+}
+
+/// load the program, which checks it against the schema, report the diagnostics of
+/// `check_program` (and `check_arithmetic` with `arithmetic`) and, if there is no error, run the
+/// pipeline and check that the program it produces still matches the schema. Returns the exit
+/// status
+pub(super) fn check(input: &InputArgs, pipeline: &PipelineArgs, arithmetic: bool) -> i32 {
+    let source_name = input.source().unwrap_or("<stdin>");
+    let program = match input.try_load() {
+        Ok(program) => program,
+        Err(e) => {
+            log::error!("{}", e);
+            return CHECK_ERRORS;
+        }
+    };
+
+    let mut diagnostics = check_program(&program.program);
+    if arithmetic {
+        diagnostics.extend(check_arithmetic(&program.program));
+    }
+    for diagnostic in &diagnostics {
+        eprintln!("{}: {}", source_name, diagnostic);
+    }
+    let severity = diagnostics.iter().map(|d| d.severity).max();
+    if severity == Some(Severity::Error) {
+        return CHECK_ERRORS;
+    }
+
+    let manager = pipeline.manager();
+    let program = match optimize_in_ssa(program, &manager) {
+        Ok(program) => program.into_program(),
+        Err(e) => {
+            eprint!("{}", e);
+            return CHECK_ERRORS;
+        }
+    };
+    print_stats(&manager, pipeline);
+    let result = serde_json::to_value(&program.program)
+        .map_err(|e| e.to_string())
+        .and_then(|json| validate_program_json(&json).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        log::error!("'{}' is malformed after the pipeline: {}", source_name, e);
+        return CHECK_ERRORS;
+    }
+
+    println!(
+        "{}: ok ({} functions, {} warnings)",
+        source_name,
+        program.program.functions.len(),
+        diagnostics.len()
+    );
+    match severity {
+        Some(_) => CHECK_WARNINGS,
+        None => CHECK_CLEAN,
+    }
+}
+
+/// convert to SSA form and run the selected passes, printing --stats when asked. Exits with the
+/// error and its source context if either fails
+pub(super) fn run_passes(args: &PipelineArgs, program: RichProgram) -> RichAbstractProgram {
+    if let Some(name) = args
+        .functions
+        .iter()
+        .find(|name| !program.program.functions.iter().any(|f| &f.name == *name))
+    {
+        log::error!("--function: the program has no function @{}", name);
+        std::process::exit(2);
+    }
+    let pipeline = args.manager();
+    let abstract_program = match optimize_in_ssa(program, &pipeline) {
+        Ok(program) => program,
+        Err(e) => {
+            eprint!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    print_stats(&pipeline, args);
+    abstract_program
+}
+
+/// convert to SSA form and run `pipeline`, failing with the error and the source lines around it
+pub(super) fn optimize_in_ssa(
+    program: RichProgram,
+    pipeline: &PassManager,
+) -> Result<RichAbstractProgram, String> {
+    let original_text = program.original_text.clone();
+    run_pipeline_in_ssa(program, pipeline).map_err(|e| e.to_string_with_context(&original_text))
+}
+
+/// print --stats and write --stats-out
+pub(super) fn print_stats(pipeline: &PassManager, args: &PipelineArgs) {
+    let statistics = pipeline.statistics();
+    match args.stats {
+        Some(StatsFormat::Table) => eprint!("{}", statistics.to_table()),
+        Some(StatsFormat::Json) => eprintln!("{}", statistics.to_json()),
+        None => {}
+    }
+    if let Some(path) = &args.stats_out {
+        let text = match path.extension().is_some_and(|e| e == "csv") {
+            true => statistics.to_csv(),
+            false => statistics.to_function_json(),
+        };
+        if let Err(e) = std::fs::write(path, text) {
+            log::error!("failed to write statistics to {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
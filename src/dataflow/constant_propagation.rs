@@ -0,0 +1,220 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    dataflow::{ParallelWorklistProperty, WorklistProperty, WorklistResult},
+    interpreter::{eval_value, Value},
+    representation::{
+        format_literal, AbstractFunction, Argument, BlockId, Code, ControlFlowGraph, Literal,
+        PhiNode, Type, ValueOp,
+    },
+};
+
+// Global constant propagation over a flat lattice per variable. A variable missing from the
+// domain has no value yet, `Constant` is the one value it can have and `Varying` means it can
+// have several, or one that is only known at run time. Instructions fold with the interpreter's
+// semantics once all of their arguments are constant; an operation that would fail (division by
+// zero, int2char of a negative number) is `Varying`, so that it stays in the program and fails
+// there.
+
+/// What is known about the value of one variable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstantValue {
+    Constant(Literal),
+    Varying,
+}
+
+impl ConstantValue {
+    /// least upper bound
+    pub fn join(self, other: Self) -> Self {
+        if self == other {
+            self
+        } else {
+            Self::Varying
+        }
+    }
+}
+
+impl fmt::Display for ConstantValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Constant(literal) => write!(f, "{}", format_literal(literal)),
+            Self::Varying => write!(f, "varying"),
+        }
+    }
+}
+
+pub struct ConstantPropagation {}
+
+impl WorklistProperty for ConstantPropagation {
+    type Domain = HashMap<String, ConstantValue>;
+
+    fn init(_: usize, _: &AbstractFunction) -> Self::Domain {
+        Self::Domain::default()
+    }
+
+    fn is_forward() -> bool {
+        true
+    }
+
+    fn merge(predecessors: Vec<(&BlockId, &Self::Domain)>) -> WorklistResult<Self::Domain> {
+        let mut result = Self::Domain::new();
+        for (_, domain) in predecessors {
+            for (var, value) in domain {
+                result
+                    .entry(var.clone())
+                    .and_modify(|merged| *merged = merged.join(*value))
+                    .or_insert(*value);
+            }
+        }
+        Ok(result)
+    }
+
+    fn transfer(
+        domain: Self::Domain,
+        block_id: usize,
+        cfg: &mut ControlFlowGraph,
+        arguments: Option<&Vec<Argument>>,
+    ) -> WorklistResult<Self::Domain> {
+        Self::transfer_shared(domain, block_id, cfg, arguments)
+    }
+}
+
+impl ParallelWorklistProperty for ConstantPropagation {
+    fn transfer_shared(
+        mut domain: Self::Domain,
+        block_id: usize,
+        cfg: &ControlFlowGraph,
+        arguments: Option<&Vec<Argument>>,
+    ) -> WorklistResult<Self::Domain> {
+        if block_id == 0 {
+            for arg in arguments.into_iter().flatten() {
+                domain.insert(arg.name.clone(), ConstantValue::Varying);
+            }
+        }
+
+        let block = &cfg.basic_blocks[block_id];
+        // phi nodes read their arguments at block entry, before any of them is written
+        let phis: Vec<_> = (block.phi_nodes.iter())
+            .map(|phi| (phi.dest.clone(), phi_value(&domain, phi)))
+            .collect();
+        for (dest, value) in phis {
+            assign(&mut domain, dest, value);
+        }
+        for code in block.preheader.iter().chain(&block.instructions) {
+            if let Some(dest) = code.get_destination() {
+                let value = evaluate(&domain, code);
+                assign(&mut domain, dest.to_string(), value);
+            }
+        }
+        Ok(domain)
+    }
+}
+
+/// The value `code` assigns to its destination in `domain`, `None` while it has none yet
+pub fn evaluate(domain: &HashMap<String, ConstantValue>, code: &Code) -> Option<ConstantValue> {
+    match code {
+        Code::Constant {
+            constant_type,
+            value,
+            ..
+        } => Some(constant(Value::from_literal(value, constant_type))),
+        Code::Value { op, value_type, .. }
+            if matches!(op, ValueOp::Call) || matches!(value_type, Type::Ptr(_)) =>
+        {
+            Some(ConstantValue::Varying)
+        }
+        Code::Value { op, args, .. } => {
+            let mut values = Vec::new();
+            let mut pending = false;
+            for arg in args.iter().flatten() {
+                match domain.get(arg) {
+                    Some(ConstantValue::Constant(literal)) => values.push(value_of(*literal)),
+                    Some(ConstantValue::Varying) => return Some(ConstantValue::Varying),
+                    None => pending = true,
+                }
+            }
+            (!pending).then(|| constant(eval_value(*op, &values).ok()))
+        }
+        _ => Some(ConstantValue::Varying),
+    }
+}
+
+fn phi_value(domain: &HashMap<String, ConstantValue>, phi: &PhiNode) -> Option<ConstantValue> {
+    (phi.phi_args.iter())
+        .filter_map(|(var, _)| domain.get(var).copied())
+        .reduce(ConstantValue::join)
+}
+
+fn assign(domain: &mut HashMap<String, ConstantValue>, dest: String, value: Option<ConstantValue>) {
+    match value {
+        Some(value) => domain.insert(dest, value),
+        None => domain.remove(&dest),
+    };
+}
+
+fn constant(value: Option<Value>) -> ConstantValue {
+    let literal = match value {
+        Some(Value::Int(x)) => Literal::Int(x),
+        Some(Value::Bool(b)) => Literal::Bool(b),
+        Some(Value::Float(x)) => Literal::Float(x),
+        Some(Value::Char(c)) => Literal::Char(c),
+        Some(Value::Ptr(_)) | None => return ConstantValue::Varying,
+    };
+    ConstantValue::Constant(literal)
+}
+
+fn value_of(literal: Literal) -> Value {
+    match literal {
+        Literal::Int(x) => Value::Int(x),
+        Literal::Bool(b) => Value::Bool(b),
+        Literal::Float(x) => Value::Float(x),
+        Literal::Char(c) => Value::Char(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dataflow::run_dataflow_analysis, testing::function_from_text};
+
+    #[test]
+    fn constants_flow_through_branches_and_loops() {
+        let text = r#"
+@main(n: int) {
+  one: int = const 1;
+  two: int = add one one;
+  zero: int = const 0;
+  bad: int = div one zero;
+  cond: bool = lt n two;
+  br cond .left .right;
+.left:
+  x: int = const 4;
+  i: int = id one;
+  jmp .loop;
+.right:
+  x: int = const 4;
+  i: int = id two;
+.loop:
+  i: int = add i one;
+  more: bool = lt i n;
+  br more .loop .end;
+.end:
+  print x i bad;
+}
+"#;
+        let mut af = function_from_text(text);
+        let result = run_dataflow_analysis::<ConstantPropagation>(&mut af).unwrap();
+
+        let end = af.cfg.label_map["end"];
+        let (in_, _) = &result[&end];
+        // the variables as printed, under their names in SSA form
+        let printed = af.cfg.basic_blocks[end].instructions[0]
+            .get_arguments()
+            .unwrap();
+        let value = |i: usize| in_[&printed[i]];
+        assert_eq!(value(0), ConstantValue::Constant(Literal::Int(4)));
+        assert_eq!(value(1), ConstantValue::Varying);
+        assert_eq!(value(2), ConstantValue::Varying);
+        assert_eq!(in_["n"].to_string(), "varying");
+    }
+}
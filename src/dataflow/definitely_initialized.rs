@@ -1,7 +1,7 @@
 use std::collections::HashSet;
 
 use crate::{
-    dataflow::{WorklistError, WorklistProperty, WorklistResult},
+    dataflow::{ParallelWorklistProperty, WorklistError, WorklistProperty, WorklistResult},
     representation::{
         AbstractFunction, Argument, BasicBlock, BlockId, Code, ControlFlowGraph, Terminator,
     },
@@ -57,26 +57,12 @@ impl WorklistProperty for DefinitelyInitialized {
         }))
     }
     fn transfer(
-        mut domain: Self::Domain,
+        domain: Self::Domain,
         block_id: usize,
         cfg: &mut ControlFlowGraph,
         args: Option<&Vec<Argument>>,
     ) -> WorklistResult<Self::Domain> {
-        let block = &mut cfg.basic_blocks[block_id];
-        if block.id == 0 {
-            if let Some(arguments) = args {
-                for arg in arguments {
-                    domain.insert(arg.name.clone());
-                }
-            }
-        }
-
-        for instructions in block.instructions.iter() {
-            if let Some(dest) = instructions.get_destination() {
-                domain.insert(dest.to_string());
-            }
-        }
-        Ok(domain)
+        Self::transfer_shared(domain, block_id, cfg, args)
     }
 
     fn should_run_final_check() -> bool {
@@ -109,7 +95,7 @@ impl WorklistProperty for DefinitelyInitialized {
 
         for instructions in block.instructions.iter() {
             if let Some(args) = instructions.get_arguments() {
-                if let Some(var) = args_in_domain(&args, &d) {
+                if let Some(var) = args_in_domain(args, &d) {
                     return Err(WorklistError::transfer_error(
                         block,
                         format!("using uninitialized variable: {}", var),
@@ -123,21 +109,49 @@ impl WorklistProperty for DefinitelyInitialized {
             }
         }
 
-        match &block.terminator {
-            Terminator::Ret(Code::Effect {
-                args: Some(a), pos, ..
-            }) => {
-                if let Some(var) = args_in_domain(a, &d) {
-                    return Err(WorklistError::transfer_error(
-                        block,
-                        format!("returning uninitialized variable: {}", var),
-                        pos,
-                    ));
+        // a branch condition needs a definition as much as a returned value does
+        let (verb, pos) = match &block.terminator {
+            Terminator::Ret(Code::Effect { pos, .. }) => ("returning", pos),
+            Terminator::Br(_, _, Code::Effect { pos, .. }) => ("branching on", pos),
+            _ => return Ok(()),
+        };
+        if let Some(var) = block
+            .terminator
+            .get_arguments()
+            .and_then(|a| args_in_domain(a, &d))
+        {
+            return Err(WorklistError::transfer_error(
+                block,
+                format!("{} uninitialized variable: {}", verb, var),
+                pos,
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl ParallelWorklistProperty for DefinitelyInitialized {
+    fn transfer_shared(
+        mut domain: Self::Domain,
+        block_id: usize,
+        cfg: &ControlFlowGraph,
+        args: Option<&Vec<Argument>>,
+    ) -> WorklistResult<Self::Domain> {
+        let block = &cfg.basic_blocks[block_id];
+        if block.id == 0 {
+            if let Some(arguments) = args {
+                for arg in arguments {
+                    domain.insert(arg.name.clone());
                 }
             }
-            _ => (),
         }
 
-        Ok(())
+        for instructions in block.instructions.iter() {
+            if let Some(dest) = instructions.get_destination() {
+                domain.insert(dest.to_string());
+            }
+        }
+        Ok(domain)
     }
 }
@@ -1,7 +1,7 @@
 use std::collections::HashSet;
 
 use crate::{
-    dataflow::{WorklistProperty, WorklistResult},
+    dataflow::{ParallelWorklistProperty, WorklistProperty, WorklistResult},
     representation::{AbstractFunction, Argument, BlockId, Code, ControlFlowGraph},
 };
 
@@ -37,12 +37,23 @@ impl WorklistProperty for LiveVariables {
         domain: Self::Domain,
         block_id: usize,
         cfg: &mut ControlFlowGraph,
+        args: Option<&Vec<Argument>>,
+    ) -> WorklistResult<Self::Domain> {
+        Self::transfer_shared(domain, block_id, cfg, args)
+    }
+}
+
+impl ParallelWorklistProperty for LiveVariables {
+    fn transfer_shared(
+        domain: Self::Domain,
+        block_id: usize,
+        cfg: &ControlFlowGraph,
         _: Option<&Vec<Argument>>,
     ) -> WorklistResult<Self::Domain> {
         // iterate backwards through the instructions
         //      1. process definitions first (remove from live set)
         //      2. then process arguments (add to live set)
-        let block = &mut cfg.basic_blocks[block_id];
+        let block = &cfg.basic_blocks[block_id];
         let mut domain_view: HashSet<&str> = domain.iter().map(|s| s.as_str()).collect();
 
         match &block.terminator {
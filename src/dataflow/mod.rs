@@ -1,9 +1,13 @@
+mod constant_propagation;
 mod definitely_initialized;
 mod live_variables;
 mod reaching_definitions;
+mod regions;
 mod worklist;
 
+pub use constant_propagation::*;
 pub use definitely_initialized::*;
 pub use live_variables::*;
 pub use reaching_definitions::*;
+pub use regions::*;
 pub use worklist::*;
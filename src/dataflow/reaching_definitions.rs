@@ -1,7 +1,7 @@
 use std::collections::{HashMap, HashSet};
 
 use crate::{
-    dataflow::{WorklistProperty, WorklistResult},
+    dataflow::{ParallelWorklistProperty, WorklistProperty, WorklistResult},
     representation::{AbstractFunction, Argument, BlockId, ControlFlowGraph},
 };
 
@@ -36,10 +36,21 @@ impl WorklistProperty for ReachingDefinitions {
     }
 
     fn transfer(
-        mut domain: Self::Domain,
+        domain: Self::Domain,
         block_id: usize,
         cfg: &mut ControlFlowGraph,
         arguments: Option<&Vec<Argument>>,
+    ) -> WorklistResult<Self::Domain> {
+        Self::transfer_shared(domain, block_id, cfg, arguments)
+    }
+}
+
+impl ParallelWorklistProperty for ReachingDefinitions {
+    fn transfer_shared(
+        mut domain: Self::Domain,
+        block_id: usize,
+        cfg: &ControlFlowGraph,
+        arguments: Option<&Vec<Argument>>,
     ) -> WorklistResult<Self::Domain> {
         // Handle function arguments in entry block
         if block_id == 0 {
@@ -0,0 +1,295 @@
+use std::collections::{HashMap, VecDeque};
+
+use rayon::prelude::*;
+
+use crate::{
+    dataflow::{max_iterations, WorklistError, WorklistOutput, WorklistProperty, WorklistResult},
+    representation::{AbstractFunction, Argument, BlockId, BlockSet, ControlFlowGraph},
+};
+
+// Parallel fixpoint for very large functions. The CFG is condensed into its strongly connected
+// components, and each component is a region whose inputs (predecessors for a forward analysis,
+// successors for a backward one) lie in the region itself or in regions of an earlier level.
+// Regions of the same level share no edges, so they are solved on separate threads, each with
+// its own worklist; the results of a level are merged before the next level starts, which is the
+// only synchronization. Since a region is only solved once all of its inputs have converged, one
+// pass over the levels reaches the same fixpoint as the sequential worklist.
+
+/// Functions with at least this many blocks are solved by regions
+pub const PARALLEL_DATAFLOW_BLOCKS: usize = 2048;
+
+/// A dataflow problem whose transfer function only reads the CFG, so that independent regions of
+/// it can be solved on different threads
+pub trait ParallelWorklistProperty: WorklistProperty<Domain: Send + Sync> {
+    fn transfer_shared(
+        domain: Self::Domain,
+        block_id: usize,
+        cfg: &ControlFlowGraph,
+        args: Option<&Vec<Argument>>,
+    ) -> WorklistResult<Self::Domain>;
+}
+
+/// Same as `run_dataflow_analysis`, but functions with at least [`PARALLEL_DATAFLOW_BLOCKS`]
+/// blocks are solved region by region on multiple threads. The iteration limit then applies to
+/// each region instead of the whole function
+pub fn run_parallel_dataflow_analysis<T: ParallelWorklistProperty>(
+    af: &mut AbstractFunction,
+) -> WorklistResult<WorklistOutput<T::Domain>> {
+    if af.cfg.basic_blocks.len() < PARALLEL_DATAFLOW_BLOCKS {
+        return super::run_dataflow_analysis::<T>(af);
+    }
+    solve_by_regions::<T>(af).map_err(|e| e.in_source(af.src.as_deref()))
+}
+
+fn solve_by_regions<T: ParallelWorklistProperty>(
+    af: &AbstractFunction,
+) -> WorklistResult<WorklistOutput<T::Domain>> {
+    let cfg = &af.cfg;
+    let forward = T::is_forward();
+    let inputs = if forward {
+        &cfg.predecessors
+    } else {
+        &cfg.successors
+    };
+    let levels = region_levels(cfg, forward);
+    let max_iterations = max_iterations(af);
+    log::debug!(
+        "solving {} blocks of {} in {} levels",
+        cfg.basic_blocks.len(),
+        af.name,
+        levels.len()
+    );
+
+    let mut done: WorklistOutput<T::Domain> = HashMap::with_capacity(cfg.basic_blocks.len());
+    for level in levels {
+        let solved: Vec<WorklistResult<_>> = level
+            .par_iter()
+            .map(|region| solve_region::<T>(af, inputs, region, &done, max_iterations))
+            .collect();
+        // the first error in block order, whichever thread found it
+        for region in solved {
+            done.extend(region?);
+        }
+    }
+
+    if T::should_run_final_check() {
+        for block in &cfg.basic_blocks {
+            T::final_check(&done[&block.id].0, block, af.args.as_ref())?;
+        }
+    }
+    Ok(done)
+}
+
+/// Worklist iteration over the blocks of one region, reading converged inputs from `done`
+fn solve_region<T: ParallelWorklistProperty>(
+    af: &AbstractFunction,
+    inputs: &[BlockSet],
+    region: &[BlockId],
+    done: &WorklistOutput<T::Domain>,
+    max_iterations: usize,
+) -> WorklistResult<WorklistOutput<T::Domain>> {
+    let mut result: WorklistOutput<T::Domain> = region
+        .iter()
+        .map(|&block| {
+            let init = T::init(block, af);
+            (block, (init.clone(), init))
+        })
+        .collect();
+    let mut worklist: VecDeque<BlockId> = region.iter().copied().collect();
+    // blocks of the region already waiting in `worklist`
+    let mut queued = BlockSet::from_ids(af.cfg.basic_blocks.len(), region.iter().copied());
+
+    let mut iterations = 0;
+    while let Some(cur) = worklist.pop_front() {
+        queued.remove(cur);
+        if iterations >= max_iterations {
+            return Err(WorklistError::ConvergenceError {
+                function_name: af.name.clone(),
+                max_iterations,
+            });
+        }
+        iterations += 1;
+
+        let ins: Vec<(&BlockId, &T::Domain)> = inputs[cur]
+            .iter()
+            .filter_map(|b| {
+                (result.get_key_value(&b).or_else(|| done.get_key_value(&b)))
+                    .map(|(b, (_, out))| (b, out))
+            })
+            .collect();
+        let in_ = T::merge(ins)?;
+        let out = T::transfer_shared(in_.clone(), cur, &af.cfg, af.args.as_ref())?;
+        let entry = result.get_mut(&cur).unwrap();
+        let changed = entry.1 != out;
+        *entry = (in_, out);
+
+        if changed {
+            // only blocks of this region read `cur`, the others are in later levels
+            let outputs = if T::is_forward() {
+                &af.cfg.successors[cur]
+            } else {
+                &af.cfg.predecessors[cur]
+            };
+            for next in outputs.iter() {
+                if result.contains_key(&next) && queued.insert(next) {
+                    worklist.push_back(next);
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Strongly connected components grouped by level: a component's inputs are in the component
+/// itself or in an earlier level. Components and their blocks are in ascending block order
+fn region_levels(cfg: &ControlFlowGraph, forward: bool) -> Vec<Vec<Vec<BlockId>>> {
+    let (components, component_of) = strongly_connected_components(&cfg.successors);
+    let inputs = if forward {
+        &cfg.predecessors
+    } else {
+        &cfg.successors
+    };
+
+    // Tarjan's algorithm finishes a component after every component it reaches, so successors
+    // come first
+    let mut level = vec![0; components.len()];
+    let order: Box<dyn Iterator<Item = usize>> = if forward {
+        Box::new((0..components.len()).rev())
+    } else {
+        Box::new(0..components.len())
+    };
+    for component in order {
+        level[component] = (components[component].iter())
+            .flat_map(|&block| inputs[block].iter())
+            .map(|input| component_of[input])
+            .filter(|&input| input != component)
+            .map(|input| level[input] + 1)
+            .max()
+            .unwrap_or(0);
+    }
+
+    let mut levels = vec![Vec::new(); level.iter().max().map_or(0, |max| max + 1)];
+    for (component, mut blocks) in components.into_iter().enumerate() {
+        blocks.sort_unstable();
+        levels[level[component]].push(blocks);
+    }
+    for regions in &mut levels {
+        regions.sort_unstable_by_key(|blocks| blocks[0]);
+    }
+    levels
+}
+
+/// Tarjan's algorithm without recursion, so that deep CFGs do not overflow the stack. Returns
+/// the components in the order they were finished and the component of each block
+fn strongly_connected_components(successors: &[BlockSet]) -> (Vec<Vec<BlockId>>, Vec<usize>) {
+    const UNVISITED: usize = usize::MAX;
+    let n = successors.len();
+    let mut index = vec![UNVISITED; n];
+    let mut low = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = Vec::new();
+    let mut component_of = vec![0; n];
+    let mut components = Vec::new();
+    let mut next_index = 0;
+
+    for root in 0..n {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        // (block, successors still to visit)
+        let mut calls = vec![(root, successors[root].iter())];
+        index[root] = next_index;
+        low[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+
+        while let Some((block, children)) = calls.last_mut() {
+            let block = *block;
+            if let Some(child) = children.next() {
+                if index[child] == UNVISITED {
+                    index[child] = next_index;
+                    low[child] = next_index;
+                    next_index += 1;
+                    stack.push(child);
+                    on_stack[child] = true;
+                    calls.push((child, successors[child].iter()));
+                } else if on_stack[child] {
+                    low[block] = low[block].min(index[child]);
+                }
+                continue;
+            }
+
+            calls.pop();
+            if let Some((parent, _)) = calls.last() {
+                low[*parent] = low[*parent].min(low[block]);
+            }
+            if low[block] == index[block] {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component_of[member] = components.len();
+                    component.push(member);
+                    if member == block {
+                        break;
+                    }
+                }
+                components.push(component);
+            }
+        }
+    }
+    (components, component_of)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dataflow::{run_dataflow_analysis, LiveVariables, ReachingDefinitions},
+        testing::function_from_text,
+    };
+
+    #[test]
+    fn regions_reach_the_sequential_fixpoint() {
+        let text = r#"
+@main(n: int) {
+  i: int = const 0;
+  one: int = const 1;
+  sum: int = const 0;
+.outer:
+  j: int = const 0;
+.inner:
+  sum: int = add sum j;
+  j: int = add j one;
+  c: bool = lt j i;
+  br c .inner .next;
+.next:
+  i: int = add i one;
+  d: bool = lt i n;
+  br d .outer .done;
+.done:
+  e: bool = lt sum n;
+  br e .small .big;
+.small:
+  print sum;
+  jmp .end;
+.big:
+  print n;
+.end:
+  ret;
+}
+"#;
+        let mut af = function_from_text(text);
+
+        let levels = region_levels(&af.cfg, true);
+        assert!(levels.iter().any(|regions| regions.len() > 1) || levels.len() > 1);
+
+        let live = run_dataflow_analysis::<LiveVariables>(&mut af).unwrap();
+        assert_eq!(solve_by_regions::<LiveVariables>(&af).unwrap(), live);
+        let reaching = run_dataflow_analysis::<ReachingDefinitions>(&mut af).unwrap();
+        assert_eq!(
+            solve_by_regions::<ReachingDefinitions>(&af).unwrap(),
+            reaching
+        );
+    }
+}
@@ -1,11 +1,12 @@
 use std::{
     any::type_name,
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{HashMap, VecDeque},
 };
 use thiserror::Error;
 
+use crate::analysis::VerifyError;
 use crate::representation::{
-    AbstractFunction, Argument, BasicBlock, BlockId, ControlFlowGraph, Position,
+    AbstractFunction, Argument, BasicBlock, BlockId, BlockSet, ControlFlowGraph, Position,
 };
 
 /// Errors that can occur during worklist algorithm execution
@@ -21,6 +22,9 @@ pub enum WorklistError {
         reason: String,
         position: Option<Position>,
         code_snippet: Option<String>,
+        /// source file of the function, when it is not the program's main file. Boxed to keep
+        /// `WorklistResult` small
+        src: Option<Box<str>>,
     },
 
     #[error("Merge error: {reason}")]
@@ -29,6 +33,7 @@ pub enum WorklistError {
         reason: String,
         position: Option<Position>,
         code_snippet: Option<String>,
+        src: Option<Box<str>>,
     },
 
     #[error("Analysis convergence failed: reached maximum iterations ({max_iterations}) at function {function_name}")]
@@ -36,6 +41,20 @@ pub enum WorklistError {
         function_name: String,
         max_iterations: usize,
     },
+
+    #[error("{pass} panicked on @{function_name}: {message}")]
+    PassPanicked {
+        pass: String,
+        function_name: String,
+        message: String,
+    },
+
+    /// the IR verifiers rejected a function after `pass`
+    #[error("IR verification failed after {pass}: {error}")]
+    VerificationFailed {
+        pass: String,
+        error: Box<VerifyError>,
+    },
 }
 
 impl WorklistError {
@@ -59,6 +78,7 @@ impl WorklistError {
             reason: reason.into(),
             position: *position,
             code_snippet: None,
+            src: None,
         }
     }
 
@@ -73,6 +93,7 @@ impl WorklistError {
             reason: reason.into(),
             position,
             code_snippet: None,
+            src: None,
         }
     }
 
@@ -81,10 +102,38 @@ impl WorklistError {
         match self {
             Self::TransferFunctionError { position, .. }
             | Self::MergeFunctionError { position, .. } => position.as_ref(),
-            Self::BlockNotFound { .. } | Self::ConvergenceError { .. } => None,
+            Self::BlockNotFound { .. }
+            | Self::ConvergenceError { .. }
+            | Self::PassPanicked { .. }
+            | Self::VerificationFailed { .. } => None,
         }
     }
 
+    /// Get the source file the error position refers to, if it is not the main program text
+    pub fn src(&self) -> Option<&str> {
+        match self {
+            Self::TransferFunctionError { src, .. } | Self::MergeFunctionError { src, .. } => {
+                src.as_deref()
+            }
+            Self::BlockNotFound { .. }
+            | Self::ConvergenceError { .. }
+            | Self::PassPanicked { .. }
+            | Self::VerificationFailed { .. } => None,
+        }
+    }
+
+    /// Attribute the error to `file` unless it already names a source file
+    pub fn in_source(mut self, file: Option<&str>) -> Self {
+        if let Self::TransferFunctionError { src, .. } | Self::MergeFunctionError { src, .. } =
+            &mut self
+        {
+            if src.is_none() {
+                *src = file.map(Box::from);
+            }
+        }
+        self
+    }
+
     /// Get the block ID associated with this error if available
     pub fn block_id(&self) -> Option<Vec<BlockId>> {
         match self {
@@ -92,17 +141,32 @@ impl WorklistError {
                 Some(vec![*block_id])
             }
             Self::MergeFunctionError { inputs, .. } => Some(inputs.clone()),
-            Self::ConvergenceError { .. } => None,
+            Self::ConvergenceError { .. }
+            | Self::PassPanicked { .. }
+            | Self::VerificationFailed { .. } => None,
         }
     }
 
-    pub fn error_with_context_then_exit(&self, text: &Vec<String>) -> ! {
-        eprintln!("{}", self);
+    /// The error message followed by the lines around the error position, if known.
+    ///
+    /// The lines come from the error's source file when it names one, and from `text` otherwise.
+    pub fn to_string_with_context(&self, text: &[String]) -> String {
+        let mut message = format!("{}\n", self);
         if let Some(pos) = self.position() {
             let line = pos.row as usize;
             let column = pos.col as usize;
 
-            let lines: &Vec<String> = text;
+            let source = self.src().and_then(|src| {
+                std::fs::read_to_string(src)
+                    .inspect_err(|e| log::warn!("cannot read {} for error context: {}", src, e))
+                    .ok()
+            });
+            let source: Option<Vec<String>> =
+                source.map(|contents| contents.lines().map(str::to_string).collect());
+            if let Some(src) = self.src() {
+                message.push_str(&format!("  --> {}:{}:{}\n", src, line, column));
+            }
+            let lines = source.as_deref().unwrap_or(text);
             let context_lines = 10; // Show 10 lines before and after the error
 
             let start_line = line.saturating_sub(context_lines + 1); // -1 because line numbers are 1-based
@@ -120,14 +184,42 @@ impl WorklistError {
                     snippet.push_str(&pointer);
                 }
             }
-            eprintln!("Error context:\n{}", snippet);
+            message.push_str(&format!("Error context:\n{}\n", snippet));
         }
-        std::process::exit(1);
+        message
     }
 }
 
 pub type WorklistResult<T> = Result<T, WorklistError>;
 
+/// blocks an analysis may visit before it is considered not to converge, however small the
+/// function
+const MIN_ITERATIONS: usize = 10_000;
+
+/// visits of each block per variable of the function an analysis may make. A visit only
+/// re-queues its neighbours when the block's output changed, and the outputs can only change a
+/// few times for each variable before the lattice bottoms out
+const VISITS_PER_VARIABLE: usize = 4;
+
+/// blocks an analysis of `af` may visit before it is considered not to converge. Grows with
+/// blocks × variables, so that large functions (say, after inlining and unrolling) still converge
+pub(crate) fn max_iterations(af: &AbstractFunction) -> usize {
+    let blocks = &af.cfg.basic_blocks;
+    let variables = af.args.as_ref().map_or(0, Vec::len)
+        + (blocks.iter())
+            .map(|b| {
+                let code = b.preheader.iter().chain(&b.instructions);
+                b.phi_nodes.len() + code.filter(|c| c.get_destination().is_some()).count()
+            })
+            .sum::<usize>();
+    (blocks.len() * (variables + 1))
+        .saturating_mul(VISITS_PER_VARIABLE)
+        .max(MIN_ITERATIONS)
+}
+
+/// (in, out) domain of every block after the analysis converges
+pub type WorklistOutput<D> = HashMap<BlockId, (D, D)>;
+
 struct WorklistAlgorithm<'a> {
     abstract_function: &'a mut AbstractFunction,
     max_iterations: usize,
@@ -162,14 +254,14 @@ pub trait WorklistProperty {
 impl<'a> WorklistAlgorithm<'a> {
     fn from(abstract_function: &'a mut AbstractFunction) -> Self {
         Self {
+            max_iterations: max_iterations(abstract_function),
             abstract_function,
-            max_iterations: 10_000,
         }
     }
 
     #[inline]
     /// Get the inputs into the basic block from the specified direction (predecessors if forward, successors if backward)
-    fn edges(&self, block_label: &BlockId, forward: bool) -> WorklistResult<&HashSet<usize>> {
+    fn edges(&self, block_label: &BlockId, forward: bool) -> WorklistResult<&BlockSet> {
         let cfg = &self.abstract_function.cfg;
         if forward {
             cfg.predecessors
@@ -193,9 +285,7 @@ impl<'a> WorklistAlgorithm<'a> {
                 })
         }
     }
-    fn run_worklist<T: WorklistProperty>(
-        &mut self,
-    ) -> WorklistResult<HashMap<BlockId, (T::Domain, T::Domain)>> {
+    fn run_worklist<T: WorklistProperty>(&mut self) -> WorklistResult<WorklistOutput<T::Domain>> {
         let mut worklist: VecDeque<usize> = self
             .abstract_function
             .cfg
@@ -203,10 +293,12 @@ impl<'a> WorklistAlgorithm<'a> {
             .iter()
             .map(|b| b.id)
             .collect();
+        // blocks already waiting in `worklist`, which are not queued a second time
+        let mut queued = BlockSet::full(worklist.len());
 
         let forward = T::is_forward();
         let mut num_it = 0;
-        let mut result: HashMap<BlockId, (T::Domain, T::Domain)> =
+        let mut result: WorklistOutput<T::Domain> =
             (0..self.abstract_function.cfg.basic_blocks.len())
                 .map(|i| {
                     let init = T::init(i, self.abstract_function);
@@ -215,6 +307,7 @@ impl<'a> WorklistAlgorithm<'a> {
                 .collect();
         log::trace!("{}: worklist={:?}", type_name::<T>(), worklist);
         while let Some(cur) = { worklist.pop_front() } {
+            queued.remove(cur);
             if num_it >= self.max_iterations {
                 return Err(WorklistError::ConvergenceError {
                     function_name: self.abstract_function.name.clone(),
@@ -227,8 +320,8 @@ impl<'a> WorklistAlgorithm<'a> {
 
             let inputs: Vec<(&BlockId, &T::Domain)> = self
                 .edges(&cur, forward)?
-                .into_iter()
-                .filter_map(|b| result.get(b).map(|(_, o)| (b, o)))
+                .iter()
+                .filter_map(|b| result.get_key_value(&b).map(|(b, (_, o))| (b, o)))
                 .collect();
             let in_ = T::merge(inputs)?;
             let out = T::transfer(
@@ -243,7 +336,11 @@ impl<'a> WorklistAlgorithm<'a> {
             if !is_same {
                 // push successor blocks if first time or output changed
                 // negate to get "children" instead of "parents"
-                worklist.extend(self.edges(&cur, !forward)?.into_iter().map(|x| *x));
+                for next in self.edges(&cur, !forward)?.iter() {
+                    if queued.insert(next) {
+                        worklist.push_back(next);
+                    }
+                }
             }
 
             num_it += 1;
@@ -263,13 +360,16 @@ impl<'a> WorklistAlgorithm<'a> {
 
 pub fn run_dataflow_analysis<T>(
     abstract_function: &mut AbstractFunction,
-) -> WorklistResult<HashMap<BlockId, (T::Domain, T::Domain)>>
+) -> WorklistResult<WorklistOutput<T::Domain>>
 where
     T: WorklistProperty,
 {
+    let src = abstract_function.src.clone();
     let result = {
         let mut algorithm: WorklistAlgorithm = WorklistAlgorithm::from(abstract_function);
-        algorithm.run_worklist::<T>()?
+        algorithm
+            .run_worklist::<T>()
+            .map_err(|e| e.in_source(src.as_deref()))?
     };
 
     Ok(result)
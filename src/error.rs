@@ -0,0 +1,45 @@
+use thiserror::Error;
+
+use crate::{
+    dataflow::WorklistError,
+    optimizations::UnknownPassError,
+    representation::{Position, ProgramError},
+};
+
+// The error of the library's entry points. Every failure is handed back to the caller: nothing
+// in the library prints an error or exits the process, that is left to the command line front
+// end in `cli`, which also shows the source lines around the offending instruction
+
+/// Anything that can go wrong loading, transforming or writing a program
+#[derive(Debug, Error)]
+pub enum BrilError {
+    /// reading, parsing, validating or writing a program
+    #[error(transparent)]
+    Program(#[from] ProgramError),
+    /// SSA construction, an analysis or a pass
+    #[error(transparent)]
+    Worklist(#[from] WorklistError),
+    #[error(transparent)]
+    UnknownPass(#[from] UnknownPassError),
+}
+
+pub type BrilResult<T> = Result<T, BrilError>;
+
+impl BrilError {
+    /// where in the source the error is, if known
+    pub fn position(&self) -> Option<&Position> {
+        match self {
+            Self::Worklist(e) => e.position(),
+            Self::Program(_) | Self::UnknownPass(_) => None,
+        }
+    }
+
+    /// The error message followed by the lines of `text` around the error position, if known.
+    /// See `WorklistError::to_string_with_context`
+    pub fn to_string_with_context(&self, text: &[String]) -> String {
+        match self {
+            Self::Worklist(e) => e.to_string_with_context(text),
+            Self::Program(_) | Self::UnknownPass(_) => format!("{}\n", self),
+        }
+    }
+}
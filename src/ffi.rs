@@ -0,0 +1,181 @@
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+
+use crate::{
+    optimizations::PassManager,
+    representation::{BrilFrontend, ProgramFormat, RichAbstractProgram, RichProgram},
+};
+
+// C interface, see include/rust_bril.h. Every function returns a `BrilStatus`; on failure the
+// message can be read with `bril_last_error` until the next call on the same thread
+
+/// Status codes returned by every FFI function
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrilStatus {
+    Ok = 0,
+    NullArgument = 1,
+    InvalidUtf8 = 2,
+    InvalidProgram = 3,
+    UnknownPass = 4,
+    PassFailed = 5,
+    Panic = 6,
+}
+
+/// Opaque handle to a program in SSA form
+pub struct BrilProgram {
+    program: RichAbstractProgram,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// run `f`, recording its error message and turning panics into `BrilStatus::Panic`
+fn guard(f: impl FnOnce() -> Result<(), (BrilStatus, String)>) -> BrilStatus {
+    LAST_ERROR.with(|e| *e.borrow_mut() = None);
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => BrilStatus::Ok,
+        Ok(Err((status, message))) => {
+            set_last_error(message);
+            status
+        }
+        Err(_) => {
+            set_last_error("rust_bril panicked".to_string());
+            BrilStatus::Panic
+        }
+    }
+}
+
+unsafe fn read_str<'a>(s: *const c_char) -> Result<&'a str, (BrilStatus, String)> {
+    if s.is_null() {
+        return Err((BrilStatus::NullArgument, "null string argument".to_string()));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|e| (BrilStatus::InvalidUtf8, e.to_string()))
+}
+
+/// Load a program from a NUL terminated Bril JSON buffer and convert it to SSA form.
+///
+/// # Safety
+/// `json` must be a valid NUL terminated string and `out` a valid pointer. The handle written to
+/// `out` must be released with `bril_program_free`.
+#[no_mangle]
+pub unsafe extern "C" fn bril_program_from_json(
+    json: *const c_char,
+    out: *mut *mut BrilProgram,
+) -> BrilStatus {
+    guard(|| {
+        if out.is_null() {
+            return Err((BrilStatus::NullArgument, "null output pointer".to_string()));
+        }
+        let json = read_str(json)?;
+        let program = RichProgram::from_text(json, ProgramFormat::Json, BrilFrontend::Native)
+            .map_err(|e| (BrilStatus::InvalidProgram, e.to_string()))?;
+        let original_text = program.original_text.clone();
+        let program = RichAbstractProgram::try_from_program(program).map_err(|e| {
+            (
+                BrilStatus::InvalidProgram,
+                e.to_string_with_context(&original_text),
+            )
+        })?;
+        *out = Box::into_raw(Box::new(BrilProgram { program }));
+        Ok(())
+    })
+}
+
+/// Run a comma separated pipeline of passes (`lvn`, `dvnt`, `dce`, `licm`, `constprop`,
+/// `copyprop`, `loadcse`, `unroll`, `rotate`, `reassoc`, `branchfold`, `simplifycfg`) on every
+/// function.
+///
+/// # Safety
+/// `program` must be a handle returned by `bril_program_from_json` and `passes` a valid NUL
+/// terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bril_run_passes(
+    program: *mut BrilProgram,
+    passes: *const c_char,
+) -> BrilStatus {
+    guard(|| {
+        let program = match program.as_mut() {
+            Some(p) => &mut p.program,
+            None => return Err((BrilStatus::NullArgument, "null program".to_string())),
+        };
+        let passes = read_str(passes)?;
+
+        // resolve every pass before running any, so a typo leaves the program untouched
+        let pipeline =
+            PassManager::from_names(passes.split(',').map(str::trim).filter(|p| !p.is_empty()))
+                .map_err(|e| (BrilStatus::UnknownPass, e.to_string()))?;
+        pipeline.run(&mut program.program).map_err(|e| {
+            (
+                BrilStatus::PassFailed,
+                e.to_string_with_context(&program.original_text),
+            )
+        })?;
+        Ok(())
+    })
+}
+
+/// Convert the program out of SSA form (unless `keep_ssa` is non-zero) and write it as Bril JSON.
+///
+/// # Safety
+/// `program` must be a handle returned by `bril_program_from_json` and `out` a valid pointer. The
+/// string written to `out` must be released with `bril_string_free`.
+#[no_mangle]
+pub unsafe extern "C" fn bril_program_to_json(
+    program: *const BrilProgram,
+    keep_ssa: i32,
+    out: *mut *mut c_char,
+) -> BrilStatus {
+    guard(|| {
+        let program = match program.as_ref() {
+            Some(p) => p.program.clone(),
+            None => return Err((BrilStatus::NullArgument, "null program".to_string())),
+        };
+        if out.is_null() {
+            return Err((BrilStatus::NullArgument, "null output pointer".to_string()));
+        }
+        let program = if keep_ssa != 0 {
+            program.into_ssa_program()
+        } else {
+            program.into_program()
+        };
+        *out = CString::new(program.to_string()).unwrap().into_raw();
+        Ok(())
+    })
+}
+
+/// Message of the last failed call on this thread, or NULL. Valid until the next FFI call.
+#[no_mangle]
+pub extern "C" fn bril_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+/// # Safety
+/// `program` must be NULL or a handle returned by `bril_program_from_json` that was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn bril_program_free(program: *mut BrilProgram) {
+    if !program.is_null() {
+        drop(Box::from_raw(program));
+    }
+}
+
+/// # Safety
+/// `s` must be NULL or a string returned by this library that was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn bril_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
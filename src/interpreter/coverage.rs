@@ -0,0 +1,248 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde::Serialize;
+
+use crate::{
+    interpreter::{Execution, ENTRY_BLOCK},
+    representation::{format_instruction, function_header, Code, Program},
+};
+
+// Which code a set of runs reached. The per-instruction counts of every run are added up; an
+// instruction is covered if it ran at least once, and a block, the instructions from one label
+// up to the next, if control entered it. Labels do not count as instructions. The annotated
+// listing is valid Bril, with the count of each instruction in a comment
+
+/// Instruction and block counts of a program, summed over runs
+#[derive(Debug, Clone)]
+pub struct Coverage<'a> {
+    program: &'a Program,
+    counts: Vec<Vec<u64>>,
+    runs: usize,
+}
+
+/// how much of something ran
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Covered {
+    pub covered: usize,
+    pub total: usize,
+}
+
+impl Covered {
+    fn add(&mut self, covered: bool) {
+        self.covered += covered as usize;
+        self.total += 1;
+    }
+
+    fn percent(&self) -> f64 {
+        match self.total {
+            0 => 100.0,
+            total => 100.0 * self.covered as f64 / total as f64,
+        }
+    }
+}
+
+impl fmt::Display for Covered {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} ({:.1}%)",
+            self.covered,
+            self.total,
+            self.percent()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FunctionCoverage {
+    pub instructions: Covered,
+    pub blocks: Covered,
+    /// labels of the blocks that never ran, ENTRY_BLOCK for the start of the function
+    pub uncovered_blocks: Vec<String>,
+    /// source lines of the instructions that never ran, where they are known
+    pub uncovered_lines: Vec<u32>,
+}
+
+/// The JSON summary of a [`Coverage`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CoverageSummary {
+    pub runs: usize,
+    pub instructions: Covered,
+    pub blocks: Covered,
+    pub functions: BTreeMap<String, FunctionCoverage>,
+}
+
+impl CoverageSummary {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("summaries are serializable")
+    }
+}
+
+impl fmt::Display for CoverageSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "instructions {}, blocks {} over {} runs",
+            self.instructions, self.blocks, self.runs
+        )
+    }
+}
+
+/// a block of a function: where it starts in the instructions and where the next one starts
+struct Block<'a> {
+    label: &'a str,
+    start: usize,
+    end: usize,
+}
+
+impl<'a> Coverage<'a> {
+    /// Nothing covered yet
+    pub fn new(program: &'a Program) -> Self {
+        let counts = program
+            .functions
+            .iter()
+            .map(|function| vec![0; function.instrs.len()])
+            .collect();
+        Self {
+            program,
+            counts,
+            runs: 0,
+        }
+    }
+
+    /// Add a run of the program
+    pub fn add(&mut self, execution: &Execution) {
+        for (counts, run) in self.counts.iter_mut().zip(&execution.counts) {
+            for (count, n) in counts.iter_mut().zip(run) {
+                *count += n;
+            }
+        }
+        self.runs += 1;
+    }
+
+    fn blocks(&self, function: usize) -> Vec<Block<'a>> {
+        let instrs = &self.program.functions[function].instrs;
+        let mut blocks: Vec<Block> = Vec::new();
+        for (i, code) in instrs.iter().enumerate() {
+            if let Code::Label { label, .. } = code {
+                blocks.push(Block {
+                    label,
+                    start: i,
+                    end: i,
+                });
+            } else if blocks.is_empty() {
+                blocks.push(Block {
+                    label: ENTRY_BLOCK,
+                    start: i,
+                    end: i,
+                });
+            }
+            blocks.last_mut().expect("a block was started").end = i + 1;
+        }
+        blocks
+    }
+
+    pub fn summary(&self) -> CoverageSummary {
+        let mut summary = CoverageSummary {
+            runs: self.runs,
+            ..CoverageSummary::default()
+        };
+        for (f, function) in self.program.functions.iter().enumerate() {
+            let counts = &self.counts[f];
+            let mut coverage = FunctionCoverage::default();
+            for block in self.blocks(f) {
+                let covered = counts[block.start] > 0;
+                coverage.blocks.add(covered);
+                if !covered {
+                    coverage.uncovered_blocks.push(block.label.to_string());
+                }
+            }
+            for (code, &n) in function.instrs.iter().zip(counts) {
+                if code.is_label() {
+                    continue;
+                }
+                coverage.instructions.add(n > 0);
+                if let (0, Some(pos)) = (n, code.get_position()) {
+                    coverage.uncovered_lines.push(pos.row);
+                }
+            }
+            summary.instructions.covered += coverage.instructions.covered;
+            summary.instructions.total += coverage.instructions.total;
+            summary.blocks.covered += coverage.blocks.covered;
+            summary.blocks.total += coverage.blocks.total;
+            summary.functions.insert(function.name.clone(), coverage);
+        }
+        summary
+    }
+
+    /// The program with how often each instruction ran, or `never`, in a comment after it
+    pub fn annotate(&self) -> String {
+        let mut functions = Vec::new();
+        for (function, counts) in self.program.functions.iter().zip(&self.counts) {
+            let lines: Vec<String> = function
+                .instrs
+                .iter()
+                .map(|code| match code.is_label() {
+                    true => format_instruction(code),
+                    false => format!("  {}", format_instruction(code)),
+                })
+                .collect();
+            let width = lines.iter().map(String::len).max().unwrap_or(0);
+            let mut out = format!("{} {{\n", function_header(function));
+            for (line, &n) in lines.iter().zip(counts) {
+                match n {
+                    0 => out.push_str(&format!("{:<width$}  # never\n", line)),
+                    n => out.push_str(&format!("{:<width$}  # {}\n", line, n)),
+                }
+            }
+            out.push_str("}\n");
+            functions.push(out);
+        }
+        functions.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interpreter::Interpreter, representation::parse_program};
+
+    #[test]
+    fn reports_what_no_run_reached() {
+        let text = "@main(n: int) {\n  zero: int = const 0;\n  neg: bool = lt n zero;\n  br neg .negative .done;\n.negative:\n  n: int = sub zero n;\n.done:\n  print n;\n}\n@unused {\n  ret;\n}\n";
+        let program = parse_program(text).unwrap();
+        let mut coverage = Coverage::new(&program);
+        for n in ["3", "4"] {
+            let execution = Interpreter::new(&program)
+                .run(&[n.to_string()], &mut std::io::sink())
+                .unwrap();
+            coverage.add(&execution);
+        }
+
+        let summary = coverage.summary();
+        assert_eq!(
+            summary.instructions,
+            Covered {
+                covered: 4,
+                total: 6
+            }
+        );
+        let main = &summary.functions["main"];
+        assert_eq!(main.uncovered_blocks, ["negative"]);
+        assert_eq!(main.uncovered_lines, [6]);
+        assert_eq!(summary.functions["unused"].uncovered_blocks, [ENTRY_BLOCK]);
+        assert_eq!(
+            summary.to_string(),
+            "instructions 4/6 (66.7%), blocks 2/4 (50.0%) over 2 runs"
+        );
+
+        let listing = coverage.annotate();
+        let comment = |instruction: &str| {
+            let line = listing.lines().find(|l| l.contains(instruction)).unwrap();
+            line.split_once("  # ").unwrap().1.to_string()
+        };
+        assert_eq!(comment("sub zero n"), "never");
+        assert_eq!(comment("print n"), "2");
+        assert!(parse_program(&listing).is_ok());
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{
+    interpreter::{Pointer, Value},
+    representation::{Position, Program},
+};
+
+// Memory of the interpreter. Allocations are never reused, so a dangling pointer always finds its
+// freed allocation. In memcheck mode every allocation also remembers the instructions that
+// allocated and freed it, which errors and leak reports point at
+
+/// an instruction, as the index of its function and its index in that function
+pub(crate) type Site = (usize, usize);
+
+/// An allocation that was never freed, found in memcheck mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leak {
+    pub function: String,
+    pub position: Option<Position>,
+    pub cells: usize,
+}
+
+struct Provenance {
+    allocated: Site,
+    freed: Option<Site>,
+}
+
+/// one allocation per `alloc`, `None` once freed. Cells are `None` until stored to
+pub(crate) struct Heap<'a> {
+    allocations: Vec<Option<Vec<Option<Value>>>>,
+    live: usize,
+    provenance: Option<(&'a Program, Vec<Provenance>)>,
+}
+
+impl<'a> Heap<'a> {
+    /// a heap that tracks provenance when given the program it runs
+    pub(crate) fn new(memcheck: Option<&'a Program>) -> Self {
+        Self {
+            allocations: Vec::new(),
+            live: 0,
+            provenance: memcheck.map(|program| (program, Vec::new())),
+        }
+    }
+
+    pub(crate) fn live(&self) -> usize {
+        self.live
+    }
+
+    pub(crate) fn alloc(&mut self, size: i64, site: Site) -> Result<Pointer, String> {
+        if size <= 0 {
+            return Err(format!("cannot allocate {} cells", size));
+        }
+        self.allocations.push(Some(vec![None; size as usize]));
+        self.live += 1;
+        if let Some((_, provenance)) = &mut self.provenance {
+            provenance.push(Provenance {
+                allocated: site,
+                freed: None,
+            });
+        }
+        Ok(Pointer {
+            allocation: self.allocations.len() - 1,
+            offset: 0,
+        })
+    }
+
+    pub(crate) fn free(&mut self, p: Pointer, site: Site) -> Result<(), String> {
+        if p.offset != 0 {
+            let origin = self.origin(p.allocation);
+            return Err(format!(
+                "cannot free a pointer at offset {}{}",
+                p.offset, origin
+            ));
+        }
+        match self.allocations.get_mut(p.allocation) {
+            Some(cells @ Some(_)) => {
+                *cells = None;
+                self.live -= 1;
+                if let Some((_, provenance)) = &mut self.provenance {
+                    provenance[p.allocation].freed = Some(site);
+                }
+                Ok(())
+            }
+            _ => Err(format!("double free{}", self.origin(p.allocation))),
+        }
+    }
+
+    pub(crate) fn cell(&mut self, p: Pointer) -> Result<&mut Option<Value>, String> {
+        let Some(Some(cells)) = self.allocations.get(p.allocation) else {
+            return Err(format!("use of freed memory{}", self.origin(p.allocation)));
+        };
+        let size = cells.len();
+        let Some(index) = usize::try_from(p.offset).ok().filter(|&i| i < size) else {
+            let origin = self.origin(p.allocation);
+            return Err(format!(
+                "offset {} is out of bounds of {} cells{}",
+                p.offset, size, origin
+            ));
+        };
+        let cells = self.allocations[p.allocation]
+            .as_mut()
+            .expect("checked above");
+        Ok(&mut cells[index])
+    }
+
+    /// Free every allocation that no pointer in `roots` reaches, directly or through pointers
+    /// stored in memory, and return how many were freed
+    pub(crate) fn collect(&mut self, roots: impl IntoIterator<Item = Value>) -> usize {
+        let allocation = |value: &Value| match value {
+            Value::Ptr(p) => Some(p.allocation),
+            _ => None,
+        };
+        let mut reached = vec![false; self.allocations.len()];
+        let mut stack: Vec<usize> = roots.into_iter().filter_map(|v| allocation(&v)).collect();
+        while let Some(a) = stack.pop() {
+            if std::mem::replace(&mut reached[a], true) {
+                continue;
+            }
+            if let Some(cells) = &self.allocations[a] {
+                stack.extend(cells.iter().flatten().filter_map(allocation));
+            }
+        }
+
+        let mut freed = 0;
+        for (cells, reached) in self.allocations.iter_mut().zip(reached) {
+            if cells.is_some() && !reached {
+                *cells = None;
+                freed += 1;
+            }
+        }
+        self.live -= freed;
+        freed
+    }
+
+    /// allocations still live, in the order they were made. Empty outside memcheck mode
+    pub(crate) fn leaks(&self) -> Vec<Leak> {
+        let Some((program, provenance)) = &self.provenance else {
+            return Vec::new();
+        };
+        self.allocations
+            .iter()
+            .zip(provenance)
+            .filter_map(|(cells, provenance)| {
+                let (function, pc) = provenance.allocated;
+                let function = &program.functions[function];
+                Some(Leak {
+                    function: function.name.clone(),
+                    position: function.instrs[pc].get_position(),
+                    cells: cells.as_ref()?.len(),
+                })
+            })
+            .collect()
+    }
+
+    /// ` (allocated at 3:5 in @main, freed at 9:3 in @f)` in memcheck mode
+    fn origin(&self, allocation: usize) -> String {
+        let Some((program, provenance)) = &self.provenance else {
+            return String::new();
+        };
+        let Some(provenance) = provenance.get(allocation) else {
+            return String::new();
+        };
+        let at = |(function, pc): Site| {
+            let function = &program.functions[function];
+            match function.instrs[pc].get_position() {
+                Some(pos) => format!("{}:{} in @{}", pos.row, pos.col, function.name),
+                None => format!("@{}", function.name),
+            }
+        };
+        match provenance.freed {
+            Some(freed) => format!(
+                " (allocated at {}, freed at {})",
+                at(provenance.allocated),
+                at(freed)
+            ),
+            None => format!(" (allocated at {})", at(provenance.allocated)),
+        }
+    }
+}
@@ -0,0 +1,772 @@
+use std::{cell::RefCell, collections::HashMap, io::Write, time::Duration};
+
+use thiserror::Error;
+use web_time::Instant;
+
+use crate::{
+    interpreter::{BlockCounter, BlockProfile, Heap, Leak, Pointer, Profile, Value},
+    representation::{
+        format_instruction, format_type, opcode, Code, EffectOp, Function, Literal, MemoryOp,
+        Position, Program, Type, ValueOp,
+    },
+};
+
+// A reference interpreter for Bril programs, in or out of SSA form, with the semantics of `brili`.
+// Calls push frames on an explicit stack, so deep recursion cannot overflow the native stack
+
+#[derive(Debug, Error)]
+pub enum InterpError {
+    #[error("the program has no @main function")]
+    NoMain,
+    #[error("@main takes {expected} arguments, got {found}")]
+    ArgumentCount { expected: usize, found: usize },
+    #[error("cannot read argument '{value}' of @main as {expected}")]
+    BadArgument { value: String, expected: String },
+    #[error("{}{message} in @{function}", at(.position))]
+    Runtime {
+        function: String,
+        position: Option<Position>,
+        message: String,
+    },
+    /// `leaks` lists every allocation in memcheck mode and is empty otherwise
+    #[error("{count} allocations were not freed by the end of execution{}", leak_list(.leaks))]
+    Leak { count: usize, leaks: Vec<Leak> },
+    #[error("ran out of fuel after {limit} instructions")]
+    OutOfFuel { limit: u64 },
+    #[error("timed out after {}s", .limit.as_secs_f64())]
+    Timeout { limit: Duration },
+    #[error("cannot write output: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl InterpError {
+    /// the run was stopped by its fuel or its timeout, so it may not terminate at all
+    pub fn is_limit(&self) -> bool {
+        matches!(
+            self,
+            InterpError::OutOfFuel { .. } | InterpError::Timeout { .. }
+        )
+    }
+}
+
+fn at(position: &Option<Position>) -> String {
+    position
+        .map(|pos| format!("{}:{}: ", pos.row, pos.col))
+        .unwrap_or_default()
+}
+
+fn leak_list(leaks: &[Leak]) -> String {
+    leaks
+        .iter()
+        .map(|leak| {
+            let position = leak.position.map(|pos| format!("{}:{} ", pos.row, pos.col));
+            format!(
+                "\n  {} cells allocated at {}in @{}",
+                leak.cells,
+                position.unwrap_or_default(),
+                leak.function
+            )
+        })
+        .collect()
+}
+
+impl InterpError {
+    /// Source position of the instruction that failed
+    pub fn position(&self) -> Option<&Position> {
+        match self {
+            InterpError::Runtime { position, .. } => position.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// What a successful run observed
+#[derive(Debug, Clone)]
+pub struct Execution {
+    pub profile: Profile,
+    /// how often each instruction ran, indexed like the functions of the program and their
+    /// instructions
+    pub counts: Vec<Vec<u64>>,
+    /// block and edge counts, if the interpreter was asked for them
+    pub blocks: Option<BlockProfile>,
+}
+
+pub struct Interpreter<'a> {
+    program: &'a Program,
+    functions: HashMap<&'a str, usize>,
+    /// instruction index of every label, per function
+    labels: Vec<HashMap<&'a str, usize>>,
+    memcheck: bool,
+    fuel: Option<u64>,
+    timeout: Option<Duration>,
+    block_counts: bool,
+    gc: bool,
+    /// receives every executed instruction
+    trace: Option<RefCell<&'a mut dyn Write>>,
+}
+
+struct Frame<'a> {
+    function: usize,
+    pc: usize,
+    env: HashMap<&'a str, Value>,
+    /// the labels of the two blocks entered last, which decide the value of a phi node
+    last_label: Option<&'a str>,
+    current_label: Option<&'a str>,
+    /// variable of this frame that receives the result of the call it is waiting for
+    result: Option<&'a str>,
+    /// state at each `speculate` that has not been committed yet, innermost last
+    speculation: Vec<Snapshot<'a>>,
+}
+
+/// what a failing `guard` restores. Memory and output are not restored
+struct Snapshot<'a> {
+    env: HashMap<&'a str, Value>,
+    last_label: Option<&'a str>,
+    current_label: Option<&'a str>,
+}
+
+fn read(env: &HashMap<&str, Value>, name: &str) -> Result<Value, String> {
+    env.get(name)
+        .copied()
+        .ok_or_else(|| format!("undefined variable {}", name))
+}
+
+fn read_all(env: &HashMap<&str, Value>, names: &Option<Vec<String>>) -> Result<Vec<Value>, String> {
+    names.iter().flatten().map(|name| read(env, name)).collect()
+}
+
+fn operands(op: impl std::fmt::Debug, args: &[Value]) -> String {
+    let types: Vec<&str> = args.iter().map(Value::type_name).collect();
+    format!(
+        "{} cannot take ({})",
+        format!("{:?}", op).to_lowercase(),
+        types.join(", ")
+    )
+}
+
+/// the result of `op` on `args`, or why it fails at run time
+pub(crate) fn eval_value(op: ValueOp, args: &[Value]) -> Result<Value, String> {
+    use Value::{Bool, Char, Float, Int};
+    Ok(match (op, args) {
+        (ValueOp::Add, [Int(a), Int(b)]) => Int(a.wrapping_add(*b)),
+        (ValueOp::Sub, [Int(a), Int(b)]) => Int(a.wrapping_sub(*b)),
+        (ValueOp::Mul, [Int(a), Int(b)]) => Int(a.wrapping_mul(*b)),
+        (ValueOp::Div, [Int(_), Int(0)]) => return Err("division by zero".to_string()),
+        (ValueOp::Div, [Int(a), Int(b)]) => Int(a.wrapping_div(*b)),
+        (ValueOp::Eq, [Int(a), Int(b)]) => Bool(a == b),
+        (ValueOp::Lt, [Int(a), Int(b)]) => Bool(a < b),
+        (ValueOp::Gt, [Int(a), Int(b)]) => Bool(a > b),
+        (ValueOp::Le, [Int(a), Int(b)]) => Bool(a <= b),
+        (ValueOp::Ge, [Int(a), Int(b)]) => Bool(a >= b),
+        (ValueOp::Not, [Bool(a)]) => Bool(!a),
+        (ValueOp::And, [Bool(a), Bool(b)]) => Bool(*a && *b),
+        (ValueOp::Or, [Bool(a), Bool(b)]) => Bool(*a || *b),
+        (ValueOp::Id, [value]) => *value,
+        (ValueOp::Fadd, [Float(a), Float(b)]) => Float(a + b),
+        (ValueOp::Fsub, [Float(a), Float(b)]) => Float(a - b),
+        (ValueOp::Fmul, [Float(a), Float(b)]) => Float(a * b),
+        (ValueOp::Fdiv, [Float(a), Float(b)]) => Float(a / b),
+        (ValueOp::Feq, [Float(a), Float(b)]) => Bool(a == b),
+        (ValueOp::Flt, [Float(a), Float(b)]) => Bool(a < b),
+        (ValueOp::Fgt, [Float(a), Float(b)]) => Bool(a > b),
+        (ValueOp::Fle, [Float(a), Float(b)]) => Bool(a <= b),
+        (ValueOp::Fge, [Float(a), Float(b)]) => Bool(a >= b),
+        (ValueOp::Ceq, [Char(a), Char(b)]) => Bool(a == b),
+        (ValueOp::Clt, [Char(a), Char(b)]) => Bool(a < b),
+        (ValueOp::Cgt, [Char(a), Char(b)]) => Bool(a > b),
+        (ValueOp::Cle, [Char(a), Char(b)]) => Bool(a <= b),
+        (ValueOp::Cge, [Char(a), Char(b)]) => Bool(a >= b),
+        (ValueOp::Char2int, [Char(c)]) => Int(*c as i64),
+        (ValueOp::Int2char, [Int(x)]) => u32::try_from(*x)
+            .ok()
+            .and_then(char::from_u32)
+            .map(Char)
+            .ok_or_else(|| format!("{} is not a character", x))?,
+        (ValueOp::Float2bits, [Float(x)]) => Int(x.to_bits() as i64),
+        (ValueOp::Bits2float, [Int(x)]) => Float(f64::from_bits(*x as u64)),
+        (op, args) => return eval_extension(op, args),
+    })
+}
+
+/// evaluates an operation outside the core set through the opcode registry
+fn eval_extension(op: ValueOp, args: &[Value]) -> Result<Value, String> {
+    let info = match opcode(op) {
+        Some(info) if info.operands.len() == args.len() && args.len() <= 2 => info,
+        _ => return Err(operands(op, args)),
+    };
+    let mut literals = [Literal::Int(0); 2];
+    for ((literal, value), t) in literals.iter_mut().zip(args).zip(info.operands) {
+        *literal = match (value, t) {
+            (Value::Int(x), Type::Int) => Literal::Int(*x),
+            (Value::Bool(b), Type::Bool) => Literal::Bool(*b),
+            (Value::Float(x), Type::Float) => Literal::Float(*x),
+            (Value::Char(c), Type::Char) => Literal::Char(*c),
+            _ => return Err(operands(op, args)),
+        };
+    }
+    let result = (info.eval)(&literals[..args.len()])?;
+    Ok(Value::from_literal(&result, &info.result).expect("results have the declared type"))
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        let functions = program
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.name.as_str(), i))
+            .collect();
+        let labels = program
+            .functions
+            .iter()
+            .map(|f| {
+                f.instrs
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, code)| match code {
+                        Code::Label { label, .. } => Some((label.as_str(), i)),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            program,
+            functions,
+            labels,
+            memcheck: false,
+            fuel: None,
+            timeout: None,
+            block_counts: false,
+            gc: false,
+            trace: None,
+        }
+    }
+
+    /// Remember where every allocation was made and freed, so that memory errors name both
+    /// instructions and leaks are listed one by one
+    pub fn memcheck(mut self, memcheck: bool) -> Self {
+        self.memcheck = memcheck;
+        self
+    }
+
+    /// Stop with an error after executing `fuel` instructions, for programs that may not terminate
+    pub fn fuel(mut self, fuel: Option<u64>) -> Self {
+        self.fuel = fuel;
+        self
+    }
+
+    /// Stop with an error once the run takes longer than `timeout`. Unlike fuel, whether a run
+    /// times out depends on the machine
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Free allocations once no variable or stored pointer reaches them, and let the program end
+    /// with memory it did not free, for benchmarks that leave freeing to the runtime
+    pub fn gc(mut self, gc: bool) -> Self {
+        self.gc = gc;
+        self
+    }
+
+    /// Count how often each block is entered and each edge between blocks taken
+    pub fn block_counts(mut self, block_counts: bool) -> Self {
+        self.block_counts = block_counts;
+        self
+    }
+
+    /// Write every instruction to `trace` as it executes, indented by the depth of the call stack
+    pub fn trace(mut self, trace: Option<&'a mut dyn Write>) -> Self {
+        self.trace = trace.map(RefCell::new);
+        self
+    }
+
+    /// Run @main with `args`, parsed by the types of its parameters, writing what it prints to
+    /// `out`
+    pub fn run(&self, args: &[String], out: &mut dyn Write) -> Result<Execution, InterpError> {
+        let main = *self.functions.get("main").ok_or(InterpError::NoMain)?;
+        let params = self.program.functions[main].args.as_deref().unwrap_or(&[]);
+        if params.len() != args.len() {
+            return Err(InterpError::ArgumentCount {
+                expected: params.len(),
+                found: args.len(),
+            });
+        }
+        let values = params
+            .iter()
+            .zip(args)
+            .map(|(param, arg)| {
+                Value::parse(arg, &param.arg_type).ok_or_else(|| InterpError::BadArgument {
+                    value: arg.clone(),
+                    expected: format_type(&param.arg_type),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut counts: Vec<Vec<u64>> = self
+            .program
+            .functions
+            .iter()
+            .map(|f| vec![0; f.instrs.len()])
+            .collect();
+        let mut heap = Heap::new(self.memcheck.then_some(self.program));
+        let mut blocks = self
+            .block_counts
+            .then(|| BlockCounter::new(self.program.functions.len()));
+        self.execute(main, values, &mut counts, &mut blocks, &mut heap, out)?;
+        out.flush()?;
+        if let Some(trace) = &self.trace {
+            trace.borrow_mut().flush()?;
+        }
+        if heap.live() > 0 && !self.gc {
+            return Err(InterpError::Leak {
+                count: heap.live(),
+                leaks: heap.leaks(),
+            });
+        }
+        Ok(Execution {
+            profile: Profile::from_counts(self.program, &counts),
+            counts,
+            blocks: blocks.map(|blocks| blocks.into_profile(self.program)),
+        })
+    }
+
+    fn frame(&self, function: usize, args: Vec<Value>) -> Frame<'a> {
+        let params = self.program.functions[function].args.iter().flatten();
+        Frame {
+            function,
+            pc: 0,
+            env: params.map(|p| p.name.as_str()).zip(args).collect(),
+            last_label: None,
+            current_label: None,
+            result: None,
+            speculation: Vec::new(),
+        }
+    }
+
+    fn callee(&self, funcs: &Option<Box<[String]>>, args: usize) -> Result<usize, String> {
+        let name = funcs
+            .as_ref()
+            .and_then(|funcs| funcs.first())
+            .ok_or("call without a function")?;
+        let index = *self
+            .functions
+            .get(name.as_str())
+            .ok_or_else(|| format!("call to unknown function @{}", name))?;
+        let expected = self.program.functions[index]
+            .args
+            .as_ref()
+            .map_or(0, Vec::len);
+        if expected != args {
+            return Err(format!(
+                "@{} takes {} arguments, got {}",
+                name, expected, args
+            ));
+        }
+        Ok(index)
+    }
+
+    fn jump(&self, function: usize, label: Option<&String>) -> Result<usize, String> {
+        let label = label.ok_or("jump without a label")?;
+        self.labels[function]
+            .get(label.as_str())
+            .copied()
+            .ok_or_else(|| format!("jump to unknown label .{}", label))
+    }
+
+    fn execute(
+        &self,
+        main: usize,
+        args: Vec<Value>,
+        counts: &mut [Vec<u64>],
+        blocks: &mut Option<BlockCounter<'a>>,
+        heap: &mut Heap<'a>,
+        out: &mut dyn Write,
+    ) -> Result<(), InterpError> {
+        let mut stack = vec![self.frame(main, args)];
+        if let Some(blocks) = blocks {
+            blocks.call(main);
+        }
+        let mut fuel = self.fuel;
+        let mut next_collection = GC_THRESHOLD;
+        let deadline = self.timeout.map(|limit| (Instant::now() + limit, limit));
+        let mut steps: u64 = 0;
+        loop {
+            if self.gc && heap.live() >= next_collection {
+                let roots = stack.iter().flat_map(|frame| {
+                    let snapshots = frame.speculation.iter().flat_map(|s| s.env.values());
+                    frame.env.values().chain(snapshots).copied()
+                });
+                let freed = heap.collect(roots);
+                log::debug!("collected {} of {} allocations", freed, freed + heap.live());
+                next_collection = (heap.live() * 2).max(GC_THRESHOLD);
+            }
+            let depth = stack.len();
+            let Some(frame) = stack.last_mut() else {
+                break;
+            };
+            let function: &'a Function = &self.program.functions[frame.function];
+            let Some(code) = function.instrs.get(frame.pc) else {
+                // falling off the end returns nothing
+                self.ret(&mut stack, None)?;
+                continue;
+            };
+            if let Some(fuel) = &mut fuel {
+                match fuel.checked_sub(1) {
+                    Some(left) => *fuel = left,
+                    None => {
+                        let limit = self.fuel.unwrap_or_default();
+                        return Err(InterpError::OutOfFuel { limit });
+                    }
+                }
+            }
+            steps += 1;
+            if let Some((deadline, limit)) = deadline {
+                if steps.is_multiple_of(CLOCK_INTERVAL) && Instant::now() >= deadline {
+                    return Err(InterpError::Timeout { limit });
+                }
+            }
+            counts[frame.function][frame.pc] += 1;
+            frame.pc += 1;
+            if let (Some(blocks), Code::Label { label, .. }) = (blocks.as_mut(), code) {
+                blocks.enter(frame.function, frame.current_label, label);
+            }
+            if let Some(trace) = &self.trace {
+                let indent = "  ".repeat(depth - 1);
+                let instruction = format_instruction(code);
+                writeln!(
+                    trace.borrow_mut(),
+                    "{}@{} {}",
+                    indent,
+                    function.name,
+                    instruction
+                )?;
+            }
+            let error = |message: String| InterpError::Runtime {
+                function: function.name.clone(),
+                position: code.get_position(),
+                message,
+            };
+
+            match self.step(frame, code, heap, out).map_err(error)? {
+                Step::Next => {}
+                Step::Call(callee, args) => {
+                    if let Some(blocks) = blocks {
+                        blocks.call(callee);
+                    }
+                    stack.push(self.frame(callee, args));
+                }
+                Step::Return(value) => self.ret(&mut stack, value)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// pop the frame on top of `stack` and hand `value` to its caller
+    fn ret(&self, stack: &mut Vec<Frame<'a>>, value: Option<Value>) -> Result<(), InterpError> {
+        let callee = stack.pop().expect("a frame returns");
+        let Some(caller) = stack.last_mut() else {
+            return Ok(());
+        };
+        let Some(dest) = caller.result.take() else {
+            return Ok(());
+        };
+        match value {
+            Some(value) => {
+                caller.env.insert(dest, value);
+                Ok(())
+            }
+            None => {
+                let function = &self.program.functions[caller.function];
+                Err(InterpError::Runtime {
+                    function: function.name.clone(),
+                    position: function.instrs[caller.pc - 1].get_position(),
+                    message: format!(
+                        "@{} returned nothing to {}",
+                        self.program.functions[callee.function].name, dest
+                    ),
+                })
+            }
+        }
+    }
+
+    /// execute one instruction of the function of `frame` other than a jump into a call
+    fn step(
+        &self,
+        frame: &mut Frame<'a>,
+        code: &'a Code,
+        heap: &mut Heap<'a>,
+        out: &mut dyn Write,
+    ) -> Result<Step, String> {
+        match code {
+            Code::Label { label, .. } => {
+                frame.last_label = frame.current_label;
+                frame.current_label = Some(label);
+            }
+            Code::Noop { .. } => {}
+            Code::Constant {
+                dest,
+                constant_type,
+                value,
+                ..
+            } => {
+                let value = Value::from_literal(value, constant_type).ok_or_else(|| {
+                    let t = format_type(constant_type);
+                    format!("{:?} is not a constant of type {}", value, t)
+                })?;
+                frame.env.insert(dest, value);
+            }
+            Code::Value {
+                op: ValueOp::Call,
+                dest,
+                args,
+                funcs,
+                ..
+            } => {
+                let values = read_all(&frame.env, args)?;
+                let callee = self.callee(funcs, values.len())?;
+                frame.result = Some(dest);
+                return Ok(Step::Call(callee, values));
+            }
+            Code::Value {
+                op: ValueOp::Phi,
+                dest,
+                args,
+                labels,
+                ..
+            } => {
+                // a phi node without an entry for the block we came from leaves its variable
+                // undefined
+                let incoming = labels
+                    .iter()
+                    .flatten()
+                    .zip(args.iter().flatten())
+                    .find(|(label, _)| Some(label.as_str()) == frame.last_label);
+                match incoming {
+                    Some((_, arg)) => {
+                        let value = read(&frame.env, arg)?;
+                        frame.env.insert(dest, value);
+                    }
+                    None => {
+                        frame.env.remove(dest.as_str());
+                    }
+                }
+            }
+            Code::Value { op, dest, args, .. } => {
+                let value = eval_value(*op, &read_all(&frame.env, args)?)?;
+                frame.env.insert(dest, value);
+            }
+            Code::Effect {
+                op,
+                args,
+                funcs,
+                labels,
+                ..
+            } => {
+                let values = read_all(&frame.env, args)?;
+                let labels = labels.as_deref().unwrap_or(&[]);
+                match (op, values.as_slice()) {
+                    (EffectOp::Ret, _) if !frame.speculation.is_empty() => {
+                        return Err("return while speculating".to_string())
+                    }
+                    (EffectOp::Jmp, []) => frame.pc = self.jump(frame.function, labels.first())?,
+                    (EffectOp::Br, [Value::Bool(condition)]) => {
+                        let target = labels.get(!condition as usize);
+                        frame.pc = self.jump(frame.function, target)?;
+                    }
+                    (EffectOp::Ret, []) => return Ok(Step::Return(None)),
+                    (EffectOp::Ret, [value]) => return Ok(Step::Return(Some(*value))),
+                    (EffectOp::Call, _) => {
+                        let callee = self.callee(funcs, values.len())?;
+                        return Ok(Step::Call(callee, values));
+                    }
+                    (EffectOp::Print, values) => {
+                        let text: Vec<String> = values.iter().map(Value::to_string).collect();
+                        writeln!(out, "{}", text.join(" ")).map_err(|e| e.to_string())?;
+                    }
+                    (EffectOp::Speculate, []) => frame.speculation.push(Snapshot {
+                        env: frame.env.clone(),
+                        last_label: frame.last_label,
+                        current_label: frame.current_label,
+                    }),
+                    (EffectOp::Commit, []) => {
+                        frame
+                            .speculation
+                            .pop()
+                            .ok_or("commit outside of speculation")?;
+                    }
+                    (EffectOp::Guard, [Value::Bool(true)]) => {}
+                    (EffectOp::Guard, [Value::Bool(false)]) => {
+                        let snapshot = frame
+                            .speculation
+                            .pop()
+                            .ok_or("guard failed outside of speculation")?;
+                        frame.env = snapshot.env;
+                        frame.last_label = snapshot.last_label;
+                        frame.current_label = snapshot.current_label;
+                        frame.pc = self.jump(frame.function, labels.first())?;
+                    }
+                    (op, values) => return Err(operands(op, values)),
+                }
+            }
+            Code::Memory { op, args, dest, .. } => {
+                let values = read_all(&frame.env, args)?;
+                let site = (frame.function, frame.pc - 1);
+                let value = match (op, values.as_slice()) {
+                    (MemoryOp::Alloc, [Value::Int(size)]) => {
+                        Some(Value::Ptr(heap.alloc(*size, site)?))
+                    }
+                    (MemoryOp::Free, [Value::Ptr(p)]) => {
+                        heap.free(*p, site)?;
+                        None
+                    }
+                    (MemoryOp::Store, [Value::Ptr(p), value]) => {
+                        *heap.cell(*p)? = Some(*value);
+                        None
+                    }
+                    (MemoryOp::Load, [Value::Ptr(p)]) => {
+                        let value = heap.cell(*p)?.ok_or("load of an uninitialized cell")?;
+                        Some(value)
+                    }
+                    (MemoryOp::PtrAdd, [Value::Ptr(p), Value::Int(n)]) => {
+                        Some(Value::Ptr(Pointer {
+                            allocation: p.allocation,
+                            offset: p.offset.wrapping_add(*n),
+                        }))
+                    }
+                    (op, values) => return Err(operands(op, values)),
+                };
+                if let (Some(dest), Some(value)) = (dest, value) {
+                    frame.env.insert(dest, value);
+                }
+            }
+        }
+        Ok(Step::Next)
+    }
+}
+
+/// live allocations before the first collection in gc mode. Later collections wait until the
+/// live allocations double
+const GC_THRESHOLD: usize = 1024;
+
+/// instructions between two looks at the clock when there is a timeout
+const CLOCK_INTERVAL: u64 = 4096;
+
+enum Step {
+    Next,
+    Call(usize, Vec<Value>),
+    Return(Option<Value>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::representation::parse_program;
+
+    fn run(text: &str, args: &[&str]) -> (String, Result<Execution, InterpError>) {
+        run_with(text, args, false)
+    }
+
+    fn run_with(
+        text: &str,
+        args: &[&str],
+        memcheck: bool,
+    ) -> (String, Result<Execution, InterpError>) {
+        let program = parse_program(text).unwrap();
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let mut out = Vec::new();
+        let result = Interpreter::new(&program)
+            .memcheck(memcheck)
+            .run(&args, &mut out);
+        (String::from_utf8(out).unwrap(), result)
+    }
+
+    #[test]
+    fn runs_calls_loops_and_memory() {
+        let text = "@main(n: int) {\n  p: ptr<int> = alloc n;\n  r: int = call @sum n;\n  store p r;\n  v: int = load p;\n  print v;\n  free p;\n}\n@sum(n: int): int {\n  i: int = const 0;\n  one: int = const 1;\n.loop:\n  done: bool = eq n i;\n  br done .end .body;\n.body:\n  i: int = add i one;\n  jmp .loop;\n.end:\n  ret i;\n}\n";
+        let (out, execution) = run(text, &["3"]);
+        assert_eq!(out, "3\n");
+        let profile = execution.unwrap().profile;
+        assert_eq!(profile.total, 23);
+        assert_eq!(profile.by_op["add"], 3);
+        assert_eq!(profile.by_op["br"], 4);
+    }
+
+    #[test]
+    fn follows_phi_nodes_and_reports_positions() {
+        let text = "@main(c: bool) {\n  br c .a .b;\n.a:\n  x: int = const 1;\n  jmp .m;\n.b:\n  y: int = const 2;\n.m:\n  z: int = phi x y .a .b;\n  print z;\n}\n";
+        assert_eq!(run(text, &["true"]).0, "1\n");
+        assert_eq!(run(text, &["false"]).0, "2\n");
+
+        let text = "@main {\n  p: ptr<int> = alloc one;\n}\n";
+        let (_, error) = run(text, &[]);
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "2:3: undefined variable one in @main"
+        );
+        let text = "@main {\n  one: int = const 1;\n  p: ptr<int> = alloc one;\n}\n";
+        assert!(matches!(
+            run(text, &[]).1,
+            Err(InterpError::Leak { count: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn memcheck_names_allocation_sites() {
+        let text =
+            "@main {\n  two: int = const 2;\n  p: ptr<int> = alloc two;\n  free p;\n  free p;\n}\n";
+        let error = run_with(text, &[], true).1.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "5:3: double free (allocated at 3:3 in @main, freed at 4:3 in @main) in @main"
+        );
+
+        let text = "@main {\n  two: int = const 2;\n  p: ptr<int> = alloc two;\n  q: ptr<int> = ptradd p two;\n  v: int = load q;\n}\n";
+        let error = run_with(text, &[], true).1.unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("5:3: offset 2 is out of bounds of 2 cells"));
+
+        let text = "@main {\n  two: int = const 2;\n  p: ptr<int> = alloc two;\n}\n";
+        let Err(InterpError::Leak { leaks, .. }) = run_with(text, &[], true).1 else {
+            panic!("expected a leak");
+        };
+        assert_eq!(leaks[0].position, Some(Position { row: 3, col: 3 }));
+        assert_eq!(leaks[0].cells, 2);
+    }
+
+    #[test]
+    fn gc_keeps_reachable_memory_and_allows_leaks() {
+        // `first` is overwritten in the loop, so its cell stays reachable only through `head`
+        let text = "@main {\n  one: int = const 1;\n  n: int = const 3000;\n  i: int = const 0;\n  head: ptr<ptr<int>> = alloc one;\n  first: ptr<int> = alloc one;\n  seven: int = const 7;\n  store first seven;\n  store head first;\n.loop:\n  more: bool = lt i n;\n  br more .body .end;\n.body:\n  first: ptr<int> = alloc one;\n  i: int = add i one;\n  jmp .loop;\n.end:\n  p: ptr<int> = load head;\n  v: int = load p;\n  print v;\n}\n";
+        assert!(matches!(
+            run(text, &[]).1,
+            Err(InterpError::Leak { count: 3002, .. })
+        ));
+
+        let program = parse_program(text).unwrap();
+        let mut out = Vec::new();
+        Interpreter::new(&program)
+            .gc(true)
+            .run(&[], &mut out)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "7\n");
+    }
+
+    #[test]
+    fn rolls_back_failed_speculation() {
+        let text = "@main(n: int) {\n  x: int = const 1;\n  zero: int = const 0;\n  speculate;\n  x: int = add x n;\n  big: bool = lt zero n;\n  guard big .failed;\n  commit;\n  print x;\n  ret;\n.failed:\n  print x;\n}\n";
+        assert_eq!(run(text, &["5"]).0, "6\n");
+        assert_eq!(run(text, &["-5"]).0, "1\n");
+
+        let text = "@main {\n  speculate;\n  ret;\n}\n";
+        let error = run(text, &[]).1.unwrap_err();
+        assert_eq!(error.to_string(), "3:3: return while speculating in @main");
+        let text = "@main {\n  f: bool = const false;\n  guard f .end;\n.end:\n}\n";
+        let error = run(text, &[]).1.unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("guard failed outside of speculation"));
+    }
+}
@@ -0,0 +1,13 @@
+mod coverage;
+mod heap;
+mod machine;
+mod profile;
+mod trace;
+mod value;
+
+pub use coverage::*;
+pub use heap::*;
+pub use machine::*;
+pub use profile::*;
+pub use trace::*;
+pub use value::*;
@@ -0,0 +1,55 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::representation::Program;
+
+// Dynamic instruction counts. `total` counts every executed instruction except labels, like
+// `brili -p`, and `by_op` splits it by opcode
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Profile {
+    pub total: u64,
+    pub by_op: BTreeMap<String, u64>,
+}
+
+impl Profile {
+    /// sum the per-instruction counts of a run of `program`, indexed like its functions and
+    /// their instructions
+    pub(crate) fn from_counts(program: &Program, counts: &[Vec<u64>]) -> Self {
+        let mut profile = Profile::default();
+        for (function, counts) in program.functions.iter().zip(counts) {
+            for (code, &n) in function.instrs.iter().zip(counts) {
+                if n > 0 && !code.is_label() {
+                    profile.total += n;
+                    *profile.by_op.entry(code.get_opcode_string()).or_default() += n;
+                }
+            }
+        }
+        profile
+    }
+
+    /// the `brili -p` line
+    pub fn summary(&self) -> String {
+        format!("total_dyn_inst: {}", self.total)
+    }
+
+    /// Opcodes by how often they ran, most frequent first, with their share of the total
+    pub fn to_table(&self) -> String {
+        let mut rows: Vec<(&String, &u64)> = self.by_op.iter().collect();
+        rows.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let width = rows.iter().map(|r| r.0.len()).max().unwrap_or(0).max(6);
+
+        let mut table = format!("{:<width$}  {:>12}  {:>6}\n", "opcode", "count", "%");
+        for (op, n) in rows {
+            let share = 100.0 * *n as f64 / self.total.max(1) as f64;
+            table.push_str(&format!("{:<width$}  {:>12}  {:>6.2}\n", op, n, share));
+        }
+        table.push_str(&format!("{:<width$}  {:>12}\n", "total", self.total));
+        table
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("profiles are serializable")
+    }
+}
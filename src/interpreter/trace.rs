@@ -0,0 +1,251 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::representation::Program;
+
+// Block and edge counts of a run, the raw data for profile-guided passes and coverage. A block is
+// entered whenever its label executes, by a jump or by falling through, and the edge into it
+// comes from the block that was running before. Every call enters the function at ENTRY_BLOCK.
+// Profiles are saved as JSON:
+//
+//   {"version": 1, "functions": {"main": {"blocks": {"<entry>": 1, "loop": 3},
+//     "edges": [{"from": "loop", "to": "end", "count": 1}, ...]}}}
+
+/// Name of the block a function starts in, before its first label
+pub const ENTRY_BLOCK: &str = "<entry>";
+
+/// How often each block of one function was entered and each edge between its blocks taken
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockCounts {
+    /// by label
+    pub blocks: BTreeMap<String, u64>,
+    /// by the labels of the block left and the block entered
+    pub edges: BTreeMap<(String, String), u64>,
+}
+
+/// Block and edge counts of every function that ran
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockProfile {
+    pub functions: BTreeMap<String, BlockCounts>,
+}
+
+/// version of the profile format that this crate reads and writes
+pub const PROFILE_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum ProfileError {
+    #[error("cannot read the profile: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed profile: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("profile format version {0} is not supported, expected {PROFILE_VERSION}")]
+    Version(u32),
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProfileFile {
+    version: u32,
+    functions: BTreeMap<String, FunctionFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FunctionFile {
+    blocks: BTreeMap<String, u64>,
+    edges: Vec<EdgeFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EdgeFile {
+    from: String,
+    to: String,
+    count: u64,
+}
+
+impl BlockProfile {
+    pub fn to_json(&self) -> String {
+        let functions = self
+            .functions
+            .iter()
+            .map(|(name, counts)| {
+                let edges = counts
+                    .edges
+                    .iter()
+                    .map(|((from, to), &count)| EdgeFile {
+                        from: from.clone(),
+                        to: to.clone(),
+                        count,
+                    })
+                    .collect();
+                let blocks = counts.blocks.clone();
+                (name.clone(), FunctionFile { blocks, edges })
+            })
+            .collect();
+        let file = ProfileFile {
+            version: PROFILE_VERSION,
+            functions,
+        };
+        serde_json::to_string_pretty(&file).expect("profiles are serializable")
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, ProfileError> {
+        let file: ProfileFile = serde_json::from_str(text)?;
+        if file.version != PROFILE_VERSION {
+            return Err(ProfileError::Version(file.version));
+        }
+        let functions = file
+            .functions
+            .into_iter()
+            .map(|(name, function)| {
+                let mut edges = BTreeMap::new();
+                for edge in function.edges {
+                    *edges.entry((edge.from, edge.to)).or_default() += edge.count;
+                }
+                let blocks = function.blocks;
+                (name, BlockCounts { blocks, edges })
+            })
+            .collect();
+        Ok(Self { functions })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ProfileError> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Add the counts of `other`, each `weight` times, so profiles of several runs make one
+    /// profile in which some runs matter more than others
+    pub fn merge(&mut self, other: &BlockProfile, weight: u64) {
+        for (name, counts) in &other.functions {
+            let merged = self.functions.entry(name.clone()).or_default();
+            for (block, n) in &counts.blocks {
+                *merged.blocks.entry(block.clone()).or_default() += n * weight;
+            }
+            for (edge, n) in &counts.edges {
+                *merged.edges.entry(edge.clone()).or_default() += n * weight;
+            }
+        }
+    }
+
+    /// Per function, its blocks and then its edges in label order
+    pub fn to_table(&self) -> String {
+        let mut table = String::new();
+        for (name, counts) in &self.functions {
+            let _ = writeln!(table, "@{}", name);
+            let rows: Vec<(String, u64)> = counts
+                .blocks
+                .iter()
+                .map(|(block, n)| (block.clone(), *n))
+                .chain(
+                    (counts.edges.iter())
+                        .map(|((from, to), n)| (format!("{} -> {}", from, to), *n)),
+                )
+                .collect();
+            let width = rows.iter().map(|r| r.0.len()).max().unwrap_or(0);
+            for (row, n) in rows {
+                let _ = writeln!(table, "  {:<width$}  {:>12}", row, n);
+            }
+        }
+        table
+    }
+}
+
+/// counts of a run in progress, indexed like the functions of the program
+pub(crate) struct BlockCounter<'a> {
+    blocks: Vec<HashMap<&'a str, u64>>,
+    edges: Vec<HashMap<(&'a str, &'a str), u64>>,
+}
+
+impl<'a> BlockCounter<'a> {
+    pub(crate) fn new(functions: usize) -> Self {
+        Self {
+            blocks: vec![HashMap::new(); functions],
+            edges: vec![HashMap::new(); functions],
+        }
+    }
+
+    pub(crate) fn call(&mut self, function: usize) {
+        *self.blocks[function].entry(ENTRY_BLOCK).or_default() += 1;
+    }
+
+    /// control reached `label` from the block `from`, `None` for the entry block
+    pub(crate) fn enter(&mut self, function: usize, from: Option<&'a str>, label: &'a str) {
+        *self.blocks[function].entry(label).or_default() += 1;
+        let edge = (from.unwrap_or(ENTRY_BLOCK), label);
+        *self.edges[function].entry(edge).or_default() += 1;
+    }
+
+    pub(crate) fn into_profile(self, program: &Program) -> BlockProfile {
+        let functions = program
+            .functions
+            .iter()
+            .zip(self.blocks.into_iter().zip(self.edges))
+            .filter(|(_, (blocks, _))| !blocks.is_empty())
+            .map(|(function, (blocks, edges))| {
+                let counts = BlockCounts {
+                    blocks: blocks
+                        .into_iter()
+                        .map(|(block, n)| (block.to_string(), n))
+                        .collect(),
+                    edges: edges
+                        .into_iter()
+                        .map(|((from, to), n)| ((from.to_string(), to.to_string()), n))
+                        .collect(),
+                };
+                (function.name.clone(), counts)
+            })
+            .collect();
+        BlockProfile { functions }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interpreter::Interpreter, representation::parse_program};
+
+    #[test]
+    fn counts_blocks_edges_and_traces() {
+        let program = parse_program(
+            "@main {\n  i: int = const 0;\n  two: int = const 2;\n  one: int = const 1;\n.loop:\n  more: bool = lt i two;\n  br more .body .end;\n.body:\n  i: int = add i one;\n  jmp .loop;\n.end:\n  print i;\n}\n",
+        )
+        .unwrap();
+        let mut trace = Vec::new();
+        let execution = Interpreter::new(&program)
+            .block_counts(true)
+            .trace(Some(&mut trace))
+            .run(&[], &mut std::io::sink())
+            .unwrap();
+        let profile = execution.blocks.unwrap();
+        let counts = &profile.functions["main"];
+
+        let blocks: Vec<(&str, u64)> = counts
+            .blocks
+            .iter()
+            .map(|(block, n)| (block.as_str(), *n))
+            .collect();
+        assert_eq!(
+            blocks,
+            [("<entry>", 1), ("body", 2), ("end", 1), ("loop", 3)]
+        );
+        let edge = |from: &str, to: &str| counts.edges[&(from.to_string(), to.to_string())];
+        assert_eq!(edge(ENTRY_BLOCK, "loop"), 1);
+        assert_eq!(edge("body", "loop"), 2);
+        assert_eq!(edge("loop", "end"), 1);
+
+        let trace = String::from_utf8(trace).unwrap();
+        assert_eq!(trace.lines().count(), 20);
+        assert_eq!(trace.lines().nth(3).unwrap(), "@main .loop:");
+
+        let mut merged = BlockProfile::default();
+        merged.merge(&profile, 1);
+        merged.merge(&profile, 2);
+        let counts = &merged.functions["main"];
+        assert_eq!(counts.blocks["loop"], 9);
+        assert_eq!(counts.edges[&("body".to_string(), "loop".to_string())], 6);
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+
+use crate::representation::{Literal, Type};
+
+// Runtime values of the interpreter. Ints wrap around on overflow and floats print with 17
+// decimals, as the reference interpreters do
+
+/// Address of a cell: an allocation and an offset into it, which may be out of bounds until it
+/// is dereferenced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Pointer {
+    pub allocation: usize,
+    pub offset: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    Float(f64),
+    Char(char),
+    Ptr(Pointer),
+}
+
+impl Value {
+    /// the value of a `const` of type `t`. Integer literals of float constants become floats
+    pub fn from_literal(literal: &Literal, t: &Type) -> Option<Self> {
+        match (t, literal) {
+            (Type::Int, Literal::Int(x)) => Some(Value::Int(*x)),
+            (Type::Bool, Literal::Bool(b)) => Some(Value::Bool(*b)),
+            (Type::Float, Literal::Float(x)) => Some(Value::Float(*x)),
+            (Type::Float, Literal::Int(x)) => Some(Value::Float(*x as f64)),
+            (Type::Char, Literal::Char(c)) => Some(Value::Char(*c)),
+            _ => None,
+        }
+    }
+
+    /// parse a command line argument of @main
+    pub fn parse(text: &str, t: &Type) -> Option<Self> {
+        match t {
+            Type::Int => text.parse().ok().map(Value::Int),
+            Type::Bool => text.parse().ok().map(Value::Bool),
+            Type::Float => text.parse().ok().map(Value::Float),
+            Type::Char => {
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(Value::Char(c)),
+                    _ => None,
+                }
+            }
+            Type::Ptr(_) | Type::None => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Bool(_) => "bool",
+            Value::Float(_) => "float",
+            Value::Char(_) => "char",
+            Value::Ptr(_) => "ptr",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(x) => write!(f, "{}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Float(x) if x.is_nan() => write!(f, "NaN"),
+            Value::Float(x) if x.is_infinite() => match x.is_sign_positive() {
+                true => write!(f, "Infinity"),
+                false => write!(f, "-Infinity"),
+            },
+            // very large and very small floats switch to scientific notation, as in brilirs
+            Value::Float(x) if *x != 0.0 && (x.abs() >= 1e10 || x.abs() <= 1e-10) => {
+                let text = format!("{:.17e}", x);
+                match text.split_once('e') {
+                    Some((mantissa, exponent)) if !exponent.starts_with('-') => {
+                        write!(f, "{}e+{}", mantissa, exponent)
+                    }
+                    _ => write!(f, "{}", text),
+                }
+            }
+            Value::Float(x) => write!(f, "{:.17}", x),
+            Value::Char(c) => write!(f, "{}", c),
+            Value::Ptr(p) => write!(f, "<ptr {}+{}>", p.allocation, p.offset),
+        }
+    }
+}
@@ -1,4 +1,18 @@
+pub mod analysis;
+pub mod backend;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod bril_logger;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cli;
 pub mod dataflow;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod interpreter;
 pub mod optimizations;
 pub mod representation;
+pub mod testing;
+pub mod timing;
+pub mod visualization;
+#[cfg(feature = "wasm")]
+pub mod wasm;
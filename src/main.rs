@@ -1,7 +1,12 @@
-use clap::{Parser, ValueEnum};
-use log::LevelFilter;
-use rust_bril::{bril_logger, optimizations::dce, optimizations::lvn};
-use std::path::Path;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use rust_bril::{
+    bril_logger::{self, LoggerOptions},
+    cli::{self, Cli, Command, Config, LogLevel, OptimizeArgs, VerifyArgs},
+    optimizations::preset_passes,
+    timing,
+};
+use std::{ffi::OsString, path::Path};
 
 // use rust_bril::{
 //     blocks::CfgGraph,
@@ -15,178 +20,153 @@ use std::path::Path;
 //     ssa, transform_print,
 // };
 
-#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
-enum LogLevel {
-    /// Trace level logging (most verbose)
-    Trace,
-    /// Debug level logging
-    Debug,
-    /// Info level logging (default)
-    Info,
-    /// Warning level logging
-    Warn,
-    /// Error level logging
-    Error,
-    /// No logging
-    Off,
-}
-
-// #[derive(ValueEnum, Clone, Debug, PartialEq, Eq)]
-// enum DataflowAnalysis {
-//     /// set of variables that are initialized by the end of each basic block
-//     InitializedVariables,
-
-//     /// set of variables that are referenced at some point in the future
-//     LiveVariables,
-// }
-
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// Input file (if omitted, read from stdin). If the file extension is .bril, will run bril2json to convert to json
-    // make this positional
-    file: String,
-
-    #[arg(short, long)]
-    output: Option<String>,
-
-    /// Set the log level (trace, debug, info, warn, error, off)
-    #[arg(long, value_enum, default_value = "info")]
-    log_level: LogLevel,
-
-    /// Don't push out of SSA form
-    #[arg(short = 'S', action)]
-    show_ssa: bool,
-
-    /// Run dead code elimination
-    #[arg(long, action)]
-    dce: bool,
-
-    /// Run local value numbering
-    #[arg(long, action)]
-    lvn: bool,
-
-    /// Run loop optimizations
-    #[arg(long, action)]
-    loops: bool,
+/// prints the --time-passes report when main returns
+struct TimeReport;
 
-    /// Skip SSA
-    #[arg(short = 's', action)]
-    skip_pass: bool,
-}
-
-impl From<LogLevel> for LevelFilter {
-    fn from(log_level: LogLevel) -> Self {
-        match log_level {
-            LogLevel::Trace => LevelFilter::Trace,
-            LogLevel::Debug => LevelFilter::Debug,
-            LogLevel::Info => LevelFilter::Info,
-            LogLevel::Warn => LevelFilter::Warn,
-            LogLevel::Error => LevelFilter::Error,
-            LogLevel::Off => LevelFilter::Off,
-        }
+impl Drop for TimeReport {
+    fn drop(&mut self) {
+        eprint!("{}", timing::report());
     }
 }
 
 fn main() {
-    let args = Args::parse();
-
-    if let Err(e) = bril_logger::init_logger(args.log_level.into()) {
-        eprintln!("Failed to initialize logger: {}", e);
-        std::process::exit(1);
+    let matches = Cli::command().get_matches_from(with_default_command(std::env::args_os()));
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let (config, config_path) = Config::load(cli.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(2);
+    });
+    let config = config.install();
+
+    if let (Some(pipeline), Some((_, matches))) = (cli.command.pipeline_mut(), matches.subcommand())
+    {
+        pipeline.pipeline = match pipeline_given(matches) {
+            true => pipeline_names(matches),
+            false => config.pipeline(),
+        };
     }
-
-    // parse into program
-    let time_start = std::time::Instant::now();
-    let file_paths = Path::new(&args.file);
-    let rich_program = match rust_bril::representation::RichProgram::from_file(file_paths) {
-        Ok(p) => p,
-        Err(e) => {
-            log::error!("Failed to load program from file '{}': {}", args.file, e);
-            std::process::exit(1);
-        }
-    };
-    log::info!(
-        "loaded program from '{}' in {:?}",
-        args.file,
-        time_start.elapsed()
-    );
-
-    if args.skip_pass {
-        if let Some(filepath) = args.output {
-            log::info!("writing program to file '{}'", filepath);
-            match rich_program.to_file(Path::new(&filepath)) {
-                Ok(_) => (),
-                Err(e) => {
-                    log::error!("Failed to write program to file '{}': {}", filepath, e);
-                    std::process::exit(1);
-                }
-            };
-        } else {
-            println!("{}", rich_program.to_string());
+    if let Command::Optimize(OptimizeArgs { output, .. }) = &mut cli.command {
+        // an --output extension still decides over the configured format
+        let by_extension = output
+            .output
+            .as_deref()
+            .and_then(|file| Path::new(file).extension())
+            .is_some_and(|ext| ext == "bril" || ext == "json");
+        if output.output_format.is_none() && !by_extension {
+            output.output_format = config.output_format;
         }
-        return;
+    }
+    // checking a program is what `verify` is for, so it runs the IR verifiers unless told not to
+    match &mut cli.command {
+        Command::Verify(VerifyArgs { pipeline, .. })
+        | Command::Optimize(OptimizeArgs {
+            pipeline,
+            check: true,
+            ..
+        }) => pipeline.verify |= !pipeline.no_verify,
+        _ => {}
     }
 
-    // convert into SSA form
-    let mut abstract_program = rust_bril::representation::RichAbstractProgram::from(rich_program);
-
-    if args.lvn {
-        abstract_program.program.functions = abstract_program
-            .program
-            .functions
-            .into_iter()
-            .map(|(n, af)| match lvn(af) {
-                Ok(af_new) => (n, af_new),
-                Err(e) => e.error_with_context_then_exit(&abstract_program.original_text),
-            })
-            .collect();
+    let log_level = cli.log_level.or(config.log_level).unwrap_or(LogLevel::Info);
+    let logger = LoggerOptions {
+        level: log_level.into(),
+        file: cli.log_file.clone(),
+        filters: cli.log_filter.clone(),
+    };
+    if let Err(e) = bril_logger::init_logger_with(&logger) {
+        eprintln!("Failed to initialize logger: {}", e);
+        std::process::exit(1);
     }
 
-    if args.dce {
-        abstract_program.program.functions = abstract_program
-            .program
-            .functions
-            .into_iter()
-            .map(|(n, af)| match dce(af) {
-                Ok(af_new) => (n, af_new),
-                Err(e) => e.error_with_context_then_exit(&abstract_program.original_text),
-            })
-            .collect();
+    if let Some(path) = config_path {
+        log::debug!("using config {}", path.display());
     }
 
-    // run optimizations
-    if args.loops {
-        abstract_program.program.functions = abstract_program
-            .program
-            .functions
-            .into_iter()
-            .map(|(n, af)| {
-                match rust_bril::optimizations::loops::loop_invariant_code_motion_pass(af) {
-                    Ok(af_new) => (n, af_new),
-                    Err(e) => e.error_with_context_then_exit(&abstract_program.original_text),
-                }
-            })
-            .collect();
+    if cli.time_passes {
+        timing::enable();
+    }
+    let _time_report = cli.time_passes.then(|| TimeReport);
+
+    match &cli.command {
+        Command::Optimize(args) => cli::optimize(args),
+        Command::Analyze(args) => cli::analyze(args),
+        Command::Interpret(args) => cli::interpret(args),
+        Command::Viz(args) => cli::viz(args),
+        Command::Verify(args) => cli::verify(args),
+        Command::Fmt(args) => cli::fmt(args),
+        Command::Fuzz(args) => cli::fuzz(args),
+        Command::Reduce(args) => cli::reduce(args),
+        Command::Bench(args) => cli::bench(args),
+        Command::Coverage(args) => cli::coverage(args),
+        Command::Instrument(args) => cli::instrument(args),
+        Command::ReadCounters(args) => cli::read_counters(args),
+        Command::MergeProfiles(args) => cli::merge_profiles(args),
     }
+}
 
-    // convert out of SSA form
-    let final_program = if args.show_ssa {
-        abstract_program.into_ssa_program()
-    } else {
-        abstract_program.into_program()
+/// Insert `optimize` when the command line names no subcommand, so `rust_bril prog.bril --dce`
+/// keeps working
+fn with_default_command(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let mut args: Vec<OsString> = args.collect();
+    let command = Cli::command();
+    let is_command = |arg: &str| {
+        arg == "help"
+            || command
+                .get_subcommands()
+                .any(|c| c.get_name() == arg || c.get_all_aliases().any(|a| a == arg))
     };
 
-    if let Some(filepath) = args.output {
-        log::info!("writing program to file '{}'", filepath);
-        match final_program.to_file(Path::new(&filepath)) {
-            Ok(_) => (),
-            Err(e) => {
-                log::error!("Failed to write program to file '{}': {}", filepath, e);
-                std::process::exit(1);
+    let mut explicit = false;
+    let mut rest = args.iter().skip(1).map(|arg| arg.to_string_lossy());
+    while let Some(arg) = rest.next() {
+        match arg.as_ref() {
+            "--log-level" | "--log-file" | "--log-filter" | "--config" => {
+                rest.next();
             }
-        };
-    } else {
-        println!("{}", final_program.to_string());
+            "-h" | "--help" | "-V" | "--version" => explicit = true,
+            arg if arg.starts_with('-') => {}
+            arg => {
+                explicit = is_command(arg);
+                break;
+            }
+        }
+    }
+    if !explicit {
+        args.insert(1.min(args.len()), "optimize".into());
+    }
+    args
+}
+
+/// whether the command line picks the pipeline, so the configured one does not apply
+fn pipeline_given(matches: &ArgMatches) -> bool {
+    ["opt_level", "passes", "lvn", "dce", "loops"]
+        .iter()
+        .any(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+}
+
+/// pass names from -O, --passes and the --lvn/--dce/--loops shorthands, in command line order
+fn pipeline_names(matches: &ArgMatches) -> Vec<String> {
+    let mut passes: Vec<(usize, String)> = Vec::new();
+    if let (Some(index), Some(level)) = (
+        matches.index_of("opt_level"),
+        matches.get_one::<u8>("opt_level"),
+    ) {
+        let preset = preset_passes(*level)
+            .iter()
+            .map(|pass| (index, pass.to_string()));
+        passes.extend(preset);
+    }
+    if let (Some(indices), Some(names)) = (
+        matches.indices_of("passes"),
+        matches.get_many::<String>("passes"),
+    ) {
+        passes.extend(indices.zip(names.cloned()));
+    }
+    for (flag, pass) in [("lvn", "lvn"), ("dce", "dce"), ("loops", "licm")] {
+        if let Some(index) = matches.index_of(flag).filter(|_| matches.get_flag(flag)) {
+            passes.push((index, pass.to_string()));
+        }
     }
+    passes.sort_by_key(|(index, _)| *index);
+    passes.into_iter().map(|(_, pass)| pass).collect()
 }
@@ -0,0 +1,169 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    dataflow::WorklistResult,
+    optimizations::statistics,
+    representation::{
+        AbstractFunction, Code, ControlFlowGraph, EffectOp, Literal, Terminator, ValueOp,
+    },
+};
+
+// Branch folding: a `br` whose arms go to the same block, or whose condition is a constant,
+// becomes a `jmp`. Conditions are followed through copies to the `const` that defines them, so
+// this is best run after lvn or constprop. Blocks that only the dropped arms reached are deleted
+// with everything they alone lead to, and phi nodes forget the values that came over the edges
+// that are gone.
+
+pub fn branch_fold(mut af: AbstractFunction) -> WorklistResult<AbstractFunction> {
+    log::info!("running branch folding on function '{}'", af.name);
+    if af.speculates() {
+        log::debug!("skipping '{}', which speculates", af.name);
+        return Ok(af);
+    }
+    let blocks = &af.cfg.basic_blocks;
+
+    let mut definitions: HashMap<&str, usize> = HashMap::new();
+    let args = af.args.iter().flatten().map(|arg| arg.name.as_str());
+    let phis = blocks
+        .iter()
+        .flat_map(|b| b.phi_nodes.iter().map(|p| p.dest.as_str()));
+    let codes = (blocks.iter())
+        .flat_map(|b| b.preheader.iter().chain(&b.instructions))
+        .filter_map(Code::get_destination);
+    for dest in args.chain(phis).chain(codes) {
+        *definitions.entry(dest).or_default() += 1;
+    }
+    let single = |var: &str| definitions.get(var) == Some(&1);
+
+    // boolean constants, and copies of them
+    let codes: Vec<&Code> = (blocks.iter())
+        .flat_map(|b| b.preheader.iter().chain(&b.instructions))
+        .collect();
+    let mut constants: HashMap<String, bool> = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for code in &codes {
+            let known = match code {
+                Code::Constant {
+                    dest,
+                    value: Literal::Bool(b),
+                    ..
+                } => Some((dest, *b)),
+                Code::Value {
+                    op: ValueOp::Id,
+                    dest,
+                    args: Some(args),
+                    ..
+                } => constants.get(&args[0]).map(|b| (dest, *b)),
+                _ => None,
+            };
+            if let Some((dest, b)) = known.filter(|(dest, _)| single(dest)) {
+                changed |= constants.insert(dest.clone(), b).is_none();
+            }
+        }
+    }
+
+    let mut folded = 0;
+    for block in &mut af.cfg.basic_blocks {
+        let Terminator::Br(then, otherwise, code) = &block.terminator else {
+            continue;
+        };
+        let Code::Effect {
+            pos, pos_end, src, ..
+        } = code.clone()
+        else {
+            continue;
+        };
+        let condition = code.get_arguments().and_then(|args| args.first());
+        let target = match condition.and_then(|c| constants.get(c)) {
+            _ if then == otherwise => then,
+            Some(true) => then,
+            Some(false) => otherwise,
+            None => continue,
+        };
+        log::debug!(
+            "branch at the end of '{}' always goes to '{}'",
+            block.label,
+            target
+        );
+        let target = target.clone();
+        block.terminator = Terminator::Jmp(
+            target.clone(),
+            Code::Effect {
+                op: EffectOp::Jmp,
+                args: None,
+                funcs: None,
+                labels: Some(Box::new([target])),
+                pos,
+                pos_end,
+                src,
+            },
+        );
+        folded += 1;
+    }
+    statistics::count("branches folded", folded);
+    if folded == 0 {
+        return Ok(af);
+    }
+
+    let blocks = std::mem::take(&mut af.cfg.basic_blocks);
+    af.cfg = ControlFlowGraph::from(blocks).prune_unreachable_blocks();
+    let cfg = &mut af.cfg;
+    for b in 0..cfg.basic_blocks.len() {
+        let predecessors: HashSet<String> = (cfg.predecessors[b].iter())
+            .map(|p| cfg.basic_blocks[p].label.clone())
+            .collect();
+        for phi in &mut cfg.basic_blocks[b].phi_nodes {
+            phi.phi_args.retain(|(_, pred)| predecessors.contains(pred));
+        }
+    }
+    af.discard_stale_dominance();
+    Ok(af)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        optimizations::{run_pipeline, PassManager},
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn folds_constant_and_single_target_branches() {
+        let text = r#"
+@main(n: int) {
+  t: bool = const true;
+  u: bool = id t;
+  br u .then .else;
+.then:
+  x: int = const 1;
+  jmp .join;
+.else:
+  x: int = const 2;
+  jmp .join;
+.join:
+  c: bool = lt x n;
+  br c .done .done;
+.done:
+  print x;
+}
+"#;
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["branchfold"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        let instrs = &optimized.program.functions[0].instrs;
+        let opcodes: Vec<String> = instrs.iter().map(|code| code.get_opcode_string()).collect();
+        assert!(
+            !opcodes.iter().any(|op| op == "br"),
+            "{}",
+            optimized.to_text()
+        );
+        // the else arm is gone
+        assert!(!optimized.to_text().contains(".else"));
+
+        for n in 0..3 {
+            assert_same_output(&program.program, &optimized.program, &[&n.to_string()]);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{
+    dataflow::{
+        evaluate, run_dataflow_analysis, ConstantPropagation, ConstantValue, WorklistResult,
+    },
+    optimizations::statistics,
+    representation::{AbstractFunction, Code, ConstantOp, Literal, Position, Type},
+};
+
+// Rewrite driven by `ConstantPropagation`. Bril arguments are always variables, so a use cannot
+// be replaced by a literal directly; instead every instruction and phi node whose value is known
+// becomes a `const`, which copy propagation in lvn then forwards and dce removes once unused.
+
+pub fn constant_propagation(mut af: AbstractFunction) -> WorklistResult<AbstractFunction> {
+    log::info!("running constant propagation on function '{}'", af.name);
+    let result = run_dataflow_analysis::<ConstantPropagation>(&mut af)?;
+
+    let mut propagated = 0;
+    for block in &mut af.cfg.basic_blocks {
+        let mut domain = result[&block.id].0.clone();
+        let (known, phis): (Vec<_>, Vec<_>) = std::mem::take(&mut block.phi_nodes)
+            .into_iter()
+            .map(|phi| {
+                let value = (phi.phi_args.iter())
+                    .filter_map(|(var, _)| domain.get(var).copied())
+                    .reduce(ConstantValue::join);
+                (phi, value)
+            })
+            .partition(|(_, value)| matches!(value, Some(ConstantValue::Constant(_))));
+        block.phi_nodes = phis.into_iter().map(|(phi, _)| phi).collect();
+        for (phi, value) in &known {
+            domain.insert(phi.dest.clone(), value.unwrap());
+        }
+
+        for code in block.preheader.iter_mut().chain(&mut block.instructions) {
+            let Some(dest) = code.get_destination() else {
+                continue;
+            };
+            let dest = dest.to_string();
+            let value = evaluate(&domain, code);
+            if let (Code::Value { value_type, .. }, Some(ConstantValue::Constant(literal))) =
+                (&*code, value)
+            {
+                let pos = code.get_position();
+                *code = constant(dest.clone(), value_type.clone(), literal, pos);
+                propagated += 1;
+            }
+            match value {
+                Some(value) => domain.insert(dest, value),
+                None => domain.remove(&dest),
+            };
+        }
+
+        // a phi node with a known value is defined by a `const` at the top of its block
+        let defined = known.into_iter().map(|(phi, value)| {
+            let Some(ConstantValue::Constant(literal)) = value else {
+                unreachable!("only constant phi nodes are known");
+            };
+            constant(phi.dest, phi.phi_type, literal, phi.pos)
+        });
+        let defined: Vec<Code> = defined.collect();
+        propagated += defined.len();
+        block.instructions.splice(0..0, defined);
+    }
+    statistics::count("values propagated", propagated as u64);
+    Ok(af)
+}
+
+/// a `const` at `pos`, where the value it replaces is defined
+fn constant(dest: String, constant_type: Type, value: Literal, pos: Option<Position>) -> Code {
+    Code::Constant {
+        op: ConstantOp::Const,
+        dest,
+        constant_type,
+        value,
+        pos,
+        pos_end: None,
+        src: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        optimizations::{run_pipeline, PassManager},
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn folds_values_known_across_blocks() {
+        let text = r#"
+@main(n: int) {
+  a: int = const 6;
+  cond: bool = lt n a;
+  br cond .left .right;
+.left:
+  b: int = const 7;
+  jmp .join;
+.right:
+  b: int = const 7;
+.join:
+  c: int = mul a b;
+  print c;
+  zero: int = const 0;
+  d: int = div c zero;
+  print d;
+}
+"#;
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["constprop"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        let rendered = optimized.to_text();
+        assert!(rendered.contains("const 42"), "{}", rendered);
+        assert!(rendered.contains("div"), "{}", rendered);
+
+        let after = assert_same_output(&program.program, &optimized.program, &["1"]);
+        assert_eq!(after.stdout, "42\n");
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    dataflow::WorklistResult,
+    optimizations::statistics,
+    representation::{AbstractFunction, Code, Terminator, ValueOp},
+};
+
+// SSA copy propagation: every use of `x` after `x = id y` reads `y` instead, in instructions,
+// terminators and phi arguments alike, and the copy goes away. Leaving SSA form turns each phi
+// node into copies to its destination at the end of the predecessors, which overwrite the
+// destination while it may still be read further down. A copy of a phi destination is therefore
+// kept, so that no use moves onto a variable that out-of-SSA assigns more than once.
+
+pub fn copy_prop(mut af: AbstractFunction) -> WorklistResult<AbstractFunction> {
+    log::info!("running copy propagation on function '{}'", af.name);
+    let blocks = &af.cfg.basic_blocks;
+
+    let mut definitions: HashMap<&str, usize> = HashMap::new();
+    let args = af.args.iter().flatten().map(|arg| arg.name.as_str());
+    let phis = blocks
+        .iter()
+        .flat_map(|b| b.phi_nodes.iter().map(|p| p.dest.as_str()));
+    let codes = (blocks.iter())
+        .flat_map(|b| b.preheader.iter().chain(&b.instructions))
+        .filter_map(Code::get_destination);
+    for dest in args.chain(phis).chain(codes) {
+        *definitions.entry(dest).or_default() += 1;
+    }
+    let phi_destinations: HashSet<&str> = (blocks.iter())
+        .flat_map(|b| b.phi_nodes.iter().map(|p| p.dest.as_str()))
+        .collect();
+
+    // copied variable -> its source, for the copies that can go
+    let mut sources: HashMap<String, String> = HashMap::new();
+    for code in blocks.iter().flat_map(|b| &b.instructions) {
+        let Some((dest, source)) = copy(code) else {
+            continue;
+        };
+        let single = |var: &str| definitions.get(var) == Some(&1);
+        if dest != source && single(dest) && single(source) && !phi_destinations.contains(source) {
+            sources.insert(dest.to_string(), source.to_string());
+        }
+    }
+    if sources.is_empty() {
+        return Ok(af);
+    }
+
+    // follow chains of copies to the variable at their start
+    let resolve = |var: &str| -> Option<String> {
+        let mut current = sources.get(var)?;
+        while let Some(next) = sources.get(current) {
+            current = next;
+        }
+        Some(current.clone())
+    };
+    let roots: HashMap<String, String> = (sources.keys())
+        .map(|var| (var.clone(), resolve(var).unwrap()))
+        .collect();
+    let rename = |args: &mut Vec<String>| {
+        for arg in args {
+            if let Some(root) = roots.get(arg.as_str()) {
+                *arg = root.clone();
+            }
+        }
+    };
+
+    for block in &mut af.cfg.basic_blocks {
+        block
+            .instructions
+            .retain(|code| copy(code).is_none_or(|(dest, _)| !roots.contains_key(dest)));
+        for code in block.preheader.iter_mut().chain(&mut block.instructions) {
+            if let Code::Value {
+                args: Some(args), ..
+            }
+            | Code::Effect {
+                args: Some(args), ..
+            }
+            | Code::Memory {
+                args: Some(args), ..
+            } = code
+            {
+                rename(args);
+            }
+        }
+        if let Terminator::Ret(code) | Terminator::Jmp(_, code) | Terminator::Br(_, _, code) =
+            &mut block.terminator
+        {
+            if let Code::Effect {
+                args: Some(args), ..
+            } = code
+            {
+                rename(args);
+            }
+        }
+        for phi in &mut block.phi_nodes {
+            for (var, _) in &mut phi.phi_args {
+                if let Some(root) = roots.get(var.as_str()) {
+                    *var = root.clone();
+                }
+            }
+        }
+    }
+    statistics::count("copies propagated", roots.len() as u64);
+    Ok(af)
+}
+
+/// (destination, source) of an `id`
+fn copy(code: &Code) -> Option<(&str, &str)> {
+    match code {
+        Code::Value {
+            op: ValueOp::Id,
+            dest,
+            args: Some(args),
+            ..
+        } if args.len() == 1 => Some((dest, &args[0])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        optimizations::{run_pipeline, PassManager},
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn propagates_copies_but_not_of_phi_destinations() {
+        let text = r#"
+@main(n: int) {
+  one: int = const 1;
+  a: int = id n;
+  b: int = id a;
+  i: int = id one;
+.loop:
+  old: int = id i;
+  i: int = add i one;
+  c: bool = lt i n;
+  br c .loop .end;
+.end:
+  print old b;
+}
+"#;
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["copyprop"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        let main = &optimized.program.functions[0];
+        let ids = (main.instrs.iter())
+            .filter(|code| code.get_opcode_string() == "id")
+            .count();
+        // the copy into `old` stays, and leaving SSA form adds one copy per phi argument
+        assert_eq!(ids, 3, "{}", optimized.to_text());
+
+        let after = assert_same_output(&program.program, &optimized.program, &["5"]);
+        assert_eq!(after.stdout, "4 5\n");
+    }
+}
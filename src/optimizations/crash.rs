@@ -0,0 +1,81 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::representation::{AbstractFunction, Program};
+
+// Repro bundles for pass failures. When a pass panics or leaves a function the verifiers reject,
+// the pass manager writes the program as it was before that pass, with a report of what failed,
+// into a fresh directory under the crash directory
+
+/// What failed, written next to the program as `crash.json`
+#[derive(Debug, Serialize)]
+pub struct CrashReport<'a> {
+    pub version: &'static str,
+    /// the failing pass, or `ssa construction` if the verifiers rejected the input
+    pub pass: &'a str,
+    /// 1-based position of the pass in `pipeline`
+    pub pass_index: Option<usize>,
+    pub pipeline: Vec<&'static str>,
+    pub function: &'a str,
+    pub error: String,
+    /// seed of the program `fuzz` generated, if the failure came from fuzzing
+    pub seed: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Bundle<'a> {
+    #[serde(flatten)]
+    report: &'a CrashReport<'a>,
+    /// command that runs the failing pass on the bundled program
+    repro: String,
+}
+
+/// Write `failing` as it was before the failed pass and the other functions of the program, out of
+/// SSA form, as `program.json` plus `report` as `crash.json`. Returns the bundle directory
+pub fn write_crash_bundle<'a>(
+    directory: &Path,
+    failing: &AbstractFunction,
+    others: impl IntoIterator<Item = &'a AbstractFunction>,
+    report: &CrashReport,
+) -> io::Result<PathBuf> {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis());
+    let pass = report.pass.replace(' ', "-");
+    let bundle = directory.join(format!("crash-{}-{}-{}", failing.name, pass, millis));
+    fs::create_dir_all(&bundle)?;
+
+    let mut functions: Vec<_> = others
+        .into_iter()
+        .filter(|af| af.name != failing.name)
+        .map(AbstractFunction::to_function)
+        .collect();
+    functions.push(failing.to_function());
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+    let program = Program { functions };
+
+    // a fuzzed program is regenerated from its seed, with the generator flags of the original run
+    let repro = match (report.seed, report.pass_index) {
+        (Some(seed), _) => format!(
+            "rust_bril fuzz --seed {} --count 1 --passes {}",
+            seed,
+            report.pipeline.join(",")
+        ),
+        (None, Some(_)) => format!("rust_bril program.json --passes {}", report.pass),
+        (None, None) => "rust_bril verify program.json".to_string(),
+    };
+    let write = |name: &str, json: serde_json::Result<String>| {
+        fs::write(bundle.join(name), json.map_err(io::Error::other)?)
+    };
+    write("program.json", serde_json::to_string_pretty(&program))?;
+    write(
+        "crash.json",
+        serde_json::to_string_pretty(&Bundle { report, repro }),
+    )?;
+    Ok(bundle)
+}
@@ -1,9 +1,12 @@
 /// Module for dead code elimination, make sure to run after local variable numbering
-use std::{collections::HashSet, vec};
+use std::collections::HashSet;
 
 use crate::{
     dataflow::{run_dataflow_analysis, WorklistProperty, WorklistResult},
-    representation::{AbstractFunction, BlockId, Code, ControlFlowGraph, Terminator},
+    optimizations::statistics,
+    representation::{
+        AbstractFunction, BlockId, Code, ControlFlowGraph, FunctionAttribute, Terminator,
+    },
 };
 
 // iterating until all variables are referenced
@@ -23,7 +26,7 @@ impl WorklistProperty for Dce {
         }
 
         for b in af.cfg.basic_blocks.iter() {
-            for instruction in b.instructions.iter() {
+            for instruction in b.preheader.iter().chain(&b.instructions) {
                 if let Some(dest) = instruction.get_destination() {
                     top.insert(dest.to_string());
                 }
@@ -57,7 +60,7 @@ impl WorklistProperty for Dce {
     }
 
     fn transfer(
-        domain: Self::Domain,
+        mut domain: Self::Domain,
         block_id: usize,
         cfg: &mut ControlFlowGraph,
         _: Option<&Vec<crate::representation::Argument>>,
@@ -67,65 +70,121 @@ impl WorklistProperty for Dce {
         //      2. then process arguments (add to live set)
 
         let block = &mut cfg.basic_blocks[block_id];
-        let mut domain_view: HashSet<&str> = domain.iter().map(|s| s.as_str()).collect();
-
         match &block.terminator {
-            Terminator::Ret(Code::Effect { args: Some(a), .. }) => {
-                domain_view.extend(a.iter().map(|s| s.as_str()));
-            }
-            Terminator::Br(_, _, Code::Effect { args: Some(a), .. }) => {
-                domain_view.extend(a.iter().map(|s| s.as_str()));
-            }
+            Terminator::Ret(Code::Effect { args: Some(a), .. }) => mark_live(&mut domain, a),
+            Terminator::Br(_, _, Code::Effect { args: Some(a), .. }) => mark_live(&mut domain, a),
             _ => (),
         }
 
-        let mut new_instructions = vec![];
-        for instructions in block.instructions.iter().rev() {
-            if let Some(dest) = instructions.get_destination() {
-                if !domain_view.contains(dest) {
-                    continue;
-                }
-            }
+        remove_dead(&mut domain, &mut block.instructions);
 
-            if let Some(dest) = instructions.get_destination() {
-                domain_view.remove(dest);
+        // phi nodes
+        block.phi_nodes.retain(|phi| {
+            if !domain.remove(phi.dest.as_str()) {
+                return false;
             }
+            mark_live(&mut domain, phi.phi_args.iter().map(|(var, _)| var));
+            true
+        });
 
-            if let Some(args) = instructions.get_arguments() {
-                domain_view.extend(args.iter().map(|s| s.as_str()));
-            }
+        // code hoisted by licm runs before the block is entered. It is still there once unrolling
+        // has removed the loop, and then nothing else keeps its arguments alive
+        remove_dead(&mut domain, &mut block.preheader);
 
-            new_instructions.push(instructions.clone());
-        }
+        Ok(domain)
+    }
+}
 
-        // phi nodes
-        let mut new_phi = vec![];
-        for phi in block.phi_nodes.iter() {
-            if !domain_view.contains(phi.dest.as_str()) {
+/// Remove the instructions of `code` whose result is not in the live set after them, updating it
+/// to the variables live before `code`
+fn remove_dead(domain: &mut HashSet<String>, code: &mut Vec<Code>) {
+    // decide backwards, and only rebuild the list when something is actually dead
+    let mut keep = Vec::with_capacity(code.len());
+    for instruction in code.iter().rev() {
+        // calls are only removed by `remove_dead_pure_calls`, since the callee may have side effects
+        if let Some(dest) = instruction.get_destination() {
+            if !domain.remove(dest) && !instruction.is_call() {
+                keep.push(false);
                 continue;
             }
+        }
 
-            domain_view.remove(phi.dest.as_str());
-
-            for (var, _) in phi.phi_args.iter() {
-                domain_view.insert(var.as_str());
-            }
+        if let Some(args) = instruction.get_arguments() {
+            mark_live(domain, args);
+        }
+        keep.push(true);
+    }
+    if keep.contains(&false) {
+        let mut keep = keep.into_iter().rev();
+        code.retain(|_| keep.next().unwrap());
+    }
+}
 
-            new_phi.push(phi.clone());
+/// add `vars` to the live set, only allocating for the ones that are new
+fn mark_live<'a>(domain: &mut HashSet<String>, vars: impl IntoIterator<Item = &'a String>) {
+    for var in vars {
+        if !domain.contains(var) {
+            domain.insert(var.clone());
         }
+    }
+}
 
-        new_phi.reverse();
-        new_instructions.reverse();
+/// Remove calls to functions annotated `pure` whose results are never used, returning how many were removed
+fn remove_dead_pure_calls(af: &mut AbstractFunction) -> usize {
+    let mut used: HashSet<String> = HashSet::new();
+    for block in af.cfg.basic_blocks.iter() {
+        for instruction in block.instructions.iter().chain(block.preheader.iter()) {
+            used.extend(instruction.get_arguments().into_iter().flatten().cloned());
+        }
+        for phi in block.phi_nodes.iter() {
+            used.extend(phi.phi_args.iter().map(|(var, _)| var.clone()));
+        }
+        used.extend(
+            block
+                .terminator
+                .get_arguments()
+                .into_iter()
+                .flatten()
+                .cloned(),
+        );
+    }
 
-        let ret = Ok(domain_view.into_iter().map(|s| s.to_string()).collect());
-        block.phi_nodes = new_phi;
-        block.instructions = new_instructions;
-        return ret;
+    let dead: Vec<(BlockId, usize)> = af
+        .cfg
+        .basic_blocks
+        .iter()
+        .flat_map(|block| {
+            block
+                .instructions
+                .iter()
+                .enumerate()
+                .filter(|(_, instr)| {
+                    af.calls_function_with(instr, FunctionAttribute::Pure)
+                        && instr
+                            .get_destination()
+                            .is_none_or(|dest| !used.contains(dest))
+                })
+                .map(move |(idx, _)| (block.id, idx))
+        })
+        .collect();
+
+    for &(block_id, idx) in dead.iter().rev() {
+        let removed = af.cfg.basic_blocks[block_id].instructions.remove(idx);
+        log::debug!("removing unused pure call: {}", removed);
     }
+
+    dead.len()
 }
 
 pub fn dce(mut af: AbstractFunction) -> WorklistResult<AbstractFunction> {
     log::info!("running DCE on function {}", af.name);
-    run_dataflow_analysis::<Dce>(&mut af)?;
+    loop {
+        run_dataflow_analysis::<Dce>(&mut af)?;
+        let removed = remove_dead_pure_calls(&mut af);
+        statistics::count("pure calls removed", removed as u64);
+        if removed == 0 {
+            break;
+        }
+    }
     Ok(af)
 }
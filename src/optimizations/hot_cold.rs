@@ -0,0 +1,116 @@
+use std::collections::HashSet;
+
+use crate::{
+    dataflow::WorklistResult,
+    optimizations::{reorder, statistics},
+    representation::{AbstractFunction, BlockId, FunctionAttribute},
+};
+
+// Hot/cold splitting. The cold blocks of a function move to the end of it, in the order they were
+// in, and the hot blocks close up behind them, so a loop body with a rarely taken error path
+// inside is emitted without a gap. A block is cold if it calls a `cold` function, or if it runs
+// less than once every ten calls of the function by `AbstractFunction::block_frequencies`; with a
+// profile (`--profile-in`), only blocks that never ran are cold. Jumps and falling through are
+// fixed up as in `layout`.
+
+/// blocks that run less often than this per call are cold, when there is no profile
+const COLD_FREQUENCY: f64 = 0.1;
+
+pub fn split_hot_cold(mut af: AbstractFunction) -> WorklistResult<AbstractFunction> {
+    log::info!("running hot/cold splitting on function '{}'", af.name);
+    if af.speculates() {
+        log::debug!("skipping '{}', which speculates", af.name);
+        return Ok(af);
+    }
+    let frequencies = af.block_frequencies();
+    let cold: Vec<bool> = (af.cfg.basic_blocks.iter().enumerate())
+        .map(|(b, block)| {
+            let runs = frequencies.block(b);
+            let rare = match frequencies.measured {
+                true => runs == 0.0,
+                false => runs < COLD_FREQUENCY,
+            };
+            let calls_cold = (block.instructions.iter())
+                .any(|code| af.calls_function_with(code, FunctionAttribute::Cold));
+            b != 0 && (rare || calls_cold)
+        })
+        .collect();
+    let (hot, cold): (Vec<BlockId>, Vec<BlockId>) = (0..cold.len()).partition(|&b| !cold[b]);
+    // cold blocks that are already last stay where they are
+    let moved = (cold.iter().rev())
+        .zip((0..af.cfg.basic_blocks.len()).rev())
+        .take_while(|&(&b, at)| b == at)
+        .count();
+    let moved = cold.len() - moved;
+    if moved == 0 {
+        return Ok(af);
+    }
+
+    let dominance = af.dominance_info();
+    let backedges: HashSet<(BlockId, BlockId)> = (af.cfg.successors.iter().enumerate())
+        .flat_map(|(from, successors)| successors.iter().map(move |to| (from, to)))
+        .filter(|&(from, to)| dominance.dominated_by(from, to))
+        .collect();
+    let order: Vec<BlockId> = hot.into_iter().chain(cold).collect();
+    reorder(&mut af, &order, &backedges);
+
+    statistics::count("cold blocks moved", moved as u64);
+    Ok(af)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::ProfileData,
+        interpreter::Interpreter,
+        optimizations::{run_pipeline, PassManager},
+        representation::Code,
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn moves_blocks_that_never_ran_out_of_loops() {
+        let text = r#"
+@main(n: int) {
+  zero: int = const 0;
+  one: int = const 1;
+  i: int = id zero;
+.loop:
+  more: bool = lt i n;
+  br more .body .done;
+.body:
+  bad: bool = lt i zero;
+  br bad .error .next;
+.error:
+  print bad;
+  ret;
+.next:
+  i: int = add i one;
+  jmp .loop;
+.done:
+  print i;
+}
+"#;
+        let program = program_from_text(text);
+        let execution = Interpreter::new(&program.program)
+            .block_counts(true)
+            .run(&["3".to_string()], &mut std::io::sink())
+            .unwrap();
+        let mut passes = PassManager::from_names(["hotcold"]).unwrap();
+        passes.profile(ProfileData::from(execution.blocks.unwrap()));
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        let labels: Vec<&str> = (optimized.program.functions[0].instrs.iter())
+            .filter_map(|code| match code {
+                Code::Label { label, .. } => Some(label.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(labels.last(), Some(&"error"), "{}", optimized.to_text());
+        let body = labels.iter().position(|l| *l == "body").unwrap();
+        assert_eq!(labels[body + 1], "next", "{}", optimized.to_text());
+
+        for n in 0..3 {
+            assert_same_output(&program.program, &optimized.program, &[&n.to_string()]);
+        }
+    }
+}
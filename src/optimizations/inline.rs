@@ -0,0 +1,387 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    analysis::BlockFrequencies,
+    dataflow::WorklistResult,
+    optimizations::{statistics, Changed, Pass},
+    representation::{
+        fresh_name, AbstractFunction, AbstractProgram, BasicBlock, BlockId, Code, ControlFlowGraph,
+        FunctionAttribute, Label, PhiNode, Terminator,
+    },
+};
+
+// Function inlining over SSA form. The block of a call is split after it and jumps to a copy of
+// the callee's blocks instead, whose returns jump to the rest of the block, where a phi node
+// merges the returned values into the call's destination. Every variable the callee defines gets
+// a fresh name in the copy, and its arguments become the arguments of the call.
+//
+// Callees marked `inline(always)` are always inlined, those marked `inline(never)` or `cold`
+// never, and the rest when they are small. Calls that run more often than the function they are
+// in, by the block frequencies of a profile (`--profile-in`) or else of the static estimate, take
+// larger callees, and the hottest calls go first, so the size budget of the caller goes where it
+// pays most. With a profile, calls that never ran stay. Calls in the copies are not inlined again, which stops recursion after
+// one level. Functions that speculate are left alone, as callers and as callees.
+
+/// callees with at most this many instructions are inlined
+const MAX_INLINED_SIZE: usize = 24;
+/// the same for calls that run more often than the function they are in
+const MAX_HOT_INLINED_SIZE: usize = 96;
+/// inlining stops short of growing a function past this many instructions
+const MAX_CALLER_SIZE: usize = 1024;
+
+/// The `inline` pass. It needs the callees, so it does nothing until the pass manager binds it
+/// to the program it runs on
+#[derive(Debug, Clone, Default)]
+pub struct Inline {
+    /// the functions of the program before any pass ran, by name
+    callees: HashMap<String, AbstractFunction>,
+}
+
+impl Pass for Inline {
+    fn name(&self) -> &'static str {
+        "inline"
+    }
+
+    fn description(&self) -> &'static str {
+        "calls to small functions replaced by their body, the hottest first with a profile"
+    }
+
+    fn run(&self, af: &mut AbstractFunction) -> WorklistResult<Changed> {
+        let inlined = inline_calls(af, &self.callees);
+        statistics::count("calls inlined", inlined as u64);
+        Ok(if inlined > 0 {
+            Changed::Yes
+        } else {
+            Changed::No
+        })
+    }
+
+    fn bind(&self, program: &AbstractProgram) -> Option<Box<dyn Pass>> {
+        let callees = (program.functions.iter())
+            .filter(|(_, af)| !af.speculates())
+            .map(|(name, af)| (name.clone(), af.clone()))
+            .collect();
+        Some(Box::new(Inline { callees }))
+    }
+}
+
+/// A call worth inlining
+struct Site<'a> {
+    block: BlockId,
+    index: usize,
+    callee: &'a AbstractFunction,
+}
+
+/// the number of calls inlined
+fn inline_calls(af: &mut AbstractFunction, callees: &HashMap<String, AbstractFunction>) -> usize {
+    log::info!("running inlining on function '{}'", af.name);
+    if af.speculates() {
+        log::debug!("skipping '{}', which speculates", af.name);
+        return 0;
+    }
+    // blocks copied from callees, whose calls stay
+    let mut copied: HashSet<Label> = HashSet::new();
+    let mut inlined = 0;
+    while let Some(site) = next_site(af, callees, &copied) {
+        log::debug!(
+            "inlining @{} into block '{}' of '{}'",
+            site.callee.name,
+            af.cfg.basic_blocks[site.block].label,
+            af.name
+        );
+        copied.extend(inline_call(af, site));
+        inlined += 1;
+    }
+    inlined
+}
+
+/// The hottest call of `af` worth inlining, the first of those that are as hot
+fn next_site<'a>(
+    af: &AbstractFunction,
+    callees: &'a HashMap<String, AbstractFunction>,
+    copied: &HashSet<Label>,
+) -> Option<Site<'a>> {
+    let blocks = &af.cfg.basic_blocks;
+    let frequencies = af.block_frequencies();
+    let sites = (blocks.iter().enumerate())
+        .filter(|(_, block)| !copied.contains(&block.label))
+        .flat_map(|(b, block)| {
+            (block.instructions.iter().enumerate()).filter_map(move |(index, code)| {
+                let name = code.get_funcs().filter(|_| code.is_call())?.first()?;
+                let callee = callees.get(name).filter(|callee| callee.name != af.name)?;
+                Some(Site {
+                    block: b,
+                    index,
+                    callee,
+                })
+            })
+        })
+        .filter(|site| wanted(af, &frequencies, site));
+    sites.min_by(|a, b| {
+        let frequency = |site: &Site| frequencies.block(site.block);
+        frequency(b).total_cmp(&frequency(a))
+    })
+}
+
+/// whether the call at `site` should be inlined, see the module comment
+fn wanted(af: &AbstractFunction, frequencies: &BlockFrequencies, site: &Site) -> bool {
+    let attrs = (af.known_attrs.get(&site.callee.name)).map_or(&[][..], Vec::as_slice);
+    if attrs.contains(&FunctionAttribute::InlineNever) || attrs.contains(&FunctionAttribute::Cold) {
+        return false;
+    }
+    if attrs.contains(&FunctionAttribute::InlineAlways) {
+        return true;
+    }
+    let runs = frequencies.block(site.block);
+    let limit = match runs {
+        _ if frequencies.measured && runs == 0.0 => return false,
+        _ if runs > 1.0 => MAX_HOT_INLINED_SIZE,
+        _ => MAX_INLINED_SIZE,
+    };
+    let callee = size(site.callee);
+    callee <= limit && size(af) + callee <= MAX_CALLER_SIZE
+}
+
+/// instructions and phi nodes of `af`
+fn size(af: &AbstractFunction) -> usize {
+    (af.cfg.basic_blocks.iter())
+        .map(|block| block.phi_nodes.len() + block.preheader.len() + block.instructions.len())
+        .sum()
+}
+
+/// variables `af` defines, in block order
+fn definitions(af: &AbstractFunction) -> Vec<&str> {
+    (af.cfg.basic_blocks.iter())
+        .flat_map(|block| {
+            let phis = block.phi_nodes.iter().map(|phi| phi.dest.as_str());
+            let code = (block.preheader.iter().chain(&block.instructions))
+                .filter_map(|code| code.get_destination());
+            phis.chain(code)
+        })
+        .collect()
+}
+
+/// Replace the call at `site` by a copy of the callee; returns the labels of the copy
+fn inline_call(af: &mut AbstractFunction, site: Site) -> Vec<Label> {
+    let Site {
+        block,
+        index,
+        callee,
+    } = site;
+    let rest = af.split_block(block, index);
+    let call = af.cfg.basic_blocks[rest].instructions.remove(0);
+    let rest_label = af.cfg.basic_blocks[rest].label.clone();
+
+    let mut names: HashSet<String> = (definitions(af).into_iter().map(String::from))
+        .chain(af.args.iter().flatten().map(|arg| arg.name.clone()))
+        .collect();
+    let parameters = callee.args.iter().flatten().map(|arg| arg.name.clone());
+    let mut renames: HashMap<String, String> = parameters
+        .zip(call.get_arguments().cloned().unwrap_or_default())
+        .collect();
+    for var in definitions(callee) {
+        let name = fresh_name(&mut names, &format!("{}_{}", var, callee.name));
+        renames.insert(var.to_string(), name);
+    }
+    let mut labels: HashSet<String> = af.cfg.label_map.keys().cloned().collect();
+    let relabels: HashMap<&str, Label> = (callee.cfg.basic_blocks.iter().enumerate())
+        .map(|(b, block)| {
+            let base = match b {
+                0 => "entry",
+                _ if block.label.starts_with("no_label_") => "block",
+                _ => block.label.as_str(),
+            };
+            let label = fresh_name(&mut labels, &format!("{}_{}", callee.name, base));
+            (block.label.as_str(), label)
+        })
+        .collect();
+    let var = |v: &str| renames.get(v).cloned().unwrap_or_else(|| v.to_string());
+    let label = |l: &str| relabels.get(l).cloned().unwrap_or_else(|| l.to_string());
+
+    // the copy runs as often as the call, in the proportions of the callee's profile
+    let site = af.cfg.basic_blocks[block].frequency;
+    let calls = callee.cfg.basic_blocks[0].frequency;
+    let scale = |n: u64| match (site, calls) {
+        (Some(site), Some(calls)) if calls > 0 => {
+            (u128::from(n) * u128::from(site) / u128::from(calls)) as u64
+        }
+        _ => 0,
+    };
+
+    let mut returns: Vec<(String, Label)> = Vec::new();
+    let mut copies: Vec<BasicBlock> = Vec::new();
+    for (b, original) in callee.cfg.basic_blocks.iter().enumerate() {
+        let fallthrough = (callee.cfg.basic_blocks.get(b + 1)).map(|next| next.label.clone());
+        let mut terminator = original.terminator.clone().explicit(fallthrough);
+        let mut edge_frequencies: HashMap<Label, u64> = (original.edge_frequencies.iter())
+            .map(|(to, n)| (label(to), scale(*n)))
+            .collect();
+        if let Terminator::Ret(code) = &terminator {
+            let value = code.get_arguments().and_then(|args| args.first());
+            returns.extend(value.map(|v| (var(v), label(&original.label))));
+            terminator = Terminator::jump(rest_label.clone());
+            edge_frequencies = (original.frequency.map(scale))
+                .map(|n| (rest_label.clone(), n))
+                .into_iter()
+                .collect();
+        } else {
+            terminator.map_targets(label);
+            if let Some(code) = terminator.code_mut() {
+                code.rename_arguments(var);
+            }
+        }
+        let copy = |code: &Code| {
+            let mut code = code.clone();
+            if let Some(dest) = code.get_destination() {
+                code.replace_destination(var(dest));
+            }
+            code.rename_arguments(var);
+            code
+        };
+        copies.push(BasicBlock {
+            id: 0,
+            label: label(&original.label),
+            instructions: original.instructions.iter().map(copy).collect(),
+            terminator,
+            phi_nodes: (original.phi_nodes.iter())
+                .map(|phi| PhiNode {
+                    dest: var(&phi.dest),
+                    original_name: phi.original_name.clone(),
+                    phi_type: phi.phi_type.clone(),
+                    phi_args: (phi.phi_args.iter())
+                        .map(|(v, pred)| (var(v), label(pred)))
+                        .collect(),
+                    pos: phi.pos,
+                })
+                .collect(),
+            preheader: original.preheader.iter().map(copy).collect(),
+            natural_loop_return: original.natural_loop_return,
+            frequency: site.and(original.frequency.map(scale)),
+            edge_frequencies: match site {
+                Some(_) => edge_frequencies,
+                None => HashMap::new(),
+            },
+        });
+    }
+
+    if let (Some(dest), Some(phi_type)) = (call.get_destination(), call.get_type()) {
+        af.cfg.basic_blocks[rest].phi_nodes.push(PhiNode {
+            dest: dest.to_string(),
+            original_name: dest.to_string(),
+            phi_type,
+            phi_args: returns,
+            pos: call.get_position(),
+        });
+    }
+    let entry = copies[0].label.clone();
+    let caller = &mut af.cfg.basic_blocks[block];
+    caller.terminator = Terminator::jump(entry.clone());
+    if let Some(n) = site {
+        caller.edge_frequencies = HashMap::from([(entry, n)]);
+    }
+
+    let labels = copies.iter().map(|copy| copy.label.clone()).collect();
+    let mut blocks = std::mem::take(&mut af.cfg.basic_blocks);
+    // the old last block fell off the end of the function, which now has to be explicit
+    if let Some(last) = blocks.last_mut() {
+        last.terminator = last.terminator.clone().explicit(None);
+    }
+    blocks.extend(copies);
+    for (id, block) in blocks.iter_mut().enumerate() {
+        block.id = id;
+    }
+    // a callee that never returns leaves the rest of the block unreachable
+    af.cfg = ControlFlowGraph::from(blocks).prune_unreachable_blocks();
+    let cfg = &mut af.cfg;
+    for b in 0..cfg.basic_blocks.len() {
+        let predecessors: HashSet<String> = (cfg.predecessors[b].iter())
+            .map(|p| cfg.basic_blocks[p].label.clone())
+            .collect();
+        for phi in &mut cfg.basic_blocks[b].phi_nodes {
+            phi.phi_args.retain(|(_, pred)| predecessors.contains(pred));
+        }
+    }
+    af.discard_stale_dominance();
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::ProfileData,
+        interpreter::Interpreter,
+        optimizations::{run_pipeline, PassManager},
+        representation::RichProgram,
+        testing::{assert_same_output, program_from_text},
+    };
+
+    const TEXT: &str = r#"
+@main(n: int) {
+  zero: int = const 0;
+  one: int = const 1;
+  i: int = id zero;
+  s: int = id zero;
+.loop:
+  c: bool = lt i n;
+  br c .body .done;
+.body:
+  t: int = call @step s i;
+  s: int = id t;
+  i: int = add i one;
+  jmp .loop;
+.done:
+  u: int = call @rare s;
+  print u;
+}
+@step(a: int, b: int): int {
+  neg: bool = lt b a;
+  br neg .small .big;
+.small:
+  r: int = add a b;
+  ret r;
+.big:
+  r: int = mul a b;
+  ret r;
+}
+@rare(a: int): int {
+  zero: int = const 0;
+  big: bool = gt a zero;
+  br big .yes .no;
+.yes:
+  ret a;
+.no:
+  print a;
+  ret zero;
+}
+"#;
+
+    fn calls(program: &RichProgram) -> usize {
+        (program.program.functions.iter())
+            .filter(|f| f.name == "main")
+            .flat_map(|f| &f.instrs)
+            .filter(|code| code.get_opcode_string() == "call")
+            .count()
+    }
+
+    #[test]
+    fn inlines_calls_and_skips_those_the_profile_never_saw() {
+        let program = program_from_text(TEXT);
+        let passes = PassManager::from_names(["inline"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        assert_eq!(calls(&optimized), 0, "{}", optimized.to_text());
+        for n in 0..5 {
+            assert_same_output(&program.program, &optimized.program, &[&n.to_string()]);
+        }
+
+        // with n = 0, the loop never calls @step
+        let execution = Interpreter::new(&program.program)
+            .block_counts(true)
+            .run(&["0".to_string()], &mut std::io::sink())
+            .unwrap();
+        let mut passes = PassManager::from_names(["inline"]).unwrap();
+        passes.profile(ProfileData::from(execution.blocks.unwrap()));
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        assert_eq!(calls(&optimized), 1, "{}", optimized.to_text());
+        assert_same_output(&program.program, &optimized.program, &["3"]);
+    }
+}
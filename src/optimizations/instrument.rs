@@ -0,0 +1,445 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    interpreter::{BlockCounts, BlockProfile, ENTRY_BLOCK},
+    representation::{
+        format_type, parse_program, Argument, Code, EffectOp, Function, Program, Type, ValueOp,
+    },
+};
+
+// Counter instrumentation, to profile a program with any Bril interpreter. Every function takes
+// a pointer to an array of counters as an extra last argument, and bumps a counter where it
+// starts and, for block granularity, after every label, which counts blocks like the built-in
+// interpreter does. The body of @main moves to a function of its own, called by a new @main that
+// allocates the counters and afterwards prints them as the last line of output, which a
+// CounterMap turns back into a BlockProfile. Edges are counted where control leaves a block, or
+// where it enters a block that has no other way in; a branch into a block with more ways in
+// (a critical edge) goes through a new block that counts it. The generated names share a prefix
+// no name of the program starts with
+
+/// What gets a counter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Granularity {
+    /// every block
+    #[default]
+    Blocks,
+    /// the entry of every function, which counts calls
+    Functions,
+    /// the entry of every function and every edge between blocks, which also gives the counts
+    /// of the blocks
+    Edges,
+}
+
+#[derive(Debug, Error)]
+pub enum InstrumentError {
+    #[error("the program has no @main function")]
+    NoMain,
+}
+
+/// version of the counter map format that this crate reads and writes
+pub const COUNTER_MAP_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum CounterError {
+    #[error("cannot read the counter map: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed counter map: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("counter map format version {0} is not supported, expected {COUNTER_MAP_VERSION}")]
+    Version(u32),
+    #[error("expected {expected} counts on the last line of the output, found `{line}`")]
+    Output { expected: usize, line: String },
+}
+
+/// The block, or the edge into it, a printed count belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counter {
+    pub function: String,
+    /// the block the edge comes from, `None` when the counter counts the block
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// label, or ENTRY_BLOCK
+    pub block: String,
+}
+
+/// What each count printed by an instrumented program means, in the order they are printed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CounterMap {
+    pub counters: Vec<Counter>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CounterFile {
+    version: u32,
+    counters: Vec<Counter>,
+}
+
+impl CounterMap {
+    pub fn to_json(&self) -> String {
+        let file = CounterFile {
+            version: COUNTER_MAP_VERSION,
+            counters: self.counters.clone(),
+        };
+        serde_json::to_string_pretty(&file).expect("counter maps are serializable")
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, CounterError> {
+        let file: CounterFile = serde_json::from_str(text)?;
+        if file.version != COUNTER_MAP_VERSION {
+            return Err(CounterError::Version(file.version));
+        }
+        Ok(Self {
+            counters: file.counters,
+        })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, CounterError> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// The profile printed as the last line of `output`. A block counted by edges runs as often
+    /// as control enters it. Blocks and edges that never ran are left out, like the interpreter
+    /// leaves them out
+    pub fn read_output(&self, output: &str) -> Result<BlockProfile, CounterError> {
+        let line = output.lines().rev().find(|l| !l.trim().is_empty());
+        let line = line.unwrap_or_default();
+        let counts: Option<Vec<u64>> = line.split_whitespace().map(|n| n.parse().ok()).collect();
+        let counts = counts.filter(|counts| counts.len() == self.counters.len());
+        let Some(counts) = counts else {
+            return Err(CounterError::Output {
+                expected: self.counters.len(),
+                line: line.to_string(),
+            });
+        };
+
+        let mut profile = BlockProfile::default();
+        for (counter, n) in self.counters.iter().zip(counts) {
+            if n == 0 {
+                continue;
+            }
+            let counts: &mut BlockCounts = profile
+                .functions
+                .entry(counter.function.clone())
+                .or_default();
+            *counts.blocks.entry(counter.block.clone()).or_default() += n;
+            if let Some(from) = &counter.from {
+                let edge = (from.clone(), counter.block.clone());
+                *counts.edges.entry(edge).or_default() += n;
+            }
+        }
+        Ok(profile)
+    }
+}
+
+/// An instrumented program and what its counters count
+#[derive(Debug, Clone)]
+pub struct Instrumented {
+    pub program: Program,
+    pub map: CounterMap,
+}
+
+/// the shortest `_prof`, `_prof_`, ... that no function, variable or label starts with
+fn fresh_prefix(program: &Program) -> String {
+    let mut names: Vec<&str> = Vec::new();
+    for function in &program.functions {
+        names.push(&function.name);
+        names.extend(function.args.iter().flatten().map(|arg| arg.name.as_str()));
+        for code in &function.instrs {
+            names.extend(code.get_destination());
+            if let Code::Label { label, .. } = code {
+                names.push(label);
+            }
+        }
+    }
+    let mut prefix = "_prof".to_string();
+    while names.iter().any(|name| name.starts_with(&prefix)) {
+        prefix.push('_');
+    }
+    prefix
+}
+
+/// functions parsed from generated text, without the positions of that text
+fn generated(text: &str) -> Program {
+    let mut program = parse_program(text).expect("generated code parses");
+    for function in &mut program.functions {
+        function.pos = None;
+        function.pos_end = None;
+        for arg in function.args.iter_mut().flatten() {
+            arg.pos = None;
+            arg.pos_end = None;
+        }
+        function.instrs.iter_mut().for_each(Code::clear_position);
+    }
+    program
+}
+
+fn instructions(text: &str) -> Vec<Code> {
+    generated(&format!("@f {{\n{}\n}}", text))
+        .functions
+        .remove(0)
+        .instrs
+}
+
+fn is_terminator(code: &Code) -> bool {
+    matches!(
+        code,
+        Code::Effect {
+            op: EffectOp::Jmp | EffectOp::Br | EffectOp::Ret,
+            ..
+        }
+    )
+}
+
+/// every way control enters a labeled block, as the label of the block it leaves and the label
+/// it enters
+fn static_edges(instrs: &[Code]) -> Vec<(&str, &str)> {
+    let mut edges = Vec::new();
+    let mut current = ENTRY_BLOCK;
+    let mut falls_through = true;
+    for code in instrs {
+        if let Code::Label { label, .. } = code {
+            if falls_through {
+                edges.push((current, label.as_str()));
+            }
+            current = label;
+        }
+        if let Code::Effect {
+            op: EffectOp::Jmp | EffectOp::Br,
+            labels: Some(targets),
+            ..
+        } = code
+        {
+            edges.extend(targets.iter().map(|target| (current, target.as_str())));
+        }
+        falls_through = !is_terminator(code);
+    }
+    edges
+}
+
+fn is_call(code: &Code) -> bool {
+    matches!(
+        code,
+        Code::Value {
+            op: ValueOp::Call,
+            ..
+        } | Code::Effect {
+            op: EffectOp::Call,
+            ..
+        }
+    )
+}
+
+/// Add counters to `program` that it prints before it ends
+pub fn instrument(
+    program: &Program,
+    granularity: Granularity,
+) -> Result<Instrumented, InstrumentError> {
+    let Some(main) = program.functions.iter().find(|f| f.name == "main") else {
+        return Err(InstrumentError::NoMain);
+    };
+    let p = fresh_prefix(program);
+    let counters = format!("{}.counters", p);
+    let body = |name: &str| match name {
+        "main" => format!("{}.main", p),
+        name => name.to_string(),
+    };
+
+    let mut map = CounterMap::default();
+    let mut functions = Vec::new();
+    for function in &program.functions {
+        let mut bump = |from: Option<&str>, block: &str, instrs: &mut Vec<Code>| {
+            let k = map.counters.len();
+            map.counters.push(Counter {
+                function: function.name.clone(),
+                from: from.map(str::to_string),
+                block: block.to_string(),
+            });
+            instrs.extend(instructions(&format!(
+                "{p}.k: int = const {k};\n{p}.p: ptr<int> = ptradd {counters} {p}.k;\n{p}.n: int = load {p}.p;\n{p}.n: int = add {p}.n {p}.one;\nstore {p}.p {p}.n;"
+            )));
+        };
+
+        let edges = granularity == Granularity::Edges;
+        let mut entries: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (from, to) in static_edges(&function.instrs) {
+            entries.entry(to).or_default().push(from);
+        }
+        // an edge into a block entered only this way is counted in that block
+        let counted_in_block = |label: &str| entries.get(label).is_some_and(|e| e.len() == 1);
+
+        let mut instrs = instructions(&format!("{p}.one: int = const 1;"));
+        bump(None, ENTRY_BLOCK, &mut instrs);
+        let mut stubs = Vec::new();
+        let mut current = ENTRY_BLOCK;
+        let mut falls_through = true;
+        for original in &function.instrs {
+            let mut code = original.clone();
+            if is_call(&code) {
+                if let Code::Value { args, funcs, .. } | Code::Effect { args, funcs, .. } =
+                    &mut code
+                {
+                    args.get_or_insert_with(Vec::new).push(counters.clone());
+                    for callee in funcs.iter_mut().flatten() {
+                        *callee = body(callee);
+                    }
+                }
+            }
+            if edges {
+                match &mut code {
+                    Code::Label { label, .. } if falls_through && !counted_in_block(label) => {
+                        bump(Some(current), label, &mut instrs);
+                    }
+                    Code::Effect {
+                        op: EffectOp::Jmp,
+                        labels: Some(targets),
+                        ..
+                    } if !counted_in_block(&targets[0]) => {
+                        bump(Some(current), &targets[0], &mut instrs);
+                    }
+                    // a critical edge gets a block of its own that counts it
+                    Code::Effect {
+                        op: EffectOp::Br,
+                        labels: Some(targets),
+                        ..
+                    } => {
+                        for target in targets.iter_mut().filter(|t| !counted_in_block(t)) {
+                            let stub = format!("{}.edge.{}", p, stubs.len());
+                            let mut code = instructions(&format!(".{}:", stub));
+                            bump(Some(current), target, &mut code);
+                            code.extend(instructions(&format!("jmp .{};", target)));
+                            stubs.push(code);
+                            *target = stub;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            falls_through = !is_terminator(&code);
+            instrs.push(code);
+            let Code::Label { label, .. } = original else {
+                continue;
+            };
+            match granularity {
+                Granularity::Blocks => bump(None, label, &mut instrs),
+                Granularity::Edges if counted_in_block(label) => {
+                    bump(Some(entries[label.as_str()][0]), label, &mut instrs)
+                }
+                Granularity::Edges | Granularity::Functions => {}
+            }
+            current = label;
+        }
+        if !stubs.is_empty() {
+            // control must not fall into the stubs
+            if falls_through {
+                instrs.extend(instructions("ret;"));
+            }
+            instrs.extend(stubs.into_iter().flatten());
+        }
+
+        let mut function = function.clone();
+        function.name = body(&function.name);
+        function.args.get_or_insert_with(Vec::new).push(Argument {
+            name: counters.clone(),
+            arg_type: Type::Ptr(Box::new(Type::Int)),
+            pos: None,
+            pos_end: None,
+            src: None,
+        });
+        function.instrs = instrs;
+        functions.push(function);
+    }
+
+    // the new @main passes its arguments on and returns what the old one returns
+    let mut args: Vec<&str> = main
+        .args
+        .iter()
+        .flatten()
+        .map(|a| a.name.as_str())
+        .collect();
+    args.push(&counters);
+    let call = format!("call @{}.main {};", p, args.join(" "));
+    let (call, ret) = match &main.return_type {
+        Some(t) => (
+            format!("{p}.result: {} = {call}", format_type(t)),
+            format!("ret {p}.result;"),
+        ),
+        None => (call, "ret;".to_string()),
+    };
+    let wrapper = instructions(&format!(
+        "{counters}: ptr<int> = call @{p}.alloc;\n{call}\ncall @{p}.report {counters};\n{ret}"
+    ));
+    functions.insert(
+        0,
+        Function {
+            instrs: wrapper,
+            attrs: None,
+            ..main.clone()
+        },
+    );
+
+    let n = map.counters.len();
+    let loads: String = (0..n)
+        .map(|k| format!("  k: int = const {k};\n  p: ptr<int> = ptradd counters k;\n  n{k}: int = load p;\n"))
+        .collect();
+    let printed: Vec<String> = (0..n).map(|k| format!("n{}", k)).collect();
+    let helpers = generated(&format!(
+        "@{p}.alloc: ptr<int> {{\n  n: int = const {n};\n  counters: ptr<int> = alloc n;\n  zero: int = const 0;\n  one: int = const 1;\n  i: int = const 0;\n.loop:\n  more: bool = lt i n;\n  br more .body .done;\n.body:\n  p: ptr<int> = ptradd counters i;\n  store p zero;\n  i: int = add i one;\n  jmp .loop;\n.done:\n  ret counters;\n}}\n@{p}.report(counters: ptr<int>) {{\n{loads}  print {};\n  free counters;\n}}\n",
+        printed.join(" ")
+    ));
+    functions.extend(helpers.functions);
+    Ok(Instrumented {
+        program: Program { functions },
+        map,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn printed_counters_match_the_interpreter() {
+        let text = "@main {\n  i: int = const 0;\n  two: int = const 3;\n  one: int = const 1;\n.loop:\n  more: bool = lt i two;\n  br more .body .end;\n.body:\n  i: int = call @inc i;\n  jmp .loop;\n.end:\n  print i;\n}\n@inc(x: int): int {\n  one: int = const 1;\n  small: bool = lt x one;\n  br small .bump .done;\n.bump:\n  x: int = add x one;\n.done:\n  x: int = add x one;\n  ret x;\n}\n";
+        let program = parse_program(text).unwrap();
+        let expected = Interpreter::new(&program)
+            .block_counts(true)
+            .run(&[], &mut std::io::sink())
+            .unwrap()
+            .blocks
+            .unwrap();
+
+        let instrumented = instrument(&program, Granularity::Blocks).unwrap();
+        let mut out = Vec::new();
+        Interpreter::new(&instrumented.program)
+            .run(&[], &mut out)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("3\n"));
+        let map = CounterMap::from_json(&instrumented.map.to_json()).unwrap();
+        let profile = map.read_output(&out).unwrap();
+        for (function, counts) in &expected.functions {
+            assert_eq!(profile.functions[function].blocks, counts.blocks);
+        }
+
+        let edges = instrument(&program, Granularity::Edges).unwrap();
+        let mut out = Vec::new();
+        Interpreter::new(&edges.program).run(&[], &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(edges.map.read_output(&out).unwrap(), expected);
+        // the branch into .done, which .bump falls into, is a critical edge
+        let labels = edges.program.functions.iter().flat_map(|f| &f.instrs);
+        let stubs =
+            labels.filter(|c| matches!(c, Code::Label { label, .. } if label.contains(".edge.")));
+        assert_eq!(stubs.count(), 1);
+
+        let calls = instrument(&program, Granularity::Functions).unwrap();
+        assert_eq!(calls.map.counters.len(), 2);
+        assert_eq!(calls.program.functions[0].name, "main");
+        assert!(map.read_output("2\n").is_err());
+    }
+}
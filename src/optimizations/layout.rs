@@ -0,0 +1,178 @@
+use std::collections::HashSet;
+
+use crate::{
+    analysis::BlockFrequencies,
+    dataflow::WorklistResult,
+    optimizations::statistics,
+    representation::{AbstractFunction, BasicBlock, BlockId, ControlFlowGraph, Terminator},
+};
+
+// Block layout. Blocks are flattened in the order of the CFG, so this pass puts them in the order
+// that lets the hot paths fall through. Every block starts as a chain of its own, and going over
+// the edges from the most to the least frequent, by `AbstractFunction::block_frequencies`, a chain
+// that ends where an edge starts takes the chain that starts where it ends. The chain of the entry
+// comes first and the others follow from hot to cold, so rarely run blocks such as error paths
+// end up last. Falling through to a block that is no longer next becomes a jump, and a jump to
+// the block that is now next falls through instead.
+//
+// As in `simplifycfg`, backedges stay jumps and blocks with code hoisted in front of them
+// (`preheader`) are only reached by jumps, so neither is laid out as falling through. Functions
+// that speculate are left alone, as `speculate` falls through to the block after it.
+
+pub fn block_layout(mut af: AbstractFunction) -> WorklistResult<AbstractFunction> {
+    log::info!("running block layout on function '{}'", af.name);
+    if af.speculates() {
+        log::debug!("skipping '{}', which speculates", af.name);
+        return Ok(af);
+    }
+    let dominance = af.dominance_info();
+    let backedges: HashSet<(BlockId, BlockId)> = (af.cfg.successors.iter().enumerate())
+        .flat_map(|(from, successors)| successors.iter().map(move |to| (from, to)))
+        .filter(|&(from, to)| dominance.dominated_by(from, to))
+        .collect();
+    let order = layout(&af, &af.block_frequencies(), &backedges);
+    let moved = (order.iter().enumerate()).filter(|&(i, &b)| i != b).count();
+    let jumps = reorder(&mut af, &order, &backedges);
+
+    statistics::count("blocks moved", moved as u64);
+    statistics::count("jumps removed", jumps);
+    Ok(af)
+}
+
+/// the blocks in their new order, see the module comment
+fn layout(
+    af: &AbstractFunction,
+    frequencies: &BlockFrequencies,
+    backedges: &HashSet<(BlockId, BlockId)>,
+) -> Vec<BlockId> {
+    let cfg = &af.cfg;
+    let mut chains: Vec<Vec<BlockId>> = (0..cfg.basic_blocks.len()).map(|b| vec![b]).collect();
+    // index of the chain each block is in
+    let mut chain_of: Vec<usize> = (0..cfg.basic_blocks.len()).collect();
+
+    let mut edges: Vec<(BlockId, BlockId)> = (cfg.successors.iter().enumerate())
+        .flat_map(|(from, successors)| successors.iter().map(move |to| (from, to)))
+        .filter(|&(from, to)| {
+            to != 0 && !backedges.contains(&(from, to)) && cfg.basic_blocks[to].preheader.is_empty()
+        })
+        .collect();
+    // a stable sort, so equally frequent edges go in source order
+    edges.sort_by(|a, b| {
+        frequencies
+            .edge(b.0, b.1)
+            .total_cmp(&frequencies.edge(a.0, a.1))
+    });
+    for (from, to) in edges {
+        let (tail, head) = (chain_of[from], chain_of[to]);
+        if tail == head || chains[tail].last() != Some(&from) || chains[head][0] != to {
+            continue;
+        }
+        let joined = std::mem::take(&mut chains[head]);
+        for &b in &joined {
+            chain_of[b] = tail;
+        }
+        chains[tail].extend(joined);
+    }
+
+    // nothing jumps to the entry, so its chain is still the first
+    let mut chains: Vec<Vec<BlockId>> = chains.into_iter().filter(|c| !c.is_empty()).collect();
+    chains[1..].sort_by(|a, b| frequencies.block(b[0]).total_cmp(&frequencies.block(a[0])));
+    chains.concat()
+}
+
+/// Put the blocks of `af` in `order`; returns the number of jumps that now fall through
+pub(super) fn reorder(
+    af: &mut AbstractFunction,
+    order: &[BlockId],
+    backedges: &HashSet<(BlockId, BlockId)>,
+) -> u64 {
+    let cfg = &mut af.cfg;
+    let labels: Vec<String> = (cfg.basic_blocks.iter())
+        .map(|block| block.label.clone())
+        .collect();
+    let mut blocks: Vec<Option<BasicBlock>> = std::mem::take(&mut cfg.basic_blocks)
+        .into_iter()
+        .map(Some)
+        .collect();
+
+    let mut jumps = 0;
+    let mut laid_out = Vec::with_capacity(order.len());
+    for (i, &b) in order.iter().enumerate() {
+        let mut block = blocks[b].take().expect("every block is laid out once");
+        let jumped = matches!(block.terminator, Terminator::Jmp(..));
+        // what used to come next may not anymore, and falling off the end is a return
+        let next = labels.get(b + 1).cloned();
+        block.terminator = block.terminator.clone().explicit(next);
+        let next = order.get(i + 1).copied();
+        if let (Terminator::Jmp(target, _), Some(next)) = (&block.terminator, next) {
+            let falls_through = *target == labels[next]
+                && !backedges.contains(&(b, next))
+                && blocks[next]
+                    .as_ref()
+                    .is_some_and(|n| n.preheader.is_empty());
+            if falls_through {
+                block.terminator = Terminator::Passthrough;
+                jumps += u64::from(jumped);
+            }
+        }
+        laid_out.push(block);
+    }
+    for (id, block) in laid_out.iter_mut().enumerate() {
+        block.id = id;
+    }
+    af.cfg = ControlFlowGraph::from(laid_out);
+    af.discard_stale_dominance();
+    jumps
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        optimizations::{run_pipeline, PassManager},
+        representation::Code,
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn error_paths_go_last_and_hot_paths_fall_through() {
+        let text = r#"
+@main(n: int) {
+  zero: int = const 0;
+  one: int = const 1;
+  negative: bool = lt n zero;
+  br negative .error .start;
+.error:
+  print negative;
+  ret;
+.start:
+  i: int = id zero;
+.loop:
+  more: bool = lt i n;
+  br more .body .done;
+.done:
+  print i;
+  ret;
+.body:
+  i: int = add i one;
+  jmp .loop;
+}
+"#;
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["layout"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        let instrs = &optimized.program.functions[0].instrs;
+        let labels: Vec<&str> = (instrs.iter())
+            .filter_map(|code| match code {
+                Code::Label { label, .. } => Some(label.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(labels.last(), Some(&"error"), "{}", optimized.to_text());
+        let start = labels.iter().position(|l| *l == "start").unwrap();
+        assert_eq!(labels[start + 1], "loop", "{}", optimized.to_text());
+
+        for n in -1..3 {
+            assert_same_output(&program.program, &optimized.program, &[&n.to_string()]);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use crate::{
+    analysis::AliasInfo,
+    dataflow::WorklistResult,
+    optimizations::statistics,
+    representation::{AbstractFunction, Code, MemoryOp, ValueOp},
+};
+
+// Redundant load elimination within each block. A load reuses the value that an earlier load
+// from the same pointer, or a store through it, left in a variable, as long as no store that
+// may alias the pointer, `free` or call came in between. Leaving SSA form only adds copies at
+// the end of blocks, so a variable read later in its own block still holds that value.
+
+pub fn load_cse(mut af: AbstractFunction) -> WorklistResult<AbstractFunction> {
+    log::info!(
+        "running redundant load elimination on function '{}'",
+        af.name
+    );
+    let alias = AliasInfo::from(&af);
+
+    let mut removed = 0;
+    for block in &mut af.cfg.basic_blocks {
+        // pointer -> variable holding the contents of the cell it points to
+        let mut available: HashMap<String, String> = HashMap::new();
+        for code in &mut block.instructions {
+            let reused = match code {
+                Code::Memory {
+                    op: MemoryOp::Load,
+                    args: Some(args),
+                    ..
+                } => available.get(alias.representative(&args[0])).cloned(),
+                _ => None,
+            };
+            if let Some(dest) = code.get_destination() {
+                available.retain(|pointer, value| pointer != dest && value != dest);
+            }
+
+            match code {
+                Code::Memory {
+                    op: MemoryOp::Load,
+                    args: Some(args),
+                    dest: Some(dest),
+                    ptr_type: Some(value_type),
+                    pos,
+                    pos_end,
+                    src,
+                } => match reused {
+                    Some(value) => {
+                        *code = Code::Value {
+                            op: ValueOp::Id,
+                            dest: std::mem::take(dest),
+                            value_type: value_type.clone(),
+                            args: Some(vec![value]),
+                            funcs: None,
+                            labels: None,
+                            pos: pos.take(),
+                            pos_end: pos_end.take(),
+                            src: src.take(),
+                        };
+                        removed += 1;
+                    }
+                    None if dest != &args[0] => {
+                        let pointer = alias.representative(&args[0]).to_string();
+                        available.insert(pointer, dest.clone());
+                    }
+                    None => {}
+                },
+                Code::Memory {
+                    op: MemoryOp::Store,
+                    args: Some(args),
+                    ..
+                } => {
+                    let pointer = alias.representative(&args[0]);
+                    available.retain(|other, _| !alias.may_alias(other, pointer));
+                    available.insert(pointer.to_string(), args[1].clone());
+                }
+                Code::Memory {
+                    op: MemoryOp::Free, ..
+                } => available.clear(),
+                code if code.is_call() => available.clear(),
+                _ => {}
+            }
+        }
+    }
+    statistics::count("loads removed", removed);
+    Ok(af)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        optimizations::{run_pipeline, PassManager},
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn reuses_loads_until_an_aliasing_store() {
+        let text = r#"
+@main(n: int) {
+  one: int = const 1;
+  a: ptr<int> = alloc n;
+  b: ptr<int> = alloc n;
+  a1: ptr<int> = ptradd a one;
+  store a n;
+  store a1 one;
+  store b one;
+  x: int = load a;
+  y: int = load a;
+  m: int = sub n one;
+  i: ptr<int> = ptradd a m;
+  store i one;
+  z: int = load a;
+  w: int = load a1;
+  print x y z w;
+  free a;
+  free b;
+}
+"#;
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["loadcse"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        let loads = (optimized.program.functions[0].instrs.iter())
+            .filter(|code| code.get_opcode_string() == "load")
+            .count();
+        // x and y come from the first store, z and w are reloaded after the store through `i`
+        assert_eq!(loads, 2, "{}", optimized.to_text());
+
+        let after = assert_same_output(&program.program, &optimized.program, &["2"]);
+        assert_eq!(after.stdout, "2 2 2 1\n");
+    }
+}
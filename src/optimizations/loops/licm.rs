@@ -5,18 +5,21 @@
 // A CFG is reducible iff every backedge has a natural loop.
 //     A language that only has for, while, if, break, continue, etc. can only generate reducible CFGs. You need goto or something to generate irreducible CFGs.
 
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::HashSet;
 
 use crate::{
-    dataflow::{run_dataflow_analysis, ReachingDefinitions, WorklistResult},
-    representation::{AbstractFunction, Code},
+    analysis::{AliasInfo, LoopInfo, NaturalLoop},
+    dataflow::{run_parallel_dataflow_analysis, ReachingDefinitions, WorklistResult},
+    optimizations::statistics,
+    representation::{AbstractFunction, BlockId, Code, FunctionAttribute, MemoryOp},
 };
 
-struct NaturalLoop {
-    header: usize,
-    nodes: HashSet<usize>,
-    backedge_source: usize,
-}
+// Memory is read in loops too: `ptradd` is plain arithmetic and moves like any other value. A
+// `load` moves when nothing in the loop can change the cell it reads, which is every store that
+// may alias its pointer, `free` and calls to impure functions. Loads trap on bad pointers, so one
+// only moves from a block that runs every time the loop is entered, one that dominates every
+// block leaving the loop. Calls to pure functions may not terminate, so they move under the same
+// condition.
 
 pub fn loop_invariant_code_motion_pass(
     mut af: AbstractFunction,
@@ -25,71 +28,68 @@ pub fn loop_invariant_code_motion_pass(
         "running loop invariant code motion pass on function {}",
         af.name
     );
-    let start_time = std::time::Instant::now();
-
-    // --- Step 0: calculate reaching definitions, made easy by SSA form
-
-    let reaching_definitions = run_dataflow_analysis::<ReachingDefinitions>(&mut af)?;
-
-    // --- Step 1: grow loop candidates
-    // key = natural loop header, value = set of nodes in the natural loop
-    let mut natural_loops: Vec<NaturalLoop> = Vec::new();
-    for source in 0..af.cfg.basic_blocks.len() {
-        for &header in &af.cfg.successors[source] {
-            if af.dominance_info.dominated_by(source, header) {
-                let header_name = &af.cfg.basic_blocks[header].label;
-                let source_name = &af.cfg.basic_blocks[source].label;
-                log::debug!(
-                    "candidate header: '{}' dominates backedge source: '{}'",
-                    header_name,
-                    source_name
-                );
-
-                let loop_nodes = find_loop_nodes(&af, header, source);
-                natural_loops.push(NaturalLoop {
-                    header,
-                    nodes: loop_nodes,
-                    backedge_source: source,
-                });
-            }
-        }
+    if af.speculates() {
+        log::debug!("skipping '{}', which speculates", af.name);
+        return Ok(af);
     }
+    // --- Step 0: calculate reaching definitions, made easy by SSA form
 
-    // --- Step 2: filter only for natural loops
-    natural_loops.retain(|candidate| is_natural_loop(&af, candidate));
+    let reaching_definitions = run_parallel_dataflow_analysis::<ReachingDefinitions>(&mut af)?;
 
-    for nl in &natural_loops {
+    // --- Step 1: find the natural loops, outermost first, so that code invariant in several
+    // nested loops is hoisted out of the outermost of them
+    let loop_info = LoopInfo::from(&af);
+    let alias = AliasInfo::from(&af);
+    for nl in &loop_info.loops {
         let header_name = &af.cfg.basic_blocks[nl.header].label;
-        log::info!("found natural loop '{}'", header_name);
-        for node in &nl.nodes {
+        log::info!("found natural loop '{}' at depth {}", header_name, nl.depth);
+        for node in &nl.body {
             log::trace!("  {}", af.cfg.basic_blocks[*node].label);
         }
     }
 
     // Step 3: identify loop-invariant instructions
     let mut final_licm = vec![];
-    for nl in &natural_loops {
-        let mut loop_invariant_instructions: HashMap<String, (Code, usize)> = HashMap::new();
+    for nl in &loop_info.loops {
+        let mut loop_invariant_instructions: HashSet<String> = HashSet::new();
+        // (block, index) of every invariant instruction, in the order they are found
         let mut loop_invariant_instructions_ordered = vec![];
+        let memory = LoopMemory::new(&af, nl);
         let mut changed = true;
-
-        // Iterate to convergence
+        // Iterate to convergence, in block order so that hoisted code comes out the same on
+        // every run
         while changed {
             changed = false;
-            for &node in &nl.nodes {
+            for &node in &nl.body {
                 let block = &af.cfg.basic_blocks[node];
-                for instruction in &block.instructions {
+                for (index, instruction) in block.instructions.iter().enumerate() {
                     let dest = match instruction.get_destination() {
                         Some(dest) => dest,
                         None => continue,
                     };
 
-                    if loop_invariant_instructions.contains_key(dest) {
+                    if loop_invariant_instructions.contains(dest) {
                         continue;
                     }
 
-                    // unless we can prove that the call function is side effect free, we cannot process it
-                    if instruction.has_side_effects() {
+                    // calls stay unless the callee is pure, by annotation or by `analysis::Purity`
+                    let movable = match instruction {
+                        Code::Memory {
+                            op: MemoryOp::PtrAdd,
+                            ..
+                        } => true,
+                        Code::Memory {
+                            op: MemoryOp::Load,
+                            args: Some(args),
+                            ..
+                        } => memory.can_hoist_load(&alias, &args[0], block.id),
+                        code if code.has_side_effects() => {
+                            af.calls_function_with(code, FunctionAttribute::Pure)
+                                && memory.always_runs(block.id)
+                        }
+                        _ => true,
+                    };
+                    if !movable {
                         continue;
                     }
 
@@ -99,18 +99,18 @@ pub fn loop_invariant_code_motion_pass(
                         args.iter().all(|arg| {
                             let reaching_defs = &reaching_definitions[&block.id].1[arg];
                             // Either all defs outside loop OR single def already marked invariant
-                            (&nl.nodes & reaching_defs).is_empty()
+                            reaching_defs.iter().all(|def| !nl.body.contains(def))
                                 || (reaching_defs.len() == 1
-                                    && loop_invariant_instructions.contains_key(arg))
+                                    && loop_invariant_instructions.contains(arg))
                         })
                     } else {
-                        false
+                        // a pure call without arguments returns the same value every time
+                        instruction.is_call()
                     };
 
                     if is_invariant {
-                        loop_invariant_instructions
-                            .insert(dest.to_owned(), (instruction.clone(), block.id));
-                        loop_invariant_instructions_ordered.push((instruction.clone(), block.id));
+                        loop_invariant_instructions.insert(dest.to_owned());
+                        loop_invariant_instructions_ordered.push((block.id, index));
                         changed = true;
                         log::info!(
                             "found loop-invariant: {} in natural loop '{}' in block '{}'",
@@ -131,89 +131,256 @@ pub fn loop_invariant_code_motion_pass(
         final_licm.push((nl, loop_invariant_instructions_ordered));
     }
 
-    // Step 4: Actually move the loop-invariant code
-    let mut already_removed = HashSet::new();
+    // Step 4: Actually move the loop-invariant code. Instructions are found by position, since
+    // calls never compare equal, and only removed once every preheader has its copy
+    let mut hoisted: HashSet<(BlockId, usize)> = HashSet::new();
     for (nl, licm_instructions_ordered) in final_licm {
         if licm_instructions_ordered.is_empty() {
             continue;
         }
         // Move instructions to preheader
-        for (instruction, source_block_id) in licm_instructions_ordered {
-            // remove instruction from original location
-            if already_removed.contains(&instruction) {
+        for (source_block_id, index) in licm_instructions_ordered {
+            // invariant in an enclosing loop too, so it already left for that loop's preheader
+            if !hoisted.insert((source_block_id, index)) {
                 continue;
             }
 
-            let s = format!(
-                "instruction '{:?}' not found in block '{}'",
-                instruction, af.cfg.basic_blocks[source_block_id].label
-            );
-            assert!(
-                af.cfg.basic_blocks[source_block_id]
-                    .instructions
-                    .contains(&instruction),
-                "{}{}\n{:#?}",
-                s,
-                af.cfg.basic_blocks[source_block_id].label,
-                af.cfg.basic_blocks[source_block_id].instructions
-            );
-
-            af.cfg.basic_blocks[source_block_id]
-                .instructions
-                .retain(|instr| instr != &instruction);
-
-            already_removed.insert(instruction.clone());
-
-            // Add to preheader
+            let instruction = af.cfg.basic_blocks[source_block_id].instructions[index].clone();
             af.cfg.basic_blocks[nl.header].preheader.push(instruction);
+            statistics::count("instructions hoisted", 1);
         }
 
-        af.cfg.basic_blocks[nl.backedge_source].natural_loop_return = true;
+        for &latch in &nl.latches {
+            af.cfg.basic_blocks[latch].natural_loop_return = true;
+        }
+    }
+    for block in af.cfg.basic_blocks.iter_mut() {
+        let mut index = 0;
+        block.instructions.retain(|_| {
+            index += 1;
+            !hoisted.contains(&(block.id, index - 1))
+        });
     }
 
-    log::info!("finished in {:?}", start_time.elapsed());
     Ok(af)
 }
 
-fn find_loop_nodes(af: &AbstractFunction, header: usize, source: usize) -> HashSet<usize> {
-    // minimal set of nodes including header and source such that for every node in the set,
-    // either all its predecessors are in the set, or it is the header
-    let mut loop_nodes = HashSet::from([header, source]);
-    let mut worklist = VecDeque::new();
+/// What the code of a loop does to memory, and where a load or call may be hoisted from
+struct LoopMemory<'a> {
+    af: &'a AbstractFunction,
+    /// pointers stored through anywhere in the loop
+    stores: Vec<&'a str>,
+    /// whether the loop frees memory or calls a function that may write to it
+    opaque: bool,
+    /// blocks of the loop with a successor outside of it
+    exiting: Vec<BlockId>,
+}
 
-    if header != source {
-        worklist.push_back(source);
-    }
+impl<'a> LoopMemory<'a> {
+    fn new(af: &'a AbstractFunction, nl: &NaturalLoop) -> Self {
+        let blocks = &af.cfg.basic_blocks;
+        let codes = (nl.body.iter())
+            .flat_map(|&b| blocks[b].preheader.iter().chain(&blocks[b].instructions));
 
-    while let Some(node) = worklist.pop_front() {
-        let node_name = &af.cfg.basic_blocks[node].label;
-        log::trace!("  visiting node '{}'", node_name);
-        for &pred in &af.cfg.predecessors[node] {
-            if !loop_nodes.contains(&pred) && pred != header {
-                loop_nodes.insert(pred);
-                worklist.push_back(pred);
+        let mut stores = vec![];
+        let mut opaque = false;
+        for code in codes {
+            match code {
+                Code::Memory {
+                    op: MemoryOp::Store,
+                    args: Some(args),
+                    ..
+                } => stores.push(args[0].as_str()),
+                Code::Memory {
+                    op: MemoryOp::Free, ..
+                } => opaque = true,
+                code if code.is_call() => {
+                    opaque |= !af.calls_function_with(code, FunctionAttribute::Pure)
+                }
+                _ => {}
             }
         }
+        let exiting = (nl.body.iter())
+            .filter(|&&b| af.cfg.successors[b].iter().any(|s| !nl.body.contains(&s)))
+            .copied()
+            .collect();
+
+        Self {
+            af,
+            stores,
+            opaque,
+            exiting,
+        }
+    }
+
+    /// whether a load through `pointer` in `block` reads the same cell on every iteration and can
+    /// run before the loop without trapping where the loop would not
+    fn can_hoist_load(&self, alias: &AliasInfo, pointer: &str, block: BlockId) -> bool {
+        !self.opaque
+            && self.always_runs(block)
+            && (self.stores.iter()).all(|store| !alias.may_alias(store, pointer))
+    }
+
+    /// whether `block` runs every time the loop is entered, because it dominates every block
+    /// leaving the loop
+    fn always_runs(&self, block: BlockId) -> bool {
+        let dominance = self.af.dominance_info();
+        !self.exiting.is_empty() && (self.exiting.iter()).all(|&e| dominance.dominated_by(e, block))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        optimizations::{run_pipeline, PassManager},
+        representation::Code,
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn hoists_loads_no_store_in_the_loop_can_clobber() {
+        let text = r#"
+@main(n: int) {
+  zero: int = const 0;
+  one: int = const 1;
+  a: ptr<int> = alloc one;
+  b: ptr<int> = alloc one;
+  store a n;
+  i: int = id zero;
+  s: int = id zero;
+.loop:
+  x: int = load a;
+  s: int = add s x;
+  store b s;
+  y: int = load b;
+  i: int = add i one;
+  c: bool = lt i n;
+  br c .loop .done;
+.done:
+  print s y;
+  free a;
+  free b;
+}
+"#;
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["licm"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        let instrs = &optimized.program.functions[0].instrs;
+        let header = (instrs.iter())
+            .position(|code| matches!(code, Code::Label { label, .. } if label == "loop"))
+            .unwrap();
+        let loads_before_header = (instrs[..header].iter())
+            .filter(|code| code.get_opcode_string() == "load")
+            .count();
+        // the load of `a` leaves the loop, the one of `b` follows a store to it
+        assert_eq!(loads_before_header, 1, "{}", optimized.to_text());
+
+        for n in 0..4 {
+            assert_same_output(&program.program, &optimized.program, &[&n.to_string()]);
+        }
     }
 
-    loop_nodes
+    #[test]
+    fn a_latch_of_one_loop_enters_the_next_through_its_preheader() {
+        // `.mid` closes the outer loop and enters the inner one, whose header gets a preheader
+        let text = r#"
+@main {
+  n: int = const 3;
+  one: int = const 1;
+  i: int = const 0;
+.outer:
+  i: int = add i one;
+  done: bool = lt n i;
+  br done .end .mid;
+.mid:
+  k: int = const 0;
+  go: bool = lt i n;
+  br go .inner .outer;
+.inner:
+  x: int = mul i i;
+  k: int = add k one;
+  print x;
+  again: bool = lt k n;
+  br again .inner .outer;
+.end:
+  ret;
 }
+"#;
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["licm"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        assert_same_output(&program.program, &optimized.program, &[]);
+    }
 
-/// Check if the given set of nodes form a natural loop
-fn is_natural_loop(af: &AbstractFunction, candidate: &NaturalLoop) -> bool {
-    // if the node is not the header, then all of its predecessors must be in the loop, or the header
-    // otherwise, this is not an natural loop
-    log::trace!(
-        "checking if candidate with header '{}' is a natural loop",
-        af.cfg.basic_blocks[candidate.header].label
-    );
-    candidate
-        .nodes
-        .iter()
-        .filter(|&&node| node != candidate.header)
-        .all(|&node| {
-            af.cfg.predecessors[node]
-                .iter()
-                .all(|pred| candidate.nodes.contains(pred) || *pred == candidate.header)
-        })
+    /// how many calls `text` makes before the `.loop` header once `licm` ran, checking that the
+    /// optimized program still prints the same for a few `n`
+    fn calls_hoisted_out_of_loop(text: &str) -> usize {
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["licm"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        for n in 0..4 {
+            assert_same_output(&program.program, &optimized.program, &[&n.to_string()]);
+        }
+        let main = &optimized.program.functions[1];
+        let header = (main.instrs.iter())
+            .position(|code| matches!(code, Code::Label { label, .. } if label == "loop"))
+            .unwrap();
+        main.instrs[..header]
+            .iter()
+            .filter(|code| code.is_call())
+            .count()
+    }
+
+    #[test]
+    fn hoists_pure_calls_that_run_on_every_iteration() {
+        let text = r#"
+@sq(x: int): int {
+  y: int = mul x x;
+  ret y;
+}
+@main(n: int) {
+  zero: int = const 0;
+  one: int = const 1;
+  i: int = id zero;
+  s: int = id zero;
+.loop:
+  v: int = call @sq n;
+  s: int = add s v;
+  i: int = add i one;
+  c: bool = lt i n;
+  br c .loop .done;
+.done:
+  print s;
+}
+"#;
+        assert_eq!(calls_hoisted_out_of_loop(text), 1);
+
+        // a pure call may still not terminate, so one the loop can skip stays in the loop
+        let text = r#"
+@sq(x: int): int {
+  y: int = mul x x;
+  ret y;
+}
+@main(n: int) {
+  zero: int = const 0;
+  one: int = const 1;
+  two: int = const 2;
+  i: int = id zero;
+  s: int = id zero;
+.loop:
+  i: int = add i one;
+  small: bool = lt n two;
+  br small .latch .body;
+.body:
+  v: int = call @sq n;
+  s: int = add s v;
+.latch:
+  c: bool = lt i n;
+  br c .loop .done;
+.done:
+  print s;
+}
+"#;
+        assert_eq!(calls_hoisted_out_of_loop(text), 0);
+    }
 }
@@ -1,2 +1,6 @@
 mod licm;
+mod rotate;
+mod unroll;
 pub use licm::*;
+pub use rotate::*;
+pub use unroll::*;
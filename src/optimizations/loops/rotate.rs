@@ -0,0 +1,386 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    analysis::{LoopInfo, NaturalLoop},
+    dataflow::WorklistResult,
+    optimizations::statistics,
+    representation::{
+        fresh_name, AbstractFunction, BasicBlock, BlockId, ControlFlowGraph, Label, PhiNode,
+        Terminator,
+    },
+};
+
+// Loop rotation over SSA form: a while loop, whose header tests the condition and either enters
+// the body or leaves, becomes a guarded do-while loop. The header is copied into a new preheader,
+// the guard, which decides whether the loop runs at all, and the original header moves to the end
+// of the iteration, after the latches, where it decides whether to run again. The first block of
+// the body becomes the header of the loop. Everything in the loop now runs on every iteration it
+// is entered on, so loads there can be hoisted into a preheader that only runs when the guard
+// lets the loop run, and the test lives next to the backedge.
+//
+// Values of the old header reach the body, and the code after the loop, from either the guard or
+// the test at the end, so they are merged by phi nodes at the new header and at the exit. Only
+// loops with a single latch that leave through the header alone, to a block nothing else jumps
+// to, are rotated; the header's phi nodes then have a single value left and go away.
+
+/// headers with more instructions than this are not copied
+const MAX_HEADER_SIZE: usize = 16;
+
+/// Rotate the while loops of `af` into guarded do-while loops, see the module comment
+pub fn rotate_loops(mut af: AbstractFunction) -> WorklistResult<AbstractFunction> {
+    log::info!("running loop rotation on function '{}'", af.name);
+    if af.speculates() {
+        log::debug!("skipping '{}', which speculates", af.name);
+        return Ok(af);
+    }
+    // the header of a rotated loop is new, so one that was rotated is not met again
+    let mut visited: HashSet<Label> = HashSet::new();
+    let mut rotated = 0;
+    loop {
+        let loop_info = LoopInfo::from(&af);
+        let candidate = (loop_info.loops.iter())
+            .filter(|l| !visited.contains(&af.cfg.basic_blocks[l.header].label))
+            .find_map(|l| Rotation::of(&af, l));
+        let Some(rotation) = candidate else {
+            break;
+        };
+        let header_label = af.cfg.basic_blocks[rotation.header].label.clone();
+        log::info!("rotating loop '{}'", header_label);
+        visited.insert(header_label);
+        visited.insert(af.cfg.basic_blocks[rotation.entry].label.clone());
+        rotation.rotate(&mut af);
+        rotated += 1;
+    }
+    statistics::count("loops rotated", rotated);
+    Ok(af)
+}
+
+/// A while loop that can be rotated
+struct Rotation {
+    header: BlockId,
+    /// the block of the body the header enters
+    entry: BlockId,
+    exit: BlockId,
+    body: Vec<BlockId>,
+}
+
+impl Rotation {
+    fn of(af: &AbstractFunction, l: &NaturalLoop) -> Option<Self> {
+        let cfg = &af.cfg;
+        let header = &cfg.basic_blocks[l.header];
+        let Terminator::Br(then, otherwise, _) = &header.terminator else {
+            return None;
+        };
+        let (then, otherwise) = (cfg.label_map[then], cfg.label_map[otherwise]);
+        let (entry, exit) = match (l.body.contains(&then), l.body.contains(&otherwise)) {
+            (true, false) => (then, otherwise),
+            (false, true) => (otherwise, then),
+            _ => return None,
+        };
+        let usable = l.header != 0
+            && entry != l.header
+            && l.latches.len() == 1
+            && l.exits.len() == 1
+            && cfg.predecessors[exit].iter().eq([l.header])
+            && cfg.predecessors[entry].iter().eq([l.header])
+            && cfg.basic_blocks[entry].phi_nodes.is_empty()
+            && header.preheader.is_empty()
+            && header.instructions.len() <= MAX_HEADER_SIZE;
+        usable.then(|| Self {
+            header: l.header,
+            entry,
+            exit,
+            body: l.body.iter().copied().collect(),
+        })
+    }
+
+    fn rotate(&self, af: &mut AbstractFunction) {
+        // one block for everything that enters the loop, which becomes the guard
+        let guard = af.insert_preheader(self.header);
+        let blocks = &af.cfg.basic_blocks;
+        let label = |b: BlockId| blocks[b].label.clone();
+        let (header, guard_label) = (label(self.header), label(guard));
+        let (entry, exit) = (label(self.entry), label(self.exit));
+
+        let mut names: HashSet<String> = (blocks.iter())
+            .flat_map(|b| {
+                let phis = b.phi_nodes.iter().map(|phi| phi.dest.clone());
+                let code = b.preheader.iter().chain(&b.instructions);
+                phis.chain(code.filter_map(|c| c.get_destination().map(str::to_string)))
+            })
+            .chain(af.args.iter().flatten().map(|arg| arg.name.clone()))
+            .collect();
+
+        // the header as the guard runs it: phi nodes take the value from before the loop, the
+        // rest is computed again under fresh names
+        let mut in_guard: HashMap<String, String> = HashMap::new();
+        for phi in &blocks[self.header].phi_nodes {
+            let (value, _) = (phi.phi_args.iter())
+                .find(|(_, pred)| *pred == guard_label)
+                .expect("the preheader is a predecessor of the header");
+            in_guard.insert(phi.dest.clone(), value.clone());
+        }
+        let mut guard_code = Vec::new();
+        for code in &blocks[self.header].instructions {
+            let mut code = code.clone();
+            code.rename_arguments(|v| in_guard.get(v).cloned().unwrap_or_else(|| v.to_string()));
+            if let Some(dest) = code.get_destination().map(str::to_string) {
+                let name = fresh_name(&mut names, &format!("{}_guard", dest));
+                code.replace_destination(name.clone());
+                in_guard.insert(dest, name);
+            }
+            guard_code.push(code);
+        }
+        let mut guard_test = blocks[self.header].terminator.clone();
+        if let Some(code) = guard_test.code_mut() {
+            code.rename_arguments(|v| in_guard.get(v).cloned().unwrap_or_else(|| v.to_string()));
+        }
+
+        // values of the header, with their types, now coming from the guard or the header
+        let header_values: Vec<(String, PhiNode)> = (blocks[self.header].phi_nodes.iter())
+            .map(|phi| {
+                (
+                    phi.dest.clone(),
+                    phi.phi_type.clone(),
+                    phi.original_name.clone(),
+                    phi.pos,
+                )
+            })
+            .chain(
+                (blocks[self.header].instructions.iter()).filter_map(|code| {
+                    let dest = code.get_destination()?.to_string();
+                    Some((dest.clone(), code.get_type()?, dest, code.get_position()))
+                }),
+            )
+            .map(|(value, phi_type, original_name, pos)| {
+                let merge = PhiNode {
+                    dest: String::new(),
+                    original_name,
+                    phi_type,
+                    pos,
+                    phi_args: vec![
+                        (in_guard[&value].clone(), guard_label.clone()),
+                        (value.clone(), header.clone()),
+                    ],
+                };
+                (value, merge)
+            })
+            .collect();
+
+        // which of them the rest of the loop and the code after it read
+        let in_loop: HashSet<BlockId> = self.body.iter().copied().collect();
+        let reads = |b: BlockId, phis: bool, code: bool| -> Vec<String> {
+            let block = &blocks[b];
+            let phis = (block.phi_nodes.iter())
+                .filter(|_| phis)
+                .flat_map(|phi| phi.phi_args.iter().map(|(v, _)| v));
+            let code = (block.preheader.iter())
+                .chain(&block.instructions)
+                .chain(block.terminator.code())
+                .filter(|_| code)
+                .flat_map(|code| code.get_arguments().into_iter().flatten());
+            phis.chain(code).cloned().collect()
+        };
+        // the header reads its own values, and the exit's phi nodes read them over its edge
+        let read_in_loop: HashSet<String> = (self.body.iter())
+            .flat_map(|&b| reads(b, true, b != self.header))
+            .collect();
+        let read_after: HashSet<String> = (0..blocks.len())
+            .filter(|b| !in_loop.contains(b) && *b != guard)
+            .flat_map(|b| reads(b, b != self.exit, true))
+            .collect();
+
+        let mut at_entry: HashMap<String, String> = HashMap::new();
+        let mut at_exit: HashMap<String, String> = HashMap::new();
+        let mut entry_phis = Vec::new();
+        let mut exit_phis = Vec::new();
+        for (value, merge) in header_values {
+            if read_in_loop.contains(&value) {
+                let dest = fresh_name(&mut names, &format!("{}_loop", value));
+                entry_phis.push(PhiNode {
+                    dest: dest.clone(),
+                    ..merge.clone()
+                });
+                at_entry.insert(value.clone(), dest);
+            }
+            if read_after.contains(&value) {
+                let dest = fresh_name(&mut names, &format!("{}_exit", value));
+                exit_phis.push(PhiNode {
+                    dest: dest.clone(),
+                    ..merge
+                });
+                at_exit.insert(value, dest);
+            }
+        }
+
+        let blocks = &mut af.cfg.basic_blocks;
+        let guard_block = &mut blocks[guard];
+        guard_block.instructions.extend(guard_code);
+        guard_block.terminator = guard_test;
+
+        // the header now only runs after the latches
+        for phi in &mut blocks[self.header].phi_nodes {
+            phi.phi_args.retain(|(_, pred)| *pred != guard_label);
+        }
+        for (b, block) in blocks.iter_mut().enumerate() {
+            let rename = |v: &str| {
+                let map = if in_loop.contains(&b) {
+                    &at_entry
+                } else {
+                    &at_exit
+                };
+                map.get(v).cloned().unwrap_or_else(|| v.to_string())
+            };
+            if b == guard {
+                continue;
+            }
+            if b == self.exit {
+                // these read the header's values over the edge from the header, and the guard's
+                // over the new one
+                for phi in &mut block.phi_nodes {
+                    let from_guard = (phi.phi_args.iter())
+                        .filter(|(_, pred)| *pred == header)
+                        .map(|(v, _)| in_guard.get(v).cloned().unwrap_or_else(|| v.clone()))
+                        .collect::<Vec<_>>();
+                    phi.phi_args
+                        .extend(from_guard.into_iter().map(|v| (v, guard_label.clone())));
+                }
+            } else {
+                for phi in &mut block.phi_nodes {
+                    for (value, _) in &mut phi.phi_args {
+                        *value = rename(value);
+                    }
+                }
+            }
+            if b == self.header {
+                continue;
+            }
+            for code in block.preheader.iter_mut().chain(&mut block.instructions) {
+                code.rename_arguments(rename);
+            }
+            if let Some(code) = block.terminator.code_mut() {
+                code.rename_arguments(rename);
+            }
+        }
+        blocks[self.entry].phi_nodes.extend(entry_phis);
+        blocks[self.exit].phi_nodes.extend(exit_phis);
+
+        // the header's phi nodes now have the latch's value as their only one, and would just
+        // cost a copy on every iteration
+        let single: HashMap<String, String> = (blocks[self.header].phi_nodes.iter())
+            .map(|phi| match phi.phi_args.as_slice() {
+                [(value, _)] => (phi.dest.clone(), value.clone()),
+                _ => unreachable!("the loop has a single latch"),
+            })
+            .collect();
+        let rename = |v: &str| single.get(v).cloned().unwrap_or_else(|| v.to_string());
+        let block = &mut blocks[self.header];
+        block.phi_nodes.clear();
+        for code in &mut block.instructions {
+            code.rename_arguments(rename);
+        }
+        if let Some(code) = block.terminator.code_mut() {
+            code.rename_arguments(rename);
+        }
+        for b in [self.entry, self.exit] {
+            for phi in &mut blocks[b].phi_nodes {
+                for (value, _) in phi.phi_args.iter_mut().filter(|(_, pred)| *pred == header) {
+                    *value = rename(value);
+                }
+            }
+        }
+
+        // copies for the exit's phi nodes would run on every iteration at the end of the header,
+        // so the header leaves through a block of its own when there are any. The guard is the
+        // last block and does not fall through
+        if !blocks[self.exit].phi_nodes.is_empty() {
+            let mut labels: HashSet<String> = af.cfg.label_map.keys().cloned().collect();
+            let landing = fresh_name(&mut labels, &format!("{}_exit", header));
+            blocks[self.header].terminator.map_targets(|to| {
+                if to == exit {
+                    landing.clone()
+                } else {
+                    to.to_string()
+                }
+            });
+            for phi in &mut blocks[self.exit].phi_nodes {
+                for (_, pred) in phi.phi_args.iter_mut().filter(|(_, pred)| *pred == header) {
+                    *pred = landing.clone();
+                }
+            }
+            blocks.push(BasicBlock {
+                id: blocks.len(),
+                label: landing,
+                instructions: Vec::new(),
+                terminator: Terminator::jump(exit.clone()),
+                phi_nodes: Vec::new(),
+                preheader: Vec::new(),
+                natural_loop_return: false,
+                frequency: None,
+                edge_frequencies: HashMap::new(),
+            });
+        }
+        log::debug!(
+            "loop '{}' now starts at '{}' and leaves to '{}'",
+            header,
+            entry,
+            exit
+        );
+
+        af.cfg = ControlFlowGraph::from(std::mem::take(&mut af.cfg.basic_blocks));
+        af.discard_stale_dominance();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        optimizations::{run_pipeline, PassManager},
+        representation::Code,
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn rotated_loops_let_licm_hoist_loads_from_the_body() {
+        let text = r#"
+@main(n: int) {
+  zero: int = const 0;
+  one: int = const 1;
+  a: ptr<int> = alloc one;
+  store a n;
+  i: int = id zero;
+  s: int = id zero;
+.cond:
+  c: bool = lt i n;
+  br c .body .done;
+.body:
+  x: int = load a;
+  s: int = add s x;
+  i: int = add i one;
+  jmp .cond;
+.done:
+  print s i;
+  free a;
+}
+"#;
+        let program = program_from_text(text);
+        // whether the load comes before the body, in the preheader of the rotated loop
+        let hoisted = |passes: &[&str]| {
+            let passes = PassManager::from_names(passes.iter().copied()).unwrap();
+            let optimized = run_pipeline(program.clone(), &passes).unwrap();
+            let instrs = &optimized.program.functions[0].instrs;
+            let position = |found: &dyn Fn(&Code) -> bool| instrs.iter().position(found).unwrap();
+            let load = position(&|code| code.get_opcode_string() == "load");
+            let body =
+                position(&|code| matches!(code, Code::Label { label, .. } if label == "body"));
+            for n in 0..4 {
+                assert_same_output(&program.program, &optimized.program, &[&n.to_string()]);
+            }
+            load < body
+        };
+
+        assert!(!hoisted(&["licm"]));
+        assert!(!hoisted(&["rotate"]));
+        assert!(hoisted(&["rotate", "licm"]));
+    }
+}
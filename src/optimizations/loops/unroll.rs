@@ -0,0 +1,669 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    analysis::{LoopInfo, NaturalLoop},
+    dataflow::WorklistResult,
+    interpreter::{eval_value, Value},
+    optimizations::{statistics, Analysis, Changed, FunctionSize, Pass},
+    representation::{
+        fresh_name, AbstractFunction, BasicBlock, BlockId, Code, ControlFlowGraph, Label, PhiNode,
+        Position, Terminator, Type, ValueOp,
+    },
+};
+
+// Loop unrolling over SSA form, for innermost loops whose header holds the only exit test and
+// jumps out to a block that nothing else jumps to, with a single latch. The loop is copied
+// whole, header included, and the copies are chained: the latch of each copy jumps to the header
+// of the next, the last one back to the original header. A copied header has a single
+// predecessor, so its phi nodes turn into the values the previous copy computed for the
+// backedge, and every other definition in a copy gets a fresh name. Each copy keeps the exit
+// test, so a partially unrolled loop is correct for any trip count. Values of the header used
+// after the loop are merged by new phi nodes in the exit block.
+//
+// When the exit test compares an induction variable that starts at a constant and steps by a
+// constant against another constant, the trip count is found by running the test with the
+// interpreter's semantics. A loop with few iterations gets one copy per iteration plus one for
+// the final test, and the test of every copy is replaced by a jump, which leaves the backedge
+// unreachable. Code hoisted into the header's preheader runs once and is not copied. Loops stop
+// being unrolled once their copies would make the function too large, however small each loop.
+
+/// loops with at most this many iterations are unrolled completely
+const MAX_FULL_TRIPS: usize = 8;
+/// unrolling stops short of a loop with more instructions than this in all its copies
+const MAX_UNROLLED_SIZE: usize = 256;
+/// and of a loop whose copies would grow the function past this many instructions, so that a
+/// function with many small loops (say, inlined copies of the same one) stays bounded
+const MAX_FUNCTION_SIZE: usize = 2048;
+
+/// Copies of the body per iteration of a partially unrolled loop when `--unroll-factor` is not
+/// given
+pub const DEFAULT_UNROLL_FACTOR: usize = 4;
+
+/// The `unroll` pass, with the factor for loops that are not unrolled completely
+#[derive(Debug, Clone, Copy)]
+pub struct Unroll {
+    pub factor: usize,
+}
+
+impl Default for Unroll {
+    fn default() -> Self {
+        Self {
+            factor: DEFAULT_UNROLL_FACTOR,
+        }
+    }
+}
+
+impl Pass for Unroll {
+    fn name(&self) -> &'static str {
+        "unroll"
+    }
+
+    fn description(&self) -> &'static str {
+        "full unrolling of loops with small constant trip counts, partial unrolling of the rest"
+    }
+
+    fn required_analyses(&self) -> &'static [Analysis] {
+        &[Analysis::Dominance]
+    }
+
+    fn run(&self, af: &mut AbstractFunction) -> WorklistResult<Changed> {
+        let unrolled = unroll_loops(af, self.factor);
+        Ok(if unrolled > 0 {
+            Changed::Yes
+        } else {
+            Changed::No
+        })
+    }
+}
+
+/// Unroll the loops of `af` that have the supported shape, see the module comment
+pub fn unroll(mut af: AbstractFunction, factor: usize) -> WorklistResult<AbstractFunction> {
+    unroll_loops(&mut af, factor);
+    Ok(af)
+}
+
+/// the number of loops unrolled
+fn unroll_loops(af: &mut AbstractFunction, factor: usize) -> usize {
+    log::info!("running loop unrolling on function '{}'", af.name);
+    if af.speculates() {
+        log::debug!("skipping '{}', which speculates", af.name);
+        return 0;
+    }
+    // a partially unrolled loop is still a loop, under the same header
+    let mut visited: HashSet<Label> = HashSet::new();
+    let (mut full, mut partial) = (0, 0);
+    let mut function_size = size_with_terminators(af);
+    loop {
+        let loop_info = LoopInfo::from(&*af);
+        let candidate = (loop_info.loops.iter())
+            .filter(|l| !visited.contains(&af.cfg.basic_blocks[l.header].label))
+            .find_map(|l| Candidate::of(af, l));
+        let Some(candidate) = candidate else {
+            break;
+        };
+        let header_label = af.cfg.basic_blocks[candidate.header].label.clone();
+        visited.insert(header_label.clone());
+
+        let size: usize = (candidate.body.iter())
+            .map(|&b| af.cfg.basic_blocks[b].instructions.len() + 1)
+            .sum();
+        // `copies` copies of the loop add all but the first to the function
+        let fits = |copies: usize| {
+            copies * size <= MAX_UNROLLED_SIZE
+                && function_size + (copies - 1) * size <= MAX_FUNCTION_SIZE
+        };
+        match candidate.trip_count(af) {
+            Some(trips) if fits(trips + 1) => {
+                log::info!(
+                    "unrolling loop '{}' fully, {} iterations",
+                    header_label,
+                    trips
+                );
+                candidate.unroll(af, trips + 1, Some(trips));
+                function_size += trips * size;
+                full += 1;
+            }
+            _ if factor > 1 && fits(factor) => {
+                log::info!("unrolling loop '{}' by {}", header_label, factor);
+                candidate.unroll(af, factor, None);
+                function_size += (factor - 1) * size;
+                partial += 1;
+            }
+            _ => log::debug!("loop '{}' is too large to unroll", header_label),
+        }
+    }
+    statistics::count("loops fully unrolled", full);
+    statistics::count("loops partially unrolled", partial);
+    (full + partial) as usize
+}
+
+/// A loop with the shape unrolling supports
+struct Candidate {
+    header: BlockId,
+    latch: BlockId,
+    exit: BlockId,
+    /// blocks of the loop in block order, header included
+    body: Vec<BlockId>,
+}
+
+impl Candidate {
+    fn of(af: &AbstractFunction, l: &NaturalLoop) -> Option<Self> {
+        let cfg = &af.cfg;
+        let (&latch, &exit) = match (l.latches.first(), l.exits.first()) {
+            (Some(latch), Some(exit)) if l.latches.len() == 1 && l.exits.len() == 1 => {
+                (latch, exit)
+            }
+            _ => return None,
+        };
+        let header = &cfg.basic_blocks[l.header];
+        let exit_label = &cfg.basic_blocks[exit].label;
+        let tests_in_header = matches!(
+            &header.terminator,
+            Terminator::Br(then, otherwise, _) if (then == exit_label) != (otherwise == exit_label)
+        );
+        let only_from_header = cfg.predecessors[exit].iter().eq([l.header]);
+        // phi nodes of the header read one value from the latch, which the copies forward
+        let latch_label = &cfg.basic_blocks[latch].label;
+        let forwards_phis = (header.phi_nodes.iter()).all(|phi| {
+            (phi.phi_args.iter())
+                .filter(|(_, label)| label == latch_label)
+                .count()
+                == 1
+        });
+        let no_preheaders =
+            (l.body.iter()).all(|&b| b == l.header || cfg.basic_blocks[b].preheader.is_empty());
+        let usable = l.header != 0
+            && l.children.is_empty()
+            && tests_in_header
+            && only_from_header
+            && forwards_phis
+            && no_preheaders;
+        usable.then(|| Self {
+            header: l.header,
+            latch,
+            exit,
+            body: l.body.iter().copied().collect(),
+        })
+    }
+
+    /// Iterations of the loop, when the exit test compares an induction variable with constant
+    /// start and step against a constant and stops within `MAX_FULL_TRIPS` iterations
+    fn trip_count(&self, af: &AbstractFunction) -> Option<usize> {
+        let blocks = &af.cfg.basic_blocks;
+        let header = &blocks[self.header];
+        let Terminator::Br(then, _, branch) = &header.terminator else {
+            return None;
+        };
+        let stays_when = then != &blocks[self.exit].label;
+        let condition = &branch.get_arguments()?[0];
+        let (compare, operands) = (header.instructions.iter()).find_map(|code| match code {
+            Code::Value { op, dest, args, .. } if dest == condition => Some((*op, args.as_ref()?)),
+            _ => None,
+        })?;
+
+        // constants, and copies of them
+        let code: Vec<&Code> = (blocks.iter())
+            .flat_map(|b| b.preheader.iter().chain(&b.instructions))
+            .collect();
+        let mut constants: HashMap<&str, Value> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for code in &code {
+                let value = match code {
+                    Code::Constant {
+                        dest,
+                        constant_type,
+                        value,
+                        ..
+                    } => Value::from_literal(value, constant_type).map(|v| (dest, v)),
+                    Code::Value {
+                        op: ValueOp::Id,
+                        dest,
+                        args: Some(args),
+                        ..
+                    } => constants.get(args[0].as_str()).map(|v| (dest, *v)),
+                    _ => None,
+                };
+                if let Some((dest, value)) = value {
+                    changed |= constants.insert(dest, value).is_none();
+                }
+            }
+        }
+        let definition = |var: &str| {
+            (self.body.iter())
+                .flat_map(|&b| &blocks[b].instructions)
+                .find(|code| code.get_destination() == Some(var))
+        };
+
+        // the induction variable: start from outside the loop, `add`/`sub` a constant each trip
+        let latch_label = &blocks[self.latch].label;
+        let induction = (header.phi_nodes.iter()).find(|phi| operands.contains(&phi.dest))?;
+        let mut start = None;
+        let mut next = None;
+        for (var, label) in &induction.phi_args {
+            if label == latch_label {
+                next = Some(var);
+            } else if start.replace(constants.get(var.as_str())?).is_some() {
+                return None;
+            }
+        }
+        let (mut value, next) = (*start?, next?);
+        let (step_op, step) = match definition(next)? {
+            Code::Value {
+                op: op @ (ValueOp::Add | ValueOp::Sub),
+                args: Some(args),
+                ..
+            } if args.len() == 2 => match (args[0] == induction.dest, args[1] == induction.dest) {
+                (true, false) => (*op, *constants.get(args[1].as_str())?),
+                (false, true) if *op == ValueOp::Add => (*op, *constants.get(args[0].as_str())?),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        for trips in 0..=MAX_FULL_TRIPS {
+            let arguments: Vec<Value> = (operands.iter())
+                .map(|arg| match arg == &induction.dest {
+                    true => Some(value),
+                    false => constants.get(arg.as_str()).copied(),
+                })
+                .collect::<Option<_>>()?;
+            match eval_value(compare, &arguments).ok()? {
+                Value::Bool(b) if b == stays_when => {}
+                Value::Bool(_) => return Some(trips),
+                _ => return None,
+            }
+            value = eval_value(step_op, &[value, step]).ok()?;
+        }
+        None
+    }
+
+    /// Make `copies` copies of the loop, the original being the first. With `trips`, every
+    /// header but the last enters the body and the last one leaves the loop
+    fn unroll(&self, af: &mut AbstractFunction, copies: usize, trips: Option<usize>) {
+        let blocks = &af.cfg.basic_blocks;
+        let label = |b: BlockId| blocks[b].label.clone();
+        let (header, latch, exit) = (label(self.header), label(self.latch), label(self.exit));
+        let in_loop: HashSet<BlockId> = self.body.iter().copied().collect();
+
+        let mut names: HashSet<String> = (blocks.iter())
+            .flat_map(|b| {
+                let phis = b.phi_nodes.iter().map(|phi| phi.dest.clone());
+                let code = b.preheader.iter().chain(&b.instructions);
+                phis.chain(code.filter_map(|c| c.get_destination().map(str::to_string)))
+            })
+            .chain(af.args.iter().flatten().map(|arg| arg.name.clone()))
+            .collect();
+        let mut labels: HashSet<String> = af.cfg.label_map.keys().cloned().collect();
+
+        // the variables a copy redefines; the header's preheader runs once and is not copied
+        let header_phis: Vec<&PhiNode> = blocks[self.header].phi_nodes.iter().collect();
+        let defined: Vec<String> = (self.body.iter())
+            .flat_map(|&b| {
+                let phis = (blocks[b].phi_nodes.iter())
+                    .filter(move |_| b != self.header)
+                    .map(|phi| phi.dest.clone());
+                let code = blocks[b].instructions.iter();
+                phis.chain(code.filter_map(|c| c.get_destination().map(str::to_string)))
+            })
+            .collect();
+        let latch_value = |phi: &PhiNode| {
+            (phi.phi_args.iter())
+                .find(|(_, pred)| *pred == latch)
+                .map(|(var, _)| var.clone())
+                .expect("checked by Candidate::of")
+        };
+
+        // per copy: original variable -> its name in the copy, and the same for labels. The
+        // first copy is the loop itself and keeps its names
+        let mut renames: Vec<HashMap<String, String>> = vec![HashMap::new()];
+        let mut relabels: Vec<HashMap<String, String>> = vec![HashMap::new()];
+        for copy in 1..copies {
+            let previous = &renames[copy - 1];
+            let mut rename: HashMap<String, String> = (header_phis.iter())
+                .map(|phi| {
+                    let value = latch_value(phi);
+                    let value = previous.get(&value).cloned().unwrap_or(value);
+                    (phi.dest.clone(), value)
+                })
+                .collect();
+            for var in &defined {
+                rename.insert(
+                    var.clone(),
+                    fresh_name(&mut names, &format!("{}_u{}", var, copy)),
+                );
+            }
+            let relabel = (self.body.iter())
+                .map(|&b| {
+                    (
+                        label(b),
+                        fresh_name(&mut labels, &format!("{}_u{}", label(b), copy)),
+                    )
+                })
+                .collect();
+            renames.push(rename);
+            relabels.push(relabel);
+        }
+        let var = |copy: usize, var: &str| -> String {
+            renames[copy]
+                .get(var)
+                .cloned()
+                .unwrap_or_else(|| var.to_string())
+        };
+        // the copy after the last one is the loop itself again
+        let block_label = |copy: usize, target: &str| -> String {
+            (relabels[copy % copies].get(target).cloned()).unwrap_or_else(|| target.to_string())
+        };
+        // where an edge of copy `copy` goes: the backedge on to the next copy, the rest within it
+        let target = |copy: usize, from: BlockId, to: &str| -> String {
+            if to == header && from == self.latch {
+                block_label(copy + 1, &header)
+            } else {
+                block_label(copy, to)
+            }
+        };
+
+        let mut new_blocks: Vec<BasicBlock> = Vec::new();
+        let mut terminators: Vec<(BlockId, Terminator)> = Vec::new();
+        for copy in 0..copies {
+            for &b in &self.body {
+                let block = &blocks[b];
+                let fallthrough = blocks.get(b + 1).map(|next| next.label.clone());
+                let mut terminator = block.terminator.clone().explicit(fallthrough);
+                terminator.map_targets(|to| target(copy, b, to));
+                if let Some(code) = terminator.code_mut() {
+                    code.rename_arguments(|v| var(copy, v));
+                }
+                if b == self.header {
+                    if let Some(trips) = trips {
+                        terminator = decide(terminator, &exit, copy < trips);
+                    }
+                }
+                if copy == 0 {
+                    terminators.push((b, terminator));
+                    continue;
+                }
+
+                let phi_nodes = (block.phi_nodes.iter())
+                    .filter(|_| b != self.header)
+                    .map(|phi| PhiNode {
+                        dest: var(copy, &phi.dest),
+                        original_name: phi.original_name.clone(),
+                        phi_type: phi.phi_type.clone(),
+                        phi_args: (phi.phi_args.iter())
+                            .map(|(v, pred)| (var(copy, v), block_label(copy, pred)))
+                            .collect(),
+                        pos: phi.pos,
+                    })
+                    .collect();
+                let instructions = (block.instructions.iter())
+                    .map(|code| {
+                        let mut code = code.clone();
+                        if let Some(dest) = code.get_destination() {
+                            code.replace_destination(var(copy, dest));
+                        }
+                        code.rename_arguments(|v| var(copy, v));
+                        code
+                    })
+                    .collect();
+                new_blocks.push(BasicBlock {
+                    id: 0,
+                    label: block_label(copy, &block.label),
+                    instructions,
+                    terminator,
+                    phi_nodes,
+                    preheader: Vec::new(),
+                    natural_loop_return: block.natural_loop_return,
+                    frequency: None,
+                    edge_frequencies: HashMap::new(),
+                });
+            }
+        }
+
+        // values of the header used after the loop, now coming from whichever copy left it
+        let header_values: Vec<(String, Type, String, Option<Position>)> = (header_phis.iter())
+            .map(|phi| {
+                (
+                    phi.dest.clone(),
+                    phi.phi_type.clone(),
+                    phi.original_name.clone(),
+                    phi.pos,
+                )
+            })
+            .chain(
+                (blocks[self.header].instructions.iter()).filter_map(|code| {
+                    let dest = code.get_destination()?.to_string();
+                    Some((dest.clone(), code.get_type()?, dest, code.get_position()))
+                }),
+            )
+            .collect();
+        let outside = (0..blocks.len()).filter(|b| !in_loop.contains(b));
+        let used_outside: HashSet<&str> = outside
+            .flat_map(|b| {
+                let block = &blocks[b];
+                let phis = (block.phi_nodes.iter())
+                    .filter(move |_| b != self.exit)
+                    .flat_map(|phi| phi.phi_args.iter().map(|(v, _)| v.as_str()));
+                let code = (block.preheader.iter())
+                    .chain(&block.instructions)
+                    .chain(block.terminator.code())
+                    .flat_map(|code| code.get_arguments().into_iter().flatten())
+                    .map(String::as_str);
+                phis.chain(code)
+            })
+            .collect();
+        let mut exit_phis = Vec::new();
+        let mut at_exit: HashMap<String, String> = HashMap::new();
+        for (value, phi_type, original_name, pos) in header_values {
+            if !used_outside.contains(value.as_str()) {
+                continue;
+            }
+            let dest = fresh_name(&mut names, &format!("{}_exit", value));
+            exit_phis.push(PhiNode {
+                dest: dest.clone(),
+                original_name,
+                phi_type,
+                phi_args: (0..copies)
+                    .map(|copy| (var(copy, &value), block_label(copy, &header)))
+                    .collect(),
+                pos,
+            });
+            at_exit.insert(value, dest);
+        }
+
+        // now rewrite the original blocks
+        let last = copies - 1;
+        let blocks = &mut af.cfg.basic_blocks;
+        for (b, terminator) in terminators {
+            blocks[b].terminator = terminator;
+        }
+        for phi in &mut blocks[self.header].phi_nodes {
+            for (value, pred) in &mut phi.phi_args {
+                if *pred == latch {
+                    *value = var(last, value);
+                    *pred = block_label(last, &latch);
+                }
+            }
+        }
+        for phi in &mut blocks[self.exit].phi_nodes {
+            let from_header: Vec<String> = (phi.phi_args.iter())
+                .filter(|(_, pred)| *pred == header)
+                .map(|(value, _)| value.clone())
+                .collect();
+            for value in from_header {
+                for copy in 1..copies {
+                    phi.phi_args
+                        .push((var(copy, &value), block_label(copy, &header)));
+                }
+            }
+        }
+        for b in (0..blocks.len()).filter(|b| !in_loop.contains(b)) {
+            let block = &mut blocks[b];
+            let rename = |v: &str| at_exit.get(v).cloned().unwrap_or_else(|| v.to_string());
+            if b != self.exit {
+                for phi in &mut block.phi_nodes {
+                    for (value, _) in &mut phi.phi_args {
+                        *value = rename(value);
+                    }
+                }
+            }
+            for code in block.preheader.iter_mut().chain(&mut block.instructions) {
+                code.rename_arguments(rename);
+            }
+            if let Some(code) = block.terminator.code_mut() {
+                code.rename_arguments(rename);
+            }
+        }
+        blocks[self.exit].phi_nodes.extend(exit_phis);
+
+        // the old last block fell off the end of the function, which now has to be explicit
+        if let Some(last) = blocks.last_mut() {
+            last.terminator = last.terminator.clone().explicit(None);
+        }
+        let mut blocks = std::mem::take(blocks);
+        blocks.extend(new_blocks);
+        for (id, block) in blocks.iter_mut().enumerate() {
+            block.id = id;
+        }
+        af.cfg = ControlFlowGraph::from(blocks).prune_unreachable_blocks();
+
+        // full unrolling cuts edges, whose phi arguments go with them
+        let cfg = &mut af.cfg;
+        for b in 0..cfg.basic_blocks.len() {
+            let predecessors: HashSet<String> = (cfg.predecessors[b].iter())
+                .map(|p| cfg.basic_blocks[p].label.clone())
+                .collect();
+            for phi in &mut cfg.basic_blocks[b].phi_nodes {
+                phi.phi_args.retain(|(_, pred)| predecessors.contains(pred));
+            }
+        }
+        af.discard_stale_dominance();
+    }
+}
+
+/// replace the exit test of a fully unrolled header by a jump into the body or out of the loop
+fn decide(terminator: Terminator, exit: &str, stays: bool) -> Terminator {
+    match terminator {
+        Terminator::Br(then, otherwise, _) => {
+            let inside = if then == exit { otherwise } else { then };
+            Terminator::jump(if stays { inside } else { exit.to_string() })
+        }
+        terminator => terminator,
+    }
+}
+
+/// instructions of `af`, counting hoisted code and one terminator per block
+fn size_with_terminators(af: &AbstractFunction) -> usize {
+    let size = FunctionSize::of(af);
+    size.instructions + size.blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{size_with_terminators, unroll, DEFAULT_UNROLL_FACTOR, MAX_FUNCTION_SIZE};
+    use crate::{
+        optimizations::{run_pipeline, PassManager},
+        testing::{assert_same_output, function_from_text, program_from_text},
+    };
+
+    #[test]
+    fn unrolls_counted_loops_fully_and_others_by_the_factor() {
+        let text = r#"
+@main(n: int) {
+  zero: int = const 0;
+  one: int = const 1;
+  three: int = const 3;
+  i: int = id zero;
+  s: int = id zero;
+.full:
+  c: bool = lt i three;
+  br c .full.body .full.done;
+.full.body:
+  s: int = add s i;
+  i: int = add i one;
+  jmp .full;
+.full.done:
+  j: int = id zero;
+.partial:
+  d: bool = lt j n;
+  br d .partial.body .partial.done;
+.partial.body:
+  s: int = add s j;
+  j: int = add j one;
+  jmp .partial;
+.partial.done:
+  print s i j;
+}
+"#;
+        let program = program_from_text(text);
+        let mut passes = PassManager::from_names(["unroll"]).unwrap();
+        passes.unroll_factor(3);
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        let branches = (optimized.program.functions[0].instrs.iter())
+            .filter(|code| code.get_opcode_string() == "br")
+            .count();
+        // no test is left in the counted loop, each of the three copies of the other keeps its own
+        assert_eq!(branches, 3, "{}", optimized.to_text());
+
+        for n in 0..7 {
+            assert_same_output(&program.program, &optimized.program, &[&n.to_string()]);
+        }
+    }
+
+    #[test]
+    fn code_hoisted_out_of_an_unrolled_loop_keeps_its_arguments() {
+        // `x` is hoisted into the preheader of `.loop`, which no longer is a loop once unrolled
+        let text = r#"
+@main(a: int) {
+  zero: int = const 0;
+  one: int = const 1;
+  two: int = const 2;
+  b: int = add a one;
+  i: int = id zero;
+  s: int = id zero;
+.loop:
+  c: bool = lt i two;
+  br c .body .done;
+.body:
+  x: int = mul b two;
+  s: int = add s x;
+  i: int = add i one;
+  jmp .loop;
+.done:
+  print s;
+}
+"#;
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["licm", "unroll", "dce"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        assert_same_output(&program.program, &optimized.program, &["5"]);
+    }
+
+    #[test]
+    fn stops_unrolling_once_the_function_is_large() {
+        // 40 loops that each fit the per-loop limit, but not all together
+        let mut text =
+            String::from("@main(n: int) {\n  one: int = const 1;\n  s: int = const 0;\n");
+        for l in 0..40 {
+            text += &format!("  i{l}: int = const 0;\n.l{l}:\n  c{l}: bool = lt i{l} n;\n");
+            text += &format!("  br c{l} .b{l} .d{l};\n.b{l}:\n");
+            for _ in 0..20 {
+                text += "  s: int = add s one;\n";
+            }
+            text += &format!("  i{l}: int = add i{l} one;\n  jmp .l{l};\n.d{l}:\n");
+        }
+        text += "  print s;\n}\n";
+
+        let before = function_from_text(&text);
+        let after = unroll(before.clone(), DEFAULT_UNROLL_FACTOR).unwrap();
+        let size = size_with_terminators(&after);
+        assert!(size > size_with_terminators(&before));
+        assert!(size <= MAX_FUNCTION_SIZE, "{}", size);
+
+        let program = program_from_text(&text);
+        let passes = PassManager::from_names(["unroll"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        assert_same_output(&program.program, &optimized.program, &["3"]);
+    }
+}
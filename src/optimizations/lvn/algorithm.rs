@@ -1,7 +1,12 @@
+use std::collections::HashSet;
+
 use crate::{
     dataflow::{run_dataflow_analysis, WorklistProperty, WorklistResult},
-    optimizations::lvn::numbering_table::LocalValueNumberingTable,
-    representation::{AbstractFunction, ControlFlowGraph},
+    optimizations::{
+        lvn::numbering_table::{with_pure_functions, LocalValueNumberingTable},
+        statistics,
+    },
+    representation::{AbstractFunction, Code, ControlFlowGraph, FunctionAttribute, ValueOp},
 };
 
 struct Lvn {}
@@ -38,7 +43,7 @@ impl WorklistProperty for Lvn {
     ) -> crate::dataflow::WorklistResult<Self::Domain> {
         let block = &mut cfg.basic_blocks[block_id];
         for instr in block.instructions.iter_mut() {
-            *instr = domain.canonicalize(instr.clone());
+            domain.canonicalize(instr);
         }
         Ok(domain)
     }
@@ -46,12 +51,133 @@ impl WorklistProperty for Lvn {
 
 pub fn lvn(mut af: AbstractFunction) -> WorklistResult<AbstractFunction> {
     log::info!("running global value numbering on function '{}'", af.name);
-    let start = std::time::Instant::now();
-    run_dataflow_analysis::<Lvn>(&mut af)?;
-    log::info!(
-        "completed global value numbering on function '{}' in {:?}",
-        af.name,
-        start.elapsed(),
-    );
+    let before = computations(&af);
+    with_pure_functions(pure_functions(&af), || {
+        run_dataflow_analysis::<Lvn>(&mut af)
+    })?;
+    count_rewrites(&af, &before);
     Ok(af)
 }
+
+/// For every instruction, whether it computes a value (`Some(true)`), copies one (`Some(false)`)
+/// or is no value operation at all. Canonicalization rewrites instructions in place, so this is
+/// enough to compare them one to one afterwards
+pub(super) fn computations(af: &AbstractFunction) -> Vec<Vec<Option<bool>>> {
+    let computes = |code: &Code| match code {
+        Code::Value { op, .. } => Some(!matches!(op, ValueOp::Id)),
+        _ => None,
+    };
+    af.cfg
+        .basic_blocks
+        .iter()
+        .map(|block| block.instructions.iter().map(computes).collect())
+        .collect()
+}
+
+/// Functions whose calls are numbered like any other value
+pub(super) fn pure_functions(af: &AbstractFunction) -> HashSet<String> {
+    (af.known_attrs.iter())
+        .filter(|(_, attrs)| attrs.contains(&FunctionAttribute::Pure))
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Report the computations that became constants or copies since `before`
+pub(super) fn count_rewrites(af: &AbstractFunction, before: &[Vec<Option<bool>>]) {
+    let (mut folded, mut reused) = (0, 0);
+    for (block, before) in af.cfg.basic_blocks.iter().zip(before.iter()) {
+        for (after, before) in block.instructions.iter().zip(before.iter()) {
+            match (before, after) {
+                (Some(_), Code::Constant { .. }) => folded += 1,
+                (
+                    Some(true),
+                    Code::Value {
+                        op: ValueOp::Id, ..
+                    },
+                ) => reused += 1,
+                _ => {}
+            }
+        }
+    }
+    statistics::count("expressions folded", folded);
+    statistics::count("redundant expressions reused", reused);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        optimizations::{run_pipeline, PassManager},
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn numbers_calls_to_pure_functions() {
+        let text = r#"
+@square(x: int): int {
+  y: int = mul x x;
+  ret y;
+}
+@show(x: int): int {
+  print x;
+  ret x;
+}
+@main(n: int) {
+  a: int = call @square n;
+  b: int = call @square n;
+  c: int = call @show n;
+  d: int = call @show n;
+  print a b c d;
+}
+"#;
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["lvn"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        let main = optimized
+            .program
+            .functions
+            .iter()
+            .find(|f| f.name == "main");
+        let calls = (main.unwrap().instrs.iter())
+            .filter(|code| code.is_call())
+            .count();
+        // the second call to @square reuses the first, both calls to @show print
+        assert_eq!(calls, 3, "{}", optimized.to_text());
+
+        let after = assert_same_output(&program.program, &optimized.program, &["3"]);
+        assert_eq!(after.stdout, "3\n3\n9 9 3 3\n");
+    }
+
+    #[cfg(feature = "bitops")]
+    #[test]
+    fn folds_bit_operations() {
+        let text = r#"
+@main(n: int) {
+  one: int = const 1;
+  minus: int = const -16;
+  six: int = const 6;
+  a: int = shl one six;
+  b: int = shr minus one;
+  c: int = bitand a six;
+  d: int = bitor a six;
+  e: int = bitxor d six;
+  f: int = bitxor n six;
+  g: int = bitxor six n;
+  print a b c d e f g;
+}
+"#;
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["lvn", "dce"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        let text = optimized.to_text();
+        // everything but `f` is folded, and `g` reuses it
+        assert_eq!(text.matches(" = bitxor ").count(), 1, "{}", text);
+        assert!(
+            !text.contains(" = shl ") && !text.contains(" = shr "),
+            "{}",
+            text
+        );
+
+        let after = assert_same_output(&program.program, &optimized.program, &["5"]);
+        assert_eq!(after.stdout, "64 -8 0 70 64 3 3\n");
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{
+    dataflow::WorklistResult,
+    optimizations::{
+        lvn::{
+            algorithm::{computations, count_rewrites, pure_functions},
+            numbering_table::{with_pure_functions, LocalValueNumberingTable},
+        },
+        statistics,
+    },
+    representation::{AbstractFunction, BasicBlock},
+};
+
+// Dominator-based value numbering (Briggs, Cooper and Simpson's DVNT). Blocks are visited in a
+// preorder walk of the dominator tree, each starting from the table its immediate dominator ended
+// with: everything in that table is computed on every path to the block, so no tables are
+// intersected at joins. The tables are persistent, so handing one to each child is cheap.
+//
+// A phi node whose arguments are all the same variable, or that has the same arguments as an
+// earlier phi node of its block, is numbered like that variable or phi node. The phi node itself
+// stays for dce to remove.
+
+pub fn dominator_value_numbering(mut af: AbstractFunction) -> WorklistResult<AbstractFunction> {
+    log::info!(
+        "running dominator value numbering on function '{}'",
+        af.name
+    );
+    let before = computations(&af);
+    let phis = with_pure_functions(pure_functions(&af), || {
+        let mut phis = 0;
+        let mut pending = vec![(0, LocalValueNumberingTable::default())];
+        while let Some((block, mut table)) = pending.pop() {
+            phis += number_phi_nodes(&af.cfg.basic_blocks[block], &mut table);
+            for code in &mut af.cfg.basic_blocks[block].instructions {
+                table.canonicalize(code);
+            }
+            let children = af.dominance_info().get_immediate_dominated(block);
+            pending.extend(children.iter().map(|child| (child, table.clone())));
+        }
+        phis
+    });
+    count_rewrites(&af, &before);
+    statistics::count("redundant phi nodes", phis);
+    Ok(af)
+}
+
+/// Alias phi nodes that are copies of a variable or of an earlier phi node, returning how many
+fn number_phi_nodes(block: &BasicBlock, table: &mut LocalValueNumberingTable) -> u64 {
+    let mut redundant = 0;
+    for (i, phi) in block.phi_nodes.iter().enumerate() {
+        let mut values = phi.phi_args.iter().map(|(var, _)| var);
+        let first = values.next();
+        let same = first.filter(|first| **first != phi.dest && values.all(|var| var == *first));
+        let earlier = block.phi_nodes[..i].iter().find(|earlier| {
+            let mut args = earlier.phi_args.clone();
+            let mut others = phi.phi_args.clone();
+            args.sort_by(|a, b| a.1.cmp(&b.1));
+            others.sort_by(|a, b| a.1.cmp(&b.1));
+            earlier.phi_type == phi.phi_type && args == others
+        });
+        let value = same.or(earlier.map(|earlier| &earlier.dest));
+        if let Some(value) = value {
+            log::debug!("phi node {} in '{}' is {}", phi.dest, block.label, value);
+            table.alias(&phi.dest, value);
+            redundant += 1;
+        }
+    }
+    redundant
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        optimizations::{run_pipeline, PassManager},
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn reuses_values_from_dominating_blocks_only() {
+        let text = r#"
+@main(a: int, b: int) {
+  x: int = add a b;
+  c: bool = lt a b;
+  br c .left .right;
+.left:
+  y: int = add b a;
+  print y;
+  z: int = mul a b;
+  jmp .join;
+.right:
+  z: int = mul a b;
+  jmp .join;
+.join:
+  w: int = add a b;
+  v: int = mul a b;
+  print x z w v;
+}
+"#;
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["dvnt", "dce"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        let ops = |op: &str| {
+            (optimized.program.functions[0].instrs.iter())
+                .filter(|code| code.get_opcode_string() == op)
+                .count()
+        };
+        // every `add a b` reuses the entry's; the `mul` in the join is not dominated by either
+        assert_eq!(ops("add"), 1, "{}", optimized.to_text());
+        assert_eq!(ops("mul"), 3, "{}", optimized.to_text());
+
+        assert_same_output(&program.program, &optimized.program, &["2", "5"]);
+    }
+}
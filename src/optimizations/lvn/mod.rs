@@ -1,4 +1,6 @@
 mod algorithm;
+mod dominator;
 mod numbering_table;
 
 pub use algorithm::lvn;
+pub use dominator::dominator_value_numbering;
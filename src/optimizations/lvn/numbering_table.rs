@@ -1,18 +1,52 @@
 use std::{
-    collections::{HashMap, HashSet},
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        OnceLock,
-    },
+    cell::{Cell, RefCell},
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::BuildHasherDefault,
 };
 
-use crate::representation::{Code, ConstantOp, EffectOp, Literal, MemoryOp, Type, ValueOp};
+use imbl::{shared_ptr::DefaultSharedPtr, GenericHashMap};
 
-static UID_COUNTER: OnceLock<AtomicUsize> = OnceLock::new();
+use crate::{
+    optimizations::order_operands,
+    representation::{opcode, Code, ConstantOp, EffectOp, Literal, MemoryOp, Type, ValueOp},
+};
+
+// The tables are persistent maps: a block's input is a copy of its predecessor's output, and
+// copies share structure until one side is written, so merges cost about as much as the
+// entries that actually differ. They hash with fixed keys and number values from zero for each
+// function, so that where lvn looks an entry up by its value, it finds the same one on every run.
+
+/// a persistent map that iterates in the same order on every run
+type HashMap<K, V> = GenericHashMap<K, V, BuildHasherDefault<DefaultHasher>, DefaultSharedPtr>;
+
+thread_local! {
+    /// functions without side effects, whose calls are numbered like any other value. The
+    /// worklist hands the tables nothing but the blocks, so `with_pure_functions` sets these
+    /// around the analysis of one function
+    static PURE_FUNCTIONS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    /// the next value number, unique within the function being numbered
+    static NEXT_UID: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Run `f` with calls to `pure` numbered as values, and value numbers counting from zero
+pub fn with_pure_functions<R>(pure: HashSet<String>, f: impl FnOnce() -> R) -> R {
+    let previous = PURE_FUNCTIONS.with(|cell| cell.replace(pure));
+    let previous_uid = NEXT_UID.replace(0);
+    let result = f();
+    PURE_FUNCTIONS.with(|cell| cell.replace(previous));
+    NEXT_UID.set(previous_uid);
+    result
+}
+
+fn is_pure_call(funcs: &Option<Box<[String]>>) -> bool {
+    let callee = funcs.as_ref().and_then(|funcs| funcs.first());
+    callee.is_some_and(|callee| PURE_FUNCTIONS.with(|pure| pure.borrow().contains(callee)))
+}
 
 fn next_uid() -> usize {
-    let counter = UID_COUNTER.get_or_init(|| AtomicUsize::new(0));
-    counter.fetch_add(1, Ordering::SeqCst)
+    let uid = NEXT_UID.get();
+    NEXT_UID.set(uid + 1);
+    uid
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -26,6 +60,8 @@ enum Operation {
     Memory(MemoryOp),
     Effect(EffectOp),
     Constant(ConstantOp),
+    /// call to a pure function
+    Call(String),
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -49,6 +85,9 @@ pub struct LocalValueNumberingTable {
 
 impl PartialEq for LocalValueNumberingTable {
     fn eq(&self, other: &Self) -> bool {
+        if self.cloud.ptr_eq(&other.cloud) {
+            return true;
+        }
         let this_ch = self
             .cloud
             .values()
@@ -76,365 +115,216 @@ impl LocalValueNumberingTable {
         self.cloud.get(var).unwrap().clone()
     }
 
-    fn flatten_copy(&self, code: &Code) -> Option<Expr> {
-        if matches!(
-            code,
-            Code::Value {
-                op: ValueOp::Id,
-                ..
-            }
-        ) {
-            let arg_var = &code.get_arguments().unwrap()[0];
-            if let Some((_, expr_var)) = self.cloud.get(arg_var) {
-                for (expr, (_, var)) in self.table.iter() {
-                    if var == expr_var {
-                        return Some(expr.clone());
-                    }
-                }
-            }
-        }
-        None
+    /// Number `var` like `value`, so that later uses of `var` read `value` instead
+    pub fn alias(&mut self, var: &str, value: &str) {
+        let numbering = self.get_variable_numbering(value);
+        self.cloud.insert(var.to_string(), numbering);
     }
 
-    fn is_commutative(&self, operation: &Operation) -> bool {
-        match operation {
-            Operation::Value(value_op) => match value_op {
-                ValueOp::And
-                | ValueOp::Or
-                | ValueOp::Add
-                | ValueOp::Mul
-                | ValueOp::Eq
-                | ValueOp::Fadd
-                | ValueOp::Fmul
-                | ValueOp::Feq
-                | ValueOp::Ceq => true,
-                _ => false,
-            },
-            Operation::Memory(_) => false,
-            Operation::Effect(_) => false,
-            Operation::Constant(_) => false,
-        }
+    /// the expression behind `arg_var`, the argument of an `id`
+    fn flatten_copy(&self, arg_var: &str) -> Option<Expr> {
+        let (_, expr_var) = self.cloud.get(arg_var)?;
+        self.table
+            .iter()
+            .find(|(_, (_, var))| var == expr_var)
+            .map(|(expr, _)| expr.clone())
     }
 
     fn is_constexpr(&self, operation: &Operation) -> bool {
-        match operation {
-            Operation::Value(value_op) => match value_op {
-                ValueOp::Add
-                | ValueOp::Sub
-                | ValueOp::Mul
-                | ValueOp::Div
-                | ValueOp::Fadd
-                | ValueOp::Fsub
-                | ValueOp::Fmul
-                | ValueOp::Fdiv
-                | ValueOp::Or
-                | ValueOp::Not
-                | ValueOp::And
-                | ValueOp::Eq
-                | ValueOp::Lt
-                | ValueOp::Gt
-                | ValueOp::Le
-                | ValueOp::Ge
-                | ValueOp::Feq
-                | ValueOp::Flt
-                | ValueOp::Fgt
-                | ValueOp::Fle
-                | ValueOp::Fge
-                | ValueOp::Ceq
-                | ValueOp::Clt
-                | ValueOp::Cle
-                | ValueOp::Cgt
-                | ValueOp::Cge
-                | ValueOp::Float2bits
-                | ValueOp::Bits2float
-                | ValueOp::Char2int
-                | ValueOp::Int2char => true,
-                _ => false,
-            },
-            _ => false,
-        }
+        matches!(operation, Operation::Value(op) if opcode(*op).is_some())
     }
 
-    fn eval_constexpr(&self, op: &Operation, _t: &Type, literals: &Vec<Literal>) -> Literal {
-        assert!(self.is_constexpr(op));
+    /// `op` on `literals`, `None` if it fails on them
+    fn eval_constexpr(&self, op: &Operation, literals: &[Literal]) -> Option<Literal> {
         match op {
-            Operation::Value(value_op) => match value_op {
-                ValueOp::Add => literals[0].cast_to(&Type::Int) + literals[1].cast_to(&Type::Int),
-                ValueOp::Sub => literals[0].cast_to(&Type::Int) - literals[1].cast_to(&Type::Int),
-                ValueOp::Mul => literals[0].cast_to(&Type::Int) * literals[1].cast_to(&Type::Int),
-                ValueOp::Div => literals[0].cast_to(&Type::Int) / literals[1].cast_to(&Type::Int),
-                ValueOp::Fadd => {
-                    literals[0].cast_to(&Type::Float) + literals[1].cast_to(&Type::Float)
-                }
-                ValueOp::Fsub => {
-                    literals[0].cast_to(&Type::Float) - literals[1].cast_to(&Type::Float)
-                }
-                ValueOp::Fmul => {
-                    literals[0].cast_to(&Type::Float) * literals[1].cast_to(&Type::Float)
-                }
-                ValueOp::Fdiv => {
-                    literals[0].cast_to(&Type::Float) / literals[1].cast_to(&Type::Float)
-                }
-                ValueOp::Or => literals[0].cast_to(&Type::Bool) | literals[1].cast_to(&Type::Bool),
-                ValueOp::Not => !literals[0].cast_to(&Type::Bool),
-                ValueOp::And => literals[0].cast_to(&Type::Bool) & literals[1].cast_to(&Type::Bool),
-                ValueOp::Eq => Literal::Bool(literals[0] == literals[1]),
-                ValueOp::Lt => Literal::Bool(literals[0] < literals[1]),
-                ValueOp::Gt => Literal::Bool(literals[0] > literals[1]),
-                ValueOp::Le => Literal::Bool(literals[0] <= literals[1]),
-                ValueOp::Ge => Literal::Bool(literals[0] >= literals[1]),
-                ValueOp::Feq => Literal::Bool(literals[0] == literals[1]),
-                ValueOp::Flt => Literal::Bool(literals[0] < literals[1]),
-                ValueOp::Fgt => Literal::Bool(literals[0] > literals[1]),
-                ValueOp::Fle => Literal::Bool(literals[0] <= literals[1]),
-                ValueOp::Fge => Literal::Bool(literals[0] >= literals[1]),
-                ValueOp::Ceq => Literal::Bool(literals[0] == literals[1]),
-                ValueOp::Clt => Literal::Bool(literals[0] < literals[1]),
-                ValueOp::Cgt => Literal::Bool(literals[0] > literals[1]),
-                ValueOp::Cle => Literal::Bool(literals[0] <= literals[1]),
-                ValueOp::Cge => Literal::Bool(literals[0] >= literals[1]),
-                ValueOp::Char2int => literals[0].cast_to(&Type::Int),
-                ValueOp::Int2char => literals[0].cast_to(&Type::Char),
-                ValueOp::Float2bits => literals[0].bitcast(&Type::Int),
-                ValueOp::Bits2float => literals[0].bitcast(&Type::Float),
-                _ => panic!("should not be here"),
-            },
-            _ => panic!("should not be here"),
+            Operation::Value(op) => opcode(*op)?.fold(literals),
+            _ => None,
         }
     }
 
     pub fn intersect(&self, other: &Self) -> Self {
-        let mut new_table = HashMap::new();
-        let mut new_cloud = HashMap::new();
-        // println!("Intersecting LVN tables:");
-        // // println!("  Self: {:?}", self);
-        // println!("  Other: {:?}", other);
-        for (expr, (num, var)) in &self.table {
-            if let Some((other_num, other_var)) = other.table.get(expr) {
-                // If both tables map the same expr to the same variable name, keep it.
-                if var == other_var && num == other_num {
-                    new_table.insert(expr.clone(), (*num, var.clone()));
-                }
-            }
+        // If both tables map the same expr to the same variable name, keep it.
+        let mut table = self.table.clone();
+        if !table.ptr_eq(&other.table) {
+            table.retain(|expr, entry| other.table.get(expr) == Some(entry));
         }
 
-        // Cloud intersection: keep only variables that remain valid in new_table.
-        for (var, num) in &self.cloud {
-            if let Some(other_num) = other.cloud.get(var) {
-                if num == other_num {
-                    new_cloud.insert(var.clone(), num.clone());
-                }
-            }
+        // Cloud intersection: keep only variables that agree in both tables.
+        let mut cloud = self.cloud.clone();
+        if !cloud.ptr_eq(&other.cloud) {
+            cloud.retain(|var, num| other.cloud.get(var) == Some(num));
         }
 
-        let ret = Self {
-            table: new_table,
-            cloud: new_cloud,
-        };
-
-        ret
+        Self { table, cloud }
     }
 
-    pub fn fold(&self, expr: Expr) -> Expr {
-        if let Expr::Expr(t, op, args) = expr.clone() {
-            if self.is_constexpr(&op) {
+    fn fold(&self, expr: Expr) -> Expr {
+        if let Expr::Expr(t, op, args) = &expr {
+            if self.is_constexpr(op) {
                 let constexpr = args
                     .iter()
                     .filter_map(|uid| {
-                        for (expr, (x, y)) in self.table.iter() {
+                        for (expr, (x, _y)) in self.table.iter() {
                             if x == uid {
                                 if let Expr::ConstExpr(_, lit) = expr {
-                                    return Some(lit.clone());
+                                    return Some(*lit);
                                 }
                             }
                         }
-                        return None;
+                        None
                     })
                     .collect::<Vec<_>>();
 
                 if constexpr.len() == args.len() {
-                    let folded_literal = self.eval_constexpr(&op, &t, &constexpr);
-                    log::trace!("folding expr {:?} into constant {:?}", expr, folded_literal);
-                    return Expr::ConstExpr(t, folded_literal);
+                    if let Some(folded_literal) = self.eval_constexpr(op, &constexpr) {
+                        log::trace!("folding expr {:?} into constant {:?}", expr, folded_literal);
+                        return Expr::ConstExpr(t.clone(), folded_literal);
+                    }
                 }
             }
         }
-        return expr;
+        expr
     }
 
-    pub fn canonicalize(&mut self, code: Code) -> Code {
+    /// Rewrite `code` in place to reuse values that are already available
+    pub fn canonicalize(&mut self, code: &mut Code) {
         log::trace!("\ncanonicalizing code instruction: {:?}", code);
-        let code_copy = code.clone();
         match code {
-            Code::Label { .. } => code,
-            Code::Effect {
-                op,
-                args,
-                funcs,
-                labels,
-                pos,
-            } => {
-                // should at least remap the arguments into effect
-                let remapped_args = args.as_ref().map(|v| {
-                    v.iter()
-                        .map(|a| self.get_variable_numbering(a).0)
-                        .collect::<Vec<_>>()
-                });
-
-                Code::Effect {
-                    op,
-                    args: remapped_args.map(|v| {
-                        v.iter()
-                            .map(|num| {
-                                // find variable name from cloud
-                                for (var, (n, _)) in self.cloud.iter() {
-                                    if n == num {
-                                        return var.clone();
-                                    }
-                                }
-                                panic!("variable number {} not found in cloud", num);
-                            })
-                            .collect()
-                    }),
-                    funcs,
-                    labels,
-                    pos,
-                }
-            }
-            Code::Memory { .. } => code,
-            Code::Noop { .. } => code,
+            Code::Label { .. } | Code::Memory { .. } | Code::Noop { .. } => {}
             Code::Value {
-                op: ValueOp::Call, ..
-            } => code,
+                op: ValueOp::Call,
+                funcs,
+                ..
+            } if !is_pure_call(funcs) => {}
             Code::Value {
                 value_type: Type::Ptr(..),
                 ..
-            } => code,
+            } => {}
+            Code::Value {
+                op: op @ ValueOp::Call,
+                dest,
+                value_type,
+                args,
+                funcs: funcs @ Some(_),
+                ..
+            } => {
+                // same callee and same argument values, same result
+                let remapped_args = (args.iter().flatten())
+                    .map(|a| self.get_variable_numbering(a).0)
+                    .collect();
+                let callee = funcs.as_ref().unwrap()[0].clone();
+                let expr = Expr::Expr(value_type.clone(), Operation::Call(callee), remapped_args);
+                let (num, ch) = if let Some((num, var)) = self.table.get(&expr) {
+                    *op = ValueOp::Id;
+                    *args = Some(vec![var.clone()]);
+                    *funcs = None;
+                    (*num, var.clone())
+                } else {
+                    let fresh_lvn = next_uid();
+                    self.table.insert(expr, (fresh_lvn, dest.clone()));
+                    for arg in args.iter_mut().flatten() {
+                        *arg = self.get_variable_numbering(arg).1;
+                    }
+                    (fresh_lvn, dest.clone())
+                };
+                self.cloud.insert(dest.clone(), (num, ch));
+            }
+            Code::Effect { args, .. } => {
+                // should at least remap the arguments into effect, to the variable that holds
+                // each value first like value operations do
+                for arg in args.iter_mut().flatten() {
+                    *arg = self.get_variable_numbering(arg).1;
+                }
+            }
             Code::Constant {
-                op,
                 dest,
                 constant_type,
                 value,
                 pos,
+                pos_end,
+                src,
+                ..
             } => {
                 // constant types allow us to skip renaming arguments
-                let expr = Expr::ConstExpr(constant_type.clone(), value);
-                let (num, ch, ret) = if let Some((num, var)) = self.table.get(&expr) {
-                    (
-                        *num,
-                        var.clone(),
-                        Code::Value {
-                            op: ValueOp::Id,
-                            dest: dest.clone(),
-                            value_type: constant_type,
-                            args: Some(vec![var.clone()]),
-                            funcs: None,
-                            labels: None,
-                            pos: pos,
-                        },
-                    )
-                } else {
-                    let fresh_lvn = next_uid();
-                    self.table.insert(expr, (fresh_lvn, dest.clone()));
-                    (
-                        fresh_lvn,
-                        dest.clone(),
-                        Code::Constant {
-                            op,
-                            dest: dest.clone(),
-                            constant_type,
-                            value,
-                            pos,
-                        },
-                    )
+                let expr = Expr::ConstExpr(constant_type.clone(), *value);
+                let (num, ch) = match self.table.get(&expr) {
+                    Some((num, var)) => (*num, var.clone()),
+                    None => {
+                        let fresh_lvn = next_uid();
+                        self.table.insert(expr, (fresh_lvn, dest.clone()));
+                        self.cloud.insert(dest.clone(), (fresh_lvn, dest.clone()));
+                        return;
+                    }
+                };
+                self.cloud.insert(dest.clone(), (num, ch.clone()));
+                *code = Code::Value {
+                    op: ValueOp::Id,
+                    dest: std::mem::take(dest),
+                    value_type: constant_type.clone(),
+                    args: Some(vec![ch]),
+                    funcs: None,
+                    labels: None,
+                    pos: pos.take(),
+                    pos_end: pos_end.take(),
+                    src: src.take(),
                 };
-                self.cloud.insert(dest, (num, ch));
-                ret
             }
             Code::Value {
                 op,
                 dest,
                 value_type,
                 args,
-                funcs,
-                labels,
                 pos,
+                pos_end,
+                src,
+                ..
             } => {
                 let mut remapped_args: Vec<usize> = args
-                    .as_ref()
-                    .unwrap_or(&vec![])
                     .iter()
+                    .flatten()
                     .map(|a| self.get_variable_numbering(a).0)
                     .collect();
 
                 // do copy propagation if possible
-                let mut expr = if let Some(expr) = self.flatten_copy(&code_copy) {
-                    expr
-                } else {
-                    if self.is_commutative(&Operation::Value(op.clone())) {
-                        remapped_args.sort();
-                    }
-                    Expr::Expr(
-                        value_type.clone(),
-                        Operation::Value(op.clone()),
-                        remapped_args.clone(),
-                    )
+                let copied = match (&op, args.as_deref()) {
+                    (ValueOp::Id, Some([arg, ..])) => self.flatten_copy(arg),
+                    _ => None,
                 };
+                let expr = copied.unwrap_or_else(|| {
+                    order_operands(*op, &mut remapped_args);
+                    Expr::Expr(value_type.clone(), Operation::Value(*op), remapped_args)
+                });
 
                 // if expression can be constant folded, do it
                 // if both expression args are constants, we can constant fold
-                expr = self.fold(expr);
-                if let Expr::ConstExpr(t, l) = expr {
-                    assert!(t == value_type);
-                    return self.canonicalize(Code::Constant {
+                let expr = self.fold(expr);
+                if let Expr::ConstExpr(t, value) = expr {
+                    assert!(t == *value_type);
+                    *code = Code::Constant {
                         op: ConstantOp::Const,
-                        dest: dest,
-                        constant_type: value_type,
-                        value: l,
-                        pos: pos,
-                    });
+                        dest: std::mem::take(dest),
+                        constant_type: t,
+                        value,
+                        pos: pos.take(),
+                        pos_end: pos_end.take(),
+                        src: src.take(),
+                    };
+                    return self.canonicalize(code);
                 }
 
-                let (num, ch, ret) = if let Some((num, var)) = self.table.get(&expr) {
-                    (
-                        *num,
-                        var.clone(),
-                        Code::Value {
-                            op: ValueOp::Id,
-                            dest: dest.clone(),
-                            value_type,
-                            args: Some(vec![var.clone()]),
-                            funcs: funcs,
-                            labels: labels,
-                            pos,
-                        },
-                    )
+                let (num, ch) = if let Some((num, var)) = self.table.get(&expr) {
+                    *op = ValueOp::Id;
+                    *args = Some(vec![var.clone()]);
+                    (*num, var.clone())
                 } else {
                     let fresh_lvn = next_uid();
                     self.table.insert(expr, (fresh_lvn, dest.clone()));
-
-                    let remapped_args: Vec<String> = args
-                        .unwrap_or(vec![])
-                        .iter()
-                        .map(|a| self.get_variable_numbering(a).1)
-                        .collect();
-
-                    (
-                        fresh_lvn,
-                        dest.clone(),
-                        Code::Value {
-                            op,
-                            dest: dest.clone(),
-                            value_type,
-                            args: Some(remapped_args),
-                            funcs: funcs,
-                            labels: labels,
-                            pos,
-                        },
-                    )
+                    for arg in args.iter_mut().flatten() {
+                        *arg = self.get_variable_numbering(arg).1;
+                    }
+                    (fresh_lvn, dest.clone())
                 };
-                self.cloud.insert(dest, (num, ch));
-                ret
+                self.cloud.insert(dest.clone(), (num, ch));
             }
         }
     }
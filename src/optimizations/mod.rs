@@ -1,6 +1,34 @@
+mod branch_fold;
+mod constant_propagation;
+mod copy_prop;
+mod crash;
 mod dce;
+mod hot_cold;
+mod inline;
+mod instrument;
+mod layout;
+mod load_cse;
 pub mod loops;
 mod lvn;
+mod pass_manager;
+mod pipeline;
+mod reassociate;
+mod simplify_cfg;
+mod statistics;
 
+pub use branch_fold::*;
+pub use constant_propagation::*;
+pub use copy_prop::*;
+pub use crash::*;
 pub use dce::*;
+pub use hot_cold::*;
+pub use inline::*;
+pub use instrument::*;
+pub use layout::*;
+pub use load_cse::*;
 pub use lvn::*;
+pub use pass_manager::*;
+pub use pipeline::*;
+pub use reassociate::*;
+pub use simplify_cfg::*;
+pub use statistics::*;
@@ -0,0 +1,1007 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    fs,
+    hash::{Hash, Hasher},
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use rayon::prelude::*;
+use thiserror::Error;
+
+use crate::{
+    analysis::{signatures, verify_function, ProfileData, Signatures},
+    dataflow::{WorklistError, WorklistResult},
+    optimizations::{
+        block_layout, branch_fold, constant_propagation, copy_prop, dce, dominator_value_numbering,
+        load_cse, loops, lvn, reassociate, simplify_cfg, split_hot_cold, statistics,
+        write_crash_bundle, CrashReport, FunctionSize, Inline, Statistics,
+    },
+    representation::{format_function, AbstractFunction, AbstractProgram, SsaForm, Terminator},
+    timing::Timer,
+};
+
+// Pipeline of function passes. Each registered pass has a command line name; the manager runs
+// them in order over every function and keeps the cached analyses of a function up to date
+
+/// Whether a pass modified the function it ran on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Changed {
+    Yes,
+    No,
+}
+
+impl Changed {
+    pub fn any(self, other: Changed) -> Changed {
+        if self == Changed::Yes || other == Changed::Yes {
+            Changed::Yes
+        } else {
+            Changed::No
+        }
+    }
+}
+
+/// Analyses a pass relies on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Analysis {
+    /// `AbstractFunction::dominance_info`, computed on first use and dropped by the manager when a
+    /// pass changes the shape of the CFG
+    Dominance,
+    /// computed by the pass itself through the dataflow framework
+    LiveVariables,
+    /// computed by the pass itself through the dataflow framework
+    ReachingDefinitions,
+    /// computed by the pass itself through the dataflow framework
+    ConstantPropagation,
+}
+
+/// A transformation over one function in SSA form. Passes are shared between the threads of a
+/// parallel batch
+pub trait Pass: Send + Sync {
+    /// command line name, e.g. `lvn`
+    fn name(&self) -> &'static str;
+
+    /// one line summary shown by `--help`
+    fn description(&self) -> &'static str;
+
+    fn required_analyses(&self) -> &'static [Analysis] {
+        &[]
+    }
+
+    fn run(&self, af: &mut AbstractFunction) -> WorklistResult<Changed>;
+
+    /// A copy of the pass for one program, for passes that look beyond the function they run
+    /// on, such as at the functions it calls. `None` runs the pass as it is
+    fn bind(&self, _program: &AbstractProgram) -> Option<Box<dyn Pass>> {
+        None
+    }
+}
+
+/// A function pass over SSA form that consumes the function and returns the rewritten one
+pub type FunctionPass = fn(AbstractFunction) -> WorklistResult<AbstractFunction>;
+
+/// Adapts a by-value `FunctionPass`, detecting changes by comparing the function before and after
+struct BuiltinPass {
+    name: &'static str,
+    description: &'static str,
+    required: &'static [Analysis],
+    run: FunctionPass,
+}
+
+impl Pass for BuiltinPass {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    fn required_analyses(&self) -> &'static [Analysis] {
+        self.required
+    }
+
+    fn run(&self, af: &mut AbstractFunction) -> WorklistResult<Changed> {
+        let before = fingerprint(af);
+        *af = (self.run)(std::mem::take(af))?;
+        Ok(if fingerprint(af) == before {
+            Changed::No
+        } else {
+            Changed::Yes
+        })
+    }
+}
+
+/// hash of everything a pass may rewrite: block contents, phi nodes and preheaders
+fn fingerprint(af: &AbstractFunction) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for block in &af.cfg.basic_blocks {
+        block.label.hash(&mut hasher);
+        block.instructions.hash(&mut hasher);
+        block.preheader.hash(&mut hasher);
+        // the order of phi nodes carries no meaning
+        let mut phis: Vec<_> = block
+            .phi_nodes
+            .iter()
+            .map(|phi| (&phi.dest, &phi.phi_args))
+            .collect();
+        phis.sort();
+        phis.hash(&mut hasher);
+        match &block.terminator {
+            Terminator::Passthrough => 0.hash(&mut hasher),
+            Terminator::Ret(code)
+            | Terminator::Jmp(_, code)
+            | Terminator::Br(_, _, code)
+            | Terminator::Speculate(_, code) => code.hash(&mut hasher),
+        }
+    }
+    hasher.finish()
+}
+
+/// Every built-in pass, in the order they are listed by `--help`
+pub fn builtin_passes() -> Vec<Box<dyn Pass>> {
+    vec![
+        Box::new(BuiltinPass {
+            name: "lvn",
+            description: "local value numbering with constant folding and copy propagation",
+            required: &[],
+            run: lvn,
+        }),
+        Box::new(BuiltinPass {
+            name: "dvnt",
+            description: "value numbering scoped by the dominator tree, with redundant phi nodes",
+            required: &[Analysis::Dominance],
+            run: dominator_value_numbering,
+        }),
+        Box::new(BuiltinPass {
+            name: "dce",
+            description: "global dead code elimination",
+            required: &[Analysis::LiveVariables],
+            run: dce,
+        }),
+        Box::new(BuiltinPass {
+            name: "licm",
+            description: "loop invariant code motion",
+            required: &[Analysis::Dominance, Analysis::ReachingDefinitions],
+            run: loops::loop_invariant_code_motion_pass,
+        }),
+        Box::new(BuiltinPass {
+            name: "constprop",
+            description: "global constant propagation into const instructions",
+            required: &[Analysis::ConstantPropagation],
+            run: constant_propagation,
+        }),
+        Box::new(BuiltinPass {
+            name: "copyprop",
+            description: "global copy propagation over SSA form",
+            required: &[],
+            run: copy_prop,
+        }),
+        Box::new(BuiltinPass {
+            name: "loadcse",
+            description: "redundant load elimination within blocks",
+            required: &[],
+            run: load_cse,
+        }),
+        Box::new(loops::Unroll::default()),
+        Box::new(BuiltinPass {
+            name: "rotate",
+            description: "loop rotation into guarded do-while loops",
+            required: &[Analysis::Dominance],
+            run: loops::rotate_loops,
+        }),
+        Box::new(BuiltinPass {
+            name: "reassoc",
+            description: "commutative operands in canonical order and constant chains combined",
+            required: &[Analysis::Dominance],
+            run: reassociate,
+        }),
+        Box::new(BuiltinPass {
+            name: "branchfold",
+            description: "branches on constants or to a single block turned into jumps",
+            required: &[],
+            run: branch_fold,
+        }),
+        Box::new(BuiltinPass {
+            name: "simplifycfg",
+            description: "block merging, empty block removal and jumps to the next block dropped",
+            required: &[],
+            run: simplify_cfg,
+        }),
+        Box::new(Inline::default()),
+        Box::new(BuiltinPass {
+            name: "layout",
+            description: "blocks reordered so that hot paths fall through and cold blocks go last",
+            required: &[Analysis::Dominance],
+            run: block_layout,
+        }),
+        Box::new(BuiltinPass {
+            name: "hotcold",
+            description: "cold blocks moved to the end of the function, out of the hot code",
+            required: &[Analysis::Dominance],
+            run: split_hot_cold,
+        }),
+    ]
+}
+
+/// Look up a built-in pass by its command line name. `loops` is accepted as an older name for
+/// `licm`
+pub fn pass_by_name(name: &str) -> Option<Box<dyn Pass>> {
+    let name = if name == "loops" { "licm" } else { name };
+    builtin_passes()
+        .into_iter()
+        .find(|pass| pass.name() == name)
+}
+
+/// Pass names of an optimization preset, `-O0` to `-O3`.
+///
+/// `-O1` folds constants (lvn) and removes dead code, `-O2` numbers values across the dominator
+/// tree (dvnt) instead, adds loop invariant code motion and cleans up after it, `-O3` also inlines
+/// small functions first and unrolls loops once their invariant code is hoisted. Every level ends
+/// by simplifying the CFG
+pub fn preset_passes(level: u8) -> &'static [&'static str] {
+    match level {
+        0 => &[],
+        1 => &["lvn", "dce", "simplifycfg"],
+        2 => &["dvnt", "dce", "licm", "dvnt", "dce", "simplifycfg"],
+        _ => &[
+            "inline",
+            "dvnt",
+            "dce",
+            "licm",
+            "unroll",
+            "dvnt",
+            "dce",
+            "simplifycfg",
+        ],
+    }
+}
+
+/// names of the built-in passes, comma separated
+pub fn builtin_pass_names() -> String {
+    builtin_passes()
+        .iter()
+        .map(|pass| pass.name())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Error, Debug, Clone)]
+#[error("unknown pass '{name}', expected one of {}", builtin_pass_names())]
+pub struct UnknownPassError {
+    pub name: String,
+}
+
+/// Which functions to dump as Bril text after which passes
+#[derive(Debug, Clone, Default)]
+pub struct PrintAfter {
+    /// dump after every pass, ignoring `passes`
+    pub all: bool,
+    pub passes: Vec<String>,
+    /// only dump these functions; empty dumps every function
+    pub functions: Vec<String>,
+    /// write one file per dump into this directory instead of stderr
+    pub directory: Option<PathBuf>,
+}
+
+impl PrintAfter {
+    fn wants(&self, pass: &str, function: &str) -> bool {
+        (self.all || self.passes.iter().any(|p| p == pass))
+            && (self.functions.is_empty() || self.functions.iter().any(|f| f == function))
+    }
+
+    /// `index` is the position of the pass in the pipeline, so repeated passes get distinct files
+    fn dump(&self, index: usize, pass: &str, af: &AbstractFunction) {
+        let text = format!(
+            "# *** IR dump after {} (pass {}) on @{} ***\n{}",
+            pass,
+            index + 1,
+            af.name,
+            format_function(&af.to_ssa_function())
+        );
+        let Some(directory) = &self.directory else {
+            eprintln!("{}", text);
+            return;
+        };
+        let path = directory.join(format!("{:02}-{}-{}.bril", index + 1, pass, af.name));
+        if let Err(e) = fs::create_dir_all(directory).and_then(|_| fs::write(&path, text)) {
+            log::warn!("failed to write IR dump {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// An ordered pipeline of passes
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    print_after: Option<PrintAfter>,
+    bisect_limit: Option<usize>,
+    verify: bool,
+    crash_dir: Option<PathBuf>,
+    /// seed of the fuzzed program being optimized, for crash reports
+    fuzz_seed: Option<u64>,
+    /// functions to transform; empty transforms every function
+    functions: Vec<String>,
+    /// phi node placement of the SSA construction in front of the passes
+    pub(super) ssa_form: SsaForm,
+    /// rerun the pipeline until a round removes no instruction, at most this many rounds
+    fixpoint: Option<usize>,
+    /// block and edge counts to attach to every function before the first pass
+    profile: Option<ProfileData>,
+    statistics: Mutex<Statistics>,
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self {
+            passes: Vec::new(),
+            print_after: None,
+            bisect_limit: None,
+            verify: cfg!(debug_assertions),
+            crash_dir: None,
+            fuzz_seed: None,
+            functions: Vec::new(),
+            ssa_form: SsaForm::default(),
+            fixpoint: None,
+            profile: None,
+            statistics: Mutex::default(),
+        }
+    }
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The pipeline of an optimization preset, see [`preset_passes`]
+    pub fn preset(level: u8) -> Self {
+        Self::from_names(preset_passes(level).iter().copied())
+            .expect("presets only use built-in passes")
+    }
+
+    /// Build a pipeline from pass names, e.g. `["lvn", "dce"]`
+    pub fn from_names<'a>(
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self, UnknownPassError> {
+        let mut manager = Self::new();
+        for name in names {
+            let pass = pass_by_name(name).ok_or_else(|| UnknownPassError {
+                name: name.to_string(),
+            })?;
+            manager.add(pass);
+        }
+        Ok(manager)
+    }
+
+    pub fn add(&mut self, pass: Box<dyn Pass>) -> &mut Self {
+        self.passes.push(pass);
+        self
+    }
+
+    /// Dump functions as Bril text after passes, see [`PrintAfter`]
+    pub fn print_after(&mut self, print_after: PrintAfter) -> &mut Self {
+        self.print_after = Some(print_after);
+        self
+    }
+
+    /// Only apply the first `limit` passes of the pipeline to each function (counting every round
+    /// of a fixpoint), and report every application on stderr, to find the pass application that
+    /// introduces a miscompile
+    pub fn bisect_limit(&mut self, limit: usize) -> &mut Self {
+        self.bisect_limit = Some(limit);
+        self
+    }
+
+    /// Check every function with the IR verifiers before the first pass and after each pass, and
+    /// fail with the name of the pass that broke it. On by default in debug builds
+    pub fn verify(&mut self, verify: bool) -> &mut Self {
+        self.verify = verify;
+        self
+    }
+
+    /// When a pass panics or the verifiers reject its output, write the function as it was before
+    /// the pass, the pipeline and the error into a new directory under `directory`
+    pub fn crash_dir(&mut self, directory: impl Into<PathBuf>) -> &mut Self {
+        self.crash_dir = Some(directory.into());
+        self
+    }
+
+    /// The seed `fuzz` generated the next programs from, so their crash reports can regenerate
+    /// them
+    pub fn fuzz_seed(&mut self, seed: u64) -> &mut Self {
+        self.fuzz_seed = Some(seed);
+        self
+    }
+
+    /// Copies of the body per iteration for the loops that `unroll` does not unroll completely
+    pub fn unroll_factor(&mut self, factor: usize) -> &mut Self {
+        for pass in self
+            .passes
+            .iter_mut()
+            .filter(|pass| pass.name() == "unroll")
+        {
+            *pass = Box::new(loops::Unroll { factor });
+        }
+        self
+    }
+
+    /// Build minimal or semi-pruned SSA form instead of pruned, which skips the liveness analysis
+    /// on large functions at the price of more phi nodes for the passes to clean up
+    pub fn ssa_form(&mut self, form: SsaForm) -> &mut Self {
+        self.ssa_form = form;
+        self
+    }
+
+    /// Rerun the whole pipeline over each function until a round leaves it with no fewer
+    /// instructions than it had, or after `max_rounds` rounds. The rounds each function took are
+    /// counted under `fixpoint` in the statistics
+    pub fn fixpoint(&mut self, max_rounds: usize) -> &mut Self {
+        self.fixpoint = Some(max_rounds);
+        self
+    }
+
+    /// Attach the block and edge counts of `profile` to the blocks of every function before the
+    /// first pass, for the passes that weigh code by how often it runs
+    pub fn profile(&mut self, profile: ProfileData) -> &mut Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Only run passes on the named functions (without the `@`) and leave the others as they are
+    pub fn only_functions(&mut self, names: impl IntoIterator<Item = String>) -> &mut Self {
+        self.functions = names.into_iter().collect();
+        self
+    }
+
+    fn selects(&self, function: &str) -> bool {
+        self.functions.is_empty() || self.functions.iter().any(|f| f == function)
+    }
+
+    /// Counters collected by every pass application so far
+    pub fn statistics(&self) -> Statistics {
+        self.statistics.lock().unwrap().clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    /// names of the passes in pipeline order
+    pub fn names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    /// Run every pass, in order, over every function of the program. Functions are independent,
+    /// so they run in parallel, unless IR dumps or bisection output should come out in order
+    pub fn run(&self, program: &mut AbstractProgram) -> WorklistResult<Changed> {
+        if let Some(profile) = &self.profile {
+            program
+                .functions
+                .values_mut()
+                .for_each(|af| profile.attach(af));
+        }
+        let bound: Vec<_> = self.passes.iter().map(|pass| pass.bind(program)).collect();
+        let signatures = self.verify.then(|| signatures(program));
+        // crash bundles hold the rest of the program as it was before the passes
+        let before = self.crash_dir.is_some().then(|| program.functions.clone());
+        let others = || before.iter().flat_map(|functions| functions.values());
+
+        // sorted, so IR dumps come out in a stable order
+        let mut functions: Vec<&mut AbstractFunction> = program
+            .functions
+            .values_mut()
+            .filter(|af| self.selects(&af.name))
+            .collect();
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let run =
+            |af: &mut AbstractFunction| self.run_passes(af, &bound, signatures.as_ref(), others());
+        let results: Vec<WorklistResult<Changed>> =
+            match self.print_after.is_some() || self.bisect_limit.is_some() {
+                true => functions.into_iter().map(run).collect(),
+                false => functions.into_par_iter().map(run).collect(),
+            };
+        // the first error by function name, however the threads were scheduled
+        results
+            .into_iter()
+            .try_fold(Changed::No, |changed, result| Ok(changed.any(result?)))
+    }
+
+    /// Run every pass, in order, over a single function
+    pub fn run_function(&self, af: &mut AbstractFunction) -> WorklistResult<Changed> {
+        if !self.selects(&af.name) {
+            return Ok(Changed::No);
+        }
+        if let Some(profile) = &self.profile {
+            profile.attach(af);
+        }
+        self.run_passes(af, &[], None, [])
+    }
+
+    /// the pipeline over one selected function, with the rest of the program for crash bundles.
+    /// `bound` holds the passes bound to the program, by position in the pipeline
+    fn run_passes<'a>(
+        &self,
+        af: &mut AbstractFunction,
+        bound: &[Option<Box<dyn Pass>>],
+        signatures: Option<&Signatures>,
+        others: impl IntoIterator<Item = &'a AbstractFunction> + Clone,
+    ) -> WorklistResult<Changed> {
+        let size = FunctionSize::of(af);
+        let opcodes = statistics::opcode_counts(af);
+        if let Err(e) = self.check("ssa construction", af, signatures) {
+            self.report_crash(None, af, others, &e);
+            return Err(e);
+        }
+        let mut changed = Changed::No;
+        let mut rounds = 0;
+        loop {
+            let instructions = FunctionSize::of(af).instructions;
+            for index in 0..self.passes.len() {
+                let before = self.crash_dir.is_some().then(|| af.clone());
+                let application = rounds * self.passes.len() + index;
+                let pass = bound.get(index).and_then(Option::as_deref);
+                let pass = pass.unwrap_or(self.passes[index].as_ref());
+                let result = self
+                    .run_pass(pass, index, application, af)
+                    .and_then(|result| {
+                        self.check(self.passes[index].name(), af, signatures)?;
+                        Ok(result)
+                    });
+                match (result, before) {
+                    (Ok(result), _) => changed = changed.any(result),
+                    (Err(e), Some(before)) => {
+                        self.report_crash(Some(index), &before, others, &e);
+                        return Err(e);
+                    }
+                    (Err(e), None) => return Err(e),
+                }
+            }
+            rounds += 1;
+            let Some(max_rounds) = self.fixpoint else {
+                break;
+            };
+            if FunctionSize::of(af).instructions >= instructions || rounds >= max_rounds {
+                log::info!("@{}: pipeline ran {} rounds to a fixpoint", af.name, rounds);
+                let mut statistics = self.statistics.lock().unwrap();
+                statistics.add("fixpoint", &af.name, "rounds", rounds as u64);
+                break;
+            }
+        }
+        let mut statistics = self.statistics.lock().unwrap();
+        statistics.add_size(&af.name, size, FunctionSize::of(af));
+        statistics.add_opcode_totals(&opcodes, &statistics::opcode_counts(af));
+        Ok(changed)
+    }
+
+    /// write a repro bundle for a panic or verifier failure, if there is a crash directory.
+    /// `index` is the failing pass, `None` when the verifiers rejected the input
+    fn report_crash<'a>(
+        &self,
+        index: Option<usize>,
+        before: &AbstractFunction,
+        others: impl IntoIterator<Item = &'a AbstractFunction>,
+        error: &WorklistError,
+    ) {
+        let Some(directory) = &self.crash_dir else {
+            return;
+        };
+        let pass = match error {
+            WorklistError::PassPanicked { pass, .. }
+            | WorklistError::VerificationFailed { pass, .. } => pass,
+            _ => return,
+        };
+        let report = CrashReport {
+            version: env!("CARGO_PKG_VERSION"),
+            pass,
+            pass_index: index.map(|index| index + 1),
+            pipeline: self.names(),
+            function: &before.name,
+            error: error.to_string(),
+            seed: self.fuzz_seed,
+        };
+        match write_crash_bundle(directory, before, others, &report) {
+            Ok(bundle) => log::error!("wrote a crash report to {}", bundle.display()),
+            Err(e) => log::warn!(
+                "failed to write a crash report in {}: {}",
+                directory.display(),
+                e
+            ),
+        }
+    }
+
+    /// run the IR verifiers on `af` if enabled, blaming `pass` for any problem
+    fn check(
+        &self,
+        pass: &str,
+        af: &AbstractFunction,
+        signatures: Option<&Signatures>,
+    ) -> WorklistResult<()> {
+        if !self.verify {
+            return Ok(());
+        }
+        let _timer = Timer::function("verify", &af.name);
+        verify_function(af, signatures).map_err(|error| WorklistError::VerificationFailed {
+            pass: pass.to_string(),
+            error: Box::new(error),
+        })
+    }
+
+    /// `pass`, at `index` of the pipeline, the `application`th pass applied to the function
+    fn run_pass(
+        &self,
+        pass: &dyn Pass,
+        index: usize,
+        application: usize,
+        af: &mut AbstractFunction,
+    ) -> WorklistResult<Changed> {
+        if let Some(limit) = self.bisect_limit {
+            let skip = application >= limit;
+            eprintln!(
+                "BISECT: {} pass ({}) {} on @{}",
+                if skip { "NOT running" } else { "running" },
+                application + 1,
+                pass.name(),
+                af.name
+            );
+            if skip {
+                return Ok(Changed::No);
+            }
+        }
+        let size_before = function_size(af);
+        let opcodes_before = statistics::opcode_counts(af);
+        statistics::take_pending();
+        let function_name = af.name.clone();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut timer = Timer::function(pass.name(), &af.name);
+            timer.instructions(size_before.0);
+            let result = pass.run(af);
+            timer.instructions(function_size(af).0);
+            result
+        }))
+        .unwrap_or_else(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(WorklistError::PassPanicked {
+                pass: pass.name().to_string(),
+                function_name,
+                message,
+            })
+        })?;
+        if result == Changed::Yes {
+            af.discard_stale_dominance();
+        }
+        self.record(pass.name(), af, size_before, &opcodes_before, result);
+
+        if let Some(print_after) = &self.print_after {
+            if print_after.wants(pass.name(), &af.name) {
+                print_after.dump(index, pass.name(), af);
+            }
+        }
+        Ok(result)
+    }
+
+    /// file the counters the pass reported, plus the size change of the function
+    fn record(
+        &self,
+        pass: &str,
+        af: &AbstractFunction,
+        (instructions_before, blocks_before): (usize, usize),
+        opcodes_before: &BTreeMap<String, u64>,
+        result: Changed,
+    ) {
+        let (instructions, blocks) = function_size(af);
+        let mut statistics = self.statistics.lock().unwrap();
+        statistics.add_opcodes(pass, opcodes_before, &statistics::opcode_counts(af));
+        let mut add = |counter: &str, n: usize| {
+            if n > 0 {
+                statistics.add(pass, &af.name, counter, n as u64);
+            }
+        };
+        add("runs", 1);
+        add("functions changed", (result == Changed::Yes) as usize);
+        add(
+            "instructions removed",
+            instructions_before.saturating_sub(instructions),
+        );
+        add(
+            "instructions added",
+            instructions.saturating_sub(instructions_before),
+        );
+        add("blocks removed", blocks_before.saturating_sub(blocks));
+        add("blocks added", blocks.saturating_sub(blocks_before));
+        for (counter, n) in statistics::take_pending() {
+            statistics.add(pass, &af.name, counter, n);
+        }
+    }
+}
+
+/// (instructions including phi nodes and preheaders, blocks)
+fn function_size(af: &AbstractFunction) -> (usize, usize) {
+    let size = FunctionSize::of(af);
+    (size.instructions + size.phis, size.blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::representation::{parse_program, RichAbstractProgram, RichProgram};
+
+    fn program(text: &str) -> RichAbstractProgram {
+        RichAbstractProgram::try_from(RichProgram {
+            original_text: text.lines().map(str::to_string).collect(),
+            program: parse_program(text).unwrap(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn reports_changes() {
+        let mut p = program("@main {\n  a: int = const 1;\n  b: int = const 2;\n  print a;\n}\n");
+        let dce = PassManager::from_names(["dce"]).unwrap();
+        assert_eq!(dce.run(&mut p.program).unwrap(), Changed::Yes);
+        assert_eq!(dce.run(&mut p.program).unwrap(), Changed::No);
+    }
+
+    #[test]
+    fn fixpoint_stops_once_a_round_removes_nothing() {
+        let text = "@main {\n  a: int = const 1;\n  b: int = add a a;\n  print a;\n}\n";
+        let rounds = |max_rounds| {
+            let mut p = program(text);
+            let mut manager = PassManager::from_names(["lvn", "dce"]).unwrap();
+            manager.fixpoint(max_rounds);
+            manager.run(&mut p.program).unwrap();
+            manager.statistics().per_function()["fixpoint"]["main"]["rounds"]
+        };
+        // the first round removes `b`, the second nothing
+        assert_eq!(rounds(10), 2);
+        assert_eq!(rounds(1), 1);
+    }
+
+    #[test]
+    fn bisect_limit_skips_later_passes() {
+        let text = "@main {\n  a: int = const 1;\n  b: int = const 2;\n  print a;\n}\n";
+        let mut p = program(text);
+        let mut manager = PassManager::from_names(["dce"]).unwrap();
+        manager.bisect_limit(0);
+        assert_eq!(manager.run(&mut p.program).unwrap(), Changed::No);
+        manager.bisect_limit(1);
+        assert_eq!(manager.run(&mut p.program).unwrap(), Changed::Yes);
+    }
+
+    /// drops every instruction, leaving uses of undefined variables behind
+    struct Clobber;
+
+    impl Pass for Clobber {
+        fn name(&self) -> &'static str {
+            "clobber"
+        }
+
+        fn description(&self) -> &'static str {
+            "breaks the function"
+        }
+
+        fn run(&self, af: &mut AbstractFunction) -> WorklistResult<Changed> {
+            af.cfg
+                .basic_blocks
+                .iter_mut()
+                .for_each(|b| b.instructions.retain(|c| c.get_destination().is_none()));
+            Ok(Changed::Yes)
+        }
+    }
+
+    #[test]
+    fn verify_blames_the_breaking_pass() {
+        let text = "@main {\n  a: int = const 1;\n  print a;\n}\n";
+        let mut manager = PassManager::from_names(["dce"]).unwrap();
+        manager.add(Box::new(Clobber)).verify(true);
+        let Err(WorklistError::VerificationFailed { pass, .. }) =
+            manager.run(&mut program(text).program)
+        else {
+            panic!("expected a verification failure");
+        };
+        assert_eq!(pass, "clobber");
+
+        manager.verify(false);
+        assert!(manager.run(&mut program(text).program).is_ok());
+    }
+
+    #[test]
+    fn writes_crash_bundles() {
+        let text = "@main {\n  a: int = const 1;\n  print a;\n}\n";
+        let directory =
+            std::env::temp_dir().join(format!("rust_bril-crash-{}", std::process::id()));
+        let mut manager = PassManager::from_names(["lvn"]).unwrap();
+        manager
+            .add(Box::new(Clobber))
+            .verify(true)
+            .crash_dir(&directory);
+        assert!(manager.run(&mut program(text).program).is_err());
+
+        let bundle = fs::read_dir(&directory)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(bundle.join("crash.json")).unwrap()).unwrap();
+        assert_eq!(report["pass"], "clobber");
+        assert_eq!(report["pass_index"], 2);
+        assert_eq!(report["repro"], "rust_bril program.json --passes clobber");
+        // the bundled program is the input of the failing pass, which still defines `a`
+        let program = fs::read_to_string(bundle.join("program.json")).unwrap();
+        assert!(program.contains("\"const\""));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn crash_bundles_of_fuzzed_programs_regenerate_them() {
+        let text = "@main {\n  a: int = const 1;\n  print a;\n}\n";
+        let directory =
+            std::env::temp_dir().join(format!("rust_bril-fuzz-crash-{}", std::process::id()));
+        let mut manager = PassManager::from_names(["dce"]).unwrap();
+        manager
+            .add(Box::new(Clobber))
+            .verify(true)
+            .crash_dir(&directory)
+            .fuzz_seed(42);
+        assert!(manager.run(&mut program(text).program).is_err());
+
+        let bundle = fs::read_dir(&directory)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(bundle.join("crash.json")).unwrap()).unwrap();
+        assert_eq!(report["seed"], 42);
+        assert_eq!(
+            report["repro"],
+            "rust_bril fuzz --seed 42 --count 1 --passes dce,clobber"
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn only_transforms_selected_functions() {
+        let text = "@main {\n  a: int = const 1;\n  print a;\n}\n@f {\n  a: int = const 1;\n}\n";
+        let mut p = program(text);
+        let mut dce = PassManager::from_names(["dce"]).unwrap();
+        dce.only_functions(["main".to_string()]);
+        assert_eq!(dce.run(&mut p.program).unwrap(), Changed::No);
+        dce.only_functions(["f".to_string()]);
+        assert_eq!(dce.run(&mut p.program).unwrap(), Changed::Yes);
+    }
+
+    #[test]
+    fn rejects_unknown_pass() {
+        let error = PassManager::from_names(["lvn", "gvn"]).err().unwrap();
+        assert_eq!(error.name, "gvn");
+    }
+
+    #[test]
+    fn presets_build_on_each_other() {
+        assert!(preset_passes(0).is_empty());
+        assert_eq!(preset_passes(1), ["lvn", "dce", "simplifycfg"]);
+        assert_eq!(
+            preset_passes(2),
+            ["dvnt", "dce", "licm", "dvnt", "dce", "simplifycfg"]
+        );
+        assert_eq!(
+            preset_passes(3),
+            [
+                "inline",
+                "dvnt",
+                "dce",
+                "licm",
+                "unroll",
+                "dvnt",
+                "dce",
+                "simplifycfg"
+            ]
+        );
+        for level in 0..=3 {
+            assert!(PassManager::from_names(preset_passes(level).iter().copied()).is_ok());
+        }
+    }
+
+    #[test]
+    fn o3_converges_on_inlined_calls_in_an_endless_loop() {
+        // reduced from fuzz seed 5180: once @f0 and its nested loops are inlined into the loop of
+        // @f1 and unrolled, dce used to give up on @f1 at the iteration limit
+        let text = "@f0(a1: int, a2: int): int {
+  v3: int = const 5;
+  v4: int = const -7;
+  v5: bool = const false;
+  v6: float = const 2.75;
+  v7: bool = not v5;
+  i9: int = const 0;
+  n10: int = const 5;
+  one11: int = const 1;
+.head13:
+  c12: bool = lt i9 n10;
+  br c12 .body14 .exit15;
+.body14:
+  i19: int = const 0;
+  n20: int = const 4;
+  one21: int = const 1;
+.head23:
+  c22: bool = lt i19 n20;
+  br c22 .body24 .exit25;
+.body24:
+  i19: int = add i19 one21;
+  jmp .head23;
+.exit25:
+  i9: int = add i9 one11;
+  jmp .head13;
+.exit15:
+  v27: bool = lt v4 a2;
+  size29: int = const 4;
+  p30: ptr<int> = alloc size29;
+  o35: int = const 3;
+  o42: int = const 2;
+  q43: ptr<int> = ptradd p30 o42;
+  size51: int = const 4;
+  p52: ptr<int> = alloc size51;
+  o57: int = const 3;
+  ret a1;
+}
+
+@f1(a68: int): int {
+  v69: int = const -6;
+  v70: int = const -2;
+  v72: float = const 3.75;
+  i73: int = const 0;
+  n74: int = const 3;
+.head77:
+  c76: bool = lt i73 n74;
+  br c76 .body78 .exit79;
+.body78:
+  d81: int = const -1;
+  size82: int = const 2;
+  p83: ptr<int> = alloc size82;
+  o84: int = const 1;
+.exit79:
+  v123: int = call @f0 v70 v69;
+  v130: bool = ge a68 v70;
+  i131: int = const 0;
+  n132: int = const 5;
+.head135:
+  v138: int = const 1;
+  v139: int = call @f0 v69 i131;
+  size140: int = const 1;
+  p141: ptr<int> = alloc size140;
+  size144: int = const 3;
+  p145: ptr<int> = alloc size144;
+  o148: int = const 2;
+  q149: ptr<int> = ptradd p145 o148;
+  size152: int = const 4;
+  p153: ptr<int> = alloc size152;
+  o154: int = const 1;
+  q155: ptr<int> = ptradd p153 o154;
+  v167: int = call @f0 v138 i131;
+  jmp .head135;
+}
+
+@main {
+  v200: int = const -6;
+  v201: int = const -4;
+  v202: bool = const false;
+  v203: float = const -3.75;
+  v204: float = fadd v203 v203;
+  v205: float = fmul v204 v203;
+  v213: bool = flt v204 v203;
+  v231: int = id v200;
+}
+";
+        let mut p = program(text);
+        let manager = PassManager::from_names(preset_passes(3).iter().copied()).unwrap();
+        manager.run(&mut p.program).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+use crate::{
+    error::BrilResult,
+    optimizations::PassManager,
+    representation::{RichAbstractProgram, RichProgram},
+};
+
+// Library entry point: SSA construction, the passes and leaving SSA form, with every failure
+// returned as a value so a long-running host can report it and carry on
+
+/// Convert `program` into SSA form and run `passes` over it, leaving it in SSA form
+pub fn run_pipeline_in_ssa(
+    program: RichProgram,
+    passes: &PassManager,
+) -> BrilResult<RichAbstractProgram> {
+    let mut abstract_program =
+        RichAbstractProgram::try_from_program_with(program, passes.ssa_form)?;
+    passes.run(&mut abstract_program.program)?;
+    Ok(abstract_program)
+}
+
+/// Run `passes` over `program` and convert the result out of SSA form
+pub fn run_pipeline(program: RichProgram, passes: &PassManager) -> BrilResult<RichProgram> {
+    Ok(run_pipeline_in_ssa(program, passes)?.into_program())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::BrilError,
+        representation::{BrilFrontend, ProgramFormat},
+    };
+
+    fn load(text: &str) -> RichProgram {
+        RichProgram::from_text(text, ProgramFormat::Bril, BrilFrontend::Native).unwrap()
+    }
+
+    #[test]
+    fn returns_errors_with_context() {
+        let passes = PassManager::from_names(["dce"]).unwrap();
+        let program = load("@main {\n  x: int = add a b;\n  print x;\n}\n");
+        let text = program.original_text.clone();
+        let Err(error @ BrilError::Worklist(_)) = run_pipeline(program, &passes) else {
+            panic!("expected a transform error");
+        };
+        assert_eq!(error.position().map(|p| p.row), Some(2));
+        let context = error.to_string_with_context(&text);
+        assert!(context.contains("uninitialized variable: a"));
+        assert!(context.contains("x: int = add a b;"));
+
+        let program = load("@main {\n  a: int = const 1;\n  b: int = const 2;\n  print a;\n}\n");
+        let optimized = run_pipeline(program, &passes).unwrap();
+        assert_eq!(optimized.program.functions.len(), 1);
+    }
+
+    #[test]
+    fn keeps_functions_in_source_order() {
+        let names = ["main", "c", "a", "d", "b"];
+        let text: String = names
+            .iter()
+            .map(|name| format!("@{} {{\n}}\n", name))
+            .collect();
+        let optimized = run_pipeline(load(&text), &PassManager::preset(2)).unwrap();
+        let order: Vec<&str> = (optimized.program.functions.iter())
+            .map(|f| f.name.as_str())
+            .collect();
+        assert_eq!(order, names);
+    }
+}
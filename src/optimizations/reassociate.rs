@@ -0,0 +1,202 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    dataflow::WorklistResult,
+    optimizations::statistics,
+    representation::{
+        fresh_name, opcode, AbstractFunction, Code, ConstantOp, Literal, Position, Type, ValueOp,
+    },
+};
+
+// Reassociation and canonicalization. The operands of commutative operations are put in a fixed
+// order: variables by rank, the order in which a preorder walk of the dominator tree defines them
+// (arguments first), and constants last. Then integer `add` and `mul` chains with a constant
+// second operand are collapsed: `y = add x 1; z = add y 2` becomes `z = add x 3`, with the 3
+// defined by a new `const` right before `z`. `y` is left for dce when nothing else reads it.
+//
+// Both make more expressions look alike to lvn and constprop. Floating point arithmetic is not
+// reassociated, as it rounds differently in another order.
+
+/// Whether the operands of `op` can be swapped
+pub fn is_commutative(op: ValueOp) -> bool {
+    opcode(op).is_some_and(|info| info.commutative)
+}
+
+/// Sort the operands of `op` into canonical order if it is commutative
+pub fn order_operands<T: Ord>(op: ValueOp, operands: &mut [T]) {
+    if is_commutative(op) {
+        operands.sort();
+    }
+}
+
+pub fn reassociate(mut af: AbstractFunction) -> WorklistResult<AbstractFunction> {
+    log::info!("running reassociation on function '{}'", af.name);
+    let order = dominator_preorder(&af);
+
+    let mut ranks: HashMap<String, usize> = HashMap::new();
+    let mut constants: HashMap<String, i64> = HashMap::new();
+    let mut names: HashSet<String> = HashSet::new();
+    for arg in af.args.iter().flatten() {
+        ranks.insert(arg.name.clone(), 0);
+        names.insert(arg.name.clone());
+    }
+    for &b in &order {
+        let block = &af.cfg.basic_blocks[b];
+        for phi in &block.phi_nodes {
+            ranks.insert(phi.dest.clone(), ranks.len());
+            names.insert(phi.dest.clone());
+        }
+        for code in block.preheader.iter().chain(&block.instructions) {
+            let Some(dest) = code.get_destination() else {
+                continue;
+            };
+            names.insert(dest.to_string());
+            match code {
+                Code::Constant { value, .. } => {
+                    if let Literal::Int(n) = value {
+                        constants.insert(dest.to_string(), *n);
+                    }
+                    ranks.insert(dest.to_string(), usize::MAX);
+                }
+                _ => {
+                    ranks.insert(dest.to_string(), ranks.len());
+                }
+            }
+        }
+    }
+
+    // `dest = op base constant` for every integer add or mul with a constant second operand
+    let mut chains: HashMap<String, (ValueOp, String, i64)> = HashMap::new();
+    let (mut reordered, mut reassociated) = (0, 0);
+    for &b in &order {
+        let block = &mut af.cfg.basic_blocks[b];
+        for code_list in [&mut block.preheader, &mut block.instructions] {
+            let mut i = 0;
+            while i < code_list.len() {
+                let Code::Value {
+                    op,
+                    dest,
+                    value_type,
+                    args: Some(args),
+                    ..
+                } = &mut code_list[i]
+                else {
+                    i += 1;
+                    continue;
+                };
+                let mut keyed: Vec<(usize, String)> = (args.iter())
+                    .map(|arg| (ranks.get(arg).copied().unwrap_or(usize::MAX), arg.clone()))
+                    .collect();
+                order_operands(*op, &mut keyed);
+                let ordered: Vec<String> = keyed.into_iter().map(|(_, arg)| arg).collect();
+                if *args != ordered {
+                    *args = ordered;
+                    reordered += 1;
+                }
+
+                let combine = |a: i64, b: i64| match op {
+                    ValueOp::Add => a.wrapping_add(b),
+                    _ => a.wrapping_mul(b),
+                };
+                let chain = match (&op, &value_type, args.as_slice()) {
+                    // a constant base is lvn's to fold
+                    (ValueOp::Add | ValueOp::Mul, Type::Int, [base, constant])
+                        if !constants.contains_key(base) =>
+                    {
+                        constants.get(constant).map(|&n| (base.clone(), n))
+                    }
+                    _ => None,
+                };
+                let Some((base, n)) = chain else {
+                    i += 1;
+                    continue;
+                };
+                let dest = dest.clone();
+                let (base, n) = match chains.get(&base) {
+                    Some((inner, inner_base, m)) if inner == op => {
+                        let folded = (inner_base.clone(), combine(*m, n));
+                        let name = fresh_name(&mut names, &format!("{}_reassociated", dest));
+                        log::debug!("{} = {:?} {} {}", dest, op, folded.0, folded.1);
+                        *args = vec![folded.0.clone(), name.clone()];
+                        constants.insert(name.clone(), folded.1);
+                        let pos = code_list[i].get_position();
+                        code_list.insert(i, constant(name, folded.1, pos));
+                        i += 1;
+                        reassociated += 1;
+                        folded
+                    }
+                    _ => (base, n),
+                };
+                let Code::Value { op, .. } = &code_list[i] else {
+                    unreachable!("the instruction was a value operation");
+                };
+                chains.insert(dest, (*op, base, n));
+                i += 1;
+            }
+        }
+    }
+
+    statistics::count("operands reordered", reordered);
+    statistics::count("expressions reassociated", reassociated);
+    Ok(af)
+}
+
+/// block ids in a preorder walk of the dominator tree, so definitions come before their uses
+fn dominator_preorder(af: &AbstractFunction) -> Vec<usize> {
+    let mut order = Vec::new();
+    let mut pending = vec![0];
+    while let Some(block) = pending.pop() {
+        order.push(block);
+        let children = af.dominance_info().get_immediate_dominated(block);
+        // reversed, so that children are visited in block order
+        let children: Vec<usize> = children.iter().collect();
+        pending.extend(children.into_iter().rev());
+    }
+    order
+}
+
+fn constant(dest: String, n: i64, pos: Option<Position>) -> Code {
+    Code::Constant {
+        op: ConstantOp::Const,
+        dest,
+        constant_type: Type::Int,
+        value: Literal::Int(n),
+        pos,
+        pos_end: None,
+        src: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        optimizations::{run_pipeline, PassManager},
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn orders_operands_and_folds_constant_chains() {
+        let text = r#"
+@main(a: int, b: int) {
+  one: int = const 1;
+  two: int = const 2;
+  x: int = add one a;
+  y: int = add x two;
+  z: int = add y two;
+  p: int = mul b a;
+  q: int = mul a b;
+  print z p q;
+}
+"#;
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["reassoc", "lvn", "dce"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        let text = optimized.to_text();
+        // one add of 5, and the second mul reuses the first
+        let count = |op: &str| text.matches(&format!(" = {} ", op)).count();
+        assert_eq!((count("add"), count("mul")), (1, 1), "{}", text);
+        assert!(text.contains("const 5;"), "{}", text);
+
+        assert_same_output(&program.program, &optimized.program, &["3", "4"]);
+    }
+}
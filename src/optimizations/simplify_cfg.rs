@@ -0,0 +1,237 @@
+use crate::{
+    dataflow::WorklistResult,
+    optimizations::statistics,
+    representation::{
+        AbstractFunction, BasicBlock, BlockId, Code, ControlFlowGraph, Label, Position, Terminator,
+        Type, ValueOp,
+    },
+};
+
+// CFG simplification. A block whose only successor has no other predecessor absorbs it, the
+// successor's phi nodes turning into copies. A block with nothing in it that only passes control
+// on is removed and its predecessors go straight to its successor, unless the copies for the
+// successor's phi nodes would then run on paths that do not lead there. Last, a `jmp` to the
+// block right after becomes falling through, so no jump is emitted for it when the blocks are
+// flattened, unless it is a backedge.
+//
+// Blocks with code hoisted in front of them (`preheader`) are left alone, as are jumps into
+// them: whether such a jump runs that code depends on the block it comes from.
+
+pub fn simplify_cfg(mut af: AbstractFunction) -> WorklistResult<AbstractFunction> {
+    log::info!("running cfg simplification on function '{}'", af.name);
+    if af.speculates() {
+        log::debug!("skipping '{}', which speculates", af.name);
+        return Ok(af);
+    }
+    let (mut merged, mut removed) = (0, 0);
+    loop {
+        if let Some((block, successor)) = mergeable(&af.cfg) {
+            merge(&mut af.cfg, block, successor);
+            merged += 1;
+        } else if let Some(block) = removable(&af.cfg) {
+            remove(&mut af.cfg, block);
+            removed += 1;
+        } else {
+            break;
+        }
+    }
+
+    // a backedge stays a jump, as code hoisted in front of the header later would otherwise run
+    // on every iteration
+    af.discard_stale_dominance();
+    let backedges: Vec<bool> = (1..af.cfg.basic_blocks.len())
+        .map(|b| af.dominance_info().dominated_by(b - 1, b))
+        .collect();
+    let blocks = &mut af.cfg.basic_blocks;
+    let mut jumps = 0;
+    for b in 1..blocks.len() {
+        let next = &blocks[b];
+        let falls_through = match &blocks[b - 1].terminator {
+            Terminator::Jmp(target, _) => *target == next.label && !backedges[b - 1],
+            _ => false,
+        };
+        if falls_through {
+            blocks[b - 1].terminator = Terminator::Passthrough;
+            jumps += 1;
+        }
+    }
+
+    statistics::count("blocks merged", merged);
+    statistics::count("empty blocks removed", removed);
+    statistics::count("jumps removed", jumps);
+    Ok(af)
+}
+
+/// a block and its only successor, whose only predecessor it is
+fn mergeable(cfg: &ControlFlowGraph) -> Option<(BlockId, BlockId)> {
+    (0..cfg.basic_blocks.len()).find_map(|b| {
+        let successor = single(cfg.successors[b].iter())?;
+        let alone = successor != b
+            && successor != 0
+            && cfg.predecessors[successor].iter().eq([b])
+            && cfg.basic_blocks[successor].preheader.is_empty()
+            && matches!(
+                cfg.basic_blocks[b].terminator,
+                Terminator::Jmp(..) | Terminator::Passthrough
+            );
+        alone.then_some((b, successor))
+    })
+}
+
+fn merge(cfg: &mut ControlFlowGraph, block: BlockId, successor: BlockId) {
+    let mut blocks = std::mem::take(&mut cfg.basic_blocks);
+    log::debug!(
+        "merging '{}' into '{}'",
+        blocks[successor].label,
+        blocks[block].label
+    );
+    let fallthrough = blocks.get(successor + 1).map(|next| next.label.clone());
+    let absorbed = blocks.remove(successor);
+    let target = &mut blocks[if block > successor { block - 1 } else { block }];
+
+    // a single predecessor leaves each phi node with one value
+    for phi in absorbed.phi_nodes {
+        let (value, _) = phi.phi_args.into_iter().next().expect("one predecessor");
+        target
+            .instructions
+            .push(copy(phi.dest, phi.phi_type, value, phi.pos));
+    }
+    target.instructions.extend(absorbed.instructions);
+    target.terminator = absorbed.terminator.explicit(fallthrough);
+    target.natural_loop_return = absorbed.natural_loop_return;
+    let label = target.label.clone();
+    relabel_phi_args(&mut blocks, &absorbed.label, &[label]);
+    *cfg = rebuild(blocks);
+}
+
+/// an empty block that only passes control on to another one, and can be bypassed
+fn removable(cfg: &ControlFlowGraph) -> Option<BlockId> {
+    let blocks = &cfg.basic_blocks;
+    (1..blocks.len()).find(|&b| {
+        let block = &blocks[b];
+        let Some(successor) = single(cfg.successors[b].iter()) else {
+            return false;
+        };
+        let empty = block.instructions.is_empty()
+            && block.phi_nodes.is_empty()
+            && block.preheader.is_empty()
+            && !block.natural_loop_return
+            && matches!(
+                block.terminator,
+                Terminator::Jmp(..) | Terminator::Passthrough
+            );
+        // the copies that leaving SSA form puts at the end of the block for the successor's phi
+        // nodes would move into its predecessors, where they run on every path unless the
+        // predecessors only go here; a predecessor of both would have two values in the phis
+        let copies_stay = blocks[successor].phi_nodes.is_empty()
+            || (cfg.predecessors[b].iter()).all(|p| cfg.successors[p].iter().eq([b]));
+        empty && successor != b && blocks[successor].preheader.is_empty() && copies_stay
+    })
+}
+
+fn remove(cfg: &mut ControlFlowGraph, block: BlockId) {
+    let successor = single(cfg.successors[block].iter()).expect("checked by removable");
+    let predecessors: Vec<BlockId> = cfg.predecessors[block].iter().collect();
+    let mut blocks = std::mem::take(&mut cfg.basic_blocks);
+    let label = blocks[block].label.clone();
+    let target = blocks[successor].label.clone();
+    log::debug!("removing empty block '{}'", label);
+
+    for &p in &predecessors {
+        let pred = &mut blocks[p];
+        match &mut pred.terminator {
+            // falling through now reaches the block after, which need not be the successor
+            Terminator::Passthrough if successor != block + 1 => {
+                pred.terminator = Terminator::jump(target.clone())
+            }
+            Terminator::Passthrough => {}
+            terminator => terminator.retarget(&label, &target),
+        }
+    }
+    let labels: Vec<Label> = (predecessors.iter())
+        .map(|&p| blocks[p].label.clone())
+        .collect();
+    relabel_phi_args(&mut blocks, &label, &labels);
+    blocks.remove(block);
+    *cfg = rebuild(blocks);
+}
+
+fn rebuild(mut blocks: Vec<BasicBlock>) -> ControlFlowGraph {
+    for (id, block) in blocks.iter_mut().enumerate() {
+        block.id = id;
+    }
+    ControlFlowGraph::from(blocks)
+}
+
+fn single<T>(mut items: impl Iterator<Item = T>) -> Option<T> {
+    let first = items.next()?;
+    items.next().is_none().then_some(first)
+}
+
+/// give the values phi nodes receive from `from` to each of `to` instead
+fn relabel_phi_args(blocks: &mut [BasicBlock], from: &str, to: &[Label]) {
+    for phi in blocks.iter_mut().flat_map(|b| &mut b.phi_nodes) {
+        let Some(at) = phi.phi_args.iter().position(|(_, pred)| pred == from) else {
+            continue;
+        };
+        let (value, _) = phi.phi_args.remove(at);
+        for label in to {
+            phi.phi_args.push((value.clone(), label.clone()));
+        }
+    }
+}
+
+fn copy(dest: String, value_type: Type, source: String, pos: Option<Position>) -> Code {
+    Code::Value {
+        op: ValueOp::Id,
+        dest,
+        value_type,
+        args: Some(vec![source]),
+        funcs: None,
+        labels: None,
+        pos,
+        pos_end: None,
+        src: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        optimizations::{run_pipeline, PassManager},
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn merges_chains_and_skips_empty_blocks() {
+        let text = r#"
+@main(n: int) {
+  one: int = const 1;
+  jmp .first;
+.first:
+  x: int = add n one;
+  jmp .second;
+.second:
+  c: bool = lt x one;
+  br c .empty .join;
+.empty:
+  jmp .join;
+.join:
+  print x;
+}
+"#;
+        let program = program_from_text(text);
+        let passes = PassManager::from_names(["simplifycfg"]).unwrap();
+        let optimized = run_pipeline(program.clone(), &passes).unwrap();
+        let text = optimized.to_text();
+        // one block up to the branch, which goes straight to the join, and the join
+        for gone in [".first", ".second", ".empty", "jmp"] {
+            assert!(!text.contains(gone), "{}", text);
+        }
+        assert!(text.contains("br c_0 .join .join;"), "{}", text);
+
+        for n in -1..2 {
+            assert_same_output(&program.program, &optimized.program, &[&n.to_string()]);
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use std::{cell::RefCell, collections::BTreeMap};
+
+use serde::Serialize;
+
+use crate::representation::{AbstractFunction, Terminator};
+
+// Counters reported by passes. A pass calls `count` while it runs; the pass manager collects the
+// pending counters after every pass application and files them under the pass and function, along
+// with how many instructions of each opcode the pass removed and added
+
+thread_local! {
+    static PENDING: RefCell<BTreeMap<&'static str, u64>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Add `n` to `counter` of the pass that is currently running
+pub fn count(counter: &'static str, n: u64) {
+    if n > 0 {
+        PENDING.with(|pending| *pending.borrow_mut().entry(counter).or_default() += n);
+    }
+}
+
+/// counters reported since the last call, on this thread
+pub(crate) fn take_pending() -> BTreeMap<&'static str, u64> {
+    PENDING.with(|pending| std::mem::take(&mut *pending.borrow_mut()))
+}
+
+/// Static size of a function in SSA form
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FunctionSize {
+    /// instructions and hoisted preheader code, without terminators and phi nodes
+    pub instructions: usize,
+    pub blocks: usize,
+    pub phis: usize,
+}
+
+impl FunctionSize {
+    pub fn of(af: &AbstractFunction) -> Self {
+        let blocks = &af.cfg.basic_blocks;
+        Self {
+            instructions: blocks
+                .iter()
+                .map(|b| b.instructions.len() + b.preheader.len())
+                .sum(),
+            blocks: blocks.len(),
+            phis: blocks.iter().map(|b| b.phi_nodes.len()).sum(),
+        }
+    }
+
+    fn add(&mut self, other: FunctionSize) {
+        self.instructions += other.instructions;
+        self.blocks += other.blocks;
+        self.phis += other.phis;
+    }
+}
+
+/// How many instructions of each opcode a function has, counting phi nodes as `phi` and the
+/// terminators
+pub fn opcode_counts(af: &AbstractFunction) -> BTreeMap<String, u64> {
+    let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+    for block in &af.cfg.basic_blocks {
+        let terminator = match &block.terminator {
+            Terminator::Passthrough => None,
+            Terminator::Ret(code)
+            | Terminator::Jmp(_, code)
+            | Terminator::Br(_, _, code)
+            | Terminator::Speculate(_, code) => Some(code),
+        };
+        let codes = block.preheader.iter().chain(&block.instructions);
+        for code in codes.chain(terminator) {
+            *counts.entry(code.get_opcode_string()).or_default() += 1;
+        }
+        if !block.phi_nodes.is_empty() {
+            *counts.entry("phi".to_string()).or_default() += block.phi_nodes.len() as u64;
+        }
+    }
+    counts
+}
+
+/// Instructions of one opcode that went away and that appeared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct OpcodeChange {
+    pub removed: u64,
+    pub added: u64,
+}
+
+/// Instructions of one opcode in every function before and after the pipeline
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct OpcodeTotal {
+    pub before: u64,
+    pub after: u64,
+}
+
+/// Sizes of a function before and after the pipeline
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct SizeChange {
+    pub before: FunctionSize,
+    pub after: FunctionSize,
+}
+
+/// Everything recorded about one function, as written by `--stats-out`
+#[derive(Debug, Serialize)]
+struct FunctionReport<'a> {
+    before: FunctionSize,
+    after: FunctionSize,
+    passes: BTreeMap<&'a str, &'a BTreeMap<String, u64>>,
+}
+
+/// Transformation counters, by pass, function and counter name, and the size of every function
+/// before and after the pipeline. Functions of the same name in different programs share entries
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Statistics {
+    counters: BTreeMap<String, BTreeMap<String, BTreeMap<String, u64>>>,
+    sizes: BTreeMap<String, SizeChange>,
+    /// pass -> opcode -> what the pass did to instructions of that opcode
+    opcodes: BTreeMap<String, BTreeMap<String, OpcodeChange>>,
+    /// opcode -> instructions before and after the pipeline
+    opcode_totals: BTreeMap<String, OpcodeTotal>,
+}
+
+/// The `--stats json` summary
+#[derive(Serialize)]
+struct Summary<'a> {
+    #[serde(flatten)]
+    passes: BTreeMap<String, BTreeMap<String, u64>>,
+    opcodes: &'a BTreeMap<String, BTreeMap<String, OpcodeChange>>,
+    opcode_totals: &'a BTreeMap<String, OpcodeTotal>,
+}
+
+/// the opcodes in `after` that are fewer or more than in `before`, with the difference
+fn differences<'a>(
+    before: &'a BTreeMap<String, u64>,
+    after: &'a BTreeMap<String, u64>,
+) -> impl Iterator<Item = (&'a String, OpcodeChange)> {
+    let added = after.keys().filter(|op| !before.contains_key(*op));
+    before.keys().chain(added).filter_map(|op| {
+        let count = |counts: &BTreeMap<String, u64>| counts.get(op).copied().unwrap_or(0);
+        let change = OpcodeChange {
+            removed: count(before).saturating_sub(count(after)),
+            added: count(after).saturating_sub(count(before)),
+        };
+        (change != OpcodeChange::default()).then_some((op, change))
+    })
+}
+
+impl Statistics {
+    pub fn add(&mut self, pass: &str, function: &str, counter: &str, n: u64) {
+        *self
+            .counters
+            .entry(pass.to_string())
+            .or_default()
+            .entry(function.to_string())
+            .or_default()
+            .entry(counter.to_string())
+            .or_default() += n;
+    }
+
+    pub fn add_size(&mut self, function: &str, before: FunctionSize, after: FunctionSize) {
+        let sizes = self.sizes.entry(function.to_string()).or_default();
+        sizes.before.add(before);
+        sizes.after.add(after);
+    }
+
+    /// Record the instructions of each opcode that `pass` removed or added
+    pub fn add_opcodes(
+        &mut self,
+        pass: &str,
+        before: &BTreeMap<String, u64>,
+        after: &BTreeMap<String, u64>,
+    ) {
+        let changes = self.opcodes.entry(pass.to_string()).or_default();
+        for (op, change) in differences(before, after) {
+            let entry = changes.entry(op.clone()).or_default();
+            entry.removed += change.removed;
+            entry.added += change.added;
+        }
+    }
+
+    /// Record the opcodes of a function before and after the whole pipeline
+    pub fn add_opcode_totals(
+        &mut self,
+        before: &BTreeMap<String, u64>,
+        after: &BTreeMap<String, u64>,
+    ) {
+        for (op, n) in before {
+            self.opcode_totals.entry(op.clone()).or_default().before += n;
+        }
+        for (op, n) in after {
+            self.opcode_totals.entry(op.clone()).or_default().after += n;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+
+    /// pass -> function -> counter -> value
+    pub fn per_function(&self) -> &BTreeMap<String, BTreeMap<String, BTreeMap<String, u64>>> {
+        &self.counters
+    }
+
+    /// function -> sizes before and after the pipeline
+    pub fn sizes(&self) -> &BTreeMap<String, SizeChange> {
+        &self.sizes
+    }
+
+    /// pass -> opcode -> instructions removed and added, summed over functions
+    pub fn opcodes(&self) -> &BTreeMap<String, BTreeMap<String, OpcodeChange>> {
+        &self.opcodes
+    }
+
+    /// opcode -> instructions in every function before and after the pipeline
+    pub fn opcode_totals(&self) -> &BTreeMap<String, OpcodeTotal> {
+        &self.opcode_totals
+    }
+
+    /// every function with its sizes and the counters of each pass
+    fn function_reports(&self) -> BTreeMap<&str, FunctionReport<'_>> {
+        let mut reports: BTreeMap<&str, FunctionReport> = self
+            .sizes
+            .iter()
+            .map(|(function, sizes)| {
+                let report = FunctionReport {
+                    before: sizes.before,
+                    after: sizes.after,
+                    passes: BTreeMap::new(),
+                };
+                (function.as_str(), report)
+            })
+            .collect();
+        for (pass, functions) in &self.counters {
+            for (function, counters) in functions {
+                let report = reports.entry(function).or_insert_with(|| FunctionReport {
+                    before: FunctionSize::default(),
+                    after: FunctionSize::default(),
+                    passes: BTreeMap::new(),
+                });
+                report.passes.insert(pass, counters);
+            }
+        }
+        reports
+    }
+
+    /// Sizes and pass counters per function as JSON, keyed by function name
+    pub fn to_function_json(&self) -> String {
+        serde_json::to_string_pretty(&self.function_reports()).expect("statistics are serializable")
+    }
+
+    /// Sizes and pass counters per function as CSV with the columns `function,stage,counter,value`.
+    /// `stage` is `before` or `after` for sizes, or the name of the pass
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("function,stage,counter,value\n");
+        let mut row = |function: &str, stage: &str, counter: &str, value: u64| {
+            csv.push_str(&format!("{},{},{},{}\n", function, stage, counter, value));
+        };
+        for (function, report) in self.function_reports() {
+            for (stage, size) in [("before", report.before), ("after", report.after)] {
+                row(function, stage, "instructions", size.instructions as u64);
+                row(function, stage, "blocks", size.blocks as u64);
+                row(function, stage, "phis", size.phis as u64);
+            }
+            for (pass, counters) in report.passes {
+                for (counter, value) in counters {
+                    row(function, pass, counter, *value);
+                }
+            }
+        }
+        csv
+    }
+
+    /// pass -> counter -> value, summed over functions
+    pub fn per_pass(&self) -> BTreeMap<String, BTreeMap<String, u64>> {
+        self.counters
+            .iter()
+            .map(|(pass, functions)| {
+                let mut totals: BTreeMap<String, u64> = BTreeMap::new();
+                for (counter, n) in functions.values().flatten() {
+                    *totals.entry(counter.clone()).or_default() += n;
+                }
+                (pass.clone(), totals)
+            })
+            .collect()
+    }
+
+    /// Summary with one row per pass and counter, then the instructions each pass removed and
+    /// added by opcode, and the opcodes of the program before and after the pipeline
+    pub fn to_table(&self) -> String {
+        let rows: Vec<(String, String, u64)> = self
+            .per_pass()
+            .into_iter()
+            .flat_map(|(pass, counters)| {
+                counters
+                    .into_iter()
+                    .map(move |(counter, n)| (pass.clone(), counter, n))
+            })
+            .collect();
+        let pass_width = rows.iter().map(|r| r.0.len()).max().unwrap_or(0).max(4);
+        let counter_width = rows.iter().map(|r| r.1.len()).max().unwrap_or(0).max(7);
+
+        let mut table = format!(
+            "{:<pass_width$}  {:<counter_width$}  {:>8}\n",
+            "pass", "counter", "count"
+        );
+        for (pass, counter, n) in rows {
+            table.push_str(&format!(
+                "{:<pass_width$}  {:<counter_width$}  {:>8}\n",
+                pass, counter, n
+            ));
+        }
+
+        let changes: Vec<(&String, &String, &OpcodeChange)> = (self.opcodes.iter())
+            .flat_map(|(pass, ops)| ops.iter().map(move |(op, change)| (pass, op, change)))
+            .collect();
+        if !changes.is_empty() {
+            let pass_width = changes.iter().map(|r| r.0.len()).max().unwrap_or(0).max(4);
+            table.push_str(&format!(
+                "\n{:<pass_width$}  {:<10}  {:>8}  {:>8}\n",
+                "pass", "opcode", "removed", "added"
+            ));
+            for (pass, op, change) in changes {
+                table.push_str(&format!(
+                    "{:<pass_width$}  {:<10}  {:>8}  {:>8}\n",
+                    pass, op, change.removed, change.added
+                ));
+            }
+        }
+        if !self.opcode_totals.is_empty() {
+            table.push_str(&format!(
+                "\n{:<10}  {:>8}  {:>8}\n",
+                "opcode", "before", "after"
+            ));
+            for (op, total) in &self.opcode_totals {
+                table.push_str(&format!(
+                    "{:<10}  {:>8}  {:>8}\n",
+                    op, total.before, total.after
+                ));
+            }
+        }
+        table
+    }
+
+    /// Summary as JSON: the counters of each pass keyed by its name, the instructions each pass
+    /// removed and added by opcode under `opcodes`, and the opcodes of the program before and
+    /// after the pipeline under `opcode_totals`
+    pub fn to_json(&self) -> String {
+        let summary = Summary {
+            passes: self.per_pass(),
+            opcodes: &self.opcodes,
+            opcode_totals: &self.opcode_totals,
+        };
+        serde_json::to_string_pretty(&summary).expect("statistics are serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_over_functions() {
+        let mut stats = Statistics::default();
+        stats.add("lvn", "main", "expressions folded", 2);
+        stats.add("lvn", "fact", "expressions folded", 3);
+        stats.add("dce", "main", "instructions removed", 1);
+        assert_eq!(stats.per_pass()["lvn"]["expressions folded"], 5);
+        assert!(stats
+            .to_table()
+            .contains("dce   instructions removed         1"));
+    }
+
+    #[test]
+    fn reports_per_function() {
+        let mut stats = Statistics::default();
+        let size = |instructions| FunctionSize {
+            instructions,
+            blocks: 1,
+            phis: 0,
+        };
+        stats.add_size("main", size(3), size(2));
+        stats.add("dce", "main", "instructions removed", 1);
+        let csv = stats.to_csv();
+        assert!(csv.contains("main,before,instructions,3\nmain,before,blocks,1\n"));
+        assert!(csv.contains("main,after,instructions,2\n"));
+        assert!(csv.ends_with("main,dce,instructions removed,1\n"));
+
+        let json: serde_json::Value = serde_json::from_str(&stats.to_function_json()).unwrap();
+        assert_eq!(json["main"]["after"]["instructions"], 2);
+        assert_eq!(json["main"]["passes"]["dce"]["instructions removed"], 1);
+    }
+
+    #[test]
+    fn reports_opcodes_per_pass() {
+        let counts = |ops: &[(&str, u64)]| -> BTreeMap<String, u64> {
+            ops.iter().map(|(op, n)| (op.to_string(), *n)).collect()
+        };
+        let before = counts(&[("add", 2), ("const", 1), ("print", 1)]);
+        let after = counts(&[("add", 1), ("const", 1), ("id", 1), ("print", 1)]);
+        let mut stats = Statistics::default();
+        stats.add("lvn", "main", "redundant expressions reused", 1);
+        stats.add_opcodes("lvn", &before, &after);
+        stats.add_opcode_totals(&before, &after);
+
+        let change = |removed, added| OpcodeChange { removed, added };
+        let lvn = &stats.opcodes()["lvn"];
+        assert_eq!(lvn.len(), 2);
+        assert_eq!((lvn["add"], lvn["id"]), (change(1, 0), change(0, 1)));
+        let table = stats.to_table();
+        assert!(
+            table.contains("lvn   add                1         0\n"),
+            "{}",
+            table
+        );
+        assert!(
+            table.contains("add                2         1\n"),
+            "{}",
+            table
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&stats.to_json()).unwrap();
+        assert_eq!(json["lvn"]["redundant expressions reused"], 1);
+        assert_eq!(json["opcodes"]["lvn"]["id"]["added"], 1);
+        assert_eq!(json["opcode_totals"]["add"]["after"], 1);
+    }
+}
@@ -1,14 +1,22 @@
 use crate::{
-    dataflow::{run_dataflow_analysis, DefinitelyInitialized, WorklistResult},
+    analysis::{BlockFrequencies, Purity},
+    dataflow::{
+        run_parallel_dataflow_analysis, DefinitelyInitialized, WorklistError, WorklistResult,
+    },
     representation::{
         phi_nodes,
         program::{Code, EffectOp, Position, Type},
-        Argument, ControlFlowGraph, DominanceInfo, Function, PhiNode, Program, RichProgram,
-        ValueOp,
+        Argument, ControlFlowGraph, DominanceInfo, Function, FunctionAttribute, PhiNode, Program,
+        RichProgram, SsaForm, ValueOp,
     },
+    timing::Timer,
+};
+use indexmap::IndexMap;
+use rayon::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, OnceLock},
 };
-use std::collections::{HashMap, HashSet};
-use uuid::Uuid;
 
 // Core types for the IR-friendly representation
 pub type BlockId = usize;
@@ -23,17 +31,25 @@ pub struct RichAbstractProgram {
 
 #[derive(Debug, Clone)]
 pub struct AbstractProgram {
-    pub functions: HashMap<String, AbstractFunction>,
+    /// the functions by name, in the order of the source, the order they are emitted in
+    pub functions: IndexMap<String, AbstractFunction>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct AbstractFunction {
     pub name: String,
     pub pos: Option<Position>,
+    pub pos_end: Option<Position>,
+    /// file the function was read from, if it is not the program's main source
+    pub src: Option<String>,
     pub cfg: ControlFlowGraph,
-    pub dominance_info: DominanceInfo,
+    /// dominators of `cfg`, computed on first use by `dominance_info`
+    pub(crate) dominance: OnceLock<DominanceInfo>,
     pub args: Option<Vec<Argument>>,
     pub return_type: Option<Type>,
+    pub attrs: Option<Vec<FunctionAttribute>>,
+    /// attributes of every function in the program, keyed by function name, shared by all of them
+    pub known_attrs: Arc<HashMap<String, Vec<FunctionAttribute>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -45,6 +61,10 @@ pub struct BasicBlock {
     pub phi_nodes: Vec<PhiNode>,
     pub preheader: Vec<Code>,
     pub natural_loop_return: bool,
+    /// how often the block ran in the profile given with `--profile-in`, `None` without one
+    pub frequency: Option<u64>,
+    /// how often each successor, by label, was entered from this block in that profile
+    pub edge_frequencies: HashMap<Label, u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -53,12 +73,16 @@ pub enum Terminator {
     Ret(Code),
     Jmp(Label, Code),
     Br(Label, Label, Code),
+    /// `speculate`, falling through to the next block. The labels are where the guards of the
+    /// speculation go when they fail, with the variables as they are here: those edges leave
+    /// from this block rather than from the guards
+    Speculate(Vec<Label>, Code),
 }
 
 impl Terminator {
     pub fn get_arguments(&self) -> Option<&Vec<String>> {
         match self {
-            Terminator::Passthrough => None,
+            Terminator::Passthrough | Terminator::Speculate(..) => None,
             Terminator::Ret(Code::Effect { args, .. }) => args.as_ref(),
             Terminator::Jmp(_, Code::Effect { args, .. }) => args.as_ref(),
             Terminator::Br(_, _, Code::Effect { args, .. }) => args.as_ref(),
@@ -69,63 +93,235 @@ impl Terminator {
 
 impl From<Function> for AbstractFunction {
     fn from(f: Function) -> Self {
-        let now = std::time::Instant::now();
-        let basic_blocks = AbstractFunction::into_basic_blocks(f.instrs);
+        let _timer = Timer::function("cfg construction", &f.name);
+        let mut basic_blocks = AbstractFunction::into_basic_blocks(f.instrs);
+        link_speculation(&mut basic_blocks);
         let cfg = ControlFlowGraph::from(basic_blocks).prune_unreachable_blocks();
-        let dominance_info = DominanceInfo::from(&cfg);
-
-        log::debug!("Converted {} into SSA in {:?}", f.name, now.elapsed());
 
         Self {
             name: f.name,
             pos: f.pos,
+            pos_end: f.pos_end,
+            src: f.src,
             cfg,
-            dominance_info,
+            dominance: OnceLock::new(),
             args: f.args,
             return_type: f.return_type,
+            attrs: f.attrs,
+            known_attrs: Arc::default(),
         }
     }
 }
 
 // Conversion implementations
-impl From<RichProgram> for RichAbstractProgram {
-    fn from(rp: RichProgram) -> Self {
-        let now = std::time::Instant::now();
+impl TryFrom<RichProgram> for RichAbstractProgram {
+    type Error = WorklistError;
+
+    /// Convert into SSA form, see `try_from_program`
+    fn try_from(rp: RichProgram) -> WorklistResult<Self> {
+        RichAbstractProgram::try_from_program(rp)
+    }
+}
+
+/// Fill in where the guards of every `speculate` in `blocks` go. Each speculation is followed
+/// from the block after it until its `commit`, counting the speculations nested in it, and a
+/// guard found outside of those belongs to it. Where a nested speculation rolls back to, the
+/// outer one is still going on, so this repeats until nothing changes
+fn link_speculation(blocks: &mut [BasicBlock]) {
+    let label_map: HashMap<&str, BlockId> = (blocks.iter())
+        .map(|block| (block.label.as_str(), block.id))
+        .collect();
+    let speculations: Vec<BlockId> = (blocks.iter())
+        .filter(|block| matches!(block.terminator, Terminator::Speculate(..)))
+        .map(|block| block.id)
+        .collect();
+    if speculations.is_empty() {
+        return;
+    }
+    // deeper nesting than this is not followed: such a speculation may go to any guard's label
+    let max_depth = blocks.len();
+    let all_guards: Vec<Label> = (blocks.iter())
+        .flat_map(|block| &block.instructions)
+        .filter_map(guard_label)
+        .cloned()
+        .collect();
+
+    let mut targets: HashMap<BlockId, Vec<Label>> = HashMap::new();
+    loop {
+        let mut changed = false;
+        for &speculation in &speculations {
+            let mut found: Vec<Label> = Vec::new();
+            let mut seen: HashSet<(BlockId, usize)> = HashSet::new();
+            let mut pending = vec![(speculation + 1, 0)];
+            while let Some((b, mut depth)) = pending.pop() {
+                if b >= blocks.len() || !seen.insert((b, depth)) {
+                    continue;
+                }
+                if depth > max_depth {
+                    found.extend(all_guards.iter().cloned());
+                    continue;
+                }
+                let block = &blocks[b];
+                let mut committed = false;
+                for code in &block.instructions {
+                    match code {
+                        Code::Effect {
+                            op: EffectOp::Commit,
+                            ..
+                        } if depth == 0 => {
+                            committed = true;
+                            break;
+                        }
+                        Code::Effect {
+                            op: EffectOp::Commit,
+                            ..
+                        } => depth -= 1,
+                        _ => found.extend(guard_label(code).filter(|_| depth == 0).cloned()),
+                    }
+                }
+                if committed {
+                    continue;
+                }
+                let id = |label: &Label| label_map.get(label.as_str()).copied();
+                match &block.terminator {
+                    Terminator::Ret(_) => {}
+                    Terminator::Passthrough => pending.push((b + 1, depth)),
+                    Terminator::Jmp(label, _) => pending.extend(id(label).map(|t| (t, depth))),
+                    Terminator::Br(then, otherwise, _) => pending.extend(
+                        [then, otherwise]
+                            .into_iter()
+                            .filter_map(id)
+                            .map(|t| (t, depth)),
+                    ),
+                    // a nested speculation, which rolls back to where this one still goes on
+                    Terminator::Speculate(..) => {
+                        pending.push((b + 1, depth + 1));
+                        let rollbacks = targets.get(&b).into_iter().flatten().filter_map(id);
+                        pending.extend(rollbacks.map(|t| (t, depth)));
+                    }
+                }
+            }
+            found.sort();
+            found.dedup();
+            if targets.get(&speculation) != Some(&found) {
+                targets.insert(speculation, found);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    for (speculation, found) in targets {
+        if let Terminator::Speculate(labels, _) = &mut blocks[speculation].terminator {
+            *labels = found;
+        }
+    }
+}
 
-        // need to run initialized variable checker first
-        let functions = rp
+/// the label `code` goes to if it is a guard
+fn guard_label(code: &Code) -> Option<&Label> {
+    match code {
+        Code::Effect {
+            op: EffectOp::Guard,
+            labels: Some(labels),
+            ..
+        } => labels.first(),
+        _ => None,
+    }
+}
+
+/// A guard that no speculation reaches fails at run time. Its label may not even be reachable,
+/// so such functions are rejected
+fn reject_stray_guards(af: &AbstractFunction) -> WorklistResult<()> {
+    let targets: HashSet<&Label> = (af.cfg.basic_blocks.iter())
+        .filter_map(|block| match &block.terminator {
+            Terminator::Speculate(labels, _) => Some(labels),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    for block in &af.cfg.basic_blocks {
+        for code in &block.instructions {
+            if let Some(label) = guard_label(code).filter(|label| !targets.contains(label)) {
+                let reason = format!(
+                    "@{} has a guard going to .{} outside of any speculation",
+                    af.name, label
+                );
+                return Err(WorklistError::transfer_error(
+                    block,
+                    reason,
+                    &code.get_position(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+impl RichAbstractProgram {
+    /// Convert into SSA form, returning the first error instead of exiting
+    pub fn try_from_program(rp: RichProgram) -> WorklistResult<Self> {
+        Self::try_from_program_with(rp, SsaForm::default())
+    }
+
+    /// [`try_from_program`](Self::try_from_program), placing phi nodes as `form` says
+    pub fn try_from_program_with(rp: RichProgram, form: SsaForm) -> WorklistResult<Self> {
+        let mut known_attrs: HashMap<String, Vec<FunctionAttribute>> = rp
             .program
             .functions
-            .into_iter()
-            .map(|function| AbstractFunction::from(function))
-            .map(
-                // this map runs an initialized variable analysis on each function and exits on error
-                |mut af| match run_dataflow_analysis::<DefinitelyInitialized>(&mut af) {
-                    Ok(_) => af,
-                    WorklistResult::Err(e) => e.error_with_context_then_exit(&rp.original_text),
-                },
-            )
-            .map(|function| phi_nodes::insert_phi_nodes(function))
-            .map(|result| match result {
-                WorklistResult::Ok(func) => (func.name.clone(), func),
-                WorklistResult::Err(e) => e.error_with_context_then_exit(&rp.original_text),
+            .iter()
+            .filter_map(|f| Some((f.name.clone(), f.attrs.clone()?)))
+            .collect();
+
+        // functions are independent, so they are converted in parallel. The first error in
+        // source order wins, whichever thread finds it
+        let functions: Vec<WorklistResult<_>> = rp
+            .program
+            .functions
+            .into_par_iter()
+            .map(AbstractFunction::from)
+            .map(|mut af| {
+                let _timer = Timer::function("ssa construction", &af.name);
+                reject_stray_guards(&af).map_err(|e| e.in_source(af.src.as_deref()))?;
+                // run an initialized variable analysis on each function before inserting phi nodes
+                run_parallel_dataflow_analysis::<DefinitelyInitialized>(&mut af)?;
+                let src = af.src.clone();
+                let func = phi_nodes::insert_phi_nodes(af, form)
+                    .map_err(|e| e.in_source(src.as_deref()))?;
+                Ok((func.name.clone(), func))
             })
             .collect();
+        let functions = functions.into_iter().collect::<WorklistResult<_>>()?;
+        let mut program = AbstractProgram { functions };
+
+        // functions proven free of side effects count as annotated `pure`
+        let purity = Purity::from(&program);
+        for name in purity.functions() {
+            let attrs = known_attrs.entry(name.clone()).or_default();
+            if !attrs.contains(&FunctionAttribute::Pure) {
+                attrs.push(FunctionAttribute::Pure);
+            }
+        }
+        let known_attrs = Arc::new(known_attrs);
+        for af in program.functions.values_mut() {
+            af.known_attrs = Arc::clone(&known_attrs);
+        }
 
-        log::info!("converted program to SSA in {:?}", now.elapsed());
-        RichAbstractProgram {
+        Ok(RichAbstractProgram {
             original_text: rp.original_text,
-            program: AbstractProgram { functions },
-        }
+            program,
+        })
     }
-}
 
-impl RichAbstractProgram {
     pub fn into_ssa_program(self) -> RichProgram {
         let functions = self
             .program
             .functions
             .into_values()
+            .collect::<Vec<_>>()
+            .into_par_iter()
             .map(|f| f.remap_phi_nodes())
             .map(|f| f.into_ssa_function())
             .collect();
@@ -141,6 +337,8 @@ impl RichAbstractProgram {
             .program
             .functions
             .into_values()
+            .collect::<Vec<_>>()
+            .into_par_iter()
             .map(|f| f.remap_phi_nodes())
             .map(|f| f.into_function())
             .collect();
@@ -153,7 +351,19 @@ impl RichAbstractProgram {
 }
 
 impl AbstractFunction {
+    /// `{stem}_{n}` for the first `n` from `start` that is not `taken`, which then holds it.
+    /// Labels are made up this way, rather than randomly, so that every run prints the same program
+    fn invent_label(taken: &mut HashSet<String>, stem: &str, start: usize) -> String {
+        let label = (start..)
+            .map(|n| format!("{}_{}", stem, n))
+            .find(|label| !taken.contains(label))
+            .expect("some number is free");
+        taken.insert(label.clone());
+        label
+    }
+
     fn emit_basic_block(
+        taken: &mut HashSet<String>,
         block_id: &mut BlockId,
         current_block_instrs: &mut Vec<Code>,
         current_label: &mut Option<String>,
@@ -161,14 +371,16 @@ impl AbstractFunction {
     ) -> BasicBlock {
         let block = BasicBlock {
             id: *block_id,
-            label: current_label.take().unwrap_or_else(|| {
-                format!("no_label_{}", Uuid::new_v4().to_string().replace("-", "_"))
-            }),
+            label: current_label
+                .take()
+                .unwrap_or_else(|| AbstractFunction::invent_label(taken, "no_label", *block_id)),
             instructions: std::mem::take(current_block_instrs),
             terminator: std::mem::replace(current_terminator, Terminator::Passthrough),
             phi_nodes: Vec::new(),
             preheader: Vec::new(),
             natural_loop_return: false,
+            frequency: None,
+            edge_frequencies: HashMap::new(),
         };
 
         *block_id += 1;
@@ -176,17 +388,25 @@ impl AbstractFunction {
     }
 
     fn into_basic_blocks(instrs: Vec<Code>) -> Vec<BasicBlock> {
+        let mut taken: HashSet<String> = (instrs.iter())
+            .filter_map(|code| match code {
+                Code::Label { label, .. } => Some(label.clone()),
+                _ => None,
+            })
+            .collect();
         let mut blocks = Vec::new();
         let mut current_block_instrs = Vec::new();
-        let mut current_label: Option<String> = Some(format!(
-            "function_preamble_{}",
-            Uuid::new_v4().to_string().replace("-", "_")
+        let mut current_label: Option<String> = Some(AbstractFunction::invent_label(
+            &mut taken,
+            "function_preamble",
+            0,
         ));
         let mut block_id = 0;
         let mut current_terminator: Terminator = Terminator::Passthrough;
 
         // insert preamble block in case original first block needs to push values up
         blocks.push(AbstractFunction::emit_basic_block(
+            &mut taken,
             &mut block_id,
             &mut current_block_instrs,
             &mut current_label,
@@ -198,6 +418,7 @@ impl AbstractFunction {
                 Code::Label { label, .. } => {
                     if !current_block_instrs.is_empty() || current_label.is_some() {
                         blocks.push(AbstractFunction::emit_basic_block(
+                            &mut taken,
                             &mut block_id,
                             &mut current_block_instrs,
                             &mut current_label,
@@ -208,23 +429,27 @@ impl AbstractFunction {
                     current_label = Some(label.clone());
                 }
                 Code::Effect {
-                    op: op @ (EffectOp::Jmp | EffectOp::Br | EffectOp::Ret),
+                    op: op @ (EffectOp::Jmp | EffectOp::Br | EffectOp::Ret | EffectOp::Speculate),
                     labels,
                     ..
                 } => {
                     // This is a terminator instruction
                     current_terminator = match op {
-                        EffectOp::Jmp => {
-                            Terminator::Jmp(labels.clone().unwrap().pop().unwrap(), code)
-                        }
+                        EffectOp::Jmp => Terminator::Jmp(
+                            labels.as_deref().unwrap().last().unwrap().clone(),
+                            code,
+                        ),
                         EffectOp::Br => {
-                            let mut v = labels.clone().unwrap();
-                            Terminator::Br(v.remove(0), v.remove(0), code)
+                            let v = labels.as_deref().unwrap();
+                            Terminator::Br(v[0].clone(), v[1].clone(), code)
                         }
                         EffectOp::Ret => Terminator::Ret(code),
+                        // the targets are filled in once every block is known
+                        EffectOp::Speculate => Terminator::Speculate(Vec::new(), code),
                         _ => unreachable!(),
                     };
                     blocks.push(AbstractFunction::emit_basic_block(
+                        &mut taken,
                         &mut block_id,
                         &mut current_block_instrs,
                         &mut current_label,
@@ -237,16 +462,20 @@ impl AbstractFunction {
             }
         }
 
-        // Handle any remaining instructions
-        if !current_block_instrs.is_empty() || current_label.is_some() {
+        // Handle any remaining instructions. An empty function still needs a block that returns
+        // after the preamble
+        if !current_block_instrs.is_empty() || current_label.is_some() || blocks.len() == 1 {
             current_terminator = Terminator::Ret(Code::Effect {
                 op: EffectOp::Ret,
                 args: None,
                 labels: None,
                 pos: None,
+                pos_end: None,
+                src: None,
                 funcs: None,
             });
             blocks.push(AbstractFunction::emit_basic_block(
+                &mut taken,
                 &mut block_id,
                 &mut current_block_instrs,
                 &mut current_label,
@@ -257,13 +486,16 @@ impl AbstractFunction {
         blocks
     }
 
-    fn flatten_basic_blocks(blocks: Vec<BasicBlock>) -> Vec<Code> {
+    fn flatten_basic_blocks(
+        blocks: Vec<BasicBlock>,
+        back_edges: &HashSet<(String, String)>,
+    ) -> Vec<Code> {
         let mut instrs = Vec::new();
 
         let natural_loop_preheaders = blocks
             .iter()
             .filter_map(|block| {
-                if block.preheader.len() > 0 {
+                if !block.preheader.is_empty() {
                     Some(block.label.clone())
                 } else {
                     None
@@ -272,11 +504,14 @@ impl AbstractFunction {
             .collect::<HashSet<_>>();
 
         for block in blocks {
+            let from = block.label.clone();
             // if this block has a natural loop preheader, emit it first
             if natural_loop_preheaders.contains(&block.label) {
                 instrs.push(Code::Label {
                     label: format!("pre_header_{}", block.label),
                     pos: None,
+                    pos_end: None,
+                    src: None,
                 });
                 for preheader_instr in block.preheader.iter() {
                     instrs.push(preheader_instr.clone());
@@ -286,12 +521,16 @@ impl AbstractFunction {
             instrs.push(Code::Label {
                 label: block.label,
                 pos: None,
+                pos_end: None,
+                src: None,
             });
 
             // add phi nodes
-            for phi in block.phi_nodes.into_iter() {
+            for mut phi in block.phi_nodes.into_iter() {
                 log::warn!("emitting phi node: {} [ignore if debugging SSA]", phi);
 
+                // by predecessor, whatever order the passes left them in
+                phi.phi_args.sort_by(|a, b| a.1.cmp(&b.1));
                 // split phi.phi_args (tuple of (var, label)) into two vectors
                 let (vars, labels): (Vec<_>, Vec<_>) = phi.phi_args.into_iter().unzip();
 
@@ -301,8 +540,10 @@ impl AbstractFunction {
                     value_type: phi.phi_type,
                     args: Some(vars),
                     funcs: None,
-                    labels: Some(labels),
-                    pos: None,
+                    labels: Some(labels.into()),
+                    pos: phi.pos,
+                    pos_end: None,
+                    src: None,
                 });
             }
 
@@ -311,7 +552,9 @@ impl AbstractFunction {
 
             // Helper function to map labels to preheaders when needed
             let map_label_to_preheader = |label: &str| -> String {
-                if !block.natural_loop_return && natural_loop_preheaders.contains(label) {
+                if natural_loop_preheaders.contains(label)
+                    && !back_edges.contains(&(from.clone(), label.to_string()))
+                {
                     format!("pre_header_{}", label)
                 } else {
                     label.to_string()
@@ -321,7 +564,9 @@ impl AbstractFunction {
             // Add terminator instruction if present
             match block.terminator {
                 Terminator::Passthrough => continue,
-                Terminator::Ret(effect_op) => instrs.push(effect_op),
+                Terminator::Ret(effect_op) | Terminator::Speculate(_, effect_op) => {
+                    instrs.push(effect_op)
+                }
                 Terminator::Jmp(_, effect_op) => {
                     // if this is not a natural loop backedge and the target has a preheader, jump to the preheader instead
                     let dest_label = effect_op.get_labels().unwrap()[0].clone();
@@ -330,8 +575,10 @@ impl AbstractFunction {
                         instrs.push(Code::Effect {
                             op: EffectOp::Jmp,
                             args: None,
-                            labels: Some(vec![mapped_label]),
-                            pos: None,
+                            labels: Some(Box::new([mapped_label])),
+                            pos: effect_op.get_position(),
+                            pos_end: effect_op.get_position_end(),
+                            src: effect_op.get_source().map(Box::from),
                             funcs: None,
                         });
                     } else {
@@ -350,8 +597,10 @@ impl AbstractFunction {
                         instrs.push(Code::Effect {
                             op: EffectOp::Br,
                             args: effect_op.get_arguments().cloned(),
-                            labels: Some(vec![mapped_true_label, mapped_false_label]),
-                            pos: None,
+                            labels: Some(Box::new([mapped_true_label, mapped_false_label])),
+                            pos: effect_op.get_position(),
+                            pos_end: effect_op.get_position_end(),
+                            src: effect_op.get_source().map(Box::from),
                             funcs: None,
                         });
                     } else {
@@ -365,35 +614,98 @@ impl AbstractFunction {
     }
 
     fn into_ssa_function(self) -> Function {
-        let instrs = AbstractFunction::flatten_basic_blocks(self.cfg.basic_blocks);
+        let back_edges = self.back_edges();
+        let instrs = AbstractFunction::flatten_basic_blocks(self.cfg.basic_blocks, &back_edges);
         Function {
             name: self.name,
             pos: self.pos,
+            pos_end: self.pos_end,
+            src: self.src,
             instrs,
             args: self.args,
             return_type: self.return_type,
+            attrs: self.attrs,
+        }
+    }
+
+    /// Whether the function speculates. Guards jump straight to their labels, so passes that
+    /// move blocks, retarget edges or hoist code in front of loops leave such functions alone
+    pub fn speculates(&self) -> bool {
+        (self.cfg.basic_blocks.iter()).any(|b| matches!(b.terminator, Terminator::Speculate(..)))
+    }
+
+    /// How often each block runs per call: the counts of the attached profile, or else a
+    /// static estimate
+    pub fn block_frequencies(&self) -> BlockFrequencies {
+        BlockFrequencies::from(self)
+    }
+
+    /// Dominators of the CFG, computed on first use and kept until the CFG changes shape
+    pub fn dominance_info(&self) -> &DominanceInfo {
+        self.dominance
+            .get_or_init(|| DominanceInfo::from(&self.cfg))
+    }
+
+    /// Drop the cached dominators if edges were added or removed since they were computed
+    pub fn discard_stale_dominance(&mut self) {
+        if self
+            .dominance
+            .get()
+            .is_some_and(|dominance| !dominance.is_current(&self.cfg))
+        {
+            log::debug!("dropping stale dominance of {}", self.name);
+            self.dominance.take();
         }
     }
 
+    /// A copy of the function in SSA form, as it would be emitted with `-S`
+    pub fn to_ssa_function(&self) -> Function {
+        self.clone().remap_phi_nodes().into_ssa_function()
+    }
+
+    /// A copy of the function out of SSA form
+    pub fn to_function(&self) -> Function {
+        self.clone().remap_phi_nodes().into_function()
+    }
+
+    /// Check whether `code` calls a function annotated with `attribute`
+    pub fn calls_function_with(&self, code: &Code, attribute: FunctionAttribute) -> bool {
+        if !code.is_call() {
+            return false;
+        }
+
+        code.get_funcs()
+            .and_then(|funcs| funcs.first())
+            .and_then(|callee| self.known_attrs.get(callee))
+            .is_some_and(|attrs| attrs.contains(&attribute))
+    }
+
     fn into_function(mut self) -> Function {
         phi_nodes::remove_phi_nodes(&mut self);
         self.into_ssa_function()
     }
 
-    fn remap_phi_nodes(mut self) -> Self {
-        // only remap if not backedge
-        let natural_loop_returns = self
-            .cfg
-            .basic_blocks
+    /// The edges, as (from, to) labels, that go back to the header of a loop from inside it.
+    /// Every other edge into a header with a preheader goes through the preheader. A block can
+    /// close one loop and enter another, so this is a property of the edge, not of the block
+    fn back_edges(&self) -> HashSet<(String, String)> {
+        let dominance = DominanceInfo::from(&self.cfg);
+        let blocks = &self.cfg.basic_blocks;
+        blocks
             .iter()
-            .filter_map(|block| {
-                if block.natural_loop_return {
-                    Some(block.label.clone())
-                } else {
-                    None
-                }
+            .filter(|header| !header.preheader.is_empty())
+            .flat_map(|header| {
+                self.cfg.predecessors[header.id]
+                    .iter()
+                    .filter(|&from| dominance.dominated_by(from, header.id))
+                    .map(|from| (blocks[from].label.clone(), header.label.clone()))
             })
-            .collect::<HashSet<_>>();
+            .collect()
+    }
+
+    fn remap_phi_nodes(mut self) -> Self {
+        // only remap if not backedge
+        let back_edges = self.back_edges();
 
         for block in &mut self.cfg.basic_blocks {
             if block.preheader.is_empty() {
@@ -410,7 +722,7 @@ impl AbstractFunction {
                         .iter()
                         .find(|instr| instr.get_destination() == Some(phi_var))
                         .is_some()
-                        && !natural_loop_returns.contains(phi_label)
+                        && !back_edges.contains(&(phi_label.clone(), block.label.clone()))
                     {
                         *phi_label = format!("pre_header_{}", block.label);
                     }
@@ -421,3 +733,106 @@ impl AbstractFunction {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        optimizations::{run_pipeline, run_pipeline_in_ssa, PassManager},
+        representation::{Code, RichAbstractProgram, ValueOp},
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn guards_roll_back_to_the_variables_of_their_speculation() {
+        // the inner guard rolls back to `.inner` with the `a` of the outer speculation
+        let text = r#"
+@main(n: int) {
+  a: int = const 10;
+  b: int = const 20;
+  speculate;
+  a: int = add a n;
+  speculate;
+  b: int = add b n;
+  c: bool = lt n a;
+  guard c .inner;
+  commit;
+  d: bool = lt a b;
+  guard d .outer;
+  commit;
+  print a b;
+  ret;
+.inner:
+  print a b;
+  e: bool = lt b a;
+  guard e .outer;
+  commit;
+  ret;
+.outer:
+  print a b;
+}
+"#;
+        let program = program_from_text(text);
+        for level in 1..=3 {
+            let optimized = run_pipeline(program.clone(), &PassManager::preset(level)).unwrap();
+            for n in ["-5", "0", "7", "30"] {
+                assert_same_output(&program.program, &optimized.program, &[n]);
+            }
+        }
+
+        let stray = "@main {\n  t: bool = const true;\n  guard t .end;\n.end:\n  ret;\n}\n";
+        let stray = program_from_text(stray);
+        let error = RichAbstractProgram::try_from_program(stray).unwrap_err();
+        assert!(format!("{:?}", error).contains("outside of any speculation"));
+    }
+
+    #[test]
+    fn every_run_prints_the_same_program() {
+        let text = r#"
+@square(x: int): int {
+  y: int = mul x x;
+  ret y;
+}
+@main(n: int) {
+  i: int = const 0;
+  one: int = const 1;
+  more: bool = lt i n;
+  br more .loop .done;
+.loop:
+  s: int = call @square i;
+  print s;
+  i: int = add i one;
+  more: bool = lt i n;
+  br more .loop .done;
+.done:
+  print i;
+}
+"#;
+        let program = program_from_text(text);
+        let print = || {
+            let passes = PassManager::preset(2);
+            let optimized = run_pipeline_in_ssa(program.clone(), &passes).unwrap();
+            optimized.into_ssa_program()
+        };
+        let first = print();
+        assert_eq!(first.to_string(), print().to_string());
+
+        let names: Vec<&str> = (first.program.functions.iter())
+            .map(|f| f.name.as_str())
+            .collect();
+        assert_eq!(names, ["square", "main"]);
+        let main = &first.program.functions[1];
+        assert!(
+            matches!(&main.instrs[0], Code::Label { label, .. } if label == "function_preamble_0")
+        );
+        for code in &main.instrs {
+            if let Code::Value {
+                op: ValueOp::Phi,
+                labels: Some(labels),
+                ..
+            } = code
+            {
+                assert!(labels.is_sorted(), "{}", code);
+            }
+        }
+    }
+}
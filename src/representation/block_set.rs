@@ -0,0 +1,198 @@
+use std::fmt;
+
+use crate::representation::BlockId;
+
+// Fixed-size bitset over the blocks of one function. Dominance sets are dense and get intersected
+// on every iteration, so a word per 64 blocks beats a `HashSet<usize>` by a wide margin.
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct BlockSet {
+    words: Vec<u64>,
+    capacity: usize,
+}
+
+impl BlockSet {
+    /// an empty set that can hold block ids `0..capacity`
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            words: vec![0; capacity.div_ceil(WORD_BITS)],
+            capacity,
+        }
+    }
+
+    /// the set of every block id `0..capacity`
+    pub fn full(capacity: usize) -> Self {
+        let mut set = Self {
+            words: vec![u64::MAX; capacity.div_ceil(WORD_BITS)],
+            capacity,
+        };
+        set.clear_tail();
+        set
+    }
+
+    pub fn from_ids(capacity: usize, ids: impl IntoIterator<Item = BlockId>) -> Self {
+        let mut set = Self::new(capacity);
+        for id in ids {
+            set.insert(id);
+        }
+        set
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// returns whether the id was newly inserted
+    pub fn insert(&mut self, id: BlockId) -> bool {
+        assert!(
+            id < self.capacity,
+            "block {} out of range for a set of {} blocks",
+            id,
+            self.capacity
+        );
+        let (word, bit) = (id / WORD_BITS, 1 << (id % WORD_BITS));
+        let fresh = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        fresh
+    }
+
+    /// returns whether the id was present
+    pub fn remove(&mut self, id: BlockId) -> bool {
+        if id >= self.capacity {
+            return false;
+        }
+        let (word, bit) = (id / WORD_BITS, 1 << (id % WORD_BITS));
+        let present = self.words[word] & bit != 0;
+        self.words[word] &= !bit;
+        present
+    }
+
+    pub fn contains(&self, id: BlockId) -> bool {
+        id < self.capacity && self.words[id / WORD_BITS] & (1 << (id % WORD_BITS)) != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&w| w == 0)
+    }
+
+    /// keep only the ids that are also in `other`
+    pub fn intersect_with(&mut self, other: &BlockSet) {
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a &= b;
+        }
+        for a in self.words.iter_mut().skip(other.words.len()) {
+            *a = 0;
+        }
+    }
+
+    /// add every id in `other`, which must fit in this set
+    pub fn union_with(&mut self, other: &BlockSet) {
+        assert!(other.capacity <= self.capacity);
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a |= b;
+        }
+    }
+
+    /// change the capacity, dropping the ids that no longer fit
+    pub fn resize(&mut self, capacity: usize) {
+        self.words.resize(capacity.div_ceil(WORD_BITS), 0);
+        self.capacity = capacity;
+        self.clear_tail();
+    }
+
+    /// remove `id` and renumber the ids above it one lower, for when a block is deleted
+    pub fn remove_and_shift(&mut self, id: BlockId) {
+        let shifted = self.iter().filter(|&other| other != id).map(|other| {
+            if other > id {
+                other - 1
+            } else {
+                other
+            }
+        });
+        *self = BlockSet::from_ids(self.capacity.saturating_sub(1), shifted.collect::<Vec<_>>());
+    }
+
+    /// ids in ascending order
+    pub fn iter(&self) -> BlockSetIter<'_> {
+        BlockSetIter {
+            words: &self.words,
+            index: 0,
+            current: self.words.first().copied().unwrap_or(0),
+        }
+    }
+
+    fn clear_tail(&mut self) {
+        let rem = self.capacity % WORD_BITS;
+        if rem != 0 {
+            if let Some(last) = self.words.last_mut() {
+                *last &= (1 << rem) - 1;
+            }
+        }
+    }
+}
+
+impl fmt::Debug for BlockSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+pub struct BlockSetIter<'a> {
+    words: &'a [u64],
+    index: usize,
+    current: u64,
+}
+
+impl Iterator for BlockSetIter<'_> {
+    type Item = BlockId;
+
+    fn next(&mut self) -> Option<BlockId> {
+        while self.current == 0 {
+            self.index += 1;
+            self.current = *self.words.get(self.index)?;
+        }
+        let bit = self.current.trailing_zeros() as usize;
+        self.current &= self.current - 1;
+        Some(self.index * WORD_BITS + bit)
+    }
+}
+
+impl<'a> IntoIterator for &'a BlockSet {
+    type Item = BlockId;
+    type IntoIter = BlockSetIter<'a>;
+
+    fn into_iter(self) -> BlockSetIter<'a> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_operations_across_words() {
+        let mut a = BlockSet::from_ids(130, [0, 3, 64, 129]);
+        assert_eq!(a.iter().collect::<Vec<_>>(), vec![0, 3, 64, 129]);
+        assert!(a.contains(64) && !a.contains(65) && !a.contains(500));
+        assert!(!a.insert(3));
+
+        a.intersect_with(&BlockSet::from_ids(130, [3, 64, 100]));
+        assert_eq!(a.iter().collect::<Vec<_>>(), vec![3, 64]);
+
+        a.union_with(&BlockSet::new(130));
+        assert_eq!(a.len(), 2);
+        assert_eq!(BlockSet::full(130).len(), 130);
+        a.remove_and_shift(10);
+        assert_eq!(a.iter().collect::<Vec<_>>(), vec![3, 63]);
+        a.resize(64);
+        assert_eq!(a.iter().collect::<Vec<_>>(), vec![3, 63]);
+        assert!(a.remove(3) && a.remove(63) && a.is_empty());
+    }
+}
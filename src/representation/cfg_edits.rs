@@ -0,0 +1,404 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::representation::{
+    program::{Code, EffectOp},
+    AbstractFunction, BasicBlock, BlockId, BlockSet, Label, PhiNode, Terminator,
+};
+
+// Edits to the CFG of a function that keep the edge sets, the label map and the phi nodes in
+// order, and update cached dominance information in place instead of leaving it to be
+// recomputed. New blocks are appended, so existing block ids stay valid except across a removal.
+
+impl AbstractFunction {
+    /// Split `block` before its instruction `at`. The instructions from `at` on and the
+    /// terminator move to a new block, which `block` jumps to; returns the new block's id.
+    pub fn split_block(&mut self, block: BlockId, at: usize) -> BlockId {
+        self.discard_stale_dominance();
+        let new = self.cfg.basic_blocks.len();
+        let label = self.fresh_label(&format!("{}_split", self.cfg.basic_blocks[block].label));
+        let old_label = self.cfg.basic_blocks[block].label.clone();
+
+        let original = &mut self.cfg.basic_blocks[block];
+        let instructions = original.instructions.split_off(at);
+        let terminator =
+            match std::mem::replace(&mut original.terminator, Terminator::jump(label.clone())) {
+                // the new block is appended, so falling through has to become a jump
+                Terminator::Passthrough if block + 1 < new => {
+                    Terminator::jump(self.cfg.basic_blocks[block + 1].label.clone())
+                }
+                terminator => terminator,
+            };
+        self.push_block(label.clone(), instructions, terminator);
+        // every run of the block goes on into the new one, which leaves along the old edges
+        let original = &mut self.cfg.basic_blocks[block];
+        let frequency = original.frequency;
+        let edges = std::mem::take(&mut original.edge_frequencies);
+        original
+            .edge_frequencies
+            .extend(frequency.map(|n| (label.clone(), n)));
+        self.cfg.basic_blocks[new].frequency = frequency;
+        self.cfg.basic_blocks[new].edge_frequencies = edges;
+
+        let successors = std::mem::replace(
+            &mut self.cfg.successors[block],
+            BlockSet::from_ids(new + 1, [new]),
+        );
+        for successor in &successors {
+            self.cfg.predecessors[successor].remove(block);
+            self.cfg.predecessors[successor].insert(new);
+            relabel_phi_args(&mut self.cfg.basic_blocks[successor], &old_label, &label);
+        }
+        self.cfg.successors[new] = successors;
+        self.cfg.predecessors[new].insert(block);
+
+        if let Some(dominance) = self.dominance.get_mut() {
+            dominance.split_block(&self.cfg, block, new);
+        }
+        new
+    }
+
+    /// Give `header`, a reachable block other than the entry, a block that every edge into it
+    /// from a block it does not dominate goes through; returns the preheader's id. Phi nodes of
+    /// the header with several incoming values from outside get their merge in the preheader.
+    pub fn insert_preheader(&mut self, header: BlockId) -> BlockId {
+        self.discard_stale_dominance();
+        let dominance = self.dominance_info();
+        assert!(header != 0 && dominance.get_immediate_dominator(header).is_some());
+
+        let preheader = self.cfg.basic_blocks.len();
+        let header_label = self.cfg.basic_blocks[header].label.clone();
+        let label = self.fresh_label(&format!("{}_preheader", header_label));
+        let outside: Vec<BlockId> = (self.cfg.predecessors[header].iter())
+            .filter(|&pred| !dominance.dominated_by(pred, header))
+            .collect();
+        let outside_labels: Vec<Label> = (outside.iter())
+            .map(|&pred| self.cfg.basic_blocks[pred].label.clone())
+            .collect();
+
+        // values coming from outside now come from the preheader, merged there if there are several
+        let mut phi_nodes = Vec::new();
+        for phi in &mut self.cfg.basic_blocks[header].phi_nodes {
+            let (from_outside, from_loop): (Vec<_>, Vec<_>) =
+                (phi.phi_args.drain(..)).partition(|(_, pred)| outside_labels.contains(pred));
+            phi.phi_args = from_loop;
+            let value = match from_outside.as_slice() {
+                [] => continue,
+                [(var, _)] => var.clone(),
+                _ => {
+                    let dest = format!("{}_{}", phi.dest, label);
+                    phi_nodes.push(PhiNode {
+                        dest: dest.clone(),
+                        original_name: phi.original_name.clone(),
+                        phi_type: phi.phi_type.clone(),
+                        phi_args: from_outside,
+                        pos: phi.pos,
+                    });
+                    dest
+                }
+            };
+            phi.phi_args.push((value, label.clone()));
+        }
+
+        for &pred in &outside {
+            let block = &mut self.cfg.basic_blocks[pred];
+            if matches!(block.terminator, Terminator::Passthrough) {
+                block.terminator = Terminator::jump(label.clone());
+            } else {
+                block.terminator.retarget(&header_label, &label);
+            }
+        }
+        self.push_block(label, Vec::new(), Terminator::jump(header_label));
+        self.cfg.basic_blocks[preheader].phi_nodes = phi_nodes;
+
+        for &pred in &outside {
+            self.cfg.successors[pred].remove(header);
+            self.cfg.successors[pred].insert(preheader);
+            self.cfg.predecessors[header].remove(pred);
+            self.cfg.predecessors[preheader].insert(pred);
+        }
+        self.cfg.successors[preheader].insert(header);
+        self.cfg.predecessors[header].insert(preheader);
+
+        let dominance = self.dominance.get_mut().expect("computed above");
+        dominance.insert_preheader(&self.cfg, header, preheader);
+        preheader
+    }
+
+    /// Remove `block`, which must not be the entry and must have no predecessors. Blocks after
+    /// it move down by one.
+    pub fn remove_unreachable_block(&mut self, block: BlockId) {
+        assert!(block != 0 && self.cfg.predecessors[block].is_empty());
+        self.discard_stale_dominance();
+
+        let removed = self.cfg.basic_blocks.remove(block);
+        for successor in &self.cfg.successors[block] {
+            let successor = if successor > block {
+                successor - 1
+            } else {
+                successor
+            };
+            if let Some(target) = self.cfg.basic_blocks.get_mut(successor) {
+                for phi in &mut target.phi_nodes {
+                    phi.phi_args.retain(|(_, pred)| *pred != removed.label);
+                }
+            }
+        }
+
+        self.cfg.successors.remove(block);
+        self.cfg.predecessors.remove(block);
+        for set in (self.cfg.successors.iter_mut()).chain(&mut self.cfg.predecessors) {
+            set.remove_and_shift(block);
+        }
+        for block in &mut self.cfg.basic_blocks[block..] {
+            block.id -= 1;
+        }
+        self.cfg.label_map.remove(&removed.label);
+        for (id, block) in self.cfg.basic_blocks.iter().enumerate().skip(block) {
+            self.cfg.label_map.insert(block.label.clone(), id);
+        }
+
+        if let Some(dominance) = self.dominance.get_mut() {
+            dominance.remove_block(&self.cfg, block);
+        }
+    }
+
+    /// `base`, or `base` with the smallest numeric suffix that no block uses yet
+    fn fresh_label(&self, base: &str) -> Label {
+        (0..)
+            .map(|i| match i {
+                0 => base.to_string(),
+                _ => format!("{}_{}", base, i),
+            })
+            .find(|label| !self.cfg.label_map.contains_key(label))
+            .unwrap()
+    }
+
+    /// Append a block without edges and grow the edge sets to match
+    fn push_block(&mut self, label: Label, instructions: Vec<Code>, terminator: Terminator) {
+        let id = self.cfg.basic_blocks.len();
+        // the old last block fell off the end of the function, which now has to be explicit
+        if let Some(last) = self.cfg.basic_blocks.last_mut() {
+            if matches!(last.terminator, Terminator::Passthrough) {
+                last.terminator = Terminator::Ret(effect(EffectOp::Ret, None));
+            }
+        }
+
+        self.cfg.label_map.insert(label.clone(), id);
+        self.cfg.basic_blocks.push(BasicBlock {
+            id,
+            label,
+            instructions,
+            terminator,
+            phi_nodes: Vec::new(),
+            preheader: Vec::new(),
+            natural_loop_return: false,
+            frequency: None,
+            edge_frequencies: HashMap::new(),
+        });
+        for set in (self.cfg.successors.iter_mut()).chain(&mut self.cfg.predecessors) {
+            set.resize(id + 1);
+        }
+        self.cfg.successors.push(BlockSet::new(id + 1));
+        self.cfg.predecessors.push(BlockSet::new(id + 1));
+    }
+}
+
+impl Terminator {
+    /// An unconditional jump to `label`
+    pub fn jump(label: Label) -> Self {
+        let code = effect(EffectOp::Jmp, Some(Box::new([label.clone()])));
+        Terminator::Jmp(label, code)
+    }
+
+    /// The terminator, with falling through to `fallthrough` spelled out as a jump, or as a
+    /// return from the last block
+    pub fn explicit(self, fallthrough: Option<Label>) -> Self {
+        match (self, fallthrough) {
+            (Terminator::Passthrough, Some(next)) => Terminator::jump(next),
+            (Terminator::Passthrough, None) => Terminator::Ret(effect(EffectOp::Ret, None)),
+            (terminator, _) => terminator,
+        }
+    }
+
+    /// The instruction that ends the block, `None` when it falls through
+    pub fn code(&self) -> Option<&Code> {
+        match self {
+            Terminator::Passthrough => None,
+            Terminator::Ret(code)
+            | Terminator::Jmp(_, code)
+            | Terminator::Br(_, _, code)
+            | Terminator::Speculate(_, code) => Some(code),
+        }
+    }
+
+    pub fn code_mut(&mut self) -> Option<&mut Code> {
+        match self {
+            Terminator::Passthrough => None,
+            Terminator::Ret(code)
+            | Terminator::Jmp(_, code)
+            | Terminator::Br(_, _, code)
+            | Terminator::Speculate(_, code) => Some(code),
+        }
+    }
+
+    /// Send every edge that goes to `label` to `target(label)` instead
+    pub fn map_targets(&mut self, target: impl Fn(&str) -> String) {
+        let (targets, code) = match self {
+            Terminator::Jmp(label, code) => (vec![label], code),
+            Terminator::Br(then, otherwise, code) => (vec![then, otherwise], code),
+            // where a speculation rolls back to is fixed by its guards
+            Terminator::Passthrough | Terminator::Ret(_) | Terminator::Speculate(..) => return,
+        };
+        for label in targets {
+            *label = target(label);
+        }
+        if let Code::Effect {
+            labels: Some(labels),
+            ..
+        } = code
+        {
+            for label in labels.iter_mut() {
+                *label = target(label);
+            }
+        }
+    }
+
+    /// Point the edges that go to `from` at `to`
+    pub fn retarget(&mut self, from: &str, to: &str) {
+        self.map_targets(|label| match label == from {
+            true => to.to_string(),
+            false => label.to_string(),
+        });
+    }
+}
+
+/// `base`, or `base` with the smallest numeric suffix not in `used`, which it is added to
+pub(crate) fn fresh_name(used: &mut HashSet<String>, base: &str) -> String {
+    let name = (0..)
+        .map(|i| match i {
+            0 => base.to_string(),
+            _ => format!("{}_{}", base, i),
+        })
+        .find(|name| !used.contains(name))
+        .unwrap();
+    used.insert(name.clone());
+    name
+}
+
+fn effect(op: EffectOp, labels: Option<Box<[String]>>) -> Code {
+    Code::Effect {
+        op,
+        args: None,
+        funcs: None,
+        labels,
+        pos: None,
+        pos_end: None,
+        src: None,
+    }
+}
+
+fn relabel_phi_args(block: &mut BasicBlock, from: &str, to: &str) {
+    for phi in &mut block.phi_nodes {
+        for (_, pred) in phi.phi_args.iter_mut().filter(|(_, pred)| pred == from) {
+            *pred = to.to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::verify_cfg,
+        representation::{parse_program, AbstractFunction, DominanceInfo, Terminator},
+    };
+
+    fn function(source: &str) -> AbstractFunction {
+        let program = parse_program(source).unwrap();
+        AbstractFunction::from(program.functions.into_iter().next().unwrap())
+    }
+
+    fn assert_consistent(af: &AbstractFunction) {
+        assert_eq!(verify_cfg(af), Vec::<String>::new());
+        let dominance = af
+            .dominance
+            .get()
+            .expect("edits keep the dominance information");
+        assert!(dominance.is_current(&af.cfg));
+        assert_eq!(*dominance, DominanceInfo::from(&af.cfg));
+    }
+
+    #[test]
+    fn edits_keep_dominance_up_to_date() {
+        let mut af = function(
+            r#"
+@main(n: int) {
+  i: int = const 0;
+  one: int = const 1;
+.header:
+  cond: bool = lt i n;
+  br cond .body .exit;
+.body:
+  i: int = add i one;
+  jmp .header;
+.exit:
+  print i;
+}
+"#,
+        );
+        let header = af.cfg.label_map["header"];
+        let preheader = af.insert_preheader(header);
+        assert_consistent(&af);
+        assert_eq!(
+            af.dominance_info().get_immediate_dominator(header),
+            Some(preheader)
+        );
+
+        let body = af.cfg.label_map["body"];
+        let tail = af.split_block(body, 1);
+        assert_consistent(&af);
+        assert_eq!(
+            af.dominance_info().get_immediate_dominator(tail),
+            Some(body)
+        );
+
+        // block 0 is the empty preamble
+        let split = af.split_block(1, 1);
+        assert_consistent(&af);
+        assert_eq!(
+            af.dominance_info().get_immediate_dominator(preheader),
+            Some(split)
+        );
+    }
+
+    #[test]
+    fn removes_blocks_without_predecessors() {
+        let mut af = function(
+            r#"
+@main(cond: bool) {
+  br cond .left .right;
+.left:
+  jmp .end;
+.right:
+  jmp .end;
+.end:
+  ret;
+}
+"#,
+        );
+        // fold the branch, leaving .right unreachable
+        let (left, right) = (af.cfg.label_map["left"], af.cfg.label_map["right"]);
+        af.cfg.basic_blocks[1].terminator = Terminator::jump("left".to_string());
+        af.cfg.successors[1].remove(right);
+        af.cfg.predecessors[right].remove(1);
+        af.discard_stale_dominance();
+        af.dominance_info();
+
+        af.remove_unreachable_block(right);
+        assert_consistent(&af);
+        assert!(matches!(
+            af.cfg.basic_blocks[left].terminator,
+            Terminator::Jmp(..)
+        ));
+        assert_eq!(af.cfg.label_map["end"], 3);
+    }
+}
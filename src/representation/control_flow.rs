@@ -1,14 +1,17 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+};
 
-use crate::representation::{BasicBlock, BlockId, Terminator};
+use crate::representation::{BasicBlock, BlockId, BlockSet, Terminator};
 
 /// module that represents control flow across basic blocks
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ControlFlowGraph {
     pub label_map: HashMap<String, BlockId>,
-    pub successors: Vec<HashSet<usize>>,
-    pub predecessors: Vec<HashSet<usize>>,
+    pub successors: Vec<BlockSet>,
+    pub predecessors: Vec<BlockSet>,
     pub basic_blocks: Vec<BasicBlock>,
 }
 
@@ -22,8 +25,9 @@ impl From<Vec<BasicBlock>> for ControlFlowGraph {
             .map(|block| (block.label.clone(), block.id))
             .collect();
 
-        let mut successors: Vec<HashSet<usize>> = vec![HashSet::new(); basic_blocks.len()];
-        let mut predecessors: Vec<HashSet<usize>> = vec![HashSet::new(); basic_blocks.len()];
+        let n = basic_blocks.len();
+        let mut successors = vec![BlockSet::new(n); n];
+        let mut predecessors = vec![BlockSet::new(n); n];
 
         for block in &basic_blocks {
             let parent = block.id;
@@ -32,21 +36,28 @@ impl From<Vec<BasicBlock>> for ControlFlowGraph {
                 Terminator::Ret(_) => vec![],
                 Terminator::Jmp(label, _) => vec![*label_map
                     .get(label)
-                    .expect(&format!("label {} not found", label))],
+                    .unwrap_or_else(|| panic!("label {} not found", label))],
                 Terminator::Br(label1, label2, _) => vec![
                     *label_map
                         .get(label1)
-                        .expect(&format!("label {} not found", label1)),
+                        .unwrap_or_else(|| panic!("label {} not found", label1)),
                     *label_map
                         .get(label2)
-                        .expect(&format!("label {} not found", label2)),
+                        .unwrap_or_else(|| panic!("label {} not found", label2)),
                 ],
+                Terminator::Speculate(rollbacks, _) => std::iter::once(parent + 1)
+                    .chain(rollbacks.iter().map(|label| {
+                        *label_map
+                            .get(label)
+                            .unwrap_or_else(|| panic!("label {} not found", label))
+                    }))
+                    .collect(),
             };
 
-            for &child in &children {
+            for child in children {
                 predecessors[child].insert(parent);
+                successors[parent].insert(child);
             }
-            successors[parent].extend(children);
         }
 
         ControlFlowGraph {
@@ -59,6 +70,13 @@ impl From<Vec<BasicBlock>> for ControlFlowGraph {
 }
 
 impl ControlFlowGraph {
+    /// hash of the edges, to tell when information computed from the graph is out of date
+    pub fn shape(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.successors.hash(&mut hasher);
+        hasher.finish()
+    }
+
     pub fn prune_unreachable_blocks(self) -> Self {
         let mut bb = self.basic_blocks;
 
@@ -66,7 +84,7 @@ impl ControlFlowGraph {
             return ControlFlowGraph::from(bb);
         }
 
-        let mut reachable = HashSet::new();
+        let mut reachable = BlockSet::new(bb.len());
         let mut stack = vec![bb.first().unwrap().id];
 
         while let Some(block_id) = stack.pop() {
@@ -74,12 +92,10 @@ impl ControlFlowGraph {
                 continue;
             }
 
-            for &succ in &self.successors[block_id] {
-                stack.push(succ);
-            }
+            stack.extend(&self.successors[block_id]);
         }
         let count_before = bb.len();
-        bb.retain(|b| reachable.contains(&b.id));
+        bb.retain(|b| reachable.contains(b.id));
         log::info!(
             "pruned {} unreachable blocks, {} remaining",
             count_before - bb.len(),
@@ -1,23 +1,34 @@
-use std::collections::HashSet;
+use crate::{
+    representation::{BlockId, BlockSet, ControlFlowGraph},
+    timing::Timer,
+};
 
-use crate::representation::{BlockId, ControlFlowGraph};
+// Blocks that cannot be reached from the entry are dominated by every block (the usual vacuous
+// definition), but have no immediate dominator and do not contribute to dominance frontiers.
+//
+// Passes that edit the CFG through `AbstractFunction::split_block`, `insert_preheader` and
+// `remove_unreachable_block` get the information updated in place. Otherwise it goes stale, which
+// `is_current` detects by the shape of the CFG it was computed for.
 
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct DominanceInfo {
-    dom: Vec<HashSet<usize>>,
+    dom: Vec<BlockSet>,
     tree: Vec<Option<usize>>,
-    tree_children: Vec<HashSet<usize>>,
-    df: Vec<HashSet<usize>>,
+    tree_children: Vec<BlockSet>,
+    df: Vec<BlockSet>,
+    /// `ControlFlowGraph::shape` of the graph this describes
+    shape: u64,
 }
 
 impl From<&ControlFlowGraph> for DominanceInfo {
     fn from(graph: &ControlFlowGraph) -> Self {
-        let dom_now = std::time::Instant::now();
-        let dom = DominanceInfo::dom_relationship(graph);
-        let tree = DominanceInfo::dom_tree(&dom);
+        let _timer = Timer::new("dominance");
+        let rpo = DominanceInfo::reverse_post_order(graph);
+        let reachable = BlockSet::from_ids(graph.successors.len(), rpo.iter().copied());
+        let dom = DominanceInfo::dom_relationship(graph, &rpo);
+        let tree = DominanceInfo::dom_tree(&dom, &reachable);
         let tree_children = tree.iter().enumerate().fold(
-            vec![HashSet::new(); tree.len()],
+            vec![BlockSet::new(tree.len()); tree.len()],
             |mut acc, (child, &parent)| {
                 if let Some(p) = parent {
                     acc[p].insert(child);
@@ -26,13 +37,13 @@ impl From<&ControlFlowGraph> for DominanceInfo {
             },
         );
 
-        let df = DominanceInfo::dom_frontier(&dom, graph);
-        log::debug!("computed dominance info in {:?}", dom_now.elapsed());
+        let df = DominanceInfo::dom_frontier(&dom, graph, &reachable);
         Self {
             dom,
             tree,
             tree_children,
             df,
+            shape: graph.shape(),
         }
     }
 }
@@ -48,7 +59,7 @@ impl DominanceInfo {
             }
             visited[curr] = true;
 
-            graph.successors[curr].iter().for_each(|&child| {
+            graph.successors[curr].iter().for_each(|child| {
                 dfs(child, graph, visited, po);
             });
 
@@ -59,35 +70,29 @@ impl DominanceInfo {
         post_order.reverse();
         post_order
     }
-    fn dom_relationship(graph: &ControlFlowGraph) -> Vec<HashSet<usize>> {
-        let rpo = DominanceInfo::reverse_post_order(graph);
+    fn dom_relationship(graph: &ControlFlowGraph, rpo: &[usize]) -> Vec<BlockSet> {
         let n = graph.successors.len();
 
         // init: all nodes
-        let mut dom: Vec<HashSet<usize>> = vec![(0..n).collect(); n];
+        let mut dom = vec![BlockSet::full(n); n];
         // entry only dominates itself
-        dom[0] = [0].iter().cloned().collect();
+        dom[0] = BlockSet::from_ids(n, [0]);
 
         let mut changed = true;
         while changed {
             changed = false;
 
-            for &vertex in &rpo {
+            for &vertex in rpo {
                 if vertex == 0 {
                     continue; // skip entry
                 }
 
                 // start with "all nodes" and intersect with preds
-                let mut new_set: Option<HashSet<usize>> = None;
-                for &pred in &graph.predecessors[vertex] {
-                    let s = dom[pred].clone();
-                    new_set = Some(match new_set {
-                        None => s,
-                        Some(acc) => &acc & &s,
-                    });
+                let mut new_dom = BlockSet::full(n);
+                for pred in &graph.predecessors[vertex] {
+                    new_dom.intersect_with(&dom[pred]);
                 }
 
-                let mut new_dom = new_set.unwrap_or_else(|| (0..n).collect());
                 new_dom.insert(vertex);
 
                 if new_dom != dom[vertex] {
@@ -99,13 +104,13 @@ impl DominanceInfo {
 
         dom
     }
-    fn dom_tree(dom: &Vec<HashSet<usize>>) -> Vec<Option<usize>> {
+    fn dom_tree(dom: &[BlockSet], reachable: &BlockSet) -> Vec<Option<usize>> {
         let n = dom.len();
         let mut tree = vec![None; n];
 
-        for id in 0..n {
+        for id in reachable {
             // strict dominators = dom[id] \ {id}
-            let strict: Vec<_> = dom[id].iter().copied().filter(|&d| d != id).collect();
+            let strict: Vec<_> = dom[id].iter().filter(|&d| d != id).collect();
 
             if !strict.is_empty() {
                 // immediate dominator = the strict dominator that is not dominated by any other
@@ -114,7 +119,7 @@ impl DominanceInfo {
                     .find(|&&d| {
                         strict
                             .iter()
-                            .all(|&other| other == d || !dom[other].contains(&d))
+                            .all(|&other| other == d || !dom[other].contains(d))
                     })
                     .unwrap();
 
@@ -124,20 +129,27 @@ impl DominanceInfo {
 
         tree
     }
-    fn dom_frontier(dom: &Vec<HashSet<usize>>, graph: &ControlFlowGraph) -> Vec<HashSet<usize>> {
-        let mut df = vec![HashSet::new(); dom.len()];
+    fn dom_frontier(
+        dom: &[BlockSet],
+        graph: &ControlFlowGraph,
+        reachable: &BlockSet,
+    ) -> Vec<BlockSet> {
+        let mut df = vec![BlockSet::new(dom.len()); dom.len()];
 
         // A's **domination frontier** contains B if A does not dominate B, but A dominates a predecessor, P, of B
         for b in 0..dom.len() {
             log::trace!("fixing B = {} dominated by {:?}", b, dom[b]);
-            for &p in &graph.predecessors[b] {
+            for p in graph.predecessors[b]
+                .iter()
+                .filter(|&p| reachable.contains(p))
+            {
                 let all_a = &dom[p];
                 log::trace!("\tchecking pred P = {} dominated by A={:?}", p, all_a);
 
                 // a by definition, dominates a predecessor of P
-                for &a in all_a.iter() {
+                for a in all_a {
                     // a must not dominate b
-                    if !dom[b].contains(&a) || a == b {
+                    if !dom[b].contains(a) || a == b {
                         log::trace!("\t\tDF(A={}) += {}", a, b);
                         df[a].insert(b);
                     }
@@ -148,16 +160,150 @@ impl DominanceInfo {
         df
     }
     /// return block ids that are in the dominance frontier of the given block iod
-    pub fn get_dominance_frontier(&self, block_id: BlockId) -> &HashSet<usize> {
+    pub fn get_dominance_frontier(&self, block_id: BlockId) -> &BlockSet {
         &self.df[block_id]
     }
     /// return the block ids that are immediately dominated by the given block id
-    pub fn get_immediate_dominated(&self, block_id: BlockId) -> &HashSet<usize> {
+    pub fn get_immediate_dominated(&self, block_id: BlockId) -> &BlockSet {
         &self.tree_children[block_id]
     }
 
     /// Check if block `a` is dominated by block `b`
     pub fn dominated_by(&self, a: BlockId, b: BlockId) -> bool {
-        self.dom[a].contains(&b)
+        self.dom[a].contains(b)
+    }
+
+    /// return the immediate dominator of the given block id, `None` for the entry block
+    pub fn get_immediate_dominator(&self, block_id: BlockId) -> Option<BlockId> {
+        self.tree[block_id]
+    }
+
+    /// whether this still describes `graph`, i.e. no edge was added or removed since
+    pub fn is_current(&self, graph: &ControlFlowGraph) -> bool {
+        self.shape == graph.shape() && self.dom.len() == graph.basic_blocks.len()
+    }
+
+    fn is_reachable(&self, block: BlockId) -> bool {
+        block == 0 || self.tree[block].is_some()
+    }
+
+    /// make room for one more block, which starts out unreachable
+    fn grow(&mut self) -> BlockId {
+        let n = self.dom.len() + 1;
+        for set in self
+            .dom
+            .iter_mut()
+            .chain(&mut self.tree_children)
+            .chain(&mut self.df)
+        {
+            set.resize(n);
+        }
+        // unreachable blocks are dominated by everything, including the new block
+        let unreachable: Vec<_> = (0..n - 1).filter(|&b| !self.is_reachable(b)).collect();
+        for block in unreachable {
+            self.dom[block].insert(n - 1);
+        }
+        self.dom.push(BlockSet::full(n));
+        self.tree.push(None);
+        self.tree_children.push(BlockSet::new(n));
+        self.df.push(BlockSet::new(n));
+        n - 1
+    }
+
+    /// Update after `block` was split: `graph` now has a new last block, `new`, that took over
+    /// the end of `block` with its out edges, and `block` only jumps to it
+    pub fn split_block(&mut self, graph: &ControlFlowGraph, block: BlockId, new: BlockId) {
+        assert_eq!(self.grow(), new);
+        if self.is_reachable(block) {
+            // everything `block` dominated is reached through `new`
+            for (id, dom) in self.dom.iter_mut().enumerate() {
+                if id != block && dom.contains(block) {
+                    dom.insert(new);
+                }
+            }
+            self.dom[new] = self.dom[block].clone();
+            self.dom[new].insert(new);
+
+            let children = std::mem::replace(
+                &mut self.tree_children[block],
+                BlockSet::from_ids(new + 1, [new]),
+            );
+            for child in &children {
+                self.tree[child] = Some(new);
+            }
+            self.tree_children[new] = children;
+            self.tree[new] = Some(block);
+
+            // the edges leaving `block` now leave `new`, which dominates what `block` did
+            self.df[new] = self.df[block].clone();
+        }
+        self.shape = graph.shape();
+    }
+
+    /// Update after `preheader`, a new last block of `graph`, was placed in front of `header`:
+    /// every edge into `header` from a block it does not dominate now goes to `preheader`, which
+    /// jumps to `header`
+    pub fn insert_preheader(
+        &mut self,
+        graph: &ControlFlowGraph,
+        header: BlockId,
+        preheader: BlockId,
+    ) {
+        assert_eq!(self.grow(), preheader);
+        assert!(header != 0 && self.is_reachable(header));
+        let in_loop = |dom: &BlockSet| dom.contains(header);
+
+        for dom in self.dom.iter_mut().filter(|dom| in_loop(dom)) {
+            dom.insert(preheader);
+        }
+        let mut dom = self.dom[header].clone();
+        dom.remove(header);
+        self.dom[preheader] = dom;
+
+        // the preheader takes the place of the header in the tree
+        let parent = self.tree[header].expect("reachable blocks other than the entry have an idom");
+        self.tree_children[parent].remove(header);
+        self.tree_children[parent].insert(preheader);
+        self.tree[preheader] = Some(parent);
+        self.tree[header] = Some(preheader);
+        self.tree_children[preheader].insert(header);
+
+        // edges from outside the loop now end in the preheader, the back edges still end in the
+        // header
+        let mut frontier = self.df[header].clone();
+        frontier.remove(header);
+        self.df[preheader] = frontier;
+        for block in (0..preheader).filter(|&block| !in_loop(&self.dom[block])) {
+            if self.df[block].remove(header) {
+                self.df[block].insert(preheader);
+            }
+        }
+        self.shape = graph.shape();
+    }
+
+    /// Update after `block`, which had no predecessors, was removed from `graph` and the blocks
+    /// after it moved down by one
+    pub fn remove_block(&mut self, graph: &ControlFlowGraph, block: BlockId) {
+        assert!(block != 0 && !self.is_reachable(block));
+        // an unreachable block dominates nothing reachable and is in no frontier, so removing it
+        // only renumbers
+        self.dom.remove(block);
+        self.tree.remove(block);
+        self.tree_children.remove(block);
+        self.df.remove(block);
+        for set in self
+            .dom
+            .iter_mut()
+            .chain(&mut self.tree_children)
+            .chain(&mut self.df)
+        {
+            set.remove_and_shift(block);
+        }
+        for parent in self.tree.iter_mut().flatten() {
+            if *parent > block {
+                *parent -= 1;
+            }
+        }
+        self.shape = graph.shape();
     }
 }
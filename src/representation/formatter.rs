@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use crate::representation::{
+    format_instruction, format_type, parse_program, Function, ParseError, Position,
+};
+
+// Canonical layout of Bril source text for `rust_bril fmt`: labels in the first column, everything
+// else indented by two spaces, single spaces between operands, one instruction per line and one
+// blank line between functions. Comments stay where they were and runs of blank lines inside a
+// function shrink to one
+
+/// the comment of each line that has one, and whether it is the only thing on its line
+struct Comments {
+    by_row: HashMap<u32, (String, bool)>,
+    blank: Vec<bool>,
+}
+
+impl Comments {
+    fn scan(text: &str) -> Self {
+        let mut by_row = HashMap::new();
+        let mut blank = vec![true];
+        for (i, line) in text.lines().enumerate() {
+            blank.push(line.trim().is_empty());
+            if let Some(start) = comment_start(line) {
+                let comment = line[start..].trim_end().to_string();
+                by_row.insert(i as u32 + 1, (comment, line[..start].trim().is_empty()));
+            }
+        }
+        Self { by_row, blank }
+    }
+
+    fn is_blank(&self, row: u32) -> bool {
+        self.blank.get(row as usize).copied().unwrap_or(false)
+    }
+
+    /// full-line comments and (collapsed) blank lines of rows `from..to`, at `indent`
+    fn between(&mut self, from: u32, to: u32, indent: &str, keep_blank: bool, out: &mut String) {
+        let mut blank_pending = false;
+        for row in from..to {
+            if self.is_blank(row) {
+                blank_pending = keep_blank;
+                continue;
+            }
+            if let Some((comment, true)) = self.by_row.remove(&row) {
+                if blank_pending && !out.ends_with("{\n") {
+                    out.push('\n');
+                }
+                blank_pending = false;
+                out.push_str(&format!("{}{}\n", indent, comment));
+            }
+        }
+        if blank_pending && !out.ends_with("{\n") {
+            out.push('\n');
+        }
+    }
+
+    /// ` # comment` if `row` ends with a comment after code
+    fn trailing(&mut self, row: u32) -> String {
+        match self.by_row.get(&row) {
+            Some((_, false)) => format!(" {}", self.by_row.remove(&row).unwrap().0),
+            _ => String::new(),
+        }
+    }
+}
+
+/// byte offset of the `#` starting a comment, skipping `'#'` character literals
+fn comment_start(line: &str) -> Option<usize> {
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '#' => return Some(i),
+            '\'' => {
+                if let Some((_, '\\')) = chars.next() {
+                    chars.next();
+                }
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn row(pos: Option<Position>) -> Option<u32> {
+    pos.map(|pos| pos.row)
+}
+
+pub(crate) fn function_header(function: &Function) -> String {
+    let mut header = format!("@{}", function.name);
+    if let Some(args) = function.args.as_ref().filter(|args| !args.is_empty()) {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| format!("{}: {}", arg.name, format_type(&arg.arg_type)))
+            .collect();
+        header.push_str(&format!("({})", args.join(", ")));
+    }
+    if let Some(return_type) = &function.return_type {
+        header.push_str(&format!(": {}", format_type(return_type)));
+    }
+    header
+}
+
+/// Reformat Bril source text in the canonical layout, keeping its comments
+pub fn format_source(text: &str) -> Result<String, ParseError> {
+    let program = parse_program(text)?;
+    let mut comments = Comments::scan(text);
+    let mut out = String::new();
+    let mut last_row = 0;
+
+    for (i, function) in program.functions.iter().enumerate() {
+        let start = row(function.pos).unwrap_or(last_row + 1);
+        if i > 0 {
+            out.push('\n');
+        }
+        comments.between(last_row + 1, start, "", false, &mut out);
+        out.push_str(&format!("{} {{", function_header(function)));
+        out.push_str(&comments.trailing(start));
+        out.push('\n');
+        last_row = start;
+
+        for (j, code) in function.instrs.iter().enumerate() {
+            let (code_start, code_end) = match code.get_position() {
+                Some(pos) => (pos.row, row(code.get_position_end()).unwrap_or(pos.row)),
+                None => (last_row, last_row),
+            };
+            let indent = if code.is_label() { "" } else { "  " };
+            comments.between(last_row + 1, code_start, "  ", true, &mut out);
+            out.push_str(&format!("{}{}", indent, format_instruction(code)));
+            // several instructions on one line: the comment goes after the last of them
+            let next_start = function.instrs.get(j + 1).and_then(|c| c.get_position());
+            if next_start.is_none_or(|next| next.row != code_end) {
+                out.push_str(&comments.trailing(code_end));
+            }
+            out.push('\n');
+            last_row = last_row.max(code_end);
+        }
+
+        let end = row(function.pos_end).unwrap_or(last_row);
+        comments.between(last_row + 1, end, "  ", false, &mut out);
+        out.push('}');
+        out.push_str(&comments.trailing(end));
+        out.push('\n');
+        last_row = last_row.max(end);
+    }
+
+    let rows = text.lines().count() as u32;
+    if !program.functions.is_empty() && comments.by_row.keys().any(|&row| row > last_row) {
+        out.push('\n');
+    }
+    comments.between(last_row + 1, rows + 1, "", false, &mut out);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_comments_in_canonical_layout() {
+        let text = "# header\n\n\n@main(n:int) { # entry\n    x:int=const 1; y: int = id x;  # two\n\n\n  # before the loop\n   .loop:\n  print   x  ;\n}\n# footer\n";
+        let formatted = format_source(text).unwrap();
+        assert_eq!(
+            formatted,
+            "# header\n@main(n: int) { # entry\n  x: int = const 1;\n  y: int = id x; # two\n\n  # before the loop\n.loop:\n  print x;\n}\n\n# footer\n"
+        );
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+        assert_eq!(comment_start("c: char = const '#'; # hash"), Some(21));
+    }
+}
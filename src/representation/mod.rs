@@ -1,11 +1,28 @@
 mod abstract_program;
+mod block_set;
+mod cfg_edits;
 mod control_flow;
 mod dominance;
+mod formatter;
+mod opcodes;
+mod parser;
 mod phi_nodes;
+mod printer;
 mod program;
+mod tool_cache;
+mod validate;
 
 pub use abstract_program::*;
+pub use block_set::*;
 pub use control_flow::*;
 pub use dominance::*;
+pub use formatter::*;
+pub use opcodes::*;
+pub use parser::*;
 pub use phi_nodes::*;
+pub use printer::*;
 pub use program::*;
+pub use tool_cache::*;
+pub use validate::*;
+
+pub(crate) use cfg_edits::fresh_name;
@@ -0,0 +1,389 @@
+use std::sync::OnceLock;
+
+use crate::representation::{Literal, Type, ValueOp};
+
+// The value operations of each Bril extension: what they take, what they produce, whether they
+// commute or can fail, and how to compute them. The type checker, the purity analysis, lvn,
+// constprop, unrolling and the interpreter look operations up here instead of matching on
+// `ValueOp`, so an extension is a `ValueOp` variant per operation plus a table in this file.
+// `id`, `call` and `phi` are not in any table: what they take depends on the program.
+
+/// What the passes know about one value operation
+#[derive(Debug)]
+pub struct OpcodeInfo {
+    pub op: ValueOp,
+    /// the opcode in Bril programs
+    pub name: &'static str,
+    pub operands: &'static [Type],
+    pub result: Type,
+    /// whether the operands can be swapped
+    pub commutative: bool,
+    /// whether it never fails at run time, so that running it where the program would not have
+    /// changes nothing
+    pub pure: bool,
+    /// the result for operands of the types in `operands`, or why it fails at run time
+    pub eval: fn(&[Literal]) -> Result<Literal, String>,
+}
+
+/// A named set of operations, e.g. the float extension
+#[derive(Debug)]
+pub struct Extension {
+    pub name: &'static str,
+    pub opcodes: &'static [OpcodeInfo],
+}
+
+impl OpcodeInfo {
+    /// `self` on `literals`, `None` if it fails or they do not fit the operands. lvn keeps the
+    /// integer literal of a `const 1` of type float, so ints pass for floats
+    pub fn fold(&self, literals: &[Literal]) -> Option<Literal> {
+        if literals.len() != self.operands.len() {
+            return None;
+        }
+        let mut cast = Vec::with_capacity(literals.len());
+        for (literal, t) in literals.iter().zip(self.operands) {
+            cast.push(match (literal, t) {
+                (Literal::Int(x), Type::Float) => Literal::Float(*x as f64),
+                (literal, t) if literal_type(literal) == *t => *literal,
+                _ => return None,
+            });
+        }
+        (self.eval)(&cast).ok()
+    }
+}
+
+/// Every extension this build knows, core first
+pub fn extensions() -> impl Iterator<Item = &'static Extension> {
+    [&CORE, &FLOAT, &CHAR].into_iter().chain(bit_operations())
+}
+
+/// The entry of `op`, `None` for `id`, `call` and `phi`
+pub fn opcode(op: ValueOp) -> Option<&'static OpcodeInfo> {
+    static BY_OP: OnceLock<Vec<Option<&'static OpcodeInfo>>> = OnceLock::new();
+    let by_op = BY_OP.get_or_init(|| {
+        let mut by_op = Vec::new();
+        for info in extensions().flat_map(|e| e.opcodes) {
+            let index = info.op as usize;
+            if by_op.len() <= index {
+                by_op.resize(index + 1, None);
+            }
+            by_op[index] = Some(info);
+        }
+        by_op
+    });
+    by_op.get(op as usize).copied().flatten()
+}
+
+/// The entry of the operation called `name` in Bril programs
+pub fn opcode_named(name: &str) -> Option<&'static OpcodeInfo> {
+    extensions()
+        .flat_map(|e| e.opcodes)
+        .find(|info| info.name == name)
+}
+
+pub fn literal_type(literal: &Literal) -> Type {
+    match literal {
+        Literal::Int(_) => Type::Int,
+        Literal::Bool(_) => Type::Bool,
+        Literal::Float(_) => Type::Float,
+        Literal::Char(_) => Type::Char,
+    }
+}
+
+fn int(args: &[Literal], i: usize) -> i64 {
+    match args[i] {
+        Literal::Int(x) => x,
+        other => unreachable!("{:?} is not an int", other),
+    }
+}
+
+fn float(args: &[Literal], i: usize) -> f64 {
+    match args[i] {
+        Literal::Float(x) => x,
+        other => unreachable!("{:?} is not a float", other),
+    }
+}
+
+fn boolean(args: &[Literal], i: usize) -> bool {
+    match args[i] {
+        Literal::Bool(b) => b,
+        other => unreachable!("{:?} is not a bool", other),
+    }
+}
+
+fn character(args: &[Literal], i: usize) -> char {
+    match args[i] {
+        Literal::Char(c) => c,
+        other => unreachable!("{:?} is not a char", other),
+    }
+}
+
+/// `op`, which takes `operands`: it does not commute and never fails
+const fn entry(
+    op: ValueOp,
+    name: &'static str,
+    operands: &'static [Type],
+    result: Type,
+    eval: fn(&[Literal]) -> Result<Literal, String>,
+) -> OpcodeInfo {
+    OpcodeInfo {
+        op,
+        name,
+        operands,
+        result,
+        commutative: false,
+        pure: true,
+        eval,
+    }
+}
+
+impl OpcodeInfo {
+    const fn commutative(mut self) -> Self {
+        self.commutative = true;
+        self
+    }
+
+    const fn fails(mut self) -> Self {
+        self.pure = false;
+        self
+    }
+}
+
+const INTS: &[Type] = &[Type::Int, Type::Int];
+const BOOLS: &[Type] = &[Type::Bool, Type::Bool];
+const FLOATS: &[Type] = &[Type::Float, Type::Float];
+const CHARS: &[Type] = &[Type::Char, Type::Char];
+
+pub static CORE: Extension = Extension {
+    name: "core",
+    opcodes: &[
+        entry(ValueOp::Add, "add", INTS, Type::Int, |x| {
+            let (a, b) = (int(x, 0), int(x, 1));
+            Ok(Literal::Int(a.wrapping_add(b)))
+        })
+        .commutative(),
+        entry(ValueOp::Sub, "sub", INTS, Type::Int, |x| {
+            let (a, b) = (int(x, 0), int(x, 1));
+            Ok(Literal::Int(a.wrapping_sub(b)))
+        }),
+        entry(ValueOp::Mul, "mul", INTS, Type::Int, |x| {
+            let (a, b) = (int(x, 0), int(x, 1));
+            Ok(Literal::Int(a.wrapping_mul(b)))
+        })
+        .commutative(),
+        entry(ValueOp::Div, "div", INTS, Type::Int, |x| {
+            match (int(x, 0), int(x, 1)) {
+                (_, 0) => Err("division by zero".to_string()),
+                (a, b) => Ok(Literal::Int(a.wrapping_div(b))),
+            }
+        })
+        .fails(),
+        entry(ValueOp::Eq, "eq", INTS, Type::Bool, |x| {
+            let (a, b) = (int(x, 0), int(x, 1));
+            Ok(Literal::Bool(a == b))
+        })
+        .commutative(),
+        entry(ValueOp::Lt, "lt", INTS, Type::Bool, |x| {
+            let (a, b) = (int(x, 0), int(x, 1));
+            Ok(Literal::Bool(a < b))
+        }),
+        entry(ValueOp::Gt, "gt", INTS, Type::Bool, |x| {
+            let (a, b) = (int(x, 0), int(x, 1));
+            Ok(Literal::Bool(a > b))
+        }),
+        entry(ValueOp::Le, "le", INTS, Type::Bool, |x| {
+            let (a, b) = (int(x, 0), int(x, 1));
+            Ok(Literal::Bool(a <= b))
+        }),
+        entry(ValueOp::Ge, "ge", INTS, Type::Bool, |x| {
+            let (a, b) = (int(x, 0), int(x, 1));
+            Ok(Literal::Bool(a >= b))
+        }),
+        entry(ValueOp::Not, "not", &[Type::Bool], Type::Bool, |x| {
+            Ok(Literal::Bool(!boolean(x, 0)))
+        }),
+        entry(ValueOp::And, "and", BOOLS, Type::Bool, |x| {
+            let (a, b) = (boolean(x, 0), boolean(x, 1));
+            Ok(Literal::Bool(a && b))
+        })
+        .commutative(),
+        entry(ValueOp::Or, "or", BOOLS, Type::Bool, |x| {
+            let (a, b) = (boolean(x, 0), boolean(x, 1));
+            Ok(Literal::Bool(a || b))
+        })
+        .commutative(),
+    ],
+};
+
+pub static FLOAT: Extension = Extension {
+    name: "float",
+    opcodes: &[
+        entry(ValueOp::Fadd, "fadd", FLOATS, Type::Float, |x| {
+            let (a, b) = (float(x, 0), float(x, 1));
+            Ok(Literal::Float(a + b))
+        })
+        .commutative(),
+        entry(ValueOp::Fsub, "fsub", FLOATS, Type::Float, |x| {
+            let (a, b) = (float(x, 0), float(x, 1));
+            Ok(Literal::Float(a - b))
+        }),
+        entry(ValueOp::Fmul, "fmul", FLOATS, Type::Float, |x| {
+            let (a, b) = (float(x, 0), float(x, 1));
+            Ok(Literal::Float(a * b))
+        })
+        .commutative(),
+        entry(ValueOp::Fdiv, "fdiv", FLOATS, Type::Float, |x| {
+            let (a, b) = (float(x, 0), float(x, 1));
+            Ok(Literal::Float(a / b))
+        }),
+        entry(ValueOp::Feq, "feq", FLOATS, Type::Bool, |x| {
+            let (a, b) = (float(x, 0), float(x, 1));
+            Ok(Literal::Bool(a == b))
+        })
+        .commutative(),
+        entry(ValueOp::Flt, "flt", FLOATS, Type::Bool, |x| {
+            let (a, b) = (float(x, 0), float(x, 1));
+            Ok(Literal::Bool(a < b))
+        }),
+        entry(ValueOp::Fgt, "fgt", FLOATS, Type::Bool, |x| {
+            let (a, b) = (float(x, 0), float(x, 1));
+            Ok(Literal::Bool(a > b))
+        }),
+        entry(ValueOp::Fle, "fle", FLOATS, Type::Bool, |x| {
+            let (a, b) = (float(x, 0), float(x, 1));
+            Ok(Literal::Bool(a <= b))
+        }),
+        entry(ValueOp::Fge, "fge", FLOATS, Type::Bool, |x| {
+            let (a, b) = (float(x, 0), float(x, 1));
+            Ok(Literal::Bool(a >= b))
+        }),
+        entry(
+            ValueOp::Float2bits,
+            "float2bits",
+            &[Type::Float],
+            Type::Int,
+            |x| Ok(Literal::Int(float(x, 0).to_bits() as i64)),
+        ),
+        entry(
+            ValueOp::Bits2float,
+            "bits2float",
+            &[Type::Int],
+            Type::Float,
+            |x| Ok(Literal::Float(f64::from_bits(int(x, 0) as u64))),
+        ),
+    ],
+};
+
+pub static CHAR: Extension = Extension {
+    name: "char",
+    opcodes: &[
+        entry(ValueOp::Ceq, "ceq", CHARS, Type::Bool, |x| {
+            let (a, b) = (character(x, 0), character(x, 1));
+            Ok(Literal::Bool(a == b))
+        })
+        .commutative(),
+        entry(ValueOp::Clt, "clt", CHARS, Type::Bool, |x| {
+            let (a, b) = (character(x, 0), character(x, 1));
+            Ok(Literal::Bool(a < b))
+        }),
+        entry(ValueOp::Cle, "cle", CHARS, Type::Bool, |x| {
+            let (a, b) = (character(x, 0), character(x, 1));
+            Ok(Literal::Bool(a <= b))
+        }),
+        entry(ValueOp::Cgt, "cgt", CHARS, Type::Bool, |x| {
+            let (a, b) = (character(x, 0), character(x, 1));
+            Ok(Literal::Bool(a > b))
+        }),
+        entry(ValueOp::Cge, "cge", CHARS, Type::Bool, |x| {
+            let (a, b) = (character(x, 0), character(x, 1));
+            Ok(Literal::Bool(a >= b))
+        }),
+        entry(
+            ValueOp::Char2int,
+            "char2int",
+            &[Type::Char],
+            Type::Int,
+            |x| Ok(Literal::Int(character(x, 0) as i64)),
+        ),
+        entry(
+            ValueOp::Int2char,
+            "int2char",
+            &[Type::Int],
+            Type::Char,
+            |x| {
+                let x = int(x, 0);
+                (u32::try_from(x).ok().and_then(char::from_u32))
+                    .map(Literal::Char)
+                    .ok_or_else(|| format!("{} is not a character", x))
+            },
+        )
+        .fails(),
+    ],
+};
+
+/// Integer bit operations, not part of standard Bril. Shifts take the amount modulo 64, and
+/// `shr` keeps the sign
+#[cfg(feature = "bitops")]
+pub static BITOPS: Extension = Extension {
+    name: "bitops",
+    opcodes: &[
+        entry(ValueOp::Shl, "shl", INTS, Type::Int, |x| {
+            let (a, b) = (int(x, 0), int(x, 1));
+            Ok(Literal::Int(a.wrapping_shl(b as u32)))
+        }),
+        entry(ValueOp::Shr, "shr", INTS, Type::Int, |x| {
+            let (a, b) = (int(x, 0), int(x, 1));
+            Ok(Literal::Int(a.wrapping_shr(b as u32)))
+        }),
+        entry(ValueOp::Bitand, "bitand", INTS, Type::Int, |x| {
+            let (a, b) = (int(x, 0), int(x, 1));
+            Ok(Literal::Int(a & b))
+        })
+        .commutative(),
+        entry(ValueOp::Bitor, "bitor", INTS, Type::Int, |x| {
+            let (a, b) = (int(x, 0), int(x, 1));
+            Ok(Literal::Int(a | b))
+        })
+        .commutative(),
+        entry(ValueOp::Bitxor, "bitxor", INTS, Type::Int, |x| {
+            let (a, b) = (int(x, 0), int(x, 1));
+            Ok(Literal::Int(a ^ b))
+        })
+        .commutative(),
+    ],
+};
+
+#[cfg(feature = "bitops")]
+fn bit_operations() -> Option<&'static Extension> {
+    Some(&BITOPS)
+}
+
+#[cfg(not(feature = "bitops"))]
+fn bit_operations() -> Option<&'static Extension> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_operation_has_one_entry_named_like_its_opcode() {
+        let mut names = std::collections::HashSet::new();
+        for info in extensions().flat_map(|e| e.opcodes) {
+            let serialized = serde_json::to_value(info.op).unwrap();
+            assert_eq!(serialized, info.name);
+            assert!(names.insert(info.name), "{} is listed twice", info.name);
+            assert!(std::ptr::eq(opcode(info.op).unwrap(), info));
+            assert!(std::ptr::eq(opcode_named(info.name).unwrap(), info));
+        }
+        assert!(opcode(ValueOp::Id).is_none() && opcode(ValueOp::Phi).is_none());
+
+        let div = opcode(ValueOp::Div).unwrap();
+        assert_eq!((div.eval)(&[Literal::Int(7), Literal::Int(0)]).ok(), None);
+        // the float operands of lvn may still be integer literals
+        let sum = opcode(ValueOp::Fadd)
+            .unwrap()
+            .fold(&[Literal::Int(1), Literal::Float(0.5)]);
+        assert!(matches!(sum, Some(Literal::Float(x)) if x == 1.5));
+    }
+}
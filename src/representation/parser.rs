@@ -0,0 +1,649 @@
+use std::{iter::Peekable, str::Chars};
+
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::representation::{
+    Argument, Code, ConstantOp, EffectOp, Function, Literal, MemoryOp, Noop, Position, Program,
+    Type, ValueOp,
+};
+
+/// module that parses the Bril text format into a `Program`, mirroring `bril2json -p`
+
+#[derive(Error, Debug, Clone)]
+#[error("line {}, column {}: {reason}", position.row, position.col)]
+pub struct ParseError {
+    pub reason: String,
+    pub position: Position,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Func(String),
+    Label(String),
+    Int(i64),
+    Float(f64),
+    Char(char),
+    Punct(char),
+}
+
+#[derive(Debug, Clone)]
+struct Spanned {
+    token: Token,
+    position: Position,
+    /// position just past the last character of the token
+    end: Position,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == '%'
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '%' || c == '.'
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    row: u32,
+    col: u32,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            chars: text.chars().peekable(),
+            row: 1,
+            col: 1,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.row += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> String {
+        let mut s = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !pred(c) {
+                break;
+            }
+            s.push(c);
+            self.bump();
+        }
+        s
+    }
+
+    fn error(&self, reason: impl Into<String>, position: Position) -> ParseError {
+        ParseError {
+            reason: reason.into(),
+            position,
+        }
+    }
+
+    fn number(&mut self, position: Position) -> Result<Token, ParseError> {
+        let mut text = String::new();
+        if self.chars.peek() == Some(&'-') || self.chars.peek() == Some(&'+') {
+            text.push(self.bump().unwrap());
+        }
+        text.push_str(&self.take_while(|c| c.is_ascii_digit() || c == '.'));
+
+        // exponent, e.g. 1e-5
+        if matches!(self.chars.peek(), Some('e') | Some('E')) {
+            text.push(self.bump().unwrap());
+            if matches!(self.chars.peek(), Some('-') | Some('+')) {
+                text.push(self.bump().unwrap());
+            }
+            text.push_str(&self.take_while(|c| c.is_ascii_digit()));
+        }
+
+        if text.contains(['.', 'e', 'E']) {
+            text.parse::<f64>()
+                .map(Token::Float)
+                .map_err(|_| self.error(format!("invalid float literal '{}'", text), position))
+        } else {
+            text.parse::<i64>()
+                .map(Token::Int)
+                .map_err(|_| self.error(format!("invalid integer literal '{}'", text), position))
+        }
+    }
+
+    fn char_literal(&mut self, position: Position) -> Result<Token, ParseError> {
+        self.bump(); // opening quote
+        let c = match self.bump() {
+            Some('\\') => match self.bump() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '\'')) => c,
+                other => {
+                    return Err(self.error(format!("invalid escape {:?}", other), position));
+                }
+            },
+            Some(c) => c,
+            None => return Err(self.error("unterminated character literal", position)),
+        };
+        if self.bump() != Some('\'') {
+            return Err(self.error("unterminated character literal", position));
+        }
+        Ok(Token::Char(c))
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Spanned>, ParseError> {
+        let mut tokens = Vec::new();
+
+        while let Some(&c) = self.chars.peek() {
+            let position = Position {
+                row: self.row,
+                col: self.col,
+            };
+
+            let token = match c {
+                c if c.is_whitespace() => {
+                    self.bump();
+                    continue;
+                }
+                '#' => {
+                    self.take_while(|c| c != '\n');
+                    continue;
+                }
+                '@' => {
+                    self.bump();
+                    Token::Func(self.take_while(is_ident_continue))
+                }
+                '.' => {
+                    let mut lookahead = self.chars.clone();
+                    lookahead.next();
+                    if lookahead.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        self.number(position)?
+                    } else {
+                        self.bump();
+                        Token::Label(self.take_while(is_ident_continue))
+                    }
+                }
+                '\'' => self.char_literal(position)?,
+                c if c.is_ascii_digit() || c == '-' || c == '+' => self.number(position)?,
+                c if is_ident_start(c) => Token::Ident(self.take_while(is_ident_continue)),
+                '(' | ')' | '{' | '}' | ':' | ';' | '=' | ',' | '<' | '>' => {
+                    self.bump();
+                    Token::Punct(c)
+                }
+                other => {
+                    return Err(self.error(format!("unexpected character '{}'", other), position))
+                }
+            };
+
+            let end = Position {
+                row: self.row,
+                col: self.col,
+            };
+            tokens.push(Spanned {
+                token,
+                position,
+                end,
+            });
+        }
+
+        Ok(tokens)
+    }
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    cursor: usize,
+    end: Position,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.cursor).map(|s| &s.token)
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.cursor + n).map(|s| &s.token)
+    }
+
+    fn position(&self) -> Position {
+        self.tokens
+            .get(self.cursor)
+            .map(|s| s.position)
+            .unwrap_or(self.end)
+    }
+
+    /// end of the most recently consumed token
+    fn previous_end(&self) -> Position {
+        self.cursor
+            .checked_sub(1)
+            .and_then(|i| self.tokens.get(i))
+            .map(|s| s.end)
+            .unwrap_or(self.end)
+    }
+
+    fn error<T>(&self, reason: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            reason: reason.into(),
+            position: self.position(),
+        })
+    }
+
+    fn next(&mut self) -> Result<Token, ParseError> {
+        match self.tokens.get(self.cursor) {
+            Some(s) => {
+                self.cursor += 1;
+                Ok(s.token.clone())
+            }
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    fn eat(&mut self, punct: char) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.cursor += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: char) -> Result<(), ParseError> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{}', found {:?}", punct, self.peek()))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, ParseError> {
+        match self.next()? {
+            Token::Ident(s) => Ok(s),
+            other => {
+                self.cursor -= 1;
+                self.error(format!("expected identifier, found {:?}", other))
+            }
+        }
+    }
+
+    fn parse_type(&mut self) -> Result<Type, ParseError> {
+        let name = self.ident()?;
+        match name.as_str() {
+            "int" => Ok(Type::Int),
+            "bool" => Ok(Type::Bool),
+            "float" => Ok(Type::Float),
+            "char" => Ok(Type::Char),
+            "ptr" => {
+                self.expect('<')?;
+                let inner = self.parse_type()?;
+                self.expect('>')?;
+                Ok(Type::Ptr(Box::new(inner)))
+            }
+            other => {
+                self.cursor -= 1;
+                self.error(format!("unknown type '{}'", other))
+            }
+        }
+    }
+
+    fn parse_program(&mut self) -> Result<Program, ParseError> {
+        let mut functions = Vec::new();
+        while self.peek().is_some() {
+            if self.peek() == Some(&Token::Ident("from".to_string())) {
+                return self.error("imports are not supported");
+            }
+            functions.push(self.parse_function()?);
+        }
+        Ok(Program { functions })
+    }
+
+    fn parse_function(&mut self) -> Result<Function, ParseError> {
+        let pos = self.position();
+        let name = match self.next()? {
+            Token::Func(name) => name,
+            other => {
+                self.cursor -= 1;
+                return self.error(format!("expected function name, found {:?}", other));
+            }
+        };
+
+        let mut args = Vec::new();
+        if self.eat('(') {
+            while !self.eat(')') {
+                if !args.is_empty() {
+                    self.expect(',')?;
+                }
+                let arg_pos = self.position();
+                let arg_name = self.ident()?;
+                self.expect(':')?;
+                let arg_type = self.parse_type()?;
+                args.push(Argument {
+                    name: arg_name,
+                    arg_type,
+                    pos: Some(arg_pos),
+                    pos_end: Some(self.previous_end()),
+                    src: None,
+                });
+            }
+        }
+
+        let return_type = if self.eat(':') {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        self.expect('{')?;
+        let mut instrs = Vec::new();
+        while !self.eat('}') {
+            instrs.push(self.parse_instruction()?);
+        }
+
+        Ok(Function {
+            name,
+            args: if args.is_empty() { None } else { Some(args) },
+            return_type,
+            instrs,
+            pos: Some(pos),
+            pos_end: Some(self.previous_end()),
+            src: None,
+            attrs: None,
+        })
+    }
+
+    fn parse_literal(&mut self, constant_type: &Type) -> Result<Literal, ParseError> {
+        let literal = match (self.next()?, constant_type) {
+            (Token::Int(x), Type::Float) => Literal::Float(x as f64),
+            (Token::Int(x), _) => Literal::Int(x),
+            (Token::Float(x), _) => Literal::Float(x),
+            (Token::Char(c), _) => Literal::Char(c),
+            (Token::Ident(s), _) if s == "true" => Literal::Bool(true),
+            (Token::Ident(s), _) if s == "false" => Literal::Bool(false),
+            (other, _) => {
+                self.cursor -= 1;
+                return self.error(format!("expected literal, found {:?}", other));
+            }
+        };
+        Ok(literal)
+    }
+
+    fn parse_instruction(&mut self) -> Result<Code, ParseError> {
+        let instruction_position = self.position();
+        let pos = Some(instruction_position);
+
+        // .label:
+        if let (Some(Token::Label(label)), Some(Token::Punct(':'))) =
+            (self.peek(), self.peek_nth(1))
+        {
+            let label = label.clone();
+            self.cursor += 2;
+            return Ok(Code::Label {
+                label,
+                pos,
+                pos_end: Some(self.previous_end()),
+                src: None,
+            });
+        }
+
+        // dest: type = op ...
+        let dest = match (self.peek(), self.peek_nth(1)) {
+            (Some(Token::Ident(dest)), Some(Token::Punct(':' | '='))) => {
+                let dest = dest.clone();
+                self.cursor += 1;
+                let dest_type = if self.eat(':') {
+                    Some(self.parse_type()?)
+                } else {
+                    None
+                };
+                self.expect('=')?;
+                Some((dest, dest_type))
+            }
+            _ => None,
+        };
+
+        let op_position = self.position();
+        let op = self.ident()?;
+
+        if op == "const" {
+            let Some((dest, Some(constant_type))) = dest else {
+                return self.error("constants need a typed destination");
+            };
+            let value = self.parse_literal(&constant_type)?;
+            self.expect(';')?;
+            let pos_end = Some(self.previous_end());
+            return Ok(Code::Constant {
+                op: ConstantOp::Const,
+                dest,
+                constant_type,
+                value,
+                pos,
+                pos_end,
+                src: None,
+            });
+        }
+
+        let mut args = Vec::new();
+        let mut funcs = Vec::new();
+        let mut labels = Vec::new();
+        while !self.eat(';') {
+            match self.next()? {
+                Token::Ident(s) => args.push(s),
+                Token::Func(s) => funcs.push(s),
+                Token::Label(s) => labels.push(s),
+                other => {
+                    self.cursor -= 1;
+                    return self.error(format!("unexpected operand {:?}", other));
+                }
+            }
+        }
+
+        let non_empty = |v: Vec<String>| if v.is_empty() { None } else { Some(v) };
+        let (args, funcs, labels) = (non_empty(args), non_empty(funcs), non_empty(labels));
+        let (funcs, labels) = (
+            funcs.map(Vec::into_boxed_slice),
+            labels.map(Vec::into_boxed_slice),
+        );
+        let op_value = Value::String(op.clone());
+        let pos_end = Some(self.previous_end());
+
+        let unknown = |kind: &str| ParseError {
+            reason: format!("unknown {} operation '{}'", kind, op),
+            position: op_position,
+        };
+
+        match dest {
+            Some((dest, dest_type)) => {
+                if let Ok(op) = serde_json::from_value::<ValueOp>(op_value.clone()) {
+                    let Some(value_type) = dest_type else {
+                        return Err(ParseError {
+                            reason: format!("missing type for '{}'", dest),
+                            position: instruction_position,
+                        });
+                    };
+                    Ok(Code::Value {
+                        op,
+                        dest,
+                        value_type,
+                        args,
+                        funcs,
+                        labels,
+                        pos,
+                        pos_end,
+                        src: None,
+                    })
+                } else if let Ok(op) = serde_json::from_value::<MemoryOp>(op_value) {
+                    Ok(Code::Memory {
+                        op,
+                        args,
+                        dest: Some(dest),
+                        ptr_type: dest_type,
+                        pos,
+                        pos_end,
+                        src: None,
+                    })
+                } else {
+                    Err(unknown("value"))
+                }
+            }
+            None => {
+                if let Ok(op) = serde_json::from_value::<EffectOp>(op_value.clone()) {
+                    Ok(Code::Effect {
+                        op,
+                        args,
+                        funcs,
+                        labels,
+                        pos,
+                        pos_end,
+                        src: None,
+                    })
+                } else if let Ok(op) = serde_json::from_value::<MemoryOp>(op_value.clone()) {
+                    Ok(Code::Memory {
+                        op,
+                        args,
+                        dest: None,
+                        ptr_type: None,
+                        pos,
+                        pos_end,
+                        src: None,
+                    })
+                } else if let Ok(op) = serde_json::from_value::<Noop>(op_value) {
+                    Ok(Code::Noop {
+                        op,
+                        pos,
+                        pos_end,
+                        src: None,
+                    })
+                } else {
+                    Err(unknown("effect"))
+                }
+            }
+        }
+    }
+}
+
+/// Parse a program in the Bril text format.
+///
+/// Positions are 1-based and attached to functions, arguments, labels and instructions,
+/// matching the output of `bril2json -p`.
+pub fn parse_program(text: &str) -> Result<Program, ParseError> {
+    let end = Position {
+        row: text.lines().count() as u32 + 1,
+        col: 1,
+    };
+    let tokens = Lexer::new(text).tokenize()?;
+    Parser {
+        tokens,
+        cursor: 0,
+        end,
+    }
+    .parse_program()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::representation::format_program;
+
+    #[test]
+    fn test_parse_function() {
+        let program = parse_program(
+            "# comment\n@main(n: int): ptr<float> {\n  one: int = const 1;\n  x: float = const .5;\n.loop:\n  p: ptr<float> = alloc n;\n  c: bool = lt one n;\n  br c .loop .done; # trailing\n.done:\n  ret p;\n}\n",
+        )
+        .unwrap();
+
+        let main = &program.functions[0];
+        assert_eq!(main.name, "main");
+        assert_eq!(main.args.as_ref().unwrap()[0].arg_type, Type::Int);
+        assert_eq!(main.return_type, Some(Type::Ptr(Box::new(Type::Float))));
+        assert_eq!(main.instrs.len(), 8);
+        assert_eq!(main.pos, Some(Position { row: 2, col: 1 }));
+        assert_eq!(
+            main.instrs[0].get_position(),
+            Some(Position { row: 3, col: 3 })
+        );
+        assert!(matches!(
+            main.instrs[1],
+            Code::Constant {
+                value: Literal::Float(x),
+                ..
+            } if x == 0.5
+        ));
+        assert!(matches!(main.instrs[2], Code::Label { .. }));
+        assert!(matches!(
+            main.instrs[3],
+            Code::Memory {
+                op: MemoryOp::Alloc,
+                ..
+            }
+        ));
+        assert_eq!(
+            main.instrs[5].get_labels(),
+            Some(&["loop".to_string(), "done".to_string()][..])
+        );
+    }
+
+    #[test]
+    fn test_parse_calls_and_effects() {
+        let program = parse_program(
+            "@f(a: int, b: char) { x: int = call @g a; call @g x; print b; store p x; nop; ret; }",
+        )
+        .unwrap();
+        let instrs = &program.functions[0].instrs;
+        assert!(matches!(
+            instrs[0],
+            Code::Value {
+                op: ValueOp::Call,
+                ..
+            }
+        ));
+        assert!(matches!(
+            instrs[1],
+            Code::Effect {
+                op: EffectOp::Call,
+                ..
+            }
+        ));
+        assert!(matches!(
+            instrs[3],
+            Code::Memory {
+                op: MemoryOp::Store,
+                dest: None,
+                ..
+            }
+        ));
+        assert!(matches!(instrs[4], Code::Noop { .. }));
+        assert_eq!(instrs[5].get_arguments(), None);
+    }
+
+    #[test]
+    fn test_round_trip_through_printer() {
+        let text = "@main(n: int): float {\n  c: char = const 'a';\n  f: float = const 2.0;\n  g: float = call @id f;\n.end:\n  ret f;\n}\n\n@id(x: float): float {\n  ret x;\n}\n";
+        let printed = format_program(&parse_program(text).unwrap());
+        assert_eq!(printed, text);
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        let err = parse_program("@main {\n  x: int = frobnicate y;\n}").unwrap_err();
+        assert_eq!(err.position, Position { row: 2, col: 12 });
+    }
+
+    #[test]
+    fn test_end_positions() {
+        let program =
+            parse_program("@main(n: int) {\n.entry:\n  x: int = add n n;\n  ret;\n}\n").unwrap();
+        let main = &program.functions[0];
+        let end = |row, col| Some(Position { row, col });
+        assert_eq!(main.pos_end, end(5, 2));
+        assert_eq!(main.args.as_ref().unwrap()[0].pos_end, end(1, 13));
+        assert_eq!(main.instrs[0].get_position_end(), end(2, 8));
+        assert_eq!(main.instrs[1].get_position_end(), end(3, 20));
+        assert_eq!(main.instrs[2].get_source(), None);
+    }
+}
@@ -2,14 +2,29 @@ use std::collections::{HashMap, HashSet, VecDeque};
 
 use crate::{
     dataflow::{
-        run_dataflow_analysis, LiveVariables, WorklistError, WorklistProperty, WorklistResult,
+        run_dataflow_analysis, run_parallel_dataflow_analysis, LiveVariables, WorklistError,
+        WorklistProperty, WorklistResult,
     },
     representation::{
         AbstractFunction, Argument, BlockId, Code, ControlFlowGraph, Label, Position, Terminator,
         Type, ValueOp, Variable,
     },
+    timing::Timer,
 };
 
+/// Where SSA construction places phi nodes, among the joins in the iterated dominance frontier of
+/// a variable's definitions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SsaForm {
+    /// at every such join
+    Minimal,
+    /// only for variables some block reads before assigning them, without a liveness analysis
+    SemiPruned,
+    /// only where the variable is live
+    #[default]
+    Pruned,
+}
+
 #[derive(Debug, Clone)]
 pub struct PhiNode {
     /// The destination variable that this phi node defines
@@ -20,6 +35,8 @@ pub struct PhiNode {
     pub phi_type: Type,
     /// Vector of incoming values for this phi node
     pub phi_args: Vec<(Variable, Label)>,
+    /// where the first incoming value with a known position is defined in the source
+    pub pos: Option<Position>,
 }
 
 impl PhiNode {
@@ -29,6 +46,7 @@ impl PhiNode {
             original_name: dest,
             phi_type: Type::None,
             phi_args: vec![],
+            pos: None,
         }
     }
 }
@@ -74,8 +92,7 @@ impl WorklistProperty for PhiTypeWorklist {
             let argument_types = phi
                 .phi_args
                 .iter()
-                .map(|(v, _)| domain.get(v))
-                .flatten()
+                .filter_map(|(v, _)| domain.get(v))
                 .collect::<Vec<_>>();
 
             if argument_types.is_empty() {
@@ -89,14 +106,15 @@ impl WorklistProperty for PhiTypeWorklist {
                 seen.insert(t);
                 if seen.len() > 1 {
                     return Err(WorklistError::transfer_error(
-                        &block,
+                        block,
                         format!("phi node has conflicting types: {:?}", seen),
                         p,
                     ));
                 }
             }
             phi.phi_type = seen.into_iter().next().unwrap().clone();
-            domain.insert(phi.dest.clone(), (phi.phi_type.clone(), None));
+            phi.pos = argument_types.iter().find_map(|(_, p)| *p);
+            domain.insert(phi.dest.clone(), (phi.phi_type.clone(), phi.pos));
             log::trace!("assigning type to phi: {}", phi);
         }
 
@@ -137,9 +155,9 @@ fn lookup_in_stack<'a>(
         .map(|old_name| {
             stack
                 .get(old_name)
-                .expect(&format!("Failed to find stack entry for {}", old_name))
+                .unwrap_or_else(|| panic!("Failed to find stack entry for {}", old_name))
                 .last()
-                .expect(&format!("Failed to find last entry for {}", old_name))
+                .unwrap_or_else(|| panic!("Failed to find last entry for {}", old_name))
                 .to_string()
         })
         .collect()
@@ -191,7 +209,7 @@ fn rename(
         log::trace!("before: {}", instruction);
         // --- step 1.
         if let Some(original_args) = instruction_arguments {
-            let renamed_arguments = lookup_in_stack(original_args.into_iter(), stack);
+            let renamed_arguments = lookup_in_stack(original_args.iter(), stack);
             instruction.replace_arguments(renamed_arguments);
         }
 
@@ -217,14 +235,14 @@ fn rename(
     // rename return
     if let Terminator::Ret(code) = &mut cb.terminator {
         if let Some(original_args) = code.get_arguments() {
-            let renamed_arguments = lookup_in_stack(original_args.into_iter(), stack);
+            let renamed_arguments = lookup_in_stack(original_args.iter(), stack);
             code.replace_arguments(renamed_arguments);
         }
     }
 
     if let Terminator::Br(_, _, code) = &mut cb.terminator {
         if let Some(original_args) = code.get_arguments() {
-            let renamed_arguments = lookup_in_stack(original_args.into_iter(), stack);
+            let renamed_arguments = lookup_in_stack(original_args.iter(), stack);
             code.replace_arguments(renamed_arguments);
         }
     }
@@ -237,22 +255,21 @@ fn rename(
 
     for successor in abstract_function.cfg.successors[current_block_id].iter() {
         log::trace!("updating successor block {}", successor);
-        let sb = &mut abstract_function.cfg.basic_blocks[*successor];
+        let sb = &mut abstract_function.cfg.basic_blocks[successor];
         for phi in &mut sb.phi_nodes {
             let var_name = phi.dest.as_str();
             let ori_name = phi.original_name.as_str();
-            let stack_entry = stack.get(ori_name).expect(&format!(
-                "Failed to find stack entry for variable '{}' in phi node for block '{}'",
-                ori_name, sb.label
-            ));
-            let incoming_value = stack_entry
-                .last()
-                .expect(&format!(
-                    "Failed to find last entry for variable {} in phi node",
-                    var_name
-                ))
-                .to_string();
-            phi.phi_args.push((incoming_value, cbl.clone()));
+            // a phi node placed without liveness may get no value from paths that never assign
+            // the variable, see `remove_partial_phi_nodes`
+            let Some(incoming_value) = stack.get(ori_name).and_then(|entry| entry.last()) else {
+                log::trace!(
+                    "no value for {} in phi node for block '{}'",
+                    var_name,
+                    sb.label
+                );
+                continue;
+            };
+            phi.phi_args.push((incoming_value.to_string(), cbl.clone()));
             log::trace!("update block {}: {} phi node: {}", sb.id, sb.label, phi);
         }
     }
@@ -260,11 +277,11 @@ fn rename(
     //   for b in blocks immediately dominated by block:
     //     # That is, children in the dominance tree.
     //     rename(b)
+    // in block order, so that the same program always gets the same names
     let dominated = abstract_function
-        .dominance_info
+        .dominance_info()
         .get_immediate_dominated(current_block_id)
-        .into_iter()
-        .copied()
+        .iter()
         .collect::<Vec<BlockId>>();
 
     log::trace!(
@@ -285,16 +302,37 @@ fn rename(
     debug_stack.pop();
 }
 
-pub fn insert_phi_nodes(mut af: AbstractFunction) -> WorklistResult<AbstractFunction> {
+pub fn insert_phi_nodes(
+    mut af: AbstractFunction,
+    form: SsaForm,
+) -> WorklistResult<AbstractFunction> {
     // Perform liveness analysis which will return used variables in the future
     // Merge: union of all successors
     // Transfer:  merge result - kill(def) + use, iterating backwards
-    let live_start = std::time::Instant::now();
-    let liveness_result = run_dataflow_analysis::<LiveVariables>(&mut af)?;
+    // without a block that joins control flow every dominance frontier is empty, so no phi node
+    // could be placed and liveness would go unused
+    let joins = af.cfg.predecessors.iter().any(|preds| preds.len() > 1);
+    let liveness_result = if joins && form == SsaForm::Pruned {
+        let _timer = Timer::function("liveness", &af.name);
+        run_parallel_dataflow_analysis::<LiveVariables>(&mut af)?
+    } else {
+        Default::default()
+    };
     log::debug!("adding phi nodes for {}", af.name);
-    log::debug!("lva took {:?}", live_start.elapsed());
     log::trace!("live variable analysis result: {:?}", liveness_result);
 
+    let globals = match form {
+        SsaForm::SemiPruned => global_names(&af),
+        SsaForm::Minimal | SsaForm::Pruned => HashSet::new(),
+    };
+    let needs_phi = |block: BlockId, var: &String| match form {
+        SsaForm::Minimal => true,
+        SsaForm::SemiPruned => globals.contains(var),
+        SsaForm::Pruned => liveness_result
+            .get(&block)
+            .is_some_and(|(_, o)| o.contains(var)),
+    };
+
     let mut definition_queue: VecDeque<(BlockId, String)> = VecDeque::new();
 
     // first record all definitions
@@ -318,14 +356,16 @@ pub fn insert_phi_nodes(mut af: AbstractFunction) -> WorklistResult<AbstractFunc
                 args: Some(vec![var.name.clone()]),
                 funcs: None,
                 labels: None,
-                pos: None,
+                pos: var.pos,
+                pos_end: var.pos_end,
+                src: None,
             },
         );
     }
 
     // we will propagate reachable definitions R and insert a phi node
     //  1. In the current block if R is defined in the block && we are revisiting R (cycle)
-    //  2. In the dominance frontier of the current block if R is live there (pruned SSA)
+    //  2. In the dominance frontier of the current block if `form` asks for it there
 
     // current block that defines R
     let mut inserted_phi_nodes: HashSet<(BlockId, String)> = HashSet::new();
@@ -344,26 +384,34 @@ pub fn insert_phi_nodes(mut af: AbstractFunction) -> WorklistResult<AbstractFunc
 
         let (definition_id, definition_ident) = definition.clone();
 
-        for frontier_id in af.dominance_info.get_dominance_frontier(definition_id) {
-            // if the variable is not live, we skip it
+        let frontier = af
+            .dominance_info()
+            .get_dominance_frontier(definition_id)
+            .clone();
+        for frontier_id in &frontier {
+            // with pruned SSA, if the variable is not live, we skip it
             log::trace!("\tchecking frontier block {}", frontier_id);
-            if !liveness_result
-                .get(frontier_id)
-                .is_some_and(|(_, o)| o.contains(&definition_ident))
-            {
-                log::trace!("\t\tskipping: not live in frontier");
+            if !needs_phi(frontier_id, &definition_ident) {
+                log::trace!("\t\tskipping: no phi node needed in frontier");
                 continue;
             }
 
-            if inserted_phi_nodes.insert((*frontier_id, definition_ident.clone())) {
-                af.cfg.basic_blocks[*frontier_id]
+            if inserted_phi_nodes.insert((frontier_id, definition_ident.clone())) {
+                af.cfg.basic_blocks[frontier_id]
                     .phi_nodes
                     .push(PhiNode::empty(definition_ident.clone()));
-                definition_queue.push_back((*frontier_id, definition_ident.clone()));
+                definition_queue.push_back((frontier_id, definition_ident.clone()));
             }
         }
     }
 
+    // frontiers are sets, so put the phi nodes of each block in a fixed order
+    for block in af.cfg.basic_blocks.iter_mut() {
+        block
+            .phi_nodes
+            .sort_by(|a, b| a.original_name.cmp(&b.original_name));
+    }
+
     for block in af.cfg.basic_blocks.iter() {
         for phi in block.phi_nodes.iter() {
             log::debug!("block {}: {} phi node: {}", block.id, block.label, phi);
@@ -377,7 +425,7 @@ pub fn insert_phi_nodes(mut af: AbstractFunction) -> WorklistResult<AbstractFunc
     for var in af.args.iter().flatten() {
         stack
             .entry(var.name.clone())
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(var.name.clone());
     }
 
@@ -392,6 +440,8 @@ pub fn insert_phi_nodes(mut af: AbstractFunction) -> WorklistResult<AbstractFunc
         &mut debug_stack,
     );
 
+    remove_partial_phi_nodes(&mut af);
+
     // run worklist top converge on types for phi nodes
     log::trace!("running type inference for phi nodes in {}", af.name);
 
@@ -400,6 +450,62 @@ pub fn insert_phi_nodes(mut af: AbstractFunction) -> WorklistResult<AbstractFunc
     Ok(af)
 }
 
+/// Variables read in some block before it assigns them, the only ones semi-pruned SSA gives phi
+/// nodes: the others are only read in the block that defines them
+fn global_names(af: &AbstractFunction) -> HashSet<String> {
+    let mut globals = HashSet::new();
+    for block in &af.cfg.basic_blocks {
+        let mut defined: HashSet<&str> = HashSet::new();
+        let terminator = match &block.terminator {
+            Terminator::Passthrough => None,
+            Terminator::Ret(code)
+            | Terminator::Jmp(_, code)
+            | Terminator::Br(_, _, code)
+            | Terminator::Speculate(_, code) => Some(code),
+        };
+        for code in block.instructions.iter().chain(terminator) {
+            for arg in code.get_arguments().into_iter().flatten() {
+                if !defined.contains(arg.as_str()) {
+                    globals.insert(arg.clone());
+                }
+            }
+            if let Some(dest) = code.get_destination() {
+                defined.insert(dest);
+            }
+        }
+    }
+    globals
+}
+
+/// Drop the phi nodes that miss a value for some predecessor, and the ones that read them. The
+/// variable is not assigned on every path into such a block, so the definitely initialized check
+/// guarantees that it is assigned again before any read: the phi node is dead. Pruned SSA never
+/// places one
+fn remove_partial_phi_nodes(af: &mut AbstractFunction) {
+    let mut removed: HashSet<String> = HashSet::new();
+    loop {
+        let before = removed.len();
+        for block in &mut af.cfg.basic_blocks {
+            let predecessors = af.cfg.predecessors[block.id].len();
+            block.phi_nodes.retain(|phi| {
+                let complete = phi.phi_args.len() == predecessors
+                    && phi.phi_args.iter().all(|(var, _)| !removed.contains(var));
+                if !complete {
+                    removed.insert(phi.dest.clone());
+                }
+                complete
+            });
+        }
+        if removed.len() == before {
+            break;
+        }
+    }
+    log::debug!(
+        "removed {} phi nodes without a value on every path",
+        removed.len()
+    );
+}
+
 pub fn remove_phi_nodes(abstract_function: &mut AbstractFunction) {
     // let mut bb = abstract_function.basic_blocks;
 
@@ -442,7 +548,9 @@ pub fn remove_phi_nodes(abstract_function: &mut AbstractFunction) {
                 args: Some(vec![var]),
                 funcs: None,
                 labels: None,
-                pos: None,
+                pos: p.pos,
+                pos_end: None,
+                src: None,
             };
 
             if is_preheader {
@@ -457,3 +565,85 @@ pub fn remove_phi_nodes(abstract_function: &mut AbstractFunction) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        representation::{RichAbstractProgram, SsaForm},
+        testing::{assert_same_output, program_from_text},
+    };
+
+    #[test]
+    fn places_fewer_phi_nodes_the_more_pruned_the_form() {
+        // `x` is only read where it is assigned, and neither it nor `y` is read after the join
+        let text = r#"
+@main(n: int) {
+  c: bool = lt n n;
+  br c .left .right;
+.left:
+  x: int = const 1;
+  y: int = const 2;
+  print x;
+  jmp .use;
+.use:
+  print y;
+  jmp .join;
+.right:
+  x: int = const 3;
+  y: int = const 4;
+  print x;
+.join:
+  print n;
+}
+"#;
+        let program = program_from_text(text);
+        let phi_nodes = |form| {
+            let ssa = RichAbstractProgram::try_from_program_with(program.clone(), form).unwrap();
+            let af = &ssa.program.functions["main"];
+            let mut phis: Vec<String> = (af.cfg.basic_blocks.iter())
+                .flat_map(|b| b.phi_nodes.iter().map(|phi| phi.original_name.clone()))
+                .collect();
+            phis.sort();
+            assert_same_output(&program.program, &ssa.into_program().program, &["3"]);
+            phis
+        };
+        assert_eq!(phi_nodes(SsaForm::Minimal), ["x", "y"]);
+        assert_eq!(phi_nodes(SsaForm::SemiPruned), ["y"]);
+        assert_eq!(phi_nodes(SsaForm::Pruned), Vec::<String>::new());
+    }
+
+    #[test]
+    fn synthesized_code_points_at_the_source() {
+        let text = r#"
+@main(n: int) {
+  i: int = const 0;
+  one: int = const 1;
+.loop:
+  more: bool = lt i n;
+  br more .body .done;
+.body:
+  i: int = add i one;
+  jmp .loop;
+.done:
+  print i;
+}
+"#;
+        let program = program_from_text(text);
+        let ssa = RichAbstractProgram::try_from_program(program).unwrap();
+        let af = &ssa.program.functions["main"];
+        let phi = (af.cfg.basic_blocks.iter())
+            .flat_map(|b| &b.phi_nodes)
+            .find(|phi| phi.original_name == "i")
+            .unwrap();
+        // the first definition of `i` that reaches the loop
+        assert_eq!(phi.pos.map(|p| p.row), Some(3));
+
+        let in_ssa = ssa.clone().into_ssa_program().program.functions.remove(0);
+        let out_of_ssa = ssa.into_program().program.functions.remove(0);
+        for code in in_ssa.instrs.iter().chain(&out_of_ssa.instrs) {
+            if code.get_destination().is_some() {
+                assert!(code.get_position().is_some(), "{:?}", code);
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use crate::representation::{Code, Function, Literal, Program, Type};
+
+// module that prints a `Program` in the Bril text format, mirroring `bril2txt`
+
+pub fn format_type(t: &Type) -> String {
+    match t {
+        Type::Int => "int".to_string(),
+        Type::Bool => "bool".to_string(),
+        Type::Float => "float".to_string(),
+        Type::Char => "char".to_string(),
+        Type::Ptr(inner) => format!("ptr<{}>", format_type(inner)),
+        Type::None => "none".to_string(),
+    }
+}
+
+pub fn format_literal(literal: &Literal) -> String {
+    match literal {
+        Literal::Int(x) => x.to_string(),
+        Literal::Bool(b) => b.to_string(),
+        // debug formatting always keeps a decimal point or exponent, so the value reads back as a float
+        Literal::Float(x) => format!("{:?}", x),
+        Literal::Char(c) => match c {
+            '\n' => "'\\n'".to_string(),
+            '\t' => "'\\t'".to_string(),
+            '\r' => "'\\r'".to_string(),
+            '\0' => "'\\0'".to_string(),
+            '\\' => "'\\\\'".to_string(),
+            '\'' => "'\\''".to_string(),
+            c => format!("'{}'", c),
+        },
+    }
+}
+
+/// Format a single instruction without indentation or trailing newline
+pub fn format_instruction(code: &Code) -> String {
+    if let Code::Label { label, .. } = code {
+        return format!(".{}:", label);
+    }
+
+    if let Code::Constant {
+        dest,
+        constant_type,
+        value,
+        ..
+    } = code
+    {
+        return format!(
+            "{}: {} = const {};",
+            dest,
+            format_type(constant_type),
+            format_literal(value)
+        );
+    }
+
+    let mut rhs = code.get_opcode_string();
+    for func in code.get_funcs().into_iter().flatten() {
+        rhs.push_str(&format!(" @{}", func));
+    }
+    for arg in code.get_arguments().into_iter().flatten() {
+        rhs.push_str(&format!(" {}", arg));
+    }
+    for label in code.get_labels().into_iter().flatten() {
+        rhs.push_str(&format!(" .{}", label));
+    }
+
+    match (code.get_destination(), code.get_type()) {
+        (Some(dest), Some(t)) => format!("{}: {} = {};", dest, format_type(&t), rhs),
+        (Some(dest), None) => format!("{} = {};", dest, rhs),
+        _ => format!("{};", rhs),
+    }
+}
+
+pub fn format_function(function: &Function) -> String {
+    let mut out = format!("@{}", function.name);
+
+    if let Some(args) = function.args.as_ref().filter(|args| !args.is_empty()) {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| format!("{}: {}", arg.name, format_type(&arg.arg_type)))
+            .collect();
+        out.push_str(&format!("({})", args.join(", ")));
+    }
+
+    if let Some(return_type) = &function.return_type {
+        out.push_str(&format!(": {}", format_type(return_type)));
+    }
+    out.push_str(" {\n");
+
+    for code in &function.instrs {
+        if code.is_label() {
+            out.push_str(&format!("{}\n", format_instruction(code)));
+        } else {
+            out.push_str(&format!("  {}\n", format_instruction(code)));
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// Print a program in the Bril text format
+pub fn format_program(program: &Program) -> String {
+    program
+        .functions
+        .iter()
+        .map(format_function)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -1,15 +1,19 @@
 use serde;
 use serde_json;
 use std::{
+    collections::HashMap,
     fs::File,
     hash::Hasher,
-    io::{self, BufReader, Read, Write},
+    io::{self, BufReader, Read},
     ops::{Add, BitAnd, BitOr, Div, Mul, Not, Sub},
-    path::Path,
-    process::{Command, Stdio},
+    path::{Path, PathBuf},
 };
 use thiserror::Error;
 
+use crate::representation::{
+    format_program, parse_program, validate_program_json, ParseError, SchemaError, ToolCache,
+};
+
 // TODO (jq54): add support for imports
 
 #[derive(Clone)]
@@ -34,6 +38,32 @@ pub struct Function {
     pub instrs: Vec<Code>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub pos: Option<Position>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pos_end: Option<Position>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub src: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attrs: Option<Vec<FunctionAttribute>>,
+}
+
+/// Hand-written facts about a function that the analyses cannot prove on their own.
+///
+/// Attributes are read from the `attrs` extension field of a function in the JSON
+/// program, or from a sidecar file (see [`RichProgram::apply_attributes_file`]).
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FunctionAttribute {
+    /// no side effects: calls may be removed when unused and hoisted out of loops
+    #[serde(rename = "pure")]
+    Pure,
+    /// rarely executed
+    #[serde(rename = "cold")]
+    Cold,
+    /// should always be inlined into callers
+    #[serde(rename = "inline(always)")]
+    InlineAlways,
+    /// should never be inlined into callers
+    #[serde(rename = "inline(never)")]
+    InlineNever,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
@@ -43,8 +73,14 @@ pub struct Argument {
     pub arg_type: Type,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub pos: Option<Position>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pos_end: Option<Position>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub src: Option<String>,
 }
 
+/// An instruction or a label. Programs hold a great many of these, so the rarely present fields
+/// are kept compact: `funcs` and `labels` are boxed slices, `src` a boxed str and positions 32 bit
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Hash, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum Code {
@@ -52,6 +88,10 @@ pub enum Code {
         label: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         pos: Option<Position>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pos_end: Option<Position>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        src: Option<Box<str>>,
     },
     Constant {
         op: ConstantOp,
@@ -61,6 +101,10 @@ pub enum Code {
         value: Literal,
         #[serde(skip_serializing_if = "Option::is_none")]
         pos: Option<Position>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pos_end: Option<Position>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        src: Option<Box<str>>,
     },
     Value {
         op: ValueOp,
@@ -70,22 +114,30 @@ pub enum Code {
         #[serde(skip_serializing_if = "Option::is_none")]
         args: Option<Vec<String>>,
         #[serde(skip_serializing_if = "Option::is_none")]
-        funcs: Option<Vec<String>>,
+        funcs: Option<Box<[String]>>,
         #[serde(skip_serializing_if = "Option::is_none")]
-        labels: Option<Vec<String>>,
+        labels: Option<Box<[String]>>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pos: Option<Position>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pos_end: Option<Position>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        src: Option<Box<str>>,
     },
     Effect {
         op: EffectOp,
         #[serde(skip_serializing_if = "Option::is_none")]
         args: Option<Vec<String>>,
         #[serde(skip_serializing_if = "Option::is_none")]
-        funcs: Option<Vec<String>>,
+        funcs: Option<Box<[String]>>,
         #[serde(skip_serializing_if = "Option::is_none")]
-        labels: Option<Vec<String>>,
+        labels: Option<Box<[String]>>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pos: Option<Position>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pos_end: Option<Position>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        src: Option<Box<str>>,
     },
 
     Memory {
@@ -98,11 +150,19 @@ pub enum Code {
         ptr_type: Option<Type>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pos: Option<Position>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pos_end: Option<Position>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        src: Option<Box<str>>,
     },
     Noop {
         op: Noop,
         #[serde(skip_serializing_if = "Option::is_none")]
         pos: Option<Position>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pos_end: Option<Position>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        src: Option<Box<str>>,
     },
 }
 
@@ -152,6 +212,17 @@ pub enum ValueOp {
     Int2char,
     Float2bits,
     Bits2float,
+    // bit operations, not part of standard Bril
+    #[cfg(feature = "bitops")]
+    Shl,
+    #[cfg(feature = "bitops")]
+    Shr,
+    #[cfg(feature = "bitops")]
+    Bitand,
+    #[cfg(feature = "bitops")]
+    Bitor,
+    #[cfg(feature = "bitops")]
+    Bitxor,
     Call,
     Phi, // special op for bril SSA from
 }
@@ -173,6 +244,10 @@ pub enum EffectOp {
     Ret,
     Call, // important, call can be both "effect" and "value op"
     Print,
+    // speculation extension
+    Speculate,
+    Commit,
+    Guard,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq, Hash)]
@@ -194,8 +269,8 @@ impl Type {
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Position {
-    pub row: u64,
-    pub col: u64,
+    pub row: u32,
+    pub col: u32,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy)]
@@ -258,6 +333,24 @@ impl Code {
         }
     }
 
+    /// Rename every argument `arg` to `rename(arg)`
+    pub fn rename_arguments(&mut self, rename: impl Fn(&str) -> String) {
+        if let Code::Value {
+            args: Some(args), ..
+        }
+        | Code::Effect {
+            args: Some(args), ..
+        }
+        | Code::Memory {
+            args: Some(args), ..
+        } = self
+        {
+            for arg in args {
+                *arg = rename(arg);
+            }
+        }
+    }
+
     pub fn get_opcode_string(&self) -> String {
         match self {
             Code::Label { .. } => "label".to_string(),
@@ -289,25 +382,82 @@ impl Code {
         }
     }
 
-    pub fn get_labels(&self) -> Option<&Vec<String>> {
+    pub fn get_position_end(&self) -> Option<Position> {
         match self {
-            Code::Value { labels, .. } => labels.as_ref(),
-            Code::Effect { labels, .. } => labels.as_ref(),
-            _ => None,
+            Code::Label { pos_end, .. } => *pos_end,
+            Code::Constant { pos_end, .. } => *pos_end,
+            Code::Value { pos_end, .. } => *pos_end,
+            Code::Effect { pos_end, .. } => *pos_end,
+            Code::Memory { pos_end, .. } => *pos_end,
+            Code::Noop { pos_end, .. } => *pos_end,
         }
     }
 
-    pub fn has_side_effects(&self) -> bool {
+    /// forget where the instruction was written, for code a transformation generates
+    pub fn clear_position(&mut self) {
+        let (Code::Label { pos, pos_end, .. }
+        | Code::Constant { pos, pos_end, .. }
+        | Code::Value { pos, pos_end, .. }
+        | Code::Effect { pos, pos_end, .. }
+        | Code::Memory { pos, pos_end, .. }
+        | Code::Noop { pos, pos_end, .. }) = self;
+        *pos = None;
+        *pos_end = None;
+    }
+
+    /// path of the source file this instruction came from, when it differs from the program's
+    pub fn get_source(&self) -> Option<&str> {
         match self {
-            Code::Effect { .. } => true,
-            Code::Memory { .. } => true,
+            Code::Label { src, .. } => src.as_deref(),
+            Code::Constant { src, .. } => src.as_deref(),
+            Code::Value { src, .. } => src.as_deref(),
+            Code::Effect { src, .. } => src.as_deref(),
+            Code::Memory { src, .. } => src.as_deref(),
+            Code::Noop { src, .. } => src.as_deref(),
+        }
+    }
+
+    pub fn get_funcs(&self) -> Option<&[String]> {
+        match self {
+            Code::Value { funcs, .. } => funcs.as_deref(),
+            Code::Effect { funcs, .. } => funcs.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn is_call(&self) -> bool {
+        matches!(
+            self,
             Code::Value {
-                op: ValueOp::Call, ..
-            } => true,
-            _ => false,
+                op: ValueOp::Call,
+                ..
+            } | Code::Effect {
+                op: EffectOp::Call,
+                ..
+            }
+        )
+    }
+
+    pub fn get_labels(&self) -> Option<&[String]> {
+        match self {
+            Code::Value { labels, .. } => labels.as_deref(),
+            Code::Effect { labels, .. } => labels.as_deref(),
+            _ => None,
         }
     }
 
+    pub fn has_side_effects(&self) -> bool {
+        matches!(
+            self,
+            Code::Effect { .. }
+                | Code::Memory { .. }
+                | Code::Value {
+                    op: ValueOp::Call,
+                    ..
+                }
+        )
+    }
+
     pub fn is_label(&self) -> bool {
         matches!(self, Code::Label { .. })
     }
@@ -349,7 +499,7 @@ impl Literal {
             },
             Type::Bool => match self {
                 Literal::Int(x) => Literal::Bool(*x != 0),
-                Literal::Bool(_) => self.clone(),
+                Literal::Bool(_) => *self,
                 Literal::Float(x) => Literal::Bool(*x != 0.),
                 Literal::Char(_) => panic!("no casts to bool from int"),
             },
@@ -457,6 +607,8 @@ impl Not for Literal {
     }
 }
 
+// cross-type comparisons have no ordering, so `partial_cmp` cannot defer to `cmp`
+#[allow(clippy::non_canonical_partial_ord_impl)]
 impl PartialOrd for Literal {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         match (self, other) {
@@ -504,6 +656,9 @@ impl PartialEq for EffectOp {
             (EffectOp::Br, EffectOp::Br) => true,
             (EffectOp::Ret, EffectOp::Ret) => true,
             (EffectOp::Print, EffectOp::Print) => true,
+            (EffectOp::Speculate, EffectOp::Speculate) => true,
+            (EffectOp::Commit, EffectOp::Commit) => true,
+            (EffectOp::Guard, EffectOp::Guard) => true,
             _ => false,
         }
     }
@@ -559,6 +714,45 @@ pub enum ProgramError {
     ProcessNotFound { process: String },
     #[error("Unsupported file extension: {ext}")]
     UnsupportedExtension { ext: String },
+    #[error("Parse error: {0}")]
+    Parse(#[from] ParseError),
+    #[error("Invalid program: {0}")]
+    Schema(#[from] SchemaError),
+    #[error("Attribute file refers to unknown function '{name}'")]
+    UnknownFunction { name: String },
+}
+
+/// Serialization format of a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramFormat {
+    /// Bril JSON
+    Json,
+    /// Bril text format
+    Bril,
+}
+
+/// How `.bril` text files are turned into a `Program`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum BrilFrontend {
+    /// the built-in parser
+    #[default]
+    Native,
+    /// an external `bril2json` tool, by path or by name on the `PATH`
+    Bril2json(PathBuf),
+}
+
+impl BrilFrontend {
+    /// `bril2json` from the `PATH`
+    pub fn bril2json() -> Self {
+        Self::Bril2json(PathBuf::from("bril2json"))
+    }
+}
+
+impl std::fmt::Display for RichProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let json = serde_json::to_string(&self.program).map_err(|_| std::fmt::Error)?;
+        write!(f, "{}", json)
+    }
 }
 
 impl RichProgram {
@@ -601,178 +795,267 @@ impl RichProgram {
         (line, column, snippet.trim_end().to_string())
     }
 
-    /// Converts a Bril source file to JSON format using the `bril2json` command.
-    ///
-    /// This function reads the specified Bril file, spawns a `bril2json` process,
-    /// pipes the file contents to its stdin, and returns the JSON output as bytes.
-    ///
-    /// # Arguments
-    /// * `file_path` - Path to the `.bril` file to convert
-    ///
-    /// # Returns
-    /// * `Ok(Vec<u8>)` - The JSON output as bytes from `bril2json`
-    /// * `Err(ProgramError)` - If the file cannot be read, process fails to spawn,
-    ///   or `bril2json` exits with a non-zero status code
+    /// Pipes `input` through the external `tool` (e.g. `bril2json -p`) and returns its stdout.
     ///
     /// # Errors
-    /// * `ProgramError::Io` - File I/O errors
-    /// * `ProgramError::ProcessNotFound` - `bril2json` command not found
-    /// * `ProgramError::ProcessFailed` - `bril2json` exited with error code
-    fn run_bril2json(file_path: &Path) -> Result<Vec<u8>, ProgramError> {
-        let file_contents = std::fs::read(file_path)?;
-        let mut child = Command::new("bril2json")
-            .args(["-p"])
+    /// * `ProgramError::Io` - Pipe I/O errors
+    /// * `ProgramError::ProcessNotFound` - `tool` could not be started
+    /// * `ProgramError::ProcessFailed` - `tool` exited with error code
+    #[cfg(not(target_arch = "wasm32"))]
+    fn run_tool(tool: &Path, args: &[&str], input: &str) -> Result<Vec<u8>, ProgramError> {
+        use std::{
+            io::Write,
+            process::{Command, Stdio},
+        };
+
+        let process = tool.display().to_string();
+        let mut child = Command::new(tool)
+            .args(args)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
             .map_err(|_| ProgramError::ProcessNotFound {
-                process: "bril2json".into(),
+                process: process.clone(),
             })?;
 
-        child.stdin.as_mut().unwrap().write_all(&file_contents)?;
+        child.stdin.as_mut().unwrap().write_all(input.as_bytes())?;
         let output = child.wait_with_output()?;
 
         if !output.status.success() {
             return Err(ProgramError::ProcessFailed {
-                process: "bril2json".into(),
+                process,
                 code: output.status.code().unwrap_or(-1),
             });
         }
         Ok(output.stdout)
     }
 
-    fn run_bril2txt(file_path: &Path) -> Result<Vec<u8>, ProgramError> {
-        let file_contents = std::fs::read(file_path)?;
-        let mut child = Command::new("bril2txt")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|_| ProgramError::ProcessNotFound {
-                process: "bril2txt".into(),
-            })?;
-
-        child.stdin.as_mut().unwrap().write_all(&file_contents)?;
-        let output = child.wait_with_output()?;
-
-        if !output.status.success() {
-            return Err(ProgramError::ProcessFailed {
-                process: "bril2txt".into(),
-                code: output.status.code().unwrap_or(-1),
-            });
-        }
-        Ok(output.stdout)
+    /// there are no external processes in the browser
+    #[cfg(target_arch = "wasm32")]
+    fn run_tool(tool: &Path, _args: &[&str], _input: &str) -> Result<Vec<u8>, ProgramError> {
+        Err(ProgramError::ProcessNotFound {
+            process: tool.display().to_string(),
+        })
     }
 
     /// Creates a Program from a file with either `.json` or `.bril` extension.
     ///
-    /// For `.bril` files, this function automatically converts them to JSON using
-    /// the `bril2json` command before parsing. For `.json` files, it directly
-    /// deserializes the content.
+    /// `.bril` files are parsed with the built-in parser, see [`RichProgram::from_file_with`]
+    /// to use `bril2json` instead. For `.json` files, it directly deserializes the content.
     ///
     /// # Arguments
     /// * `filename` - Path to the program file (`.json` or `.bril`)
     ///
     /// # Returns
-    /// * `Some(Program)` - Successfully parsed program
-    /// * `None` - If file cannot be read, parsed, or converted
-    ///
-    /// # Panics
-    /// This function will panic if:
-    /// * File I/O operations fail
-    /// * JSON deserialization fails
-    /// * UTF-8 conversion fails (for `.bril` files)
-    /// * The `bril2json` process fails (for `.bril` files)
+    /// * `Ok(RichProgram)` - Successfully parsed program
+    /// * `Err(ProgramError)` - If file cannot be read, parsed, or converted
     ///
     /// # Examples
-    /// ```rust
+    /// ```no_run
+    /// use rust_bril::representation::RichProgram;
+    /// use std::path::Path;
+    ///
     /// // Load a JSON program file
-    /// let program = Program::from_file("examples/test.json").unwrap();
+    /// let program = RichProgram::from_file(Path::new("examples/test.json")).unwrap();
     ///
-    /// // Load and convert a Bril source file
-    /// let program = Program::from_file("examples/test.bril").unwrap();
+    /// // Load and parse a Bril source file
+    /// let program = RichProgram::from_file(Path::new("examples/test.bril")).unwrap();
     /// ```
-    ///
-    /// # Note
-    /// This function uses `unwrap()` extensively and will panic on errors.
-    /// Consider using a Result-returning version for production code.
     pub fn from_file(filename: &Path) -> Result<Self, ProgramError> {
-        match filename.extension().and_then(|ext| ext.to_str()) {
-            Some("bril") => {
-                let raw_text = std::fs::read_to_string(filename)?
-                    .lines()
-                    .map(|s| s.to_string())
-                    .collect();
-                let json_output = Self::run_bril2json(filename)?;
-                let json_string = String::from_utf8(json_output)?;
-                let program = serde_json::from_str::<Program>(&json_string).map_err(|error| {
-                    let (line, column, json_snippet) =
-                        Self::extract_json_error_context(&json_string, &error);
-                    ProgramError::JsonWithContent {
-                        error,
-                        line,
-                        column,
-                        json_snippet,
-                    }
-                })?;
+        Self::from_file_with(filename, BrilFrontend::Native)
+    }
 
-                Ok(RichProgram {
-                    original_text: raw_text,
-                    program,
+    /// Same as [`RichProgram::from_file`], choosing how `.bril` files are converted.
+    pub fn from_file_with(filename: &Path, frontend: BrilFrontend) -> Result<Self, ProgramError> {
+        let format = match filename.extension().and_then(|ext| ext.to_str()) {
+            Some("bril") => ProgramFormat::Bril,
+            Some("json") => ProgramFormat::Json,
+            Some(ext) => {
+                return Err(ProgramError::UnsupportedExtension {
+                    ext: ext.to_string(),
+                })
+            }
+            None => {
+                return Err(ProgramError::UnsupportedExtension {
+                    ext: "none".to_string(),
                 })
             }
-            Some("json") => {
-                let file = File::open(filename)?;
-                let mut reader = BufReader::new(file);
-                let mut json_content = String::new();
-                reader.read_to_string(&mut json_content)?;
-
-                let program = serde_json::from_str::<Program>(&json_content).map_err(|error| {
-                    let (line, column, json_snippet) =
-                        Self::extract_json_error_context(&json_content, &error);
-                    ProgramError::JsonWithContent {
-                        error,
-                        line,
-                        column,
-                        json_snippet,
+        };
+
+        Self::from_reader(BufReader::new(File::open(filename)?), format, frontend)
+    }
+
+    /// Creates a Program from everything `reader` yields, e.g. stdin in a pipeline like
+    /// `bril2json < prog.bril | rust_bril -`.
+    pub fn from_reader(
+        mut reader: impl Read,
+        format: ProgramFormat,
+        frontend: BrilFrontend,
+    ) -> Result<Self, ProgramError> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        Self::from_text(&content, format, frontend)
+    }
+
+    /// Creates a Program from source text in the given format.
+    ///
+    /// Bril text is kept as `original_text` so errors can be shown in context; JSON
+    /// input carries no source text.
+    pub fn from_text(
+        content: &str,
+        format: ProgramFormat,
+        frontend: BrilFrontend,
+    ) -> Result<Self, ProgramError> {
+        match format {
+            ProgramFormat::Bril => {
+                let program = match frontend {
+                    BrilFrontend::Native => parse_program(content)?,
+                    BrilFrontend::Bril2json(tool) => {
+                        let run = || Self::run_tool(&tool, &["-p"], content);
+                        let json_output = match ToolCache::user() {
+                            Some(cache) => cache.output(&tool, &["-p"], content, run)?,
+                            None => run()?,
+                        };
+                        let json_string = String::from_utf8(json_output)?;
+                        Self::parse_json(&json_string)?
                     }
-                })?;
+                };
+
                 Ok(RichProgram {
-                    original_text: vec![],
+                    original_text: content.lines().map(|s| s.to_string()).collect(),
                     program,
                 })
             }
-            Some(ext) => Err(ProgramError::UnsupportedExtension {
-                ext: ext.to_string(),
-            }),
-            None => Err(ProgramError::UnsupportedExtension {
-                ext: "none".to_string(),
+            ProgramFormat::Json => Ok(RichProgram {
+                original_text: vec![],
+                program: Self::parse_json(content)?,
             }),
         }
     }
 
-    #[allow(dead_code)]
-    pub fn to_string(self) -> String {
-        serde_json::to_string(&self.program).unwrap()
+    fn parse_json(json_content: &str) -> Result<Program, ProgramError> {
+        let value = serde_json::from_str::<serde_json::Value>(json_content).map_err(|error| {
+            let (line, column, json_snippet) =
+                Self::extract_json_error_context(json_content, &error);
+            ProgramError::JsonWithContent {
+                error,
+                line,
+                column,
+                json_snippet,
+            }
+        })?;
+
+        // report malformed instructions precisely before serde's untagged matching hides why
+        validate_program_json(&value)?;
+        Ok(serde_json::from_value::<Program>(value)?)
     }
 
-    #[allow(dead_code)]
-    pub fn to_file(self, file_name: &Path) -> Result<(), ProgramError> {
-        // if the file extension ends in .bril, write to tmp file, convert to text, and then write to file
-        if file_name.to_str().unwrap().ends_with(".bril") {
-            let tmp_file = tempfile::NamedTempFile::new().unwrap();
-            let tmp_file_path = tmp_file.path();
-            std::fs::write(tmp_file_path, self.to_string()).unwrap();
+    /// Merges function attributes from a sidecar JSON file into the program.
+    ///
+    /// The file maps function names to lists of attributes, e.g.
+    /// `{ "sum": ["pure"], "report": ["cold", "inline(never)"] }`.
+    pub fn apply_attributes_file(&mut self, file_path: &Path) -> Result<(), ProgramError> {
+        let content = std::fs::read_to_string(file_path)?;
+        let sidecar: HashMap<String, Vec<FunctionAttribute>> = serde_json::from_str(&content)?;
+
+        for (name, attributes) in sidecar {
+            let function = self
+                .program
+                .functions
+                .iter_mut()
+                .find(|f| f.name == name)
+                .ok_or(ProgramError::UnknownFunction { name })?;
+
+            let attrs = function.attrs.get_or_insert_with(Vec::new);
+            for attribute in attributes {
+                if !attrs.contains(&attribute) {
+                    attrs.push(attribute);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the program in the Bril text format
+    pub fn to_text(&self) -> String {
+        format_program(&self.program)
+    }
 
-            let output = Self::run_bril2txt(tmp_file_path)?;
-            std::fs::write(file_name, output).unwrap();
-            println!("Wrote to {}", file_name.display());
-            return Ok(());
+    /// Serialize the program in the given format, with JSON optionally pretty-printed
+    pub fn render(&self, format: ProgramFormat, pretty: bool) -> Result<String, ProgramError> {
+        match format {
+            ProgramFormat::Bril => Ok(self.to_text()),
+            ProgramFormat::Json if pretty => Ok(serde_json::to_string_pretty(&self.program)?),
+            ProgramFormat::Json => Ok(serde_json::to_string(&self.program)?),
         }
+    }
 
-        let file = File::create(file_name).unwrap();
-        serde_json::to_writer_pretty(file, &self.program).unwrap();
+    /// Bril text printed by an external `bril2txt` tool instead of the built-in printer
+    pub fn render_with_bril2txt(&self, tool: &Path) -> Result<String, ProgramError> {
+        let json = serde_json::to_string(&self.program)?;
+        Ok(String::from_utf8(Self::run_tool(tool, &[], &json)?)?)
+    }
+
+    /// Write the program to a file, as Bril text if the file extension is `.bril` and pretty JSON otherwise
+    pub fn to_file(self, file_name: &Path) -> Result<(), ProgramError> {
+        let format = match file_name.extension().and_then(|ext| ext.to_str()) {
+            Some("bril") => ProgramFormat::Bril,
+            _ => ProgramFormat::Json,
+        };
+        self.to_file_as(file_name, format, true)
+    }
+
+    /// Write the program to a file in the given format, regardless of the file extension
+    pub fn to_file_as(
+        self,
+        file_name: &Path,
+        format: ProgramFormat,
+        pretty: bool,
+    ) -> Result<(), ProgramError> {
+        std::fs::write(file_name, self.render(format, pretty)?)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speculation_ops_equal_themselves() {
+        let ops = [EffectOp::Speculate, EffectOp::Commit, EffectOp::Guard];
+        for (i, a) in ops.iter().enumerate() {
+            for (j, b) in ops.iter().enumerate() {
+                assert_eq!(a == b, i == j, "{:?} == {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn code_stays_small() {
+        // large programs hold millions of these, mostly copies of one another during SSA
+        // construction
+        assert!(std::mem::size_of::<Code>() <= 144);
+        let json = r#"{"op": "call", "dest": "x", "type": "int", "funcs": ["f"], "pos": {"row": 1, "col": 2}}"#;
+        let code: Code = serde_json::from_str(json).unwrap();
+        assert_eq!(code.get_funcs(), Some(&["f".to_string()][..]));
+        assert_eq!(code.get_position(), Some(Position { row: 1, col: 2 }));
+    }
+
+    #[test]
+    fn reads_programs_from_readers() {
+        let text = "@main {\n  print;\n}\n";
+        let rich =
+            RichProgram::from_reader(text.as_bytes(), ProgramFormat::Bril, BrilFrontend::Native)
+                .unwrap();
+        assert_eq!(rich.original_text, ["@main {", "  print;", "}"]);
+
+        let json = rich.to_string();
+        let read =
+            RichProgram::from_reader(json.as_bytes(), ProgramFormat::Json, BrilFrontend::Native)
+                .unwrap();
+        assert_eq!(read.program.functions[0].name, "main");
+    }
+}
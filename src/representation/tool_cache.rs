@@ -0,0 +1,152 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+// Output of external tools like `bril2json`, cached by a hash of the tool and its input so that
+// running over the same benchmark suite again does not start a process per file. Entries live in
+// `$XDG_CACHE_HOME/rust_bril/tools` (or `~/.cache/rust_bril/tools`), one file per input.
+// Rebuilding or replacing the tool changes its modification time and so the key; stale entries
+// are never read again and can be removed with the directory.
+
+/// Environment variable that moves the cache to another directory
+pub const CACHE_DIR_VAR: &str = "RUST_BRIL_CACHE_DIR";
+/// Environment variable that turns the cache off
+pub const NO_CACHE_VAR: &str = "RUST_BRIL_NO_CACHE";
+
+#[derive(Debug, Clone)]
+pub struct ToolCache {
+    directory: PathBuf,
+}
+
+impl ToolCache {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// The cache of the current user, `None` when it is turned off or there is no home directory
+    pub fn user() -> Option<Self> {
+        let var = |name| std::env::var_os(name).filter(|v| !v.is_empty());
+        if var(NO_CACHE_VAR).is_some_and(|v| v != "0") {
+            return None;
+        }
+        let directory = match var(CACHE_DIR_VAR) {
+            Some(directory) => PathBuf::from(directory),
+            None => var("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| var("HOME").map(|home| PathBuf::from(home).join(".cache")))?
+                .join("rust_bril")
+                .join("tools"),
+        };
+        Some(Self::new(directory))
+    }
+
+    /// The output of `tool` with `args` on `input`, from the cache if it ran on the same input
+    /// before and otherwise from `run`. Failures are not cached, and a cache that cannot be read
+    /// or written only costs the process.
+    pub fn output<E>(
+        &self,
+        tool: &Path,
+        args: &[&str],
+        input: &str,
+        run: impl FnOnce() -> Result<Vec<u8>, E>,
+    ) -> Result<Vec<u8>, E> {
+        let entry = self
+            .directory
+            .join(format!("{:032x}", key(tool, args, input)));
+        if let Ok(output) = fs::read(&entry) {
+            log::debug!("{} output cached in {}", tool.display(), entry.display());
+            return Ok(output);
+        }
+
+        let output = run()?;
+        // write to a file of our own and rename, so concurrent runs never read half an entry
+        let partial = entry.with_extension(format!("{}.tmp", std::process::id()));
+        let written = fs::create_dir_all(&self.directory)
+            .and_then(|_| fs::write(&partial, &output))
+            .and_then(|_| fs::rename(&partial, &entry));
+        if let Err(e) = written {
+            log::warn!(
+                "failed to cache {} output in {}: {}",
+                tool.display(),
+                entry.display(),
+                e
+            );
+            let _ = fs::remove_file(&partial);
+        }
+        Ok(output)
+    }
+}
+
+/// FNV-1a over the tool, its modification time, its arguments and the input. 128 bits, so that a
+/// collision, which would hand back the output for another program, does not happen in practice
+fn key(tool: &Path, args: &[&str], input: &str) -> u128 {
+    const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+    let modified = locate(tool)
+        .and_then(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |elapsed| elapsed.as_nanos());
+    let tool = tool.display().to_string();
+    let modified = modified.to_le_bytes();
+
+    // a zero byte after each part keeps ("ab", "c") apart from ("a", "bc")
+    let parts = [tool.as_bytes(), &modified[..]]
+        .into_iter()
+        .chain(args.iter().map(|arg| arg.as_bytes()))
+        .chain([input.as_bytes()]);
+    parts.fold(OFFSET, |hash, part| {
+        part.iter().chain(&[0]).fold(hash, |hash, &byte| {
+            (hash ^ byte as u128).wrapping_mul(PRIME)
+        })
+    })
+}
+
+/// the file `tool` runs, looking it up on the `PATH` like `Command` does for bare names
+fn locate(tool: &Path) -> Option<PathBuf> {
+    if tool.components().count() > 1 {
+        return Some(tool.to_path_buf());
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|directory| directory.join(tool))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_the_tool_once_per_input() {
+        let directory =
+            std::env::temp_dir().join(format!("rust_bril_cache_{}", uuid::Uuid::new_v4()));
+        let cache = ToolCache::new(&directory);
+        let tool = Path::new("bril2json");
+        let mut runs = 0;
+        let mut output = |input: &str| {
+            cache
+                .output(tool, &["-p"], input, || {
+                    runs += 1;
+                    Ok::<_, ()>(input.to_uppercase().into_bytes())
+                })
+                .unwrap()
+        };
+
+        assert_eq!(output("@main {}"), b"@MAIN {}");
+        assert_eq!(output("@main {}"), b"@MAIN {}");
+        assert_eq!(output("@other {}"), b"@OTHER {}");
+        assert_eq!(runs, 2);
+        assert_ne!(key(tool, &["-p"], ""), key(tool, &[], "-p"));
+
+        // failures are not cached
+        let failed = cache.output(tool, &[], "fails", || Err("no such tool"));
+        assert_eq!(failed, Err("no such tool"));
+        let retried = cache.output(tool, &[], "fails", || Ok::<_, ()>(b"ok".to_vec()));
+        assert_eq!(retried, Ok(b"ok".to_vec()));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,355 @@
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::representation::{opcode_named, Position};
+
+// Structural checks on raw Bril JSON. `Code` is an untagged enum, so serde can only say that an
+// instruction "did not match any variant"; these checks run first and name the offending field
+
+/// A Bril JSON program that does not match the schema
+#[derive(Debug, Error)]
+#[error("{path}: {reason}{}", .position.map(|p| format!(" (line {}, column {})", p.row, p.col)).unwrap_or_default())]
+pub struct SchemaError {
+    /// where the problem is, e.g. `functions[0] (@main).instrs[3]`
+    pub path: String,
+    pub reason: String,
+    /// source position of the enclosing instruction or function, if the JSON has one
+    pub position: Option<Position>,
+}
+
+/// value operations besides those of the opcode registry
+const VALUE_OPS: &[&str] = &["id", "phi"];
+const EFFECT_OPS: &[&str] = &["jmp", "br", "ret", "print", "speculate", "commit", "guard"];
+const MEMORY_OPS: &[&str] = &["alloc", "free", "store", "load", "ptradd"];
+const ATTRIBUTES: &[&str] = &["pure", "cold", "inline(always)", "inline(never)"];
+
+struct Context {
+    path: String,
+    position: Option<Position>,
+}
+
+impl Context {
+    fn error(&self, reason: impl Into<String>) -> SchemaError {
+        SchemaError {
+            path: self.path.clone(),
+            reason: reason.into(),
+            position: self.position,
+        }
+    }
+}
+
+/// Check that `value` has the shape of a Bril program
+pub fn validate_program_json(value: &Value) -> Result<(), SchemaError> {
+    let root = Context {
+        path: "program".to_string(),
+        position: None,
+    };
+    let program = value
+        .as_object()
+        .ok_or_else(|| root.error("expected an object with a 'functions' array"))?;
+    let functions = match program.get("functions") {
+        Some(Value::Array(functions)) => functions,
+        Some(_) => return Err(root.error("'functions' must be an array")),
+        None => return Err(root.error("missing field 'functions'")),
+    };
+
+    for (i, function) in functions.iter().enumerate() {
+        validate_function(i, function)?;
+    }
+    Ok(())
+}
+
+fn validate_function(index: usize, value: &Value) -> Result<(), SchemaError> {
+    let mut ctx = Context {
+        path: format!("functions[{}]", index),
+        position: None,
+    };
+    let function = value
+        .as_object()
+        .ok_or_else(|| ctx.error("expected a function object"))?;
+    ctx.position = position(function);
+    validate_source_fields(function, &ctx)?;
+
+    let name = match function.get("name") {
+        Some(Value::String(name)) => name,
+        Some(_) => return Err(ctx.error("'name' must be a string")),
+        None => return Err(ctx.error("missing field 'name'")),
+    };
+    ctx.path = format!("functions[{}] (@{})", index, name);
+
+    if let Some(args) = function.get("args") {
+        let args = args
+            .as_array()
+            .ok_or_else(|| ctx.error("'args' must be an array"))?;
+        for (i, arg) in args.iter().enumerate() {
+            let arg_ctx = Context {
+                path: format!("{}.args[{}]", ctx.path, i),
+                position: arg.as_object().and_then(position).or(ctx.position),
+            };
+            let arg = arg
+                .as_object()
+                .ok_or_else(|| arg_ctx.error("expected an object with 'name' and 'type'"))?;
+            validate_source_fields(arg, &arg_ctx)?;
+            if !matches!(arg.get("name"), Some(Value::String(_))) {
+                return Err(arg_ctx.error("'name' must be a string"));
+            }
+            match arg.get("type") {
+                Some(t) => validate_type(t, &arg_ctx)?,
+                None => return Err(arg_ctx.error("missing field 'type'")),
+            }
+        }
+    }
+    if let Some(t) = function.get("type") {
+        validate_type(t, &ctx)?;
+    }
+    if let Some(attrs) = function.get("attrs") {
+        let attrs = attrs
+            .as_array()
+            .ok_or_else(|| ctx.error("'attrs' must be an array"))?;
+        for attr in attrs {
+            if !attr.as_str().is_some_and(|a| ATTRIBUTES.contains(&a)) {
+                return Err(ctx.error(format!(
+                    "unknown attribute {}, expected one of {}",
+                    attr,
+                    ATTRIBUTES.join(", ")
+                )));
+            }
+        }
+    }
+
+    let instrs = match function.get("instrs") {
+        Some(Value::Array(instrs)) => instrs,
+        Some(_) => return Err(ctx.error("'instrs' must be an array")),
+        None => return Err(ctx.error("missing field 'instrs'")),
+    };
+    for (i, instr) in instrs.iter().enumerate() {
+        let instr_ctx = Context {
+            path: format!("{}.instrs[{}]", ctx.path, i),
+            position: instr.as_object().and_then(position).or(ctx.position),
+        };
+        validate_instruction(instr, &instr_ctx)?;
+    }
+    Ok(())
+}
+
+fn validate_instruction(value: &Value, ctx: &Context) -> Result<(), SchemaError> {
+    let instr = value
+        .as_object()
+        .ok_or_else(|| ctx.error("expected an instruction or label object"))?;
+    validate_source_fields(instr, ctx)?;
+
+    if let Some(label) = instr.get("label") {
+        return match label {
+            Value::String(_) => Ok(()),
+            _ => Err(ctx.error("'label' must be a string")),
+        };
+    }
+
+    let op = match instr.get("op") {
+        Some(Value::String(op)) => op.as_str(),
+        Some(_) => return Err(ctx.error("'op' must be a string")),
+        None => return Err(ctx.error("expected an 'op' or a 'label' field")),
+    };
+
+    // our own serializer writes `"type": null` for memory operations without a result
+    let has = |field: &str| instr.get(field).is_some_and(|v| !v.is_null());
+    let has_dest = has("dest");
+    let schema = match op {
+        "const" => "const takes 'dest', 'type' and 'value'",
+        "call" if has_dest => "value call takes 'dest', 'type', one entry in 'funcs' and 'args'",
+        "call" => "effect call takes one entry in 'funcs' and 'args'",
+        "nop" => "nop takes no fields",
+        op if VALUE_OPS.contains(&op) || opcode_named(op).is_some() => {
+            "value operations take 'dest', 'type' and 'args'"
+        }
+        op if EFFECT_OPS.contains(&op) => "effect operations take 'args' and 'labels', no 'dest'",
+        op if MEMORY_OPS.contains(&op) => {
+            "memory operations take 'args', plus 'dest' and 'type' when they produce a value"
+        }
+        op => return Err(ctx.error(format!("unknown opcode '{}'", op))),
+    };
+    let fail = |reason: String| ctx.error(format!("{} ({})", reason, schema));
+
+    // shape of the fields that are present
+    for field in ["args", "funcs", "labels"] {
+        if let Some(v) = instr.get(field) {
+            let valid = v
+                .as_array()
+                .is_some_and(|items| items.iter().all(Value::is_string));
+            if !valid {
+                return Err(fail(format!(
+                    "'{}' of '{}' must be an array of strings",
+                    field, op
+                )));
+            }
+        }
+    }
+    if has("dest") && !instr["dest"].is_string() {
+        return Err(fail(format!("'dest' of '{}' must be a string", op)));
+    }
+    if has("type") {
+        validate_type(&instr["type"], ctx)?;
+    }
+
+    let count = |field: &str| {
+        instr
+            .get(field)
+            .and_then(Value::as_array)
+            .map_or(0, Vec::len)
+    };
+    let require = |field: &str| match has(field) {
+        true => Ok(()),
+        false => Err(fail(format!("'{}' is missing field '{}'", op, field))),
+    };
+    let forbid = |field: &str| match has(field) {
+        true => Err(fail(format!("'{}' does not take field '{}'", op, field))),
+        false => Ok(()),
+    };
+    let expect = |field: &str, n: usize| match count(field) == n {
+        true => Ok(()),
+        false => Err(fail(format!(
+            "'{}' expects {} {}, found {}",
+            op,
+            n,
+            field,
+            count(field)
+        ))),
+    };
+
+    match op {
+        "const" => {
+            require("dest")?;
+            require("type")?;
+            require("value")?;
+            match &instr["value"] {
+                Value::Number(_) | Value::Bool(_) => {}
+                Value::String(s) if s.chars().count() == 1 => {}
+                v => {
+                    return Err(fail(format!(
+                        "'value' must be a number, boolean or single character, found {}",
+                        v
+                    )))
+                }
+            }
+        }
+        "call" => {
+            expect("funcs", 1)?;
+            if has_dest {
+                require("type")?;
+            }
+        }
+        "nop" => {}
+        "jmp" => expect("labels", 1)?,
+        "br" => {
+            expect("args", 1)?;
+            expect("labels", 2)?;
+        }
+        "ret" => {
+            if count("args") > 1 {
+                return Err(fail(format!(
+                    "'ret' takes at most 1 args, found {}",
+                    count("args")
+                )));
+            }
+        }
+        "print" => {}
+        "speculate" | "commit" => expect("args", 0)?,
+        "guard" => {
+            expect("args", 1)?;
+            expect("labels", 1)?;
+        }
+        "alloc" | "load" | "ptradd" => {
+            require("dest")?;
+            require("type")?;
+            expect("args", if op == "ptradd" { 2 } else { 1 })?;
+        }
+        "free" => expect("args", 1)?,
+        "store" => expect("args", 2)?,
+        "phi" => {
+            require("dest")?;
+            require("type")?;
+            if count("args") != count("labels") {
+                return Err(fail("'phi' needs one label per argument".to_string()));
+            }
+        }
+        _ => {
+            // remaining value operations
+            require("dest")?;
+            require("type")?;
+        }
+    }
+
+    if EFFECT_OPS.contains(&op) || op == "free" || op == "store" {
+        forbid("dest")?;
+    }
+    Ok(())
+}
+
+fn validate_type(value: &Value, ctx: &Context) -> Result<(), SchemaError> {
+    match value {
+        Value::String(t) if ["int", "bool", "float", "char", "none"].contains(&t.as_str()) => {
+            Ok(())
+        }
+        Value::Object(t) if t.len() == 1 && t.contains_key("ptr") => validate_type(&t["ptr"], ctx),
+        t => Err(ctx.error(format!(
+            "invalid type {}, expected int, bool, float, char or {{\"ptr\": <type>}}",
+            t
+        ))),
+    }
+}
+
+/// `pos` and `pos_end` are `{"row": n, "col": n}` objects and `src` is a file path
+fn validate_source_fields(object: &Map<String, Value>, ctx: &Context) -> Result<(), SchemaError> {
+    for field in ["pos", "pos_end"] {
+        if let Some(v) = object.get(field) {
+            if serde_json::from_value::<Position>(v.clone()).is_err() {
+                return Err(ctx.error(format!(
+                    "'{}' must be an object with integer 'row' and 'col', found {}",
+                    field, v
+                )));
+            }
+        }
+    }
+    if object.get("src").is_some_and(|v| !v.is_string()) {
+        return Err(ctx.error("'src' must be a string"));
+    }
+    Ok(())
+}
+
+fn position(object: &Map<String, Value>) -> Option<Position> {
+    serde_json::from_value(object.get("pos")?.clone()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(json: &str) -> Result<(), SchemaError> {
+        validate_program_json(&serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn accepts_well_formed_program() {
+        let program = r#"{"functions": [{"name": "main", "args": [{"name": "p", "type": {"ptr": "int"}}],
+            "instrs": [
+                {"label": "entry"},
+                {"op": "const", "dest": "one", "type": "int", "value": 1},
+                {"op": "load", "dest": "x", "type": "int", "args": ["p"]},
+                {"op": "store", "args": ["p", "one"], "type": null},
+                {"op": "print", "args": ["x"]},
+                {"op": "ret"}
+            ]}]}"#;
+        assert!(check(program).is_ok());
+    }
+
+    #[test]
+    fn names_missing_field_and_position() {
+        let program = r#"{"functions": [{"name": "main", "instrs": [
+            {"op": "add", "dest": "x", "args": ["a", "b"], "pos": {"row": 3, "col": 5}}
+        ]}]}"#;
+        let error = check(program).unwrap_err();
+        assert_eq!(error.path, "functions[0] (@main).instrs[0]");
+        assert!(error.reason.contains("missing field 'type'"));
+        assert_eq!(error.position, Some(Position { row: 3, col: 5 }));
+    }
+}
@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+
+use proptest::{
+    collection::vec,
+    prelude::*,
+    sample::{select, Index},
+};
+
+use crate::representation::{
+    parse_program, BasicBlock, Code, ConstantOp, EffectOp, Literal, Noop, Program, Terminator,
+    Type, ValueOp,
+};
+
+// Proptest strategies for the IR. Literals, instructions and blocks are arbitrary and need not
+// make sense together, which suits printers, parsers and serialization. Programs are well formed
+// by construction: they are built from a tree of statements whose operands are indices into the
+// variables in scope, so every variable is defined before it is used, branches join again and
+// loops are counted. Shrinking the tree shrinks the program
+
+const VARIABLES: &[&str] = &["a", "b", "c", "x", "y"];
+const LABELS: &[&str] = &["entry", "then", "else", "head", "exit"];
+
+impl Arbitrary for Literal {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![
+            any::<i64>().prop_map(Literal::Int),
+            any::<bool>().prop_map(Literal::Bool),
+            any::<f64>().prop_map(Literal::Float),
+            any::<char>().prop_map(Literal::Char),
+        ]
+        .boxed()
+    }
+}
+
+fn literal_type(literal: &Literal) -> Type {
+    match literal {
+        Literal::Int(_) => Type::Int,
+        Literal::Bool(_) => Type::Bool,
+        Literal::Float(_) => Type::Float,
+        Literal::Char(_) => Type::Char,
+    }
+}
+
+fn variable() -> impl Strategy<Value = String> {
+    select(VARIABLES).prop_map(str::to_string)
+}
+
+fn label() -> impl Strategy<Value = String> {
+    select(LABELS).prop_map(str::to_string)
+}
+
+/// value operations with their operand count and result type
+const VALUE_OPS: &[(ValueOp, usize, Type)] = &[
+    (ValueOp::Add, 2, Type::Int),
+    (ValueOp::Sub, 2, Type::Int),
+    (ValueOp::Mul, 2, Type::Int),
+    (ValueOp::Div, 2, Type::Int),
+    (ValueOp::Eq, 2, Type::Bool),
+    (ValueOp::Lt, 2, Type::Bool),
+    (ValueOp::And, 2, Type::Bool),
+    (ValueOp::Or, 2, Type::Bool),
+    (ValueOp::Not, 1, Type::Bool),
+    (ValueOp::Id, 1, Type::Int),
+    (ValueOp::Fadd, 2, Type::Float),
+    (ValueOp::Flt, 2, Type::Bool),
+    (ValueOp::Char2int, 1, Type::Int),
+];
+
+/// an instruction that neither is a label nor ends a block
+fn instruction() -> impl Strategy<Value = Code> {
+    let constant = (variable(), any::<Literal>()).prop_map(|(dest, value)| Code::Constant {
+        op: ConstantOp::Const,
+        dest,
+        constant_type: literal_type(&value),
+        value,
+        pos: None,
+        pos_end: None,
+        src: None,
+    });
+    let value = (variable(), select(VALUE_OPS), vec(variable(), 2)).prop_map(
+        |(dest, (op, arity, value_type), mut args)| {
+            args.truncate(arity);
+            Code::Value {
+                op,
+                dest,
+                value_type,
+                args: Some(args),
+                funcs: None,
+                labels: None,
+                pos: None,
+                pos_end: None,
+                src: None,
+            }
+        },
+    );
+    let print = vec(variable(), 1..3).prop_map(|args| effect(EffectOp::Print, args, vec![]));
+    let nop = Just(Code::Noop {
+        op: Noop::Nop,
+        pos: None,
+        pos_end: None,
+        src: None,
+    });
+    prop_oneof![4 => constant, 4 => value, 2 => print, 1 => nop]
+}
+
+fn effect(op: EffectOp, args: Vec<String>, labels: Vec<String>) -> Code {
+    Code::Effect {
+        op,
+        args: (!args.is_empty()).then_some(args),
+        funcs: None,
+        labels: (!labels.is_empty()).then_some(labels.into()),
+        pos: None,
+        pos_end: None,
+        src: None,
+    }
+}
+
+fn terminator() -> impl Strategy<Value = Terminator> {
+    prop_oneof![
+        proptest::option::of(variable()).prop_map(|arg| Terminator::Ret(effect(
+            EffectOp::Ret,
+            arg.into_iter().collect(),
+            vec![]
+        ))),
+        label().prop_map(|to| Terminator::Jmp(to.clone(), effect(EffectOp::Jmp, vec![], vec![to]))),
+        (variable(), label(), label()).prop_map(|(condition, yes, no)| {
+            let code = effect(EffectOp::Br, vec![condition], vec![yes.clone(), no.clone()]);
+            Terminator::Br(yes, no, code)
+        }),
+    ]
+}
+
+impl Arbitrary for Code {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let label = label().prop_map(|label| Code::Label {
+            label,
+            pos: None,
+            pos_end: None,
+            src: None,
+        });
+        let control = terminator().prop_map(|terminator| match terminator {
+            Terminator::Ret(code) | Terminator::Jmp(_, code) | Terminator::Br(_, _, code) => code,
+            Terminator::Passthrough | Terminator::Speculate(..) => unreachable!("not generated"),
+        });
+        prop_oneof![8 => instruction(), 1 => label, 2 => control].boxed()
+    }
+}
+
+impl Arbitrary for BasicBlock {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (label(), vec(instruction(), 0..8), terminator())
+            .prop_map(|(label, instructions, terminator)| BasicBlock {
+                id: 0,
+                label,
+                instructions,
+                terminator,
+                phi_nodes: Vec::new(),
+                preheader: Vec::new(),
+                natural_loop_return: false,
+                frequency: None,
+                edge_frequencies: HashMap::new(),
+            })
+            .boxed()
+    }
+}
+
+/// A statement of a generated @main. Indices pick among the variables of the right type in scope
+#[derive(Debug, Clone)]
+enum Statement {
+    Int(i64),
+    Bool(bool),
+    Arithmetic(Index, Index, Index),
+    Compare(Index, Index, Index),
+    Logic(Index, Index, Index),
+    /// assign to a variable defined earlier, so that branches and loops merge values
+    Update(Index, Index, Index, Index),
+    Print(Index),
+    If(Index, Vec<Statement>, Vec<Statement>),
+    Loop(u8, Vec<Statement>),
+}
+
+const ARITHMETIC: &[&str] = &["add", "sub", "mul"];
+const COMPARISONS: &[&str] = &["eq", "lt", "gt", "le", "ge"];
+const LOGIC: &[&str] = &["and", "or"];
+
+fn statement() -> impl Strategy<Value = Statement> {
+    let index = any::<Index>;
+    let leaf = prop_oneof![
+        (-20i64..20).prop_map(Statement::Int),
+        any::<bool>().prop_map(Statement::Bool),
+        (index(), index(), index()).prop_map(|(op, a, b)| Statement::Arithmetic(op, a, b)),
+        (index(), index(), index()).prop_map(|(op, a, b)| Statement::Compare(op, a, b)),
+        (index(), index(), index()).prop_map(|(op, a, b)| Statement::Logic(op, a, b)),
+        (index(), index(), index(), index())
+            .prop_map(|(target, op, a, b)| Statement::Update(target, op, a, b)),
+        index().prop_map(Statement::Print),
+    ];
+    leaf.prop_recursive(3, 32, 4, move |inner| {
+        prop_oneof![
+            (index(), vec(inner.clone(), 0..4), vec(inner.clone(), 0..4))
+                .prop_map(|(condition, yes, no)| Statement::If(condition, yes, no)),
+            (0u8..4, vec(inner, 0..4)).prop_map(|(trips, body)| Statement::Loop(trips, body)),
+        ]
+    })
+}
+
+/// emits the text of @main, tracking the variables in scope by type
+#[derive(Default)]
+struct Writer {
+    lines: Vec<String>,
+    ints: Vec<String>,
+    bools: Vec<String>,
+    next: usize,
+}
+
+impl Writer {
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.next += 1;
+        format!("{}{}", prefix, self.next)
+    }
+
+    fn int(&mut self, index: &Index) -> String {
+        if self.ints.is_empty() {
+            self.statement(&Statement::Int(0));
+        }
+        self.ints[index.index(self.ints.len())].clone()
+    }
+
+    fn bool(&mut self, index: &Index) -> String {
+        if self.bools.is_empty() {
+            self.statement(&Statement::Bool(false));
+        }
+        self.bools[index.index(self.bools.len())].clone()
+    }
+
+    /// the statements of a branch or loop body, whose variables go out of scope after it
+    fn nested(&mut self, statements: &[Statement]) {
+        let (ints, bools) = (self.ints.len(), self.bools.len());
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.ints.truncate(ints);
+        self.bools.truncate(bools);
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Int(value) => {
+                let dest = self.fresh("v");
+                self.lines
+                    .push(format!("  {}: int = const {};", dest, value));
+                self.ints.push(dest);
+            }
+            Statement::Bool(value) => {
+                let dest = self.fresh("v");
+                self.lines
+                    .push(format!("  {}: bool = const {};", dest, value));
+                self.bools.push(dest);
+            }
+            Statement::Arithmetic(op, a, b) => {
+                let (a, b) = (self.int(a), self.int(b));
+                let dest = self.fresh("v");
+                let op = op.get(ARITHMETIC);
+                self.lines
+                    .push(format!("  {}: int = {} {} {};", dest, op, a, b));
+                self.ints.push(dest);
+            }
+            Statement::Compare(op, a, b) => {
+                let (a, b) = (self.int(a), self.int(b));
+                let dest = self.fresh("v");
+                let op = op.get(COMPARISONS);
+                self.lines
+                    .push(format!("  {}: bool = {} {} {};", dest, op, a, b));
+                self.bools.push(dest);
+            }
+            Statement::Logic(op, a, b) => {
+                let (a, b) = (self.bool(a), self.bool(b));
+                let dest = self.fresh("v");
+                let op = op.get(LOGIC);
+                self.lines
+                    .push(format!("  {}: bool = {} {} {};", dest, op, a, b));
+                self.bools.push(dest);
+            }
+            Statement::Update(target, op, a, b) => {
+                let (target, a, b) = (self.int(target), self.int(a), self.int(b));
+                let op = op.get(ARITHMETIC);
+                self.lines
+                    .push(format!("  {}: int = {} {} {};", target, op, a, b));
+            }
+            Statement::Print(index) => {
+                let variable = match index.index(2) {
+                    0 => self.int(index),
+                    _ => self.bool(index),
+                };
+                self.lines.push(format!("  print {};", variable));
+            }
+            Statement::If(condition, yes, no) => {
+                let condition = self.bool(condition);
+                let (then, otherwise, join) =
+                    (self.fresh("then"), self.fresh("else"), self.fresh("join"));
+                self.lines
+                    .push(format!("  br {} .{} .{};", condition, then, otherwise));
+                self.lines.push(format!(".{}:", then));
+                self.nested(yes);
+                self.lines.push(format!("  jmp .{};", join));
+                self.lines.push(format!(".{}:", otherwise));
+                self.nested(no);
+                self.lines.push(format!(".{}:", join));
+            }
+            Statement::Loop(trips, body) => {
+                let (counter, limit, one, more) = (
+                    self.fresh("i"),
+                    self.fresh("n"),
+                    self.fresh("one"),
+                    self.fresh("more"),
+                );
+                let (head, inside, exit) =
+                    (self.fresh("head"), self.fresh("body"), self.fresh("exit"));
+                self.lines.extend([
+                    format!("  {}: int = const 0;", counter),
+                    format!("  {}: int = const {};", limit, trips),
+                    format!("  {}: int = const 1;", one),
+                    format!(".{}:", head),
+                    format!("  {}: bool = lt {} {};", more, counter, limit),
+                    format!("  br {} .{} .{};", more, inside, exit),
+                    format!(".{}:", inside),
+                ]);
+                self.nested(body);
+                self.lines.extend([
+                    format!("  {}: int = add {} {};", counter, counter, one),
+                    format!("  jmp .{};", head),
+                    format!(".{}:", exit),
+                ]);
+            }
+        }
+    }
+}
+
+impl Arbitrary for Program {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    /// Small well-formed programs: a @main without arguments that terminates and prints its
+    /// variables
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        vec(statement(), 1..10)
+            .prop_map(|statements| {
+                let mut writer = Writer::default();
+                for statement in &statements {
+                    writer.statement(statement);
+                }
+                // print what the program computed, so that a wrong value shows in the output
+                let variables: Vec<String> =
+                    writer.ints.iter().chain(&writer.bools).cloned().collect();
+                for variable in variables {
+                    writer.lines.push(format!("  print {};", variable));
+                }
+                let text = format!("@main {{\n{}\n}}\n", writer.lines.join("\n"));
+                parse_program(&text).expect("generated programs parse")
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        optimizations::{run_pipeline, PassManager},
+        representation::{format_program, Function, RichProgram},
+        testing::differential_test,
+    };
+
+    fn optimize(program: &Program, passes: &PassManager) -> Program {
+        let rich = RichProgram {
+            original_text: Vec::new(),
+            program: program.clone(),
+        };
+        run_pipeline(rich, passes)
+            .expect("the pipeline succeeds")
+            .program
+    }
+
+    proptest! {
+        // every case runs the pipeline and the interpreter twice
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn ssa_round_trip_preserves_output(program in any::<Program>()) {
+            let round_trip = optimize(&program, &PassManager::new());
+            let divergence = differential_test(&program, &round_trip, &[Vec::new()]).err();
+            prop_assert!(divergence.is_none(), "{}", divergence.unwrap());
+        }
+
+        #[test]
+        fn o2_never_changes_output(program in any::<Program>()) {
+            let optimized = optimize(&program, &PassManager::preset(2));
+            let divergence = differential_test(&program, &optimized, &[Vec::new()]).err();
+            prop_assert!(divergence.is_none(), "{}", divergence.unwrap());
+        }
+
+        #[test]
+        fn dce_never_changes_output(program in any::<Program>()) {
+            let optimized = optimize(&program, &PassManager::from_names(["dce"]).unwrap());
+            let divergence = differential_test(&program, &optimized, &[Vec::new()]).err();
+            prop_assert!(divergence.is_none(), "{}", divergence.unwrap());
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn printed_blocks_parse_back(block in any::<BasicBlock>()) {
+            let function = Function {
+                name: "f".to_string(),
+                args: None,
+                return_type: None,
+                instrs: block.instructions,
+                pos: None,
+                pos_end: None,
+                src: None,
+                attrs: None,
+            };
+            let text = format_program(&Program { functions: vec![function] });
+            let parsed = parse_program(&text).unwrap();
+            prop_assert_eq!(format_program(&parsed), text);
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    optimizations::{run_pipeline, PassManager},
+    representation::{BrilFrontend, RichProgram},
+    testing::{args_comment, Run},
+};
+
+// brench as a library: every benchmark runs as written, which is the baseline, and after each
+// configured pipeline, with the arguments of its `# ARGS:` comment. A run counts its dynamic
+// instructions if it prints what the baseline prints; otherwise it is incorrect. Results are
+// written like brench writes them, one `benchmark,run,result` line each
+
+/// What became of one benchmark under one pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchResult {
+    /// dynamic instructions of a correct run
+    Count(u64),
+    /// the output differs from the baseline
+    Incorrect,
+    /// the run did not finish within the fuel of the bench
+    Timeout,
+    /// the benchmark did not load, the pipeline failed, or the run failed like the baseline did
+    Missing,
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BenchResult::Count(n) => write!(f, "{}", n),
+            BenchResult::Incorrect => write!(f, "incorrect"),
+            BenchResult::Timeout => write!(f, "timeout"),
+            BenchResult::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+/// The results of one benchmark, in the order of [`BenchReport::runs`]
+#[derive(Debug, Clone)]
+pub struct BenchRow {
+    pub benchmark: String,
+    pub results: Vec<BenchResult>,
+}
+
+impl BenchRow {
+    /// count of run `run` over the baseline count, if both runs are correct
+    pub fn ratio(&self, run: usize) -> Option<f64> {
+        match (self.results[0], self.results[run]) {
+            (BenchResult::Count(base), BenchResult::Count(n)) if base > 0 => {
+                Some(n as f64 / base as f64)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    /// names of the runs, starting with the baseline
+    pub runs: Vec<String>,
+    pub rows: Vec<BenchRow>,
+}
+
+/// name of the run of the unoptimized programs
+pub const BASELINE: &str = "baseline";
+
+impl BenchReport {
+    /// brench's format: a `benchmark,run,result` header and one line per benchmark and run
+    pub fn to_csv(&self) -> String {
+        let mut csv = "benchmark,run,result\n".to_string();
+        for row in &self.rows {
+            for (run, result) in self.runs.iter().zip(&row.results) {
+                csv.push_str(&format!("{},{},{}\n", row.benchmark, run, result));
+            }
+        }
+        csv
+    }
+
+    /// One line per benchmark with a column per run, each count followed by its ratio to the
+    /// baseline, and the geometric mean of those ratios over the benchmarks where both are correct
+    pub fn to_table(&self) -> String {
+        let width = self
+            .rows
+            .iter()
+            .map(|r| r.benchmark.len())
+            .max()
+            .unwrap_or(0);
+        let width = width.max("geomean".len());
+        let columns: Vec<usize> = self.runs.iter().map(|run| run.len().max(20)).collect();
+        let line = |first: &str, cells: Vec<String>| {
+            let mut line = format!("{:<width$}", first);
+            for (cell, column) in cells.iter().zip(&columns) {
+                line.push_str(&format!("  {:>column$}", cell));
+            }
+            line + "\n"
+        };
+
+        let mut table = line("benchmark", self.runs.clone());
+        for row in &self.rows {
+            let cells = (row.results.iter().enumerate())
+                .map(|(run, result)| match (run, row.ratio(run)) {
+                    (1.., Some(ratio)) => format!("{} ({:.3})", result, ratio),
+                    _ => result.to_string(),
+                })
+                .collect();
+            table.push_str(&line(&row.benchmark, cells));
+        }
+        let means = (0..self.runs.len())
+            .map(|run| match self.geomean(run) {
+                Some(mean) => format!("{:.3}", mean),
+                None => "-".to_string(),
+            })
+            .collect();
+        table.push_str(&line("geomean", means));
+        table
+    }
+
+    /// geometric mean of the count of run `run` over the baseline count
+    pub fn geomean(&self, run: usize) -> Option<f64> {
+        let ratios: Vec<f64> = self.rows.iter().filter_map(|row| row.ratio(run)).collect();
+        if ratios.is_empty() {
+            return None;
+        }
+        let logs: f64 = ratios.iter().map(|r| r.ln()).sum();
+        Some((logs / ratios.len() as f64).exp())
+    }
+
+    /// runs that printed something else than the baseline
+    pub fn incorrect(&self) -> usize {
+        let results = self.rows.iter().flat_map(|row| &row.results);
+        results.filter(|r| **r == BenchResult::Incorrect).count()
+    }
+}
+
+/// Pipelines to compare on a set of benchmarks
+pub struct Bench {
+    runs: Vec<(String, PassManager)>,
+    fuel: u64,
+    frontend: BrilFrontend,
+}
+
+impl Default for Bench {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bench {
+    /// Only the baseline, with a budget of a billion instructions per run
+    pub fn new() -> Self {
+        Self {
+            runs: Vec::new(),
+            fuel: 1_000_000_000,
+            frontend: BrilFrontend::Native,
+        }
+    }
+
+    /// Add a run of every benchmark after `passes`
+    pub fn run(mut self, name: impl Into<String>, passes: PassManager) -> Self {
+        self.runs.push((name.into(), passes));
+        self
+    }
+
+    /// Instructions a run may execute before it times out
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = fuel;
+        self
+    }
+
+    /// How `.bril` benchmarks are parsed
+    pub fn frontend(mut self, frontend: BrilFrontend) -> Self {
+        self.frontend = frontend;
+        self
+    }
+
+    /// Run every benchmark, one after the other
+    pub fn measure(&self, benchmarks: &[PathBuf]) -> BenchReport {
+        let rows = benchmarks
+            .iter()
+            .map(|path| self.measure_one(path))
+            .collect();
+        self.report(rows)
+    }
+
+    /// The row of one benchmark, named by its file stem, to measure benchmarks in parallel
+    pub fn measure_one(&self, path: &Path) -> BenchRow {
+        let benchmark = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        let results = match RichProgram::from_file_with(path, self.frontend.clone()) {
+            Ok(program) => self.measure_program(program),
+            Err(e) => {
+                log::error!("cannot load {}: {}", path.display(), e);
+                vec![BenchResult::Missing; self.runs.len() + 1]
+            }
+        };
+        BenchRow { benchmark, results }
+    }
+
+    /// A report of rows from [`Bench::measure_one`]
+    pub fn report(&self, rows: Vec<BenchRow>) -> BenchReport {
+        let runs = std::iter::once(BASELINE.to_string())
+            .chain(self.runs.iter().map(|(name, _)| name.clone()))
+            .collect();
+        BenchReport { runs, rows }
+    }
+
+    fn measure_program(&self, program: RichProgram) -> Vec<BenchResult> {
+        let args = args_comment(&program.original_text.join("\n")).unwrap_or_default();
+        let Some(baseline) = Run::with_fuel(&program.program, &args, self.fuel) else {
+            return vec![BenchResult::Timeout; self.runs.len() + 1];
+        };
+        let result = |run: Option<Run>| match run {
+            None => BenchResult::Timeout,
+            Some(run) if !run.agrees_with(&baseline) => BenchResult::Incorrect,
+            Some(run) => run
+                .instructions
+                .map_or(BenchResult::Missing, BenchResult::Count),
+        };
+
+        let mut results = vec![result(Some(baseline.clone()))];
+        for (name, passes) in &self.runs {
+            let optimized = match run_pipeline(program.clone(), passes) {
+                Ok(optimized) => optimized,
+                Err(e) => {
+                    log::error!("{} fails: {}", name, e);
+                    results.push(BenchResult::Missing);
+                    continue;
+                }
+            };
+            results.push(result(Run::with_fuel(&optimized.program, &args, self.fuel)));
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_pipelines_on_benchmarks() {
+        let benchmarks = ["core/loopfact.bril", "core/fizz-buzz.bril"].map(|name| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("benchmarks")
+                .join(name)
+        });
+        let report = Bench::new()
+            .run("ssa", PassManager::new())
+            .run("O2", PassManager::preset(2))
+            .measure(&benchmarks);
+
+        assert_eq!(report.runs, [BASELINE, "ssa", "O2"]);
+        assert_eq!(report.rows[0].benchmark, "loopfact");
+        assert_eq!(report.incorrect(), 0);
+        let counts = |row: &BenchRow| {
+            row.results
+                .iter()
+                .map(|r| match r {
+                    BenchResult::Count(n) => *n,
+                    other => panic!("{} of {}", other, row.benchmark),
+                })
+                .collect::<Vec<_>>()
+        };
+        for row in &report.rows {
+            let counts = counts(row);
+            assert!(counts[2] < counts[0], "{:?}", counts);
+        }
+        assert!(report.geomean(2).unwrap() < 1.0);
+        assert_eq!(report.rows[0].ratio(0), Some(1.0));
+        let ratio = format!(" ({:.3})", report.rows[0].ratio(2).unwrap());
+        let loopfact = report.to_table().lines().nth(1).unwrap().to_string();
+        assert!(loopfact.contains(&ratio), "{}", loopfact);
+        assert!(report
+            .to_csv()
+            .starts_with("benchmark,run,result\nloopfact,baseline,"));
+    }
+}
@@ -0,0 +1,202 @@
+use std::{fmt, time::Duration};
+
+use crate::{interpreter::Interpreter, representation::Program};
+
+// Differential testing: a transformation is wrong if the program it produces prints something
+// else, or fails where the original succeeded (or the other way around), for the same arguments
+// of @main. Error messages may differ, since positions and variable names change. Generated
+// programs may not terminate, so runs can be bounded by fuel and time: a run that hits a limit
+// times out, which is no divergence when the original times out too
+
+/// What one run of a program printed and how it ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub stdout: String,
+    /// the error the run stopped with, if any
+    pub error: Option<String>,
+    /// executed instructions, if the run succeeded
+    pub instructions: Option<u64>,
+    /// the run ran out of fuel or time, and `error` says which
+    pub timed_out: bool,
+}
+
+/// How far a run may go before it times out, unbounded by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// instructions
+    pub fuel: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+impl Run {
+    /// Run `program` with the built-in interpreter
+    pub fn of(program: &Program, args: &[String]) -> Self {
+        Self::limited(program, args, Limits::default())
+    }
+
+    /// Run `program` within `limits`
+    pub fn limited(program: &Program, args: &[String], limits: Limits) -> Self {
+        let mut stdout = Vec::new();
+        let result = Interpreter::new(program)
+            .fuel(limits.fuel)
+            .timeout(limits.timeout)
+            .run(args, &mut stdout);
+        Self {
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            instructions: result.as_ref().ok().map(|e| e.profile.total),
+            timed_out: result.as_ref().is_err_and(|e| e.is_limit()),
+            error: result.err().map(|e| e.to_string()),
+        }
+    }
+
+    /// Run `program` for at most `fuel` instructions, `None` if it does not finish
+    pub fn with_fuel(program: &Program, args: &[String], fuel: u64) -> Option<Self> {
+        let limits = Limits {
+            fuel: Some(fuel),
+            timeout: None,
+        };
+        Some(Self::limited(program, args, limits)).filter(|run| !run.timed_out)
+    }
+
+    /// same output and both succeeded or both failed. Output is only compared up to where a run
+    /// that timed out stopped
+    pub fn agrees_with(&self, other: &Run) -> bool {
+        match (self.timed_out, other.timed_out) {
+            (false, false) => {
+                self.stdout == other.stdout && self.error.is_some() == other.error.is_some()
+            }
+            (true, true) => {
+                let shorter = self.stdout.len().min(other.stdout.len());
+                self.stdout.as_bytes()[..shorter] == other.stdout.as_bytes()[..shorter]
+            }
+            (true, false) | (false, true) => false,
+        }
+    }
+}
+
+/// Arguments for which the original and the optimized program behave differently
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub args: Vec<String>,
+    pub original: Run,
+    pub optimized: Run,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "the optimized program diverges with arguments [{}]",
+            self.args.join(" ")
+        )?;
+        let original: Vec<&str> = self.original.stdout.lines().collect();
+        let optimized: Vec<&str> = self.optimized.stdout.lines().collect();
+        let lines = original.len().max(optimized.len());
+        if let Some(i) = (0..lines).find(|&i| original.get(i) != optimized.get(i)) {
+            writeln!(f, "  line {} of the output differs", i + 1)?;
+            writeln!(f, "    original:  {}", line(&original, i))?;
+            writeln!(f, "    optimized: {}", line(&optimized, i))?;
+        }
+        let ending = |run: &Run| match &run.error {
+            Some(error) if run.timed_out => format!("times out: {}", error),
+            Some(error) => format!("fails: {}", error),
+            None => "succeeds".to_string(),
+        };
+        writeln!(f, "  original {}", ending(&self.original))?;
+        write!(f, "  optimized {}", ending(&self.optimized))
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+fn line<'a>(lines: &[&'a str], i: usize) -> &'a str {
+    lines.get(i).copied().unwrap_or("<end of output>")
+}
+
+/// Run `original` and `optimized` with every argument list of `inputs` and return the runs of
+/// the optimized program, or the first arguments they disagree on
+pub fn differential_test(
+    original: &Program,
+    optimized: &Program,
+    inputs: &[Vec<String>],
+) -> Result<Vec<Run>, Box<Divergence>> {
+    differential_test_with(original, optimized, inputs, Limits::default())
+}
+
+/// [`differential_test`] with both programs run within `limits`. The runs that come back may
+/// have timed out, if the original timed out too
+pub fn differential_test_with(
+    original: &Program,
+    optimized: &Program,
+    inputs: &[Vec<String>],
+    limits: Limits,
+) -> Result<Vec<Run>, Box<Divergence>> {
+    inputs
+        .iter()
+        .map(|args| {
+            let before = Run::limited(original, args, limits);
+            let after = Run::limited(optimized, args, limits);
+            match before.agrees_with(&after) {
+                true => Ok(after),
+                false => Err(Box::new(Divergence {
+                    args: args.clone(),
+                    original: before,
+                    optimized: after,
+                })),
+            }
+        })
+        .collect()
+}
+
+/// The arguments of a `# ARGS: 3 6` comment in Bril text, as the benchmarks record them
+pub fn args_comment(text: &str) -> Option<Vec<String>> {
+    text.lines().find_map(|line| {
+        let args = line.trim_start().strip_prefix('#')?.trim_start();
+        let args = args.strip_prefix("ARGS:")?;
+        Some(args.split_whitespace().map(str::to_string).collect())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::representation::parse_program;
+
+    #[test]
+    fn finds_the_first_divergent_input() {
+        let original = parse_program("@main(n: int) {\n  print n;\n}\n").unwrap();
+        let broken = parse_program(
+            "@main(n: int) {\n  one: int = const 1;\n  br_n: bool = lt n one;\n  br br_n .a .b;\n.a:\n  print n;\n  ret;\n.b:\n  print one;\n}\n",
+        )
+        .unwrap();
+        let inputs = vec![vec!["0".to_string()], vec!["5".to_string()]];
+        assert!(differential_test(&original, &original, &inputs).is_ok());
+
+        let divergence = differential_test(&original, &broken, &inputs).unwrap_err();
+        assert_eq!(divergence.args, ["5"]);
+        assert!(divergence.to_string().contains("original:  5"));
+
+        // counting down from a negative number never ends
+        let endless = parse_program(
+            "@main(n: int) {\n  one: int = const 1;\n  zero: int = const 0;\n.loop:\n  done: bool = eq n zero;\n  br done .end .body;\n.body:\n  n: int = sub n one;\n  jmp .loop;\n.end:\n  print n;\n}\n",
+        )
+        .unwrap();
+        let limits = Limits {
+            fuel: Some(10_000),
+            timeout: Some(Duration::from_secs(10)),
+        };
+        let negative = vec![vec!["-1".to_string()]];
+        let runs = differential_test_with(&endless, &endless, &negative, limits).unwrap();
+        assert!(runs[0].timed_out);
+        let divergence = differential_test_with(&original, &endless, &negative, limits);
+        assert!(divergence
+            .unwrap_err()
+            .to_string()
+            .contains("optimized times out"));
+
+        assert_eq!(
+            args_comment("# compute\n# ARGS: 3 6\n@main {}"),
+            Some(vec!["3".to_string(), "6".to_string()])
+        );
+    }
+}
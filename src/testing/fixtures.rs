@@ -0,0 +1,43 @@
+use crate::{
+    representation::{
+        parse_program, AbstractFunction, AbstractProgram, Program, RichAbstractProgram, RichProgram,
+    },
+    testing::{differential_test, Run},
+};
+
+// Building blocks for tests of passes and analyses, which start from a snippet of Bril text and
+// check that a transformed program still behaves like the original. They panic instead of
+// returning errors, since a test has nothing better to do with one
+
+/// The program written in Bril text `text`
+pub fn program_from_text(text: &str) -> RichProgram {
+    RichProgram {
+        original_text: Vec::new(),
+        program: parse_program(text).expect("the test program parses"),
+    }
+}
+
+/// The program written in Bril text `text`, in SSA form
+pub fn abstract_program_from_text(text: &str) -> AbstractProgram {
+    RichAbstractProgram::try_from_program(program_from_text(text))
+        .expect("the test program converts to SSA form")
+        .program
+}
+
+/// `@main` of the program written in Bril text `text`, in SSA form
+pub fn function_from_text(text: &str) -> AbstractFunction {
+    abstract_program_from_text(text)
+        .functions
+        .shift_remove("main")
+        .expect("the test program has a @main")
+}
+
+/// Run `before` and `after` with `args` as the arguments of `@main` and panic with the first
+/// difference unless they print the same and end the same way. Returns the run of `after`
+pub fn assert_same_output(before: &Program, after: &Program, args: &[&str]) -> Run {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    match differential_test(before, after, &[args]) {
+        Ok(mut runs) => runs.remove(0),
+        Err(divergence) => panic!("{}", divergence),
+    }
+}
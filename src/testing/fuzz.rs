@@ -0,0 +1,454 @@
+use crate::representation::{parse_program, Program};
+
+// Random, well-typed Bril programs for differential testing. Every generated program terminates
+// without a runtime error: loops count up to a small constant, functions only call functions
+// generated before them, divisors are nonzero constants, memory is only indexed in bounds and
+// freed before the allocating block ends, and variables are only read where every path defines
+// them. The same seed and configuration always give the same program
+
+/// What the generated programs may contain
+#[derive(Debug, Clone)]
+pub struct FuzzConfig {
+    /// functions besides @main
+    pub functions: usize,
+    /// statements per block, before nesting
+    pub statements: usize,
+    /// how deep branches and loops nest
+    pub depth: usize,
+    pub loops: bool,
+    pub memory: bool,
+    pub calls: bool,
+    pub floats: bool,
+}
+
+impl Default for FuzzConfig {
+    fn default() -> Self {
+        Self {
+            functions: 2,
+            statements: 8,
+            depth: 2,
+            loops: true,
+            memory: true,
+            calls: true,
+            floats: true,
+        }
+    }
+}
+
+/// SplitMix64, so programs do not depend on the version of a random number crate
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        match items.is_empty() {
+            true => None,
+            false => Some(&items[self.below(items.len())]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ty {
+    Int,
+    Bool,
+    Float,
+}
+
+impl Ty {
+    fn name(self) -> &'static str {
+        match self {
+            Ty::Int => "int",
+            Ty::Bool => "bool",
+            Ty::Float => "float",
+        }
+    }
+}
+
+/// a variable that is defined on every path to the current statement
+#[derive(Debug, Clone)]
+struct Var {
+    name: String,
+    ty: Ty,
+    /// loop counters must keep their values
+    mutable: bool,
+}
+
+/// a live allocation of `cells` ints
+#[derive(Debug, Clone)]
+struct Buffer {
+    name: String,
+    cells: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    vars: Vec<Var>,
+    buffers: Vec<Buffer>,
+}
+
+impl Scope {
+    fn of(&self, ty: Ty) -> Vec<&Var> {
+        self.vars.iter().filter(|v| v.ty == ty).collect()
+    }
+}
+
+/// the signature of a generated function other than @main
+struct Signature {
+    name: String,
+    params: Vec<Ty>,
+}
+
+/// Generates programs from a seed
+pub struct Fuzzer {
+    rng: Rng,
+    config: FuzzConfig,
+    names: usize,
+    lines: Vec<String>,
+    signatures: Vec<Signature>,
+}
+
+impl Fuzzer {
+    pub fn new(seed: u64, config: FuzzConfig) -> Self {
+        Self {
+            rng: Rng(seed),
+            config,
+            names: 0,
+            lines: Vec::new(),
+            signatures: Vec::new(),
+        }
+    }
+
+    /// The next program, as Bril text
+    pub fn text(&mut self) -> String {
+        self.lines.clear();
+        self.signatures.clear();
+        for i in 0..self.config.functions {
+            self.function(&format!("f{}", i));
+        }
+        self.function("main");
+        let mut text = self.lines.join("\n");
+        text.push('\n');
+        text
+    }
+
+    /// The next program
+    pub fn program(&mut self) -> Program {
+        parse_program(&self.text()).expect("generated programs parse")
+    }
+
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.names += 1;
+        format!("{}{}", prefix, self.names)
+    }
+
+    fn emit(&mut self, line: String) {
+        self.lines.push(line);
+    }
+
+    fn types(&self) -> Vec<Ty> {
+        match self.config.floats {
+            true => vec![Ty::Int, Ty::Int, Ty::Bool, Ty::Float],
+            false => vec![Ty::Int, Ty::Int, Ty::Bool],
+        }
+    }
+
+    fn function(&mut self, name: &str) {
+        let mut scope = Scope::default();
+        let mut header = format!("@{}", name);
+        let mut signature = None;
+        if name != "main" {
+            let count = 1 + self.rng.below(3);
+            let params: Vec<Ty> = (0..count)
+                .map(|_| *self.rng.pick(&self.types()).unwrap())
+                .collect();
+            let args: Vec<String> = params
+                .iter()
+                .map(|&ty| {
+                    let name = self.fresh("a");
+                    let arg = format!("{}: {}", name, ty.name());
+                    scope.vars.push(Var {
+                        name,
+                        ty,
+                        mutable: true,
+                    });
+                    arg
+                })
+                .collect();
+            header.push_str(&format!("({}): int", args.join(", ")));
+            signature = Some(Signature {
+                name: name.to_string(),
+                params,
+            });
+        }
+        self.emit(format!("{} {{", header));
+
+        // one variable of every type, so every statement finds operands
+        for ty in self.types() {
+            self.constant(ty, &mut scope);
+        }
+        self.block(self.config.depth, &mut scope);
+
+        if name == "main" {
+            let vars: Vec<String> = scope.vars.iter().map(|v| v.name.clone()).collect();
+            self.emit(format!("  print {};", vars.join(" ")));
+        } else {
+            let result = self.rng.pick(&scope.of(Ty::Int)).unwrap().name.clone();
+            self.emit(format!("  ret {};", result));
+        }
+        self.emit("}".to_string());
+        // only later functions can call this one, so there is no recursion
+        self.signatures.extend(signature);
+    }
+
+    fn literal(&mut self, ty: Ty) -> String {
+        match ty {
+            Ty::Int => (self.rng.below(21) as i64 - 10).to_string(),
+            Ty::Bool => self.rng.chance(50).to_string(),
+            Ty::Float => format!("{:?}", (self.rng.below(41) as f64 - 20.0) / 4.0),
+        }
+    }
+
+    fn constant(&mut self, ty: Ty, scope: &mut Scope) {
+        let name = self.fresh("v");
+        let literal = self.literal(ty);
+        self.emit(format!("  {}: {} = const {};", name, ty.name(), literal));
+        scope.vars.push(Var {
+            name,
+            ty,
+            mutable: true,
+        });
+    }
+
+    /// an existing mutable variable of type `ty` or a new one
+    fn destination(&mut self, ty: Ty, scope: &mut Scope) -> String {
+        let mutable: Vec<&Var> = scope.of(ty).into_iter().filter(|v| v.mutable).collect();
+        if let (true, Some(var)) = (self.rng.chance(40), self.rng.pick(&mutable)) {
+            return var.name.clone();
+        }
+        let name = self.fresh("v");
+        scope.vars.push(Var {
+            name: name.clone(),
+            ty,
+            mutable: true,
+        });
+        name
+    }
+
+    fn operand(&mut self, ty: Ty, scope: &Scope) -> String {
+        self.rng.pick(&scope.of(ty)).unwrap().name.clone()
+    }
+
+    fn block(&mut self, depth: usize, scope: &mut Scope) {
+        let statements = 1 + self.rng.below(self.config.statements);
+        for _ in 0..statements {
+            self.statement(depth, scope);
+        }
+    }
+
+    /// generate `body` in a copy of `scope`, so what it defines is not visible afterwards
+    fn nested(&mut self, depth: usize, scope: &Scope) {
+        let mut inner = scope.clone();
+        self.block(depth, &mut inner);
+    }
+
+    fn statement(&mut self, depth: usize, scope: &mut Scope) {
+        match self.rng.below(12) {
+            0 => {
+                let ty = *self.rng.pick(&self.types()).unwrap();
+                self.constant(ty, scope);
+            }
+            1 if depth > 0 => self.branch(depth, scope),
+            2 if depth > 0 && self.config.loops => self.counted_loop(depth, scope),
+            3 if self.config.calls && !self.signatures.is_empty() => self.call(scope),
+            4 if self.config.memory => self.buffer(depth, scope),
+            5 if !scope.buffers.is_empty() => self.memory_access(scope),
+            6 => {
+                let var = self.rng.pick(&scope.vars).unwrap().name.clone();
+                self.emit(format!("  print {};", var));
+            }
+            _ => self.arithmetic(scope),
+        }
+    }
+
+    fn arithmetic(&mut self, scope: &mut Scope) {
+        let mut ops = vec![
+            ("add", Ty::Int, Ty::Int),
+            ("sub", Ty::Int, Ty::Int),
+            ("mul", Ty::Int, Ty::Int),
+            ("div", Ty::Int, Ty::Int),
+            ("eq", Ty::Int, Ty::Bool),
+            ("lt", Ty::Int, Ty::Bool),
+            ("ge", Ty::Int, Ty::Bool),
+            ("and", Ty::Bool, Ty::Bool),
+            ("or", Ty::Bool, Ty::Bool),
+            ("not", Ty::Bool, Ty::Bool),
+            ("id", Ty::Int, Ty::Int),
+        ];
+        if self.config.floats {
+            ops.extend([
+                ("fadd", Ty::Float, Ty::Float),
+                ("fmul", Ty::Float, Ty::Float),
+                ("fsub", Ty::Float, Ty::Float),
+                ("flt", Ty::Float, Ty::Bool),
+            ]);
+        }
+        let (op, input, output) = *self.rng.pick(&ops).unwrap();
+        let a = self.operand(input, scope);
+        let b = match op {
+            "not" | "id" => None,
+            // a fresh nonzero constant, so division never traps
+            "div" => {
+                let divisor = self.fresh("d");
+                let value =
+                    (1 + self.rng.below(5)) as i64 * if self.rng.chance(50) { 1 } else { -1 };
+                self.emit(format!("  {}: int = const {};", divisor, value));
+                Some(divisor)
+            }
+            _ => Some(self.operand(input, scope)),
+        };
+        let dest = self.destination(output, scope);
+        let args = match b {
+            Some(b) => format!("{} {}", a, b),
+            None => a,
+        };
+        self.emit(format!("  {}: {} = {} {};", dest, output.name(), op, args));
+    }
+
+    fn branch(&mut self, depth: usize, scope: &mut Scope) {
+        let condition = self.operand(Ty::Bool, scope);
+        let (then, otherwise, join) = (self.fresh("then"), self.fresh("else"), self.fresh("join"));
+        self.emit(format!("  br {} .{} .{};", condition, then, otherwise));
+        self.emit(format!(".{}:", then));
+        self.nested(depth - 1, scope);
+        self.emit(format!("  jmp .{};", join));
+        self.emit(format!(".{}:", otherwise));
+        if self.rng.chance(70) {
+            self.nested(depth - 1, scope);
+        }
+        self.emit(format!(".{}:", join));
+    }
+
+    fn counted_loop(&mut self, depth: usize, scope: &mut Scope) {
+        let counter = self.fresh("i");
+        let (bound, one, more) = (self.fresh("n"), self.fresh("one"), self.fresh("c"));
+        let (head, body, exit) = (self.fresh("head"), self.fresh("body"), self.fresh("exit"));
+        let trips = 1 + self.rng.below(5);
+        self.emit(format!("  {}: int = const 0;", counter));
+        self.emit(format!("  {}: int = const {};", bound, trips));
+        self.emit(format!("  {}: int = const 1;", one));
+        self.emit(format!(".{}:", head));
+        self.emit(format!("  {}: bool = lt {} {};", more, counter, bound));
+        self.emit(format!("  br {} .{} .{};", more, body, exit));
+        self.emit(format!(".{}:", body));
+        let mut inner = scope.clone();
+        inner.vars.push(Var {
+            name: counter.clone(),
+            ty: Ty::Int,
+            mutable: false,
+        });
+        self.block(depth - 1, &mut inner);
+        self.emit(format!("  {}: int = add {} {};", counter, counter, one));
+        self.emit(format!("  jmp .{};", head));
+        self.emit(format!(".{}:", exit));
+    }
+
+    fn call(&mut self, scope: &mut Scope) {
+        let index = self.rng.below(self.signatures.len());
+        let params = self.signatures[index].params.clone();
+        let name = self.signatures[index].name.clone();
+        let args: Vec<String> = params.iter().map(|&ty| self.operand(ty, scope)).collect();
+        let dest = self.destination(Ty::Int, scope);
+        self.emit(format!(
+            "  {}: int = call @{} {};",
+            dest,
+            name,
+            args.join(" ")
+        ));
+    }
+
+    /// allocate, fill, use and free an array of ints within one block
+    fn buffer(&mut self, depth: usize, scope: &mut Scope) {
+        let cells = 1 + self.rng.below(4);
+        let (size, name) = (self.fresh("size"), self.fresh("p"));
+        self.emit(format!("  {}: int = const {};", size, cells));
+        self.emit(format!("  {}: ptr<int> = alloc {};", name, size));
+        for offset in 0..cells {
+            let value = self.operand(Ty::Int, scope);
+            let cell = self.cell(&name, offset);
+            self.emit(format!("  store {} {};", cell, value));
+        }
+        let mut inner = scope.clone();
+        inner.buffers.push(Buffer {
+            name: name.clone(),
+            cells,
+        });
+        self.block(depth.saturating_sub(1), &mut inner);
+        self.emit(format!("  free {};", name));
+    }
+
+    /// a pointer to cell `offset` of `buffer`
+    fn cell(&mut self, buffer: &str, offset: usize) -> String {
+        if offset == 0 {
+            return buffer.to_string();
+        }
+        let (amount, pointer) = (self.fresh("o"), self.fresh("q"));
+        self.emit(format!("  {}: int = const {};", amount, offset));
+        self.emit(format!(
+            "  {}: ptr<int> = ptradd {} {};",
+            pointer, buffer, amount
+        ));
+        pointer
+    }
+
+    fn memory_access(&mut self, scope: &mut Scope) {
+        let buffer = self.rng.pick(&scope.buffers).unwrap().clone();
+        let offset = self.rng.below(buffer.cells);
+        let cell = self.cell(&buffer.name, offset);
+        if self.rng.chance(50) {
+            let value = self.operand(Ty::Int, scope);
+            self.emit(format!("  store {} {};", cell, value));
+        } else {
+            let dest = self.destination(Ty::Int, scope);
+            self.emit(format!("  {}: int = load {};", dest, cell));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn generates_programs_that_run() {
+        for seed in 0..50 {
+            let text = Fuzzer::new(seed, FuzzConfig::default()).text();
+            assert_eq!(Fuzzer::new(seed, FuzzConfig::default()).text(), text);
+            let program = parse_program(&text).unwrap();
+            let result = Interpreter::new(&program).run(&[], &mut Vec::new());
+            assert!(
+                result.is_ok(),
+                "seed {}: {:?}\n{}",
+                seed,
+                result.err(),
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use similar::TextDiff;
+
+use crate::{
+    optimizations::{run_pipeline, PassManager},
+    representation::{format_program, Code, Program, RichProgram},
+};
+
+// Golden tests: every program in a fixture directory goes through a pipeline and its canonical
+// Bril text is compared with the checked-in file next to it. With RUST_BRIL_BLESS set, missing or
+// different golden files are rewritten instead, so updating them after an intended change is
+// `RUST_BRIL_BLESS=1 cargo test` and a look at the diff
+
+/// Environment variable that turns on bless mode
+pub const BLESS_VAR: &str = "RUST_BRIL_BLESS";
+
+/// What became of one fixture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Matched,
+    /// the golden file was written in bless mode
+    Blessed,
+    /// the golden file does not exist
+    Missing,
+    /// unified diff from the golden file to the output
+    Mismatch(String),
+    /// the fixture did not load or the pipeline failed
+    Error(String),
+}
+
+/// Outcomes of all fixtures, in path order
+#[derive(Debug, Clone, Default)]
+pub struct GoldenReport {
+    pub outcomes: Vec<(PathBuf, Outcome)>,
+}
+
+impl GoldenReport {
+    pub fn failures(&self) -> impl Iterator<Item = &(PathBuf, Outcome)> {
+        self.outcomes.iter().filter(|(_, outcome)| {
+            matches!(
+                outcome,
+                Outcome::Missing | Outcome::Mismatch(_) | Outcome::Error(_)
+            )
+        })
+    }
+
+    /// Panic with every failure, for use in `#[test]` functions
+    pub fn assert_passed(&self) {
+        if self.failures().next().is_some() {
+            panic!("{}", self);
+        }
+    }
+}
+
+impl fmt::Display for GoldenReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (path, outcome) in self.failures() {
+            match outcome {
+                Outcome::Missing => writeln!(f, "{}: no golden file", path.display())?,
+                Outcome::Mismatch(diff) => {
+                    writeln!(f, "{}: output changed\n{}", path.display(), diff)?
+                }
+                Outcome::Error(e) => writeln!(f, "{}: {}", path.display(), e)?,
+                Outcome::Matched | Outcome::Blessed => {}
+            }
+        }
+        let failed = self.failures().count();
+        write!(f, "{} fixtures, {} failed", self.outcomes.len(), failed)?;
+        if failed > 0 {
+            write!(f, " (set {}=1 to update the golden files)", BLESS_VAR)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares the output of a pipeline on every `.bril` and `.json` file of a directory with
+/// `<name>.<suffix>` next to it
+pub struct GoldenTest<'a> {
+    directory: PathBuf,
+    passes: &'a PassManager,
+    suffix: String,
+    bless: bool,
+}
+
+impl<'a> GoldenTest<'a> {
+    /// Golden files end in `.golden`, and bless mode follows [`BLESS_VAR`]
+    pub fn new(directory: impl Into<PathBuf>, passes: &'a PassManager) -> Self {
+        Self {
+            directory: directory.into(),
+            passes,
+            suffix: "golden".to_string(),
+            bless: std::env::var_os(BLESS_VAR).is_some_and(|v| !v.is_empty() && v != "0"),
+        }
+    }
+
+    /// Extension of the golden files, so one directory can hold the output of several pipelines
+    pub fn suffix(mut self, suffix: impl Into<String>) -> Self {
+        self.suffix = suffix.into();
+        self
+    }
+
+    pub fn bless(mut self, bless: bool) -> Self {
+        self.bless = bless;
+        self
+    }
+
+    pub fn run(&self) -> GoldenReport {
+        let mut fixtures: Vec<PathBuf> = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| {
+                    let extension = path.extension().and_then(|e| e.to_str());
+                    matches!(extension, Some("bril" | "json"))
+                })
+                .collect(),
+            Err(e) => {
+                let outcome = Outcome::Error(format!("cannot read the fixtures: {}", e));
+                return GoldenReport {
+                    outcomes: vec![(self.directory.clone(), outcome)],
+                };
+            }
+        };
+        fixtures.sort();
+        let outcomes = fixtures
+            .into_iter()
+            .map(|path| {
+                let outcome = self.check(&path);
+                (path, outcome)
+            })
+            .collect();
+        GoldenReport { outcomes }
+    }
+
+    fn check(&self, fixture: &Path) -> Outcome {
+        let output = match self.output(fixture) {
+            Ok(output) => output,
+            Err(e) => return Outcome::Error(e),
+        };
+        let golden = fixture.with_extension(&self.suffix);
+        let expected = std::fs::read_to_string(&golden).ok();
+        if expected.as_deref() == Some(output.as_str()) {
+            return Outcome::Matched;
+        }
+        if self.bless {
+            return match std::fs::write(&golden, &output) {
+                Ok(()) => Outcome::Blessed,
+                Err(e) => Outcome::Error(format!("cannot write {}: {}", golden.display(), e)),
+            };
+        }
+        let Some(expected) = expected else {
+            return Outcome::Missing;
+        };
+        let name = golden.display().to_string();
+        let diff = TextDiff::from_lines(&expected, &output);
+        Outcome::Mismatch(diff.unified_diff().header(&name, "output").to_string())
+    }
+
+    fn output(&self, fixture: &Path) -> Result<String, String> {
+        let program = RichProgram::from_file(fixture).map_err(|e| e.to_string())?;
+        let optimized = run_pipeline(program, self.passes).map_err(|e| e.to_string())?;
+        Ok(canonical_text(&optimized.program))
+    }
+}
+
+/// The program as Bril text, with the blocks the conversion to SSA form invents numbered in the
+/// order they appear in, so that passes adding or removing blocks do not rename the others
+pub fn canonical_text(program: &Program) -> String {
+    let mut program = program.clone();
+    for function in &mut program.functions {
+        let mut renamed: HashMap<String, String> = HashMap::new();
+        for code in &function.instrs {
+            if let Code::Label { label, .. } = code {
+                let stem = ["function_preamble_", "no_label_"]
+                    .into_iter()
+                    .find(|prefix| label.starts_with(prefix));
+                if let Some(stem) = stem {
+                    let name = format!("{}{}", stem, renamed.len());
+                    renamed.insert(label.clone(), name);
+                }
+            }
+        }
+        for code in &mut function.instrs {
+            let labels = match code {
+                Code::Label { label, .. } => {
+                    if let Some(name) = renamed.get(label) {
+                        *label = name.clone();
+                    }
+                    continue;
+                }
+                Code::Value { labels, .. } | Code::Effect { labels, .. } => labels,
+                _ => continue,
+            };
+            for label in labels.iter_mut().flatten() {
+                if let Some(name) = renamed.get(label) {
+                    *label = name.clone();
+                }
+            }
+        }
+    }
+    format_program(&program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blesses_then_matches_then_reports_changes() {
+        let directory =
+            std::env::temp_dir().join(format!("rust_bril_golden_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("sum.bril"),
+            "@main {\n  a: int = const 1;\n  b: int = const 2;\n  c: int = add a b;\n  print c;\n}\n",
+        )
+        .unwrap();
+        let passes = PassManager::preset(1);
+
+        let missing = GoldenTest::new(&directory, &passes).bless(false).run();
+        assert_eq!(missing.outcomes[0].1, Outcome::Missing);
+        let blessed = GoldenTest::new(&directory, &passes).bless(true).run();
+        assert_eq!(blessed.outcomes[0].1, Outcome::Blessed);
+        let golden = std::fs::read_to_string(directory.join("sum.golden")).unwrap();
+        assert!(golden.contains(".function_preamble_0:"));
+        GoldenTest::new(&directory, &passes)
+            .bless(false)
+            .run()
+            .assert_passed();
+
+        let other = PassManager::new();
+        let changed = GoldenTest::new(&directory, &other).bless(false).run();
+        assert!(matches!(changed.outcomes[0].1, Outcome::Mismatch(_)));
+        assert!(changed.to_string().contains(BLESS_VAR));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,15 @@
+#[cfg(any(test, feature = "proptest"))]
+mod arbitrary;
+mod bench;
+mod differential;
+mod fixtures;
+mod fuzz;
+mod golden;
+mod reduce;
+
+pub use bench::*;
+pub use differential::*;
+pub use fixtures::*;
+pub use fuzz::*;
+pub use golden::*;
+pub use reduce::*;
@@ -0,0 +1,326 @@
+use std::{
+    collections::HashSet,
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::{
+    analysis::{check_program, Severity},
+    interpreter::Interpreter,
+    optimizations::{run_pipeline, PassManager},
+    representation::{Code, EffectOp, Program, RichProgram},
+    testing::Run,
+};
+
+// Delta debugging: shrink a program one piece at a time, keeping every smaller program that still
+// fails. The pieces are whole functions, the blocks between labels, runs of instructions that
+// halve down to single ones, and branches that become jumps. Removing a piece often leaves an
+// invalid or nonterminating program, which the failure test has to reject
+
+/// How the passes break a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// a pass returned an error or panicked
+    Pipeline,
+    /// the optimized program behaves differently
+    Divergence,
+}
+
+/// The failure of a program under some passes, to find again in smaller programs
+pub struct Miscompilation<'a> {
+    passes: &'a PassManager,
+    inputs: Vec<Vec<String>>,
+    fuel: u64,
+    pub failure: Failure,
+}
+
+/// the original program runs at most this long before it counts as nonterminating
+const ORIGINAL_FUEL: u64 = 100_000_000;
+
+impl<'a> Miscompilation<'a> {
+    /// How `passes` break `program` for the arguments in `inputs`, if they do
+    pub fn find(
+        program: &Program,
+        passes: &'a PassManager,
+        inputs: &[Vec<String>],
+    ) -> Option<Self> {
+        let mut steps = 0;
+        for args in inputs {
+            steps = steps.max(run(program, args, ORIGINAL_FUEL)?.1);
+        }
+        let mut found = Self {
+            passes,
+            inputs: inputs.to_vec(),
+            // smaller programs may run longer, but not much longer
+            fuel: steps.saturating_mul(4).saturating_add(10_000),
+            failure: Failure::Pipeline,
+        };
+        found.failure = found.check(program)?;
+        Some(found)
+    }
+
+    /// Whether `program` is valid, terminates, and still fails the same way
+    pub fn reproduces(&self, program: &Program) -> bool {
+        self.check(program) == Some(self.failure)
+    }
+
+    fn check(&self, program: &Program) -> Option<Failure> {
+        if !well_formed(program) {
+            return None;
+        }
+        // SSA construction takes a sound program for granted, so a candidate that slips past
+        // `well_formed` may panic there, which is not the failure being looked for
+        let errors = panic::catch_unwind(AssertUnwindSafe(|| check_program(program))).ok()?;
+        if errors.iter().any(|d| d.severity == Severity::Error) {
+            return None;
+        }
+        let originals = self
+            .inputs
+            .iter()
+            .map(|args| run(program, args, self.fuel).map(|(run, _)| run))
+            .collect::<Option<Vec<_>>>()?;
+
+        let rich = RichProgram {
+            original_text: Vec::new(),
+            program: program.clone(),
+        };
+        let optimized = panic::catch_unwind(AssertUnwindSafe(|| run_pipeline(rich, self.passes)));
+        let Ok(Ok(optimized)) = optimized else {
+            return Some(Failure::Pipeline);
+        };
+        let diverges = originals.iter().zip(&self.inputs).any(|(original, args)| {
+            // the optimized program gets twice the budget, so that running out of it is a failure
+            let optimized = run(&optimized.program, args, self.fuel.saturating_mul(2));
+            !optimized.is_some_and(|(optimized, _)| optimized.agrees_with(original))
+        });
+        diverges.then_some(Failure::Divergence)
+    }
+}
+
+/// every jump goes to a label of its function, every call to a function of the program and every
+/// variable is defined somewhere in its function, which the conversion to SSA form takes for
+/// granted
+fn well_formed(program: &Program) -> bool {
+    let functions: HashSet<&str> = program.functions.iter().map(|f| f.name.as_str()).collect();
+    program.functions.iter().all(|function| {
+        let labels: HashSet<&str> = function
+            .instrs
+            .iter()
+            .filter_map(|code| match code {
+                Code::Label { label, .. } => Some(label.as_str()),
+                _ => None,
+            })
+            .collect();
+        let params = function.args.iter().flatten().map(|p| p.name.as_str());
+        let defined: HashSet<&str> = function
+            .instrs
+            .iter()
+            .filter_map(Code::get_destination)
+            .chain(params)
+            .collect();
+        function.instrs.iter().all(|code| {
+            let mut targets = code.get_labels().into_iter().flatten();
+            let mut callees = code.get_funcs().into_iter().flatten();
+            let mut uses = code.get_arguments().into_iter().flatten();
+            targets.all(|label| labels.contains(label.as_str()))
+                && callees.all(|f| functions.contains(f.as_str()))
+                && uses.all(|var| defined.contains(var.as_str()))
+        })
+    })
+}
+
+/// a run of `program` and the instructions it took, or `None` if it did not finish within `fuel`
+fn run(program: &Program, args: &[String], fuel: u64) -> Option<(Run, u64)> {
+    let mut stdout = Vec::new();
+    let result = Interpreter::new(program)
+        .fuel(Some(fuel))
+        .run(args, &mut stdout);
+    let steps = match &result {
+        Err(e) if e.is_limit() => return None,
+        Ok(execution) => execution.counts.iter().flatten().sum(),
+        Err(_) => 0,
+    };
+    let run = Run {
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        instructions: result.as_ref().ok().map(|e| e.profile.total),
+        error: result.err().map(|e| e.to_string()),
+        timed_out: false,
+    };
+    Some((run, steps))
+}
+
+/// Shrink `program` as long as `interesting` holds for the smaller program. `interesting` should
+/// hold for `program` itself
+pub fn reduce(program: &Program, mut interesting: impl FnMut(&Program) -> bool) -> Program {
+    let mut best = program.clone();
+    loop {
+        let before = size(&best);
+        let mut changed = remove_functions(&mut best, &mut interesting);
+        for f in 0..best.functions.len() {
+            changed |= remove_blocks(&mut best, f, &mut interesting);
+            changed |= remove_runs(&mut best, f, &mut interesting);
+            changed |= straighten_branches(&mut best, f, &mut interesting);
+        }
+        log::info!("reduced {} instructions to {}", before, size(&best));
+        if !changed {
+            return best;
+        }
+    }
+}
+
+fn size(program: &Program) -> usize {
+    program.functions.iter().map(|f| f.instrs.len()).sum()
+}
+
+/// replace `best` with `candidate` if it is still interesting
+fn attempt(
+    best: &mut Program,
+    candidate: Program,
+    interesting: &mut impl FnMut(&Program) -> bool,
+) -> bool {
+    if !interesting(&candidate) {
+        return false;
+    }
+    *best = candidate;
+    true
+}
+
+fn remove_functions(best: &mut Program, interesting: &mut impl FnMut(&Program) -> bool) -> bool {
+    let mut changed = false;
+    for f in (0..best.functions.len()).rev() {
+        if best.functions[f].name == "main" {
+            continue;
+        }
+        let mut candidate = best.clone();
+        candidate.functions.remove(f);
+        changed |= attempt(best, candidate, interesting);
+    }
+    changed
+}
+
+/// remove each label together with the instructions up to the next label
+fn remove_blocks(
+    best: &mut Program,
+    f: usize,
+    interesting: &mut impl FnMut(&Program) -> bool,
+) -> bool {
+    let mut changed = false;
+    let labels: Vec<usize> = best.functions[f]
+        .instrs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, code)| code.is_label().then_some(i))
+        .collect();
+    for &start in labels.iter().rev() {
+        let instrs = &best.functions[f].instrs;
+        let end = (start + 1..instrs.len())
+            .find(|&i| instrs[i].is_label())
+            .unwrap_or(instrs.len());
+        let mut candidate = best.clone();
+        candidate.functions[f].instrs.drain(start..end);
+        changed |= attempt(best, candidate, interesting);
+    }
+    changed
+}
+
+/// remove runs of instructions, halving their length down to single instructions
+fn remove_runs(
+    best: &mut Program,
+    f: usize,
+    interesting: &mut impl FnMut(&Program) -> bool,
+) -> bool {
+    let mut changed = false;
+    let mut length = (best.functions[f].instrs.len() / 2).max(1);
+    loop {
+        let mut start = 0;
+        while start < best.functions[f].instrs.len() {
+            let end = (start + length).min(best.functions[f].instrs.len());
+            let mut candidate = best.clone();
+            candidate.functions[f].instrs.drain(start..end);
+            match attempt(best, candidate, interesting) {
+                true => changed = true,
+                false => start += length,
+            }
+        }
+        if length == 1 {
+            return changed;
+        }
+        length /= 2;
+    }
+}
+
+/// turn each `br` into a `jmp` to one of its targets
+fn straighten_branches(
+    best: &mut Program,
+    f: usize,
+    interesting: &mut impl FnMut(&Program) -> bool,
+) -> bool {
+    let mut changed = false;
+    for i in 0..best.functions[f].instrs.len() {
+        let Code::Effect {
+            op: EffectOp::Br,
+            labels: Some(labels),
+            ..
+        } = &best.functions[f].instrs[i]
+        else {
+            continue;
+        };
+        for label in labels.clone() {
+            let mut candidate = best.clone();
+            if let Code::Effect {
+                op, args, labels, ..
+            } = &mut candidate.functions[f].instrs[i]
+            {
+                *op = EffectOp::Jmp;
+                *args = None;
+                *labels = Some(Box::new([label]));
+            }
+            if attempt(best, candidate, interesting) {
+                changed = true;
+                break;
+            }
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::representation::parse_program;
+
+    #[test]
+    fn keeps_only_what_the_failure_needs() {
+        let program = parse_program(
+            "@double(x: int): int {\n  y: int = add x x;\n  ret y;\n}\n@main {\n  a: int = const 40;\n  two: int = const 2;\n  b: bool = lt a two;\n  br b .small .big;\n.small:\n  print two;\n  jmp .end;\n.big:\n  c: int = add a two;\n  d: int = call @double c;\n  print c;\n.end:\n  print a;\n}\n",
+        )
+        .unwrap();
+        let prints_42 = |p: &Program| Run::of(p, &[]).stdout.starts_with("42\n");
+        let reduced = reduce(&program, prints_42);
+
+        assert!(prints_42(&reduced));
+        assert_eq!(reduced.functions.len(), 1);
+        let instrs: Vec<String> = reduced.functions[0]
+            .instrs
+            .iter()
+            .map(Code::get_opcode_string)
+            .collect();
+        assert_eq!(instrs, ["const", "const", "add", "print"]);
+    }
+
+    #[test]
+    fn rejects_uses_without_a_reaching_definition() {
+        let passes = PassManager::from_names(["dce"]).unwrap();
+        let failure = Miscompilation {
+            passes: &passes,
+            inputs: vec![Vec::new()],
+            fuel: 1_000,
+            failure: Failure::Pipeline,
+        };
+        // `c` is defined, so the candidate is well formed, but not before the branch reads it
+        let candidate = parse_program(
+            "@main {\n  br c .a .b;\n.a:\n  c: bool = const true;\n  print c;\n.b:\n  ret;\n}\n",
+        )
+        .unwrap();
+        assert!(!failure.reproduces(&candidate));
+    }
+}
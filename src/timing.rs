@@ -0,0 +1,160 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use web_time::Instant;
+
+// Wall clock time per phase (parsing, SSA construction, each pass, emission) and function.
+// `Timer`s always log their duration at debug level; they are only recorded for the report once
+// `enable` has been called, which `--time-passes` does. A timer can also be told the size of the
+// function it times, and the report shows the largest size each phase saw, so a pass that grows
+// code (unrolling, inlining) stands out next to the time it took
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static RECORDS: Mutex<Vec<Record>> = Mutex::new(Vec::new());
+static STARTED: Mutex<Option<Instant>> = Mutex::new(None);
+
+struct Record {
+    phase: String,
+    function: Option<String>,
+    elapsed: Duration,
+    instructions: Option<usize>,
+}
+
+/// Start recording timers for [`report`]
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+    *STARTED.lock().unwrap() = Some(Instant::now());
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Measures a phase from creation until it is dropped
+pub struct Timer {
+    phase: &'static str,
+    function: Option<String>,
+    start: Instant,
+    instructions: Option<usize>,
+}
+
+impl Timer {
+    /// Time a whole-program phase
+    pub fn new(phase: &'static str) -> Self {
+        Self {
+            phase,
+            function: None,
+            start: Instant::now(),
+            instructions: None,
+        }
+    }
+
+    /// Time a phase of one function
+    pub fn function(phase: &'static str, function: &str) -> Self {
+        Self {
+            phase,
+            function: Some(function.to_string()),
+            start: Instant::now(),
+            instructions: None,
+        }
+    }
+
+    /// Note that the function has `n` instructions, keeping the largest count seen
+    pub fn instructions(&mut self, n: usize) {
+        self.instructions = self.instructions.max(Some(n));
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        match &self.function {
+            Some(function) => log::debug!("{} of {} took {:?}", self.phase, function, elapsed),
+            None => log::debug!("{} took {:?}", self.phase, elapsed),
+        }
+        if is_enabled() {
+            RECORDS.lock().unwrap().push(Record {
+                phase: self.phase.to_string(),
+                function: self.function.take(),
+                elapsed,
+                instructions: self.instructions,
+            });
+        }
+    }
+}
+
+fn millis(d: Duration) -> String {
+    format!("{:.3}ms", d.as_secs_f64() * 1000.0)
+}
+
+/// Breakdown of the recorded time, per phase and then per phase and function, slowest first,
+/// with the peak instruction count of each function where it was noted. Phases nest (dominance
+/// is part of SSA construction), so the percentages do not add up to 100
+pub fn report() -> String {
+    let records = RECORDS.lock().unwrap();
+    let total = STARTED
+        .lock()
+        .unwrap()
+        .map(|start| start.elapsed())
+        .unwrap_or_default();
+    let percent = |d: Duration| match total.is_zero() {
+        true => 0.0,
+        false => 100.0 * d.as_secs_f64() / total.as_secs_f64(),
+    };
+
+    let mut phases: HashMap<&str, (Duration, usize)> = HashMap::new();
+    let mut functions: HashMap<(&str, &str), (Duration, Option<usize>)> = HashMap::new();
+    for record in records.iter() {
+        let entry = phases.entry(&record.phase).or_default();
+        entry.0 += record.elapsed;
+        entry.1 += 1;
+        if let Some(function) = &record.function {
+            let entry = functions.entry((&record.phase, function)).or_default();
+            entry.0 += record.elapsed;
+            entry.1 = entry.1.max(record.instructions);
+        }
+    }
+    let mut phases: Vec<_> = phases.into_iter().collect();
+    phases.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(a.0.cmp(b.0)));
+    let mut functions: Vec<_> = functions.into_iter().collect();
+    functions.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(a.0.cmp(&b.0)));
+
+    let mut out = format!("===== time report (total {}) =====\n", millis(total));
+    out.push_str(&format!(
+        "{:>12}  {:>6}  {:>5}  phase\n",
+        "time", "%", "count"
+    ));
+    for (phase, (elapsed, count)) in phases {
+        out.push_str(&format!(
+            "{:>12}  {:>6.1}  {:>5}  {}\n",
+            millis(elapsed),
+            percent(elapsed),
+            count,
+            phase
+        ));
+    }
+    if !functions.is_empty() {
+        out.push_str(&format!(
+            "\n{:>12}  {:>6}  {:>6}  phase / function\n",
+            "time", "%", "peak"
+        ));
+        for ((phase, function), (elapsed, instructions)) in functions {
+            let peak = instructions.map_or("-".to_string(), |n| n.to_string());
+            out.push_str(&format!(
+                "{:>12}  {:>6.1}  {:>6}  {} / @{}\n",
+                millis(elapsed),
+                percent(elapsed),
+                peak,
+                phase,
+                function
+            ));
+        }
+    }
+    out
+}
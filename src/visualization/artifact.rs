@@ -0,0 +1,100 @@
+use std::str::FromStr;
+
+use crate::{
+    analysis::{program_facts, CallGraph, InterferenceGraph, LoopInfo},
+    dataflow::WorklistResult,
+    representation::{RichAbstractProgram, RichProgram},
+    visualization::{html_report, Graph},
+};
+
+/// Analysis artifacts that can be rendered instead of the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Artifact {
+    CfgDot,
+    CfgMermaid,
+    DomDot,
+    DomMermaid,
+    CallgraphDot,
+    LoopsDot,
+    InterferenceDot,
+    Html,
+    AnalysisJson,
+}
+
+impl FromStr for Artifact {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cfg-dot" => Ok(Artifact::CfgDot),
+            "cfg-mermaid" => Ok(Artifact::CfgMermaid),
+            "dom-dot" => Ok(Artifact::DomDot),
+            "dom-mermaid" => Ok(Artifact::DomMermaid),
+            "callgraph-dot" => Ok(Artifact::CallgraphDot),
+            "loops-dot" => Ok(Artifact::LoopsDot),
+            "interference-dot" => Ok(Artifact::InterferenceDot),
+            "html" => Ok(Artifact::Html),
+            "analysis-json" => Ok(Artifact::AnalysisJson),
+            _ => Err(format!("unknown artifact '{}'", s)),
+        }
+    }
+}
+
+/// Render `kind` for the whole program, per-function artifacts are ordered by function name.
+/// The HTML report diffs against `original_program` if given
+pub fn render_artifact(
+    kind: Artifact,
+    original_program: Option<&RichProgram>,
+    abstract_program: &RichAbstractProgram,
+) -> WorklistResult<String> {
+    match kind {
+        Artifact::CallgraphDot => {
+            return Ok(Graph::call_graph(&CallGraph::from(&abstract_program.program)).to_dot())
+        }
+        Artifact::Html => {
+            let unoptimized;
+            let original_program = match original_program {
+                Some(p) => p,
+                None => {
+                    unoptimized = abstract_program.clone().into_program();
+                    &unoptimized
+                }
+            };
+            return html_report(original_program, abstract_program);
+        }
+        Artifact::AnalysisJson => {
+            let facts = program_facts(&abstract_program.program)?;
+            return Ok(format!(
+                "{}\n",
+                serde_json::to_string_pretty(&facts).unwrap()
+            ));
+        }
+        _ => {}
+    }
+
+    let mut functions: Vec<_> = abstract_program.program.functions.values().collect();
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let rendered = functions
+        .into_iter()
+        .map(|af| {
+            Ok(match kind {
+                Artifact::CfgDot => Graph::control_flow(af).to_dot(),
+                Artifact::CfgMermaid => mermaid_block(&Graph::control_flow(af)),
+                Artifact::DomDot => Graph::dominator_tree(af).to_dot(),
+                Artifact::DomMermaid => mermaid_block(&Graph::dominator_tree(af)),
+                Artifact::LoopsDot => Graph::loop_nest(af, &LoopInfo::from(af)).to_dot(),
+                Artifact::InterferenceDot => InterferenceGraph::build(&mut af.clone())?.to_dot(),
+                Artifact::CallgraphDot | Artifact::Html | Artifact::AnalysisJson => {
+                    unreachable!("{:?} is rendered for the whole program", kind)
+                }
+            })
+        })
+        .collect::<WorklistResult<Vec<_>>>()?;
+    Ok(rendered.join("\n"))
+}
+
+/// wrap a Mermaid flowchart in a fenced block so it renders when pasted into Markdown
+fn mermaid_block(graph: &Graph) -> String {
+    format!("```mermaid\n{}```\n", graph.to_mermaid())
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use similar::{ChangeTag, TextDiff};
+
+use crate::representation::{format_function, Program};
+
+// Per-function comparison of two versions of a program, in Bril text form. Functions are matched
+// by name, so a pass that renames variables shows up as changed lines rather than a new function
+
+/// How a function differs between two programs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionChange {
+    Unchanged,
+    Changed,
+    Added,
+    Removed,
+}
+
+/// One function before and after, as Bril text. A missing side is empty
+#[derive(Debug, Clone)]
+pub struct FunctionDiff {
+    pub name: String,
+    pub change: FunctionChange,
+    pub before: String,
+    pub after: String,
+}
+
+impl FunctionDiff {
+    /// number of (removed, added) lines
+    pub fn line_counts(&self) -> (usize, usize) {
+        let diff = TextDiff::from_lines(&self.before, &self.after);
+        diff.iter_all_changes()
+            .fold((0, 0), |(removed, added), change| match change.tag() {
+                ChangeTag::Delete => (removed + 1, added),
+                ChangeTag::Insert => (removed, added + 1),
+                ChangeTag::Equal => (removed, added),
+            })
+    }
+
+    /// Unified diff with 3 lines of context, in ANSI colors if `color` is set. Empty when the
+    /// function is unchanged
+    pub fn to_unified(&self, color: bool) -> String {
+        if self.change == FunctionChange::Unchanged {
+            return String::new();
+        }
+        let paint = |code: &str, line: &str| match color {
+            true => format!("\x1b[{}m{}\x1b[0m\n", code, line),
+            false => format!("{}\n", line),
+        };
+
+        let mut out = paint("1", &format!("--- @{} (before)", self.name));
+        out.push_str(&paint("1", &format!("+++ @{} (after)", self.name)));
+        let diff = TextDiff::from_lines(&self.before, &self.after);
+        for hunk in diff.unified_diff().context_radius(3).iter_hunks() {
+            out.push_str(&paint("36", &hunk.header().to_string()));
+            for change in hunk.iter_changes() {
+                let line = change.to_string_lossy();
+                let line = line.trim_end_matches('\n');
+                match change.tag() {
+                    ChangeTag::Delete => out.push_str(&paint("31", &format!("-{}", line))),
+                    ChangeTag::Insert => out.push_str(&paint("32", &format!("+{}", line))),
+                    ChangeTag::Equal => out.push_str(&format!(" {}\n", line)),
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Compare every function of `before` with the function of the same name in `after`, sorted by
+/// name
+pub fn diff_programs(before: &Program, after: &Program) -> Vec<FunctionDiff> {
+    let mut functions: BTreeMap<&str, (Option<String>, Option<String>)> = BTreeMap::new();
+    for function in &before.functions {
+        functions.entry(&function.name).or_default().0 = Some(format_function(function));
+    }
+    for function in &after.functions {
+        functions.entry(&function.name).or_default().1 = Some(format_function(function));
+    }
+
+    functions
+        .into_iter()
+        .map(|(name, (before, after))| {
+            let change = match (&before, &after) {
+                (Some(b), Some(a)) if a == b => FunctionChange::Unchanged,
+                (Some(_), Some(_)) => FunctionChange::Changed,
+                (None, _) => FunctionChange::Added,
+                (_, None) => FunctionChange::Removed,
+            };
+            FunctionDiff {
+                name: name.to_string(),
+                change,
+                before: before.unwrap_or_default(),
+                after: after.unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::representation::parse_program;
+
+    #[test]
+    fn pairs_functions_by_name() {
+        let before =
+            parse_program("@main {\n  a: int = const 1;\n  print a;\n}\n@dead {\n  ret;\n}\n")
+                .unwrap();
+        let after = parse_program("@main {\n  print a;\n}\n@dead {\n  ret;\n}\n").unwrap();
+        let diffs = diff_programs(&before, &after);
+
+        assert_eq!(diffs[0].name, "dead");
+        assert_eq!(diffs[0].change, FunctionChange::Unchanged);
+        assert_eq!(diffs[1].change, FunctionChange::Changed);
+        assert_eq!(diffs[1].line_counts(), (1, 0));
+        assert!(diffs[1]
+            .to_unified(false)
+            .contains("-  a: int = const 1;\n"));
+    }
+}
@@ -0,0 +1,283 @@
+use crate::{
+    analysis::{CallGraph, LoopInfo},
+    representation::{format_instruction, format_type, AbstractFunction, BasicBlock, Terminator},
+};
+
+// format-independent description of the graphs we visualize, so that every exporter (DOT,
+// Mermaid) shares the same extraction code
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: String,
+    /// may span several lines
+    pub label: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub label: Option<String>,
+}
+
+/// Group of nodes drawn together, e.g. mutually recursive functions
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    pub label: String,
+    pub nodes: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Graph {
+    pub name: String,
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    pub clusters: Vec<Cluster>,
+}
+
+impl Graph {
+    fn new(name: String) -> Self {
+        Graph {
+            name,
+            nodes: vec![],
+            edges: vec![],
+            clusters: vec![],
+        }
+    }
+
+    fn edge(&mut self, from: String, to: String, label: Option<String>) {
+        self.edges.push(Edge { from, to, label });
+    }
+
+    /// CFG of a function, one node per basic block with its code; branch edges are labelled
+    pub fn control_flow(af: &AbstractFunction) -> Self {
+        let mut graph = Graph::new(af.name.clone());
+        for block in &af.cfg.basic_blocks {
+            graph.nodes.push(Node {
+                id: format!("b{}", block.id),
+                label: block_lines(block).join("\n"),
+            });
+
+            for succ in &af.cfg.successors[block.id] {
+                let label = match &block.terminator {
+                    Terminator::Br(t, _, _) if af.cfg.label_map.get(t) == Some(&succ) => {
+                        Some("true".to_string())
+                    }
+                    Terminator::Br(_, f, _) if af.cfg.label_map.get(f) == Some(&succ) => {
+                        Some("false".to_string())
+                    }
+                    _ => None,
+                };
+                graph.edge(format!("b{}", block.id), format!("b{}", succ), label);
+            }
+        }
+        graph
+    }
+
+    /// dominator tree of a function, edges go from the immediate dominator to the block
+    pub fn dominator_tree(af: &AbstractFunction) -> Self {
+        let mut graph = Graph::new(af.name.clone());
+        for block in &af.cfg.basic_blocks {
+            graph.nodes.push(Node {
+                id: format!("b{}", block.id),
+                label: block.label.clone(),
+            });
+            if let Some(parent) = af.dominance_info().get_immediate_dominator(block.id) {
+                graph.edge(format!("b{}", parent), format!("b{}", block.id), None);
+            }
+        }
+        graph
+    }
+
+    /// loop nest tree of a function, rooted at the function itself
+    pub fn loop_nest(af: &AbstractFunction, loop_info: &LoopInfo) -> Self {
+        let labels = |blocks: &std::collections::BTreeSet<usize>| {
+            blocks
+                .iter()
+                .map(|&b| format!(".{}", af.cfg.basic_blocks[b].label))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        let mut graph = Graph::new(format!("loops_{}", af.name));
+        graph.nodes.push(Node {
+            id: "root".to_string(),
+            label: format!("@{}", af.name),
+        });
+        for (i, l) in loop_info.loops.iter().enumerate() {
+            graph.nodes.push(Node {
+                id: format!("l{}", i),
+                label: [
+                    format!("header: .{}", af.cfg.basic_blocks[l.header].label),
+                    format!("depth: {}", l.depth),
+                    format!("latches: {}", labels(&l.latches)),
+                    format!("exits: {}", labels(&l.exits)),
+                    format!("blocks: {}", labels(&l.body)),
+                ]
+                .join("\n"),
+            });
+            let parent = l.parent.map_or("root".to_string(), |p| format!("l{}", p));
+            graph.edge(parent, format!("l{}", i), None);
+        }
+        graph
+    }
+
+    /// call graph of a program, edges labelled with static call counts and recursive functions
+    /// clustered together
+    pub fn call_graph(call_graph: &CallGraph) -> Self {
+        // callees outside of the program still get a node
+        let mut names: Vec<&String> = call_graph.functions.iter().collect();
+        for (_, callee) in call_graph.calls.keys() {
+            if !names.contains(&callee) {
+                names.push(callee);
+            }
+        }
+        let id = |f: &str| format!("f{}", names.iter().position(|g| *g == f).unwrap());
+
+        let mut graph = Graph::new("callgraph".to_string());
+        for f in names.iter().copied() {
+            graph.nodes.push(Node {
+                id: id(f),
+                label: f.clone(),
+            });
+        }
+        for scc in call_graph.sccs() {
+            let recursive = scc.len() > 1
+                || call_graph
+                    .calls
+                    .contains_key(&(scc[0].clone(), scc[0].clone()));
+            if recursive {
+                graph.clusters.push(Cluster {
+                    label: format!("scc {}", graph.clusters.len()),
+                    nodes: scc.iter().map(|f| id(f)).collect(),
+                });
+            }
+        }
+        for ((caller, callee), count) in &call_graph.calls {
+            graph.edge(id(caller), id(callee), Some(count.to_string()));
+        }
+        graph
+    }
+
+    /// Render as a Graphviz DOT digraph
+    pub fn to_dot(&self) -> String {
+        let node = |n: &Node, indent: &str| {
+            format!("{}{} [label=\"{}\"];\n", indent, n.id, escape_dot(&n.label))
+        };
+
+        let mut dot = format!(
+            "digraph \"{}\" {{\n  node [shape=box];\n",
+            escape_dot(&self.name)
+        );
+        for (i, cluster) in self.clusters.iter().enumerate() {
+            dot.push_str(&format!(
+                "  subgraph cluster_{} {{\n    style=dashed;\n    label=\"{}\";\n",
+                i,
+                escape_dot(&cluster.label)
+            ));
+            for n in self.nodes.iter().filter(|n| cluster.nodes.contains(&n.id)) {
+                dot.push_str(&node(n, "    "));
+            }
+            dot.push_str("  }\n");
+        }
+        for n in self.nodes.iter().filter(|n| !self.in_cluster(&n.id)) {
+            dot.push_str(&node(n, "  "));
+        }
+        for e in &self.edges {
+            match &e.label {
+                Some(label) => dot.push_str(&format!(
+                    "  {} -> {} [label=\"{}\"];\n",
+                    e.from,
+                    e.to,
+                    escape_dot(label)
+                )),
+                None => dot.push_str(&format!("  {} -> {};\n", e.from, e.to)),
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render as a Mermaid flowchart, ready to paste into a ```mermaid block
+    pub fn to_mermaid(&self) -> String {
+        let node = |n: &Node, indent: &str| {
+            format!("{}{}[\"{}\"]\n", indent, n.id, escape_mermaid(&n.label))
+        };
+
+        let mut out = format!("flowchart TD\n  %% {}\n", self.name);
+        for (i, cluster) in self.clusters.iter().enumerate() {
+            out.push_str(&format!(
+                "  subgraph cluster_{} [\"{}\"]\n",
+                i,
+                escape_mermaid(&cluster.label)
+            ));
+            for n in self.nodes.iter().filter(|n| cluster.nodes.contains(&n.id)) {
+                out.push_str(&node(n, "    "));
+            }
+            out.push_str("  end\n");
+        }
+        for n in self.nodes.iter().filter(|n| !self.in_cluster(&n.id)) {
+            out.push_str(&node(n, "  "));
+        }
+        for e in &self.edges {
+            match &e.label {
+                Some(label) => out.push_str(&format!(
+                    "  {} -->|\"{}\"| {}\n",
+                    e.from,
+                    escape_mermaid(label),
+                    e.to
+                )),
+                None => out.push_str(&format!("  {} --> {}\n", e.from, e.to)),
+            }
+        }
+        out
+    }
+
+    fn in_cluster(&self, id: &str) -> bool {
+        self.clusters
+            .iter()
+            .any(|c| c.nodes.iter().any(|n| n == id))
+    }
+}
+
+/// lines of a block as shown in the CFG: label, phi nodes, hoisted code, body and terminator
+pub(crate) fn block_lines(block: &BasicBlock) -> Vec<String> {
+    let mut lines = vec![format!(".{}:", block.label)];
+    for phi in &block.phi_nodes {
+        let args: Vec<String> = phi
+            .phi_args
+            .iter()
+            .map(|(var, label)| format!("{} .{}", var, label))
+            .collect();
+        lines.push(format!(
+            "{}: {} = phi {};",
+            phi.dest,
+            format_type(&phi.phi_type),
+            args.join(" ")
+        ));
+    }
+    for code in block.preheader.iter().chain(block.instructions.iter()) {
+        lines.push(format_instruction(code));
+    }
+    match &block.terminator {
+        Terminator::Passthrough => {}
+        Terminator::Ret(code)
+        | Terminator::Jmp(_, code)
+        | Terminator::Br(_, _, code)
+        | Terminator::Speculate(_, code) => lines.push(format_instruction(code)),
+    }
+    lines
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape_mermaid(s: &str) -> String {
+    s.replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+        .replace('\n', "<br>")
+}
@@ -0,0 +1,255 @@
+use std::collections::{HashMap, HashSet};
+
+use similar::TextDiff;
+
+use crate::{
+    dataflow::{
+        run_parallel_dataflow_analysis, LiveVariables, ReachingDefinitions, WorklistResult,
+    },
+    representation::{
+        format_function, AbstractFunction, Function, RichAbstractProgram, RichProgram,
+    },
+    visualization::block_lines,
+};
+
+// single-file HTML report: CFG (laid out in the browser), per-block dataflow facts, SSA form and
+// a diff of each function before and after optimization
+
+/// Render a self-contained HTML report comparing `before` with the optimized `after` program
+pub fn html_report(before: &RichProgram, after: &RichAbstractProgram) -> WorklistResult<String> {
+    let ssa_functions = function_map(after.clone().into_ssa_program().program.functions);
+    let optimized_functions = function_map(after.clone().into_program().program.functions);
+    let original_functions = function_map(before.program.functions.clone());
+
+    let mut functions: Vec<_> = after.program.functions.values().collect();
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut body = String::new();
+    for af in functions {
+        let before_text = original_functions
+            .get(&af.name)
+            .map(format_function)
+            .unwrap_or_default();
+        let after_text = optimized_functions
+            .get(&af.name)
+            .map(format_function)
+            .unwrap_or_default();
+        let ssa_text = ssa_functions
+            .get(&af.name)
+            .map(format_function)
+            .unwrap_or_default();
+
+        body.push_str(&format!(
+            "<details open>\n<summary>@{}</summary>\n",
+            escape_html(&af.name)
+        ));
+        body.push_str(&section(
+            "Control flow graph",
+            &format!(
+                "<div class=\"cfg\" data-graph=\"{}\"></div>",
+                escape_html(&cfg_json(af))
+            ),
+            true,
+        ));
+        body.push_str(&section("Dataflow", &dataflow_table(af)?, false));
+        body.push_str(&section(
+            "SSA form",
+            &format!("<pre>{}</pre>", escape_html(&ssa_text)),
+            false,
+        ));
+        body.push_str(&section(
+            "Optimization diff",
+            &diff_html(&before_text, &after_text),
+            false,
+        ));
+        body.push_str("</details>\n");
+    }
+
+    Ok(format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>rust_bril report</title>\n<style>{}</style>\n</head>\n<body>\n<h1>rust_bril report</h1>\n{}<script>{}</script>\n</body>\n</html>\n",
+        STYLE, body, SCRIPT
+    ))
+}
+
+fn function_map(functions: Vec<Function>) -> HashMap<String, Function> {
+    functions.into_iter().map(|f| (f.name.clone(), f)).collect()
+}
+
+fn section(title: &str, content: &str, open: bool) -> String {
+    format!(
+        "<details{}>\n<summary>{}</summary>\n{}\n</details>\n",
+        if open { " open" } else { "" },
+        title,
+        content
+    )
+}
+
+fn cfg_json(af: &AbstractFunction) -> String {
+    let nodes: Vec<_> = af
+        .cfg
+        .basic_blocks
+        .iter()
+        .map(|block| serde_json::json!({ "id": block.id, "lines": block_lines(block) }))
+        .collect();
+    let mut edges: Vec<(usize, usize)> = af
+        .cfg
+        .successors
+        .iter()
+        .enumerate()
+        .flat_map(|(from, succs)| succs.iter().map(move |to| (from, to)))
+        .collect();
+    edges.sort();
+    serde_json::json!({ "nodes": nodes, "edges": edges }).to_string()
+}
+
+fn sorted(set: &HashSet<String>) -> String {
+    let mut vars: Vec<_> = set.iter().cloned().collect();
+    vars.sort();
+    vars.join(" ")
+}
+
+fn dataflow_table(af: &AbstractFunction) -> WorklistResult<String> {
+    let mut af = af.clone();
+    let live = run_parallel_dataflow_analysis::<LiveVariables>(&mut af)?;
+    let reaching = run_parallel_dataflow_analysis::<ReachingDefinitions>(&mut af)?;
+
+    let reaching_text = |defs: &HashMap<String, HashSet<usize>>| {
+        let mut entries: Vec<String> = defs
+            .iter()
+            .map(|(var, blocks)| {
+                let mut blocks: Vec<_> = blocks
+                    .iter()
+                    .map(|&b| format!(".{}", af.cfg.basic_blocks[b].label))
+                    .collect();
+                blocks.sort();
+                format!(
+                    "{} &larr; {}",
+                    escape_html(var),
+                    escape_html(&blocks.join(" "))
+                )
+            })
+            .collect();
+        entries.sort();
+        entries.join("<br>")
+    };
+
+    let mut table = String::from(
+        "<table>\n<tr><th>block</th><th>live in</th><th>live out</th><th>reaching definitions in</th><th>reaching definitions out</th></tr>\n",
+    );
+    for block in &af.cfg.basic_blocks {
+        let (live_in, live_out) = &live[&block.id];
+        let (reach_in, reach_out) = &reaching[&block.id];
+        table.push_str(&format!(
+            "<tr><td>.{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&block.label),
+            escape_html(&sorted(live_in)),
+            escape_html(&sorted(live_out)),
+            reaching_text(reach_in),
+            reaching_text(reach_out),
+        ));
+    }
+    table.push_str("</table>");
+    Ok(table)
+}
+
+fn diff_html(before: &str, after: &str) -> String {
+    let diff = TextDiff::from_lines(before, after);
+    let mut out = String::from("<pre class=\"diff\">");
+    for change in diff.iter_all_changes() {
+        let (class, sign) = match change.tag() {
+            similar::ChangeTag::Delete => ("del", "-"),
+            similar::ChangeTag::Insert => ("ins", "+"),
+            similar::ChangeTag::Equal => ("eq", " "),
+        };
+        out.push_str(&format!(
+            "<span class=\"{}\">{}{}</span>",
+            class,
+            sign,
+            escape_html(&change.to_string())
+        ));
+    }
+    out.push_str("</pre>");
+    out
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; }
+summary { cursor: pointer; font-weight: bold; margin: 0.4em 0; }
+details details { margin-left: 1.5em; }
+pre { background: #f6f8fa; padding: 0.8em; }
+table { border-collapse: collapse; font-family: monospace; }
+td, th { border: 1px solid #ccc; padding: 0.2em 0.5em; vertical-align: top; }
+.diff .del { background: #ffebe9; display: block; }
+.diff .ins { background: #e6ffec; display: block; }
+.diff .eq { display: block; }
+.cfg svg text { font-family: monospace; font-size: 12px; }
+"#;
+
+// lays the CFG out in layers by breadth-first depth from the entry block; edges that go back up
+// (loops) are drawn dashed
+const SCRIPT: &str = r#"
+document.querySelectorAll('.cfg').forEach(function (el) {
+  var g = JSON.parse(el.dataset.graph);
+  var ns = 'http://www.w3.org/2000/svg';
+  var succ = {}, depth = {};
+  g.edges.forEach(function (e) { (succ[e[0]] = succ[e[0]] || []).push(e[1]); });
+  var queue = [0]; depth[0] = 0;
+  while (queue.length) {
+    var n = queue.shift();
+    (succ[n] || []).forEach(function (s) {
+      if (!(s in depth)) { depth[s] = depth[n] + 1; queue.push(s); }
+    });
+  }
+  var maxDepth = Math.max.apply(null, Object.values(depth));
+  var layers = [];
+  g.nodes.forEach(function (node) {
+    if (!(node.id in depth)) depth[node.id] = ++maxDepth;
+    (layers[depth[node.id]] = layers[depth[node.id]] || []).push(node);
+  });
+  var pos = {}, y = 10, width = 0;
+  layers.forEach(function (layer) {
+    var x = 10, tallest = 0;
+    layer.forEach(function (node) {
+      var w = 16 + 7.2 * Math.max.apply(null, node.lines.map(function (l) { return l.length; }));
+      var h = 8 + 15 * node.lines.length;
+      pos[node.id] = { x: x, y: y, w: w, h: h, lines: node.lines };
+      x += w + 30; tallest = Math.max(tallest, h);
+    });
+    width = Math.max(width, x); y += tallest + 40;
+  });
+  var svg = document.createElementNS(ns, 'svg');
+  svg.setAttribute('width', width); svg.setAttribute('height', y);
+  svg.innerHTML = '<defs><marker id="arrow" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="6" markerHeight="6" orient="auto"><path d="M0,0L10,5L0,10z"/></marker></defs>';
+  g.edges.forEach(function (e) {
+    var a = pos[e[0]], b = pos[e[1]];
+    var line = document.createElementNS(ns, 'line');
+    line.setAttribute('x1', a.x + a.w / 2); line.setAttribute('y1', a.y + a.h);
+    line.setAttribute('x2', b.x + b.w / 2); line.setAttribute('y2', b.y);
+    line.setAttribute('stroke', '#555'); line.setAttribute('marker-end', 'url(#arrow)');
+    if (depth[e[1]] <= depth[e[0]]) line.setAttribute('stroke-dasharray', '4 3');
+    svg.appendChild(line);
+  });
+  Object.values(pos).forEach(function (p) {
+    var rect = document.createElementNS(ns, 'rect');
+    rect.setAttribute('x', p.x); rect.setAttribute('y', p.y);
+    rect.setAttribute('width', p.w); rect.setAttribute('height', p.h);
+    rect.setAttribute('fill', '#fff'); rect.setAttribute('stroke', '#333');
+    svg.appendChild(rect);
+    p.lines.forEach(function (l, i) {
+      var text = document.createElementNS(ns, 'text');
+      text.setAttribute('x', p.x + 8); text.setAttribute('y', p.y + 17 + 15 * i);
+      text.textContent = l;
+      svg.appendChild(text);
+    });
+  });
+  el.appendChild(svg);
+});
+"#;
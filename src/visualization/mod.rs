@@ -0,0 +1,9 @@
+mod artifact;
+mod diff;
+mod graph;
+mod html;
+
+pub use artifact::*;
+pub use diff::*;
+pub use graph::*;
+pub use html::*;
@@ -0,0 +1,80 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    analysis::program_facts,
+    optimizations::{run_pipeline_in_ssa, PassManager},
+    representation::{BrilFrontend, ProgramFormat, RichAbstractProgram, RichProgram},
+    visualization::{render_artifact, Artifact},
+};
+
+// browser entry points. Programs are accepted as Bril JSON or Bril text, errors are thrown as
+// JavaScript exceptions carrying the message (with source context for text input)
+
+fn load(program: &str) -> Result<RichProgram, String> {
+    let format = if program.trim_start().starts_with('{') {
+        ProgramFormat::Json
+    } else {
+        ProgramFormat::Bril
+    };
+    RichProgram::from_text(program, format, BrilFrontend::Native).map_err(|e| e.to_string())
+}
+
+fn into_ssa(program: RichProgram) -> Result<RichAbstractProgram, String> {
+    let original_text = program.original_text.clone();
+    RichAbstractProgram::try_from_program(program)
+        .map_err(|e| e.to_string_with_context(&original_text))
+}
+
+/// Run a comma separated list of passes (`lvn`, `dvnt`, `dce`, `licm`, `constprop`, `copyprop`,
+/// `loadcse`, `unroll`, `rotate`, `reassoc`, `branchfold`, `simplifycfg`) and return the program
+/// as JSON. Adding `ssa` to the list keeps the result in SSA form
+#[wasm_bindgen]
+pub fn optimize(json: &str, passes: &str) -> Result<String, String> {
+    let mut keep_ssa = false;
+
+    let mut names = Vec::new();
+    for pass in passes.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        if pass == "ssa" {
+            keep_ssa = true;
+        } else {
+            names.push(pass);
+        }
+    }
+    let pipeline = PassManager::from_names(names).map_err(|e| e.to_string())?;
+    let program = load(json)?;
+    let original_text = program.original_text.clone();
+    let program = run_pipeline_in_ssa(program, &pipeline)
+        .map_err(|e| e.to_string_with_context(&original_text))?;
+
+    let program = if keep_ssa {
+        program.into_ssa_program()
+    } else {
+        program.into_program()
+    };
+    Ok(program.to_string())
+}
+
+/// Convert a program to the Bril text format
+#[wasm_bindgen]
+pub fn to_bril(program: &str) -> Result<String, String> {
+    Ok(load(program)?.to_text())
+}
+
+/// Liveness, reaching definitions, dominance and loop facts as JSON
+#[wasm_bindgen]
+pub fn analyze(program: &str) -> Result<String, String> {
+    let program = into_ssa(load(program)?)?;
+    let facts = program_facts(&program.program)
+        .map_err(|e| e.to_string_with_context(&program.original_text))?;
+    serde_json::to_string(&facts).map_err(|e| e.to_string())
+}
+
+/// Render an artifact such as `cfg-mermaid`, `dom-dot` or `html` (same names as `--emit`)
+#[wasm_bindgen]
+pub fn visualize(program: &str, kind: &str) -> Result<String, String> {
+    let kind: Artifact = kind.parse()?;
+    let original = load(program)?;
+    let abstract_program = into_ssa(original.clone())?;
+    render_artifact(kind, Some(&original), &abstract_program)
+        .map_err(|e| e.to_string_with_context(&abstract_program.original_text))
+}
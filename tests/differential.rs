@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+
+use rayon::prelude::*;
+use rust_bril::{
+    optimizations::{run_pipeline, PassManager},
+    representation::{RichProgram, SsaForm},
+    testing::{args_comment, Divergence, Limits, Run},
+};
+
+// Every benchmark runs before and after each pipeline with the arguments of its `# ARGS:` comment,
+// and the two runs must print the same and end the same way. Runs are bounded by fuel so that the
+// long benchmarks stay cheap in debug builds. SSA form executes more instructions than the
+// original, so the optimized program gets a multiple of what the original needed; when the
+// original runs out, only the output both printed is compared
+
+const FUEL: u64 = 200_000;
+const SSA_OVERHEAD: u64 = 4;
+
+fn pipelines() -> Vec<(String, PassManager)> {
+    let mut pipelines = vec![("ssa".to_string(), PassManager::new())];
+    let passes = [
+        "lvn",
+        "dvnt",
+        "dce",
+        "licm",
+        "constprop",
+        "copyprop",
+        "loadcse",
+        "unroll",
+        "rotate",
+        "reassoc",
+        "branchfold",
+        "simplifycfg",
+        "inline",
+        "layout",
+        "hotcold",
+    ];
+    for name in passes {
+        pipelines.push((name.to_string(), PassManager::from_names([name]).unwrap()));
+    }
+    let rotated = PassManager::from_names(["rotate", "licm", "lvn", "dce"]).unwrap();
+    pipelines.push(("rotate,licm,lvn,dce".to_string(), rotated));
+    // inlining and block placement in front of and behind the scalar passes
+    let combined = [
+        "inline",
+        "lvn",
+        "licm",
+        "dce",
+        "simplifycfg",
+        "layout",
+        "hotcold",
+    ];
+    let manager = PassManager::from_names(combined).unwrap();
+    pipelines.push((combined.join(","), manager));
+    for level in 1..=3 {
+        pipelines.push((format!("-O{}", level), PassManager::preset(level)));
+    }
+    for (name, form) in [
+        ("minimal", SsaForm::Minimal),
+        ("semi-pruned", SsaForm::SemiPruned),
+    ] {
+        let mut manager = PassManager::preset(2);
+        manager.ssa_form(form);
+        pipelines.push((format!("-O2 --ssa {}", name), manager));
+    }
+    pipelines
+}
+
+fn benchmarks() -> Vec<PathBuf> {
+    let root = concat!(env!("CARGO_MANIFEST_DIR"), "/benchmarks");
+    let mut benchmarks: Vec<PathBuf> = std::fs::read_dir(root)
+        .unwrap()
+        .flat_map(|suite| std::fs::read_dir(suite.unwrap().path()).unwrap())
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "bril"))
+        .collect();
+    benchmarks.sort();
+    benchmarks
+}
+
+fn fuel(fuel: u64) -> Limits {
+    Limits {
+        fuel: Some(fuel),
+        timeout: None,
+    }
+}
+
+#[test]
+fn optimized_benchmarks_behave_like_the_originals() {
+    let pipelines = pipelines();
+    let failures: Vec<String> = benchmarks()
+        .par_iter()
+        .flat_map_iter(|path| {
+            let program = RichProgram::from_file(path).unwrap();
+            let args = args_comment(&std::fs::read_to_string(path).unwrap()).unwrap_or_default();
+            let original = Run::limited(&program.program, &args, fuel(FUEL));
+            let budget = original
+                .instructions
+                .map_or(FUEL, |n| SSA_OVERHEAD * n + 1_000);
+            let pipelines = pipelines.iter().filter_map(move |(name, passes)| {
+                let optimized = match run_pipeline(program.clone(), passes) {
+                    Ok(optimized) => optimized,
+                    Err(e) => return Some(format!("{} with {}: {}", path.display(), name, e)),
+                };
+                let run = Run::limited(&optimized.program, &args, fuel(budget));
+                if original.agrees_with(&run) {
+                    return None;
+                }
+                let divergence = Divergence {
+                    args: args.clone(),
+                    original: original.clone(),
+                    optimized: run,
+                };
+                Some(format!("{} with {}: {}", path.display(), name, divergence))
+            });
+            pipelines.collect::<Vec<_>>()
+        })
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,11 @@
+use rust_bril::{optimizations::PassManager, testing::GoldenTest};
+
+// The -O2 output of the programs in tests/golden. After an intended change to a pass, update the
+// .golden files with `RUST_BRIL_BLESS=1 cargo test --test golden` and review the diff
+
+#[test]
+fn optimized_programs_match_golden_files() {
+    let passes = PassManager::preset(2);
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+    GoldenTest::new(fixtures, &passes).run().assert_passed();
+}